@@ -1,25 +1,112 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::error::Error;
 use tokio::net::TcpStream;
 use crate::errors::Result;
-use tokio::sync::{mpsc, Mutex};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex, Notify};
 use uuid::Uuid;
 use futures::{SinkExt, StreamExt};
-use tracing::{error, info};
-use nexus_tui_common::{ClientMessage, ServerMessage};
+use tracing::{error, info, warn};
+use nexus_tui_common::{ClientMessage, ConnectionInfo, ServerMessage};
 
 use crate::api::routes::MessageRouter;
+use crate::api::transport::Transport;
 use crate::db;
-use crate::services::BroadcastService;
+use crate::services::{metrics_service, BroadcastService, ContentFilterService, MetricsService, RateLimitService};
 use tokio_rustls::server::TlsStream;
-use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Protocol version this server speaks, reported in HelloAck
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Optional features a client can ask for in Hello
+///
+/// - `seq`: every message to the peer is wrapped in `ServerMessage::Sequenced`
+///   with a per-connection `seq` starting at 0. All messages to one peer are
+///   totally ordered by `seq`, whichever task produced them.
+/// - `lazy_servers`: `GetServers` returns `ServerSummaries` without channels;
+///   the client fetches each server's channels with `GetServerDetail`.
+///
+/// Compression is negotiated separately: Hello lists the client's algorithms,
+/// HelloAck names the one chosen (or none), and every later frame in both
+/// directions uses it. Clients that never say Hello stay uncompressed.
+pub const SERVER_FEATURES: &[&str] = &["seq", "lazy_servers"];
+
+/// Malformed frames tolerated from one peer before it is disconnected
+const MAX_MALFORMED_FRAMES: u32 = 5;
+/// Outgoing messages that may fail to serialize before the peer is disconnected
+const MAX_SERIALIZATION_FAILURES: u32 = 10;
+/// How many leading bytes of a malformed frame are logged
+const MALFORMED_FRAME_PREVIEW_BYTES: usize = 32;
+/// Largest frame a compressed client frame may inflate to
+const MAX_DECOMPRESSED_FRAME_BYTES: usize = 8 * 1024 * 1024;
+/// Connections listed in an ActiveConnections report, deepest queue first
+pub const SLOWEST_CONNECTIONS_REPORTED: usize = 10;
+
+/// Sending half of a peer's outgoing queue. The queue is bounded: a client
+/// that stops reading fills it, and the next send fails and signals its
+/// connection task to drop it instead of buffering without limit.
+#[derive(Clone)]
+pub struct PeerSender {
+    tx: mpsc::Sender<ServerMessage>,
+    overflow: Arc<Notify>,
+    /// Signals the connection task to flush what is queued and hang up
+    close: Arc<Notify>,
+    bytes_sent: Arc<AtomicU64>,
+}
+
+impl PeerSender {
+    pub(crate) fn new(tx: mpsc::Sender<ServerMessage>) -> Self {
+        Self {
+            tx,
+            overflow: Arc::new(Notify::new()),
+            close: Arc::new(Notify::new()),
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Messages queued for the peer but not yet written to its socket
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Bytes written to the peer's socket so far
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Queue a message without waiting; fails if the peer is gone or too far behind
+    pub fn send(&self, message: ServerMessage) -> std::result::Result<(), TrySendError<ServerMessage>> {
+        let result = self.tx.try_send(message);
+        if let Err(TrySendError::Full(_)) = &result {
+            self.overflow.notify_one();
+        }
+        result
+    }
+
+    /// Queue a message, waiting for room instead of failing when the peer is
+    /// behind. For bulk transfers that would otherwise overflow the queue.
+    pub async fn send_wait(&self, message: ServerMessage) -> std::result::Result<(), mpsc::error::SendError<ServerMessage>> {
+        self.tx.send(message).await
+    }
+
+    /// Resolves once the peer overflowed its queue or was told to hang up
+    #[cfg(feature = "irc-gateway")]
+    pub(crate) async fn closed(&self) {
+        tokio::select! {
+            _ = self.overflow.notified() => {}
+            _ = self.close.notified() => {}
+        }
+    }
+}
 
 /// Represents a connected peer/client
 pub struct Peer {
     pub user_id: Option<Uuid>,
-    pub tx: mpsc::UnboundedSender<ServerMessage>,
+    pub tx: PeerSender,
+    /// Set once disconnect handling has run, so it only happens once per peer
+    pub disconnected: AtomicBool,
 }
 
 /// Thread-safe map of all connected peers
@@ -29,14 +116,19 @@ pub type PeerMap = Arc<Mutex<HashMap<Uuid, Peer>>>;
 async fn handle_user_disconnect(peer_map: &PeerMap, peer_id: Uuid, reason: &str) {
     info!("Handling user disconnect for peer {}: {}", peer_id, reason);
     
-    // Get user info before cleanup
+    // Get user info before cleanup, skipping peers whose disconnect was already handled
     let user_id_opt = {
         let peers = peer_map.lock().await;
-        peers.get(&peer_id).and_then(|p| p.user_id)
+        match peers.get(&peer_id) {
+            Some(peer) if !peer.disconnected.swap(true, Ordering::SeqCst) => peer.user_id,
+            _ => None,
+        }
     };
     
     // Broadcast user disconnect if they were authenticated
     if let Some(user_id) = user_id_opt {
+        let _ = db::users::db_touch_user_last_seen(user_id).await;
+
         if let Ok(profile) = db::users::db_get_user_by_id(user_id).await {
             let user = nexus_tui_common::User {
                 id: profile.id,
@@ -54,16 +146,282 @@ async fn handle_user_disconnect(peer_map: &PeerMap, peer_id: Uuid, reason: &str)
     }
 }
 
-/// Main connection handler - processes client connections and messages
-pub async fn handle_connection<S>(
-    stream: S,
+/// Schedule removal of peers whose channel is closed, found while broadcasting.
+/// Runs on a separate task so callers can release the peer map lock first.
+pub(crate) fn schedule_dead_peer_cleanup(peer_map: &PeerMap, peer_ids: Vec<Uuid>) {
+    if peer_ids.is_empty() {
+        return;
+    }
+    let peer_map = peer_map.clone();
+    tokio::spawn(async move {
+        for peer_id in peer_ids {
+            handle_user_disconnect(&peer_map, peer_id, "send failed").await;
+            peer_map.lock().await.remove(&peer_id);
+        }
+    });
+}
+
+/// Snapshot of every connection's outgoing queue, slowest consumers first.
+/// Also refreshes the aggregate queued-messages gauge.
+pub(crate) async fn connection_stats(peer_map: &PeerMap) -> (usize, Vec<ConnectionInfo>) {
+    let mut connections: Vec<ConnectionInfo> = {
+        let peers = peer_map.lock().await;
+        peers
+            .iter()
+            .map(|(peer_id, peer)| ConnectionInfo {
+                peer_id: *peer_id,
+                user_id: peer.user_id,
+                queue_depth: peer.tx.queue_depth(),
+                bytes_sent: peer.tx.bytes_sent(),
+            })
+            .collect()
+    };
+
+    let queued: usize = connections.iter().map(|connection| connection.queue_depth).sum();
+    MetricsService::set_gauge(metrics_service::QUEUED_MESSAGES, queued as u64);
+
+    connections.sort_by(|a, b| b.queue_depth.cmp(&a.queue_depth));
+    (queued, connections)
+}
+
+/// Kick a peer: queue a final notice, broadcast its disconnect, then have its
+/// connection task flush and close. Returns false if no such peer is connected.
+pub(crate) async fn disconnect_peer(peer_map: &PeerMap, peer_id: Uuid, reason: &str) -> bool {
+    let sender = match peer_map.lock().await.get(&peer_id) {
+        Some(peer) => peer.tx.clone(),
+        None => return false,
+    };
+
+    let _ = sender.send(ServerMessage::Notification(format!("Disconnected by an admin: {}", reason), true));
+    handle_user_disconnect(peer_map, peer_id, reason).await;
+    sender.close.notify_one();
+    true
+}
+
+/// End every session of a user, telling each client why. Returns how many were closed.
+pub(crate) async fn revoke_user_sessions(peer_map: &PeerMap, user_id: Uuid, reason: &str) -> usize {
+    close_user_sessions(peer_map, user_id, ServerMessage::SessionRevoked { reason: reason.to_string() }, reason).await
+}
+
+/// End every session of a user, queueing `notice` as the last message each
+/// client gets. Returns how many were closed.
+pub(crate) async fn close_user_sessions(peer_map: &PeerMap, user_id: Uuid, notice: ServerMessage, reason: &str) -> usize {
+    let sessions: Vec<(Uuid, PeerSender)> = peer_map.lock().await
+        .iter()
+        .filter(|(_, peer)| peer.user_id == Some(user_id))
+        .map(|(peer_id, peer)| (*peer_id, peer.tx.clone()))
+        .collect();
+
+    for (peer_id, sender) in &sessions {
+        let _ = sender.send(notice.clone());
+        handle_user_disconnect(peer_map, *peer_id, reason).await;
+        sender.close.notify_one();
+    }
+    sessions.len()
+}
+
+/// Register a session that is not driven by `handle_connection` (the IRC
+/// gateway), so broadcasts, kicks and revocations reach it like any client.
+/// The caller drains the receiver and must call `unregister_external_peer`.
+#[cfg(feature = "irc-gateway")]
+pub(crate) async fn register_external_peer(
+    peer_map: &PeerMap,
+    user_id: Uuid,
+) -> (Uuid, PeerSender, mpsc::Receiver<ServerMessage>) {
+    let peer_id = Uuid::new_v4();
+    let capacity = crate::config::settings().connections.send_buffer_capacity.max(1);
+    let (tx, rx) = mpsc::channel(capacity);
+    let tx = PeerSender::new(tx);
+    peer_map.lock().await.insert(
+        peer_id,
+        Peer {
+            user_id: Some(user_id),
+            tx: tx.clone(),
+            disconnected: AtomicBool::new(false),
+        },
+    );
+    (peer_id, tx, rx)
+}
+
+/// Broadcast an external session's disconnect and drop it from the peer map
+#[cfg(feature = "irc-gateway")]
+pub(crate) async fn unregister_external_peer(peer_map: &PeerMap, peer_id: Uuid, reason: &str) {
+    handle_user_disconnect(peer_map, peer_id, reason).await;
+    peer_map.lock().await.remove(&peer_id);
+}
+
+/// Frame compression agreed in Hello; applies to every frame after the HelloAck, both ways
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Zstd,
+}
+
+impl Compression {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// First algorithm in the server's preference order that the client also supports
+    fn choose(client_algorithms: &[String]) -> Option<Self> {
+        crate::config::settings()
+            .connections
+            .compression
+            .iter()
+            .filter(|name| client_algorithms.contains(name))
+            .find_map(|name| Self::from_name(name))
+    }
+}
+
+/// What a client negotiated with Hello; old clients that never send it get the defaults
+#[derive(Debug, Default)]
+struct Session {
+    protocol_version: Option<u32>,
+    sequenced: bool,
+    lazy_servers: bool,
+    compression: Option<Compression>,
+    next_seq: u64,
+}
+
+impl Session {
+    /// Agree on features and compression from a client's Hello, returning the
+    /// ack to send back. The ack itself must go out uncompressed.
+    fn negotiate(&mut self, protocol_version: u32, features: Vec<String>, compression: Vec<String>) -> ServerMessage {
+        let features: Vec<String> = features
+            .into_iter()
+            .filter(|feature| SERVER_FEATURES.contains(&feature.as_str()))
+            .collect();
+
+        self.protocol_version = Some(protocol_version.min(PROTOCOL_VERSION));
+        self.sequenced = features.iter().any(|feature| feature == "seq");
+        self.lazy_servers = features.iter().any(|feature| feature == "lazy_servers");
+        self.compression = Compression::choose(&compression);
+
+        ServerMessage::HelloAck {
+            protocol_version: PROTOCOL_VERSION,
+            features,
+            compression: self.compression.map(|compression| compression.name().to_string()),
+            server_time: crate::util::now_millis(),
+        }
+    }
+
+    /// Wrap an outgoing message in the negotiated envelope, if any
+    fn envelope(&self, message: ServerMessage) -> ServerMessage {
+        if !self.sequenced {
+            return message;
+        }
+        ServerMessage::Sequenced {
+            seq: self.next_seq,
+            message: Box::new(message),
+        }
+    }
+
+    /// Serialize an outgoing message in its envelope. A sequence number is only
+    /// used up by a frame that serialized, so skipped frames leave no gap.
+    fn serialize(&mut self, message: ServerMessage) -> std::result::Result<Vec<u8>, String> {
+        let message = self.envelope(message);
+        match serialize_frame(&message) {
+            Ok(frame) => {
+                if self.sequenced {
+                    self.next_seq += 1;
+                }
+                Ok(frame)
+            }
+            Err(e) => Err(format!("{} could not be serialized: {}", variant_name(&message), e)),
+        }
+    }
+
+    /// Serialize an outgoing message into a frame, compressed if negotiated
+    fn encode(&mut self, message: ServerMessage) -> std::result::Result<Vec<u8>, String> {
+        let frame = self.serialize(message)?;
+        Ok(match self.compression {
+            Some(Compression::Zstd) => {
+                let level = crate::config::settings().connections.compression_level;
+                zstd::bulk::compress(&frame, level).unwrap_or(frame)
+            }
+            None => frame,
+        })
+    }
+
+    /// Parse an incoming frame, inflating it first if compression was negotiated
+    fn decode(&self, frame: &[u8]) -> std::result::Result<ClientMessage, String> {
+        match self.compression {
+            Some(Compression::Zstd) => {
+                let inflated = zstd::bulk::decompress(frame, MAX_DECOMPRESSED_FRAME_BYTES)
+                    .map_err(|e| format!("zstd: {}", e))?;
+                bincode::deserialize(&inflated).map_err(|e| e.to_string())
+            }
+            None => bincode::deserialize(frame).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The bincode encoding of an outgoing message
+#[cfg(not(test))]
+fn serialize_frame(message: &ServerMessage) -> bincode::Result<Vec<u8>> {
+    bincode::serialize(message)
+}
+
+/// Every ServerMessage serializes today, so tests stand in for one that
+/// can't: a Notification reading `tests::UNSERIALIZABLE` fails to encode
+#[cfg(test)]
+fn serialize_frame(message: &ServerMessage) -> bincode::Result<Vec<u8>> {
+    let inner = match message {
+        ServerMessage::Sequenced { message, .. } => message.as_ref(),
+        other => other,
+    };
+    if matches!(inner, ServerMessage::Notification(text, _) if text == tests::UNSERIALIZABLE) {
+        return Err(Box::new(bincode::ErrorKind::Custom("test message can't be serialized".to_string())));
+    }
+    bincode::serialize(message)
+}
+
+/// Name of a message's variant for logs, looking through the sequencing envelope
+fn variant_name(message: &ServerMessage) -> String {
+    if let ServerMessage::Sequenced { message, .. } = message {
+        return variant_name(message);
+    }
+    let debug = format!("{:?}", message);
+    debug
+        .split(|c: char| c == '(' || c == '{' || c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Hex preview of the start of a frame for logging
+fn frame_preview(frame: &[u8]) -> String {
+    frame
+        .iter()
+        .take(MALFORMED_FRAME_PREVIEW_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Main connection handler - processes client connections and messages,
+/// whichever transport carries their frames
+pub async fn handle_connection<T: Transport>(
+    transport: T,
+    peer_ip: IpAddr,
     peer_map: PeerMap,
-) -> Result<()>
-where
-    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-{
+    content_filter: Arc<ContentFilterService>,
+    rate_limiter: Arc<RateLimitService>,
+) -> Result<()> {
     let peer_id = Uuid::new_v4();
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    let capacity = crate::config::settings().connections.send_buffer_capacity.max(1);
+    let (tx, mut rx) = mpsc::channel(capacity);
+    let tx = PeerSender::new(tx);
+    let overflow = tx.overflow.clone();
+    let close = tx.close.clone();
 
     {
         let mut peers = peer_map.lock().await;
@@ -72,24 +430,72 @@ where
             Peer {
                 user_id: None,
                 tx: tx.clone(),
+                disconnected: AtomicBool::new(false),
             },
         );
     }
 
-    let framed = Framed::new(stream, LengthDelimitedCodec::new());
-    let (mut sink, mut stream) = framed.split();
+    let (mut sink, mut stream) = transport.into_frames();
 
     let peer_map_task = peer_map.clone();
     tokio::spawn(async move {
         let mut current_user: Option<nexus_tui_common::User> = None;
-        let router = MessageRouter::new(peer_map_task.clone());
+        let router = MessageRouter::new(peer_map_task.clone(), peer_ip, content_filter, rate_limiter);
+        let mut malformed_frames = 0u32;
+        let max_pre_auth_messages = crate::config::settings().connections.max_pre_auth_messages;
+        let mut pre_auth_messages = 0u32;
+        let mut session = Session::default();
+        let mut serialization_failures = 0u32;
         
         loop {
             tokio::select! {
                 stream_result = stream.next() => {
                     match stream_result {
                         Some(Ok(msg)) => {
-                            match bincode::deserialize::<ClientMessage>(&msg) {
+                            let decoded = session.decode(&msg);
+                            // Anonymous clients get a handful of messages to look at the server
+                            // info and log in; anything beyond that is probing. Hello and Ping
+                            // are connection upkeep and don't count, and a logout starts afresh.
+                            if current_user.is_some() {
+                                pre_auth_messages = 0;
+                            } else if !matches!(decoded, Ok(ClientMessage::Hello { .. } | ClientMessage::Ping)) {
+                                pre_auth_messages += 1;
+                                if pre_auth_messages > max_pre_auth_messages {
+                                    warn!("Disconnecting peer {} ({}): too many messages before login", peer_id, peer_ip);
+                                    handle_user_disconnect(&peer_map_task, peer_id, "pre-auth message limit").await;
+                                    break;
+                                }
+                            }
+                            match decoded {
+                                Ok(ClientMessage::Hello { protocol_version, features, compression }) => {
+                                    // Connection-level negotiation. The ack is written straight to the
+                                    // socket, uncompressed; the chosen compression applies from the next frame.
+                                    let ack = session.negotiate(protocol_version, features, compression);
+                                    router.set_lazy_servers(session.lazy_servers);
+                                    info!(
+                                        "Peer {} negotiated protocol v{:?} (sequenced: {}, compression: {:?})",
+                                        peer_id, session.protocol_version, session.sequenced, session.compression
+                                    );
+                                    let frame = match session.serialize(ack) {
+                                        Ok(frame) => frame,
+                                        Err(e) => {
+                                            error!("Cannot send HelloAck to peer {}: {}", peer_id, e);
+                                            MetricsService::increment(metrics_service::SERIALIZATION_FAILURES);
+                                            handle_user_disconnect(&peer_map_task, peer_id, "handshake failed").await;
+                                            break;
+                                        }
+                                    };
+                                    tx.bytes_sent.fetch_add(frame.len() as u64, Ordering::Relaxed);
+                                    if let Err(e) = sink.send(frame.into()).await {
+                                        error!("Error sending HelloAck: {:?}", e);
+                                        handle_user_disconnect(&peer_map_task, peer_id, "stream error").await;
+                                        break;
+                                    }
+                                    // Queued, so it already goes out in the negotiated envelope
+                                    if let Err(e) = tx.send(ServerMessage::TimeSync { server_time: crate::util::now_millis() }) {
+                                        error!("Failed to queue TimeSync for peer {}: {:?}", peer_id, e);
+                                    }
+                                }
                                 Ok(message) => {
                                     // tracing::info!("Parsed ClientMessage: {:?}", message);
                                     
@@ -101,10 +507,29 @@ where
                                         &tx,
                                     ).await {
                                         error!("Error handling message: {:?}", e);
+                                        MetricsService::increment(metrics_service::HANDLER_ERRORS);
                                     }
                                 }
                                 Err(e) => {
-                                    error!("Error parsing message: {:?}", e);
+                                    malformed_frames += 1;
+                                    MetricsService::increment(metrics_service::MALFORMED_FRAMES);
+                                    error!(
+                                        "Error parsing message from peer {} (protocol {:?}, {} bytes, {}/{} malformed): {:?} [{}]",
+                                        peer_id,
+                                        session.protocol_version,
+                                        msg.len(),
+                                        malformed_frames,
+                                        MAX_MALFORMED_FRAMES,
+                                        e,
+                                        frame_preview(&msg),
+                                    );
+
+                                    // Likely not speaking our protocol at all (port scanner, TLS client, ...)
+                                    if malformed_frames >= MAX_MALFORMED_FRAMES {
+                                        warn!("Disconnecting peer {} after {} malformed frames", peer_id, malformed_frames);
+                                        handle_user_disconnect(&peer_map_task, peer_id, "malformed frames").await;
+                                        break;
+                                    }
                                 }
                             }
                         }
@@ -121,9 +546,70 @@ where
                         }
                     }
                 }
+                _ = overflow.notified() => {
+                    warn!("Disconnecting peer {}: send buffer full ({} messages)", peer_id, capacity);
+                    MetricsService::increment(metrics_service::SEND_BUFFER_OVERFLOWS);
+                    handle_user_disconnect(&peer_map_task, peer_id, "send buffer full").await;
+                    break;
+                }
+                _ = close.notified() => {
+                    // Disconnect was already handled by whoever closed us; deliver what's left first
+                    info!("Closing peer {} on request", peer_id);
+                    while let Ok(msg) = rx.try_recv() {
+                        let frame = match session.encode(msg) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                error!("Skipping frame to peer {}: {}", peer_id, e);
+                                MetricsService::increment(metrics_service::SERIALIZATION_FAILURES);
+                                continue;
+                            }
+                        };
+                        if sink.send(frame.into()).await.is_err() {
+                            break;
+                        }
+                    }
+                    break;
+                }
                 Some(msg) = rx.recv() => {
                     // tracing::debug!("Sending ServerMessage: {:?}", msg);
-                    if let Err(e) = sink.send(bincode::serialize(&msg).unwrap().into()).await {
+                    // Role changes apply to this session's permission checks from here on
+                    if let ServerMessage::RoleChanged { new_role } = &msg {
+                        if let Some(user) = current_user.as_mut() {
+                            info!("Peer {} role changed to {:?}", peer_id, new_role);
+                            user.role = new_role.clone();
+                        }
+                    }
+                    let frame = match session.encode(msg) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            // A message the protocol can't carry shouldn't take the whole connection down
+                            serialization_failures += 1;
+                            MetricsService::increment(metrics_service::SERIALIZATION_FAILURES);
+                            error!(
+                                "Skipping frame to peer {} ({}/{} serialization failures): {}",
+                                peer_id, serialization_failures, MAX_SERIALIZATION_FAILURES, e
+                            );
+                            if serialization_failures > MAX_SERIALIZATION_FAILURES {
+                                warn!("Disconnecting peer {} after {} serialization failures", peer_id, serialization_failures);
+                                handle_user_disconnect(&peer_map_task, peer_id, "serialization failures").await;
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+                    tx.bytes_sent.fetch_add(frame.len() as u64, Ordering::Relaxed);
+                    // A client that stopped reading stalls this write, so the
+                    // overflow signal has to be watched here as well
+                    let sent = tokio::select! {
+                        sent = sink.send(frame.into()) => sent,
+                        _ = overflow.notified() => {
+                            warn!("Disconnecting peer {}: send buffer full ({} messages)", peer_id, capacity);
+                            MetricsService::increment(metrics_service::SEND_BUFFER_OVERFLOWS);
+                            handle_user_disconnect(&peer_map_task, peer_id, "send buffer full").await;
+                            break;
+                        }
+                    };
+                    if let Err(e) = sent {
                         error!("Error sending message: {:?}", e);
                         
                         // Check if it's a broken pipe error for immediate handling
@@ -139,19 +625,294 @@ where
             }
         }
         
-        // Final cleanup - remove from peer map and handle any remaining disconnect
-        let was_authenticated = {
-            let mut peers = peer_map_task.lock().await;
-            let was_auth = peers.get(&peer_id).and_then(|p| p.user_id).is_some();
-            peers.remove(&peer_id);
-            was_auth
-        };
-        
-        // Only do final disconnect handling if we haven't already handled it above
-        if was_authenticated {
-            handle_user_disconnect(&peer_map_task, peer_id, "connection cleanup").await;
-        }
+        // Final cleanup - the guard flag makes this a no-op if the disconnect was
+        // already handled above or by a failed broadcast
+        handle_user_disconnect(&peer_map_task, peer_id, "connection cleanup").await;
+        peer_map_task.lock().await.remove(&peer_id);
     });
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::transport::LengthDelimited;
+    use crate::config::RateLimitConfig;
+    use crate::services::UserService;
+    use crate::test_support::{self, TestDb};
+    use bytes::Bytes;
+    use nexus_tui_common::UserRole;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+    type Client = Framed<DuplexStream, LengthDelimitedCodec>;
+
+    /// Notification text that `serialize_frame` refuses to encode
+    pub(super) const UNSERIALIZABLE: &str = "unserializable test message";
+
+    /// Serve one in-memory connection; returns the client end, the peer map
+    /// and the connection's peer id
+    async fn connect(buffer: usize) -> (Client, PeerMap, Uuid) {
+        let peer_map = test_support::peer_map();
+        let (client, server) = tokio::io::duplex(buffer);
+        handle_connection(
+            LengthDelimited(server),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            peer_map.clone(),
+            Arc::new(test_support::content_filter()),
+            Arc::new(RateLimitService::new(&RateLimitConfig::default())),
+        ).await.unwrap();
+        let peer_id = *peer_map.lock().await.keys().next().unwrap();
+        (Framed::new(client, LengthDelimitedCodec::new()), peer_map, peer_id)
+    }
+
+    /// Not a ClientMessage: the variant index is far out of range
+    fn garbage() -> Bytes {
+        Bytes::from_static(&[0xff; 16])
+    }
+
+    async fn send(client: &mut Client, message: &ClientMessage) {
+        client.send(Bytes::from(bincode::serialize(message).unwrap())).await.unwrap();
+    }
+
+    /// Next frame from the server, or None once it hung up
+    async fn next_message(client: &mut Client) -> Option<ServerMessage> {
+        let frame = tokio::time::timeout(Duration::from_secs(5), client.next()).await
+            .expect("timed out waiting for the server")?
+            .ok()?;
+        Some(bincode::deserialize(&frame).expect("server frame"))
+    }
+
+    /// Read messages until `pick` accepts one
+    async fn next_matching<T>(client: &mut Client, mut pick: impl FnMut(ServerMessage) -> Option<T>) -> T {
+        loop {
+            let message = next_message(client).await.expect("server hung up");
+            if let Some(picked) = pick(message) {
+                return picked;
+            }
+        }
+    }
+
+    /// Say Hello offering `compression`, returning what the server picked.
+    /// Only for plain frames: the TimeSync after the ack is read as one.
+    async fn hello(client: &mut Client, compression: &[&str]) -> Option<String> {
+        send(client, &ClientMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: Vec::new(),
+            compression: compression.iter().map(|name| name.to_string()).collect(),
+        }).await;
+        let picked = match next_message(client).await {
+            Some(ServerMessage::HelloAck { compression, .. }) => compression,
+            _ => panic!("expected a HelloAck"),
+        };
+        if picked.is_none() {
+            assert!(matches!(next_message(client).await, Some(ServerMessage::TimeSync { .. })));
+        }
+        picked
+    }
+
+    #[tokio::test]
+    async fn zstd_clients_get_compressed_frames() {
+        let (mut client, _peer_map, _) = connect(64 * 1024).await;
+        assert_eq!(hello(&mut client, &["gzip", "zstd"]).await.as_deref(), Some("zstd"));
+
+        let ping = zstd::bulk::compress(&bincode::serialize(&ClientMessage::Ping).unwrap(), 3).unwrap();
+        client.send(Bytes::from(ping)).await.unwrap();
+        // The clock sync following the ack, then the reply to the Ping
+        for _ in 0..2 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap().unwrap().unwrap();
+            let inflated = zstd::bulk::decompress(&frame, MAX_DECOMPRESSED_FRAME_BYTES).expect("a zstd frame");
+            let reply: ServerMessage = bincode::deserialize(&inflated).unwrap();
+            assert!(matches!(reply, ServerMessage::TimeSync { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_without_a_shared_algorithm_get_plain_frames() {
+        let (mut client, _peer_map, _) = connect(64 * 1024).await;
+        assert_eq!(hello(&mut client, &[]).await, None);
+
+        send(&mut client, &ClientMessage::Ping).await;
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::TimeSync { .. })));
+
+        let (mut client, _peer_map, _) = connect(64 * 1024).await;
+        assert_eq!(hello(&mut client, &["gzip"]).await, None);
+        send(&mut client, &ClientMessage::Ping).await;
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::TimeSync { .. })));
+    }
+
+    #[tokio::test]
+    async fn a_role_change_applies_to_the_live_session() {
+        let db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let mallory = test_support::create_user("mallory").await;
+        let post_id = Uuid::new_v4();
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "INSERT INTO posts (id, thread_id, author_id, content, timestamp, depth) VALUES (?1, ?2, ?3, 'post', 0, 0)",
+            rusqlite::params![post_id.to_string(), Uuid::new_v4().to_string(), admin.id.to_string()],
+        ).unwrap();
+        let (mut client, peer_map, _) = connect(64 * 1024).await;
+
+        send(&mut client, &ClientMessage::Login {
+            username: "mallory".to_string(),
+            password: test_support::TEST_PASSWORD.to_string(),
+        }).await;
+        next_matching(&mut client, |message| matches!(message, ServerMessage::AuthSuccess(_)).then_some(())).await;
+
+        // Moderator-only request: refused while mallory is a User
+        let diff = ClientMessage::GetPostEditDiff { post_id, from_revision: 0, to_revision: 0 };
+        send(&mut client, &diff).await;
+        let refusal = next_matching(&mut client, |message| match message {
+            ServerMessage::Notification(text, true) => Some(text),
+            _ => None,
+        }).await;
+        assert!(refusal.contains("Only moderators"), "{}", refusal);
+
+        UserService::set_user_role(&admin, mallory.id, UserRole::Moderator, &peer_map).await.unwrap();
+        next_matching(&mut client, |message| matches!(message, ServerMessage::RoleChanged { .. }).then_some(())).await;
+
+        // Same connection, no new login
+        send(&mut client, &diff).await;
+        let diffed = next_matching(&mut client, |message| match message {
+            ServerMessage::PostEditDiff { post_id, .. } => Some(post_id),
+            ServerMessage::Notification(text, true) => panic!("still refused: {}", text),
+            _ => None,
+        }).await;
+        assert_eq!(diffed, post_id);
+    }
+
+    #[tokio::test]
+    async fn peer_is_dropped_at_the_malformed_frame_limit() {
+        let (mut client, peer_map, peer_id) = connect(64 * 1024).await;
+
+        for _ in 1..MAX_MALFORMED_FRAMES {
+            client.send(garbage()).await.unwrap();
+        }
+        // Still connected one short of the limit
+        send(&mut client, &ClientMessage::Ping).await;
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::TimeSync { .. })));
+
+        // Valid frames in between don't reset the count
+        client.send(garbage()).await.unwrap();
+        assert!(next_message(&mut client).await.is_none());
+        assert!(test_support::peer_removed(&peer_map, peer_id).await);
+    }
+
+    #[tokio::test]
+    async fn only_requests_count_towards_the_pre_auth_limit() {
+        let _db = TestDb::new().await;
+        test_support::create_user("alice").await;
+        let (mut client, peer_map, peer_id) = connect(64 * 1024).await;
+        let max = crate::config::settings().connections.max_pre_auth_messages;
+        let anonymous_request = ClientMessage::GetProfile { user_id: Uuid::new_v4() };
+
+        // Keepalives and renegotiation are free, however many there are
+        for _ in 0..=max {
+            hello(&mut client, &[]).await;
+            send(&mut client, &ClientMessage::Ping).await;
+            assert!(matches!(next_message(&mut client).await, Some(ServerMessage::TimeSync { .. })));
+        }
+
+        send(&mut client, &ClientMessage::Login {
+            username: "alice".to_string(),
+            password: test_support::TEST_PASSWORD.to_string(),
+        }).await;
+        next_matching(&mut client, |message| matches!(message, ServerMessage::AuthSuccess(_)).then_some(())).await;
+        send(&mut client, &ClientMessage::Logout).await;
+
+        // Logging out starts a fresh allowance
+        for _ in 0..max {
+            send(&mut client, &anonymous_request).await;
+            next_matching(&mut client, |message| matches!(message, ServerMessage::Notification(_, true)).then_some(())).await;
+        }
+        send(&mut client, &anonymous_request).await;
+        while next_message(&mut client).await.is_some() {}
+        assert!(test_support::peer_removed(&peer_map, peer_id).await);
+    }
+
+    #[tokio::test]
+    async fn peer_that_stops_reading_is_dropped_once_its_queue_fills() {
+        // The client never reads and the pipe holds less than one frame,
+        // so the connection's writer stalls on its first message
+        let (_client, peer_map, peer_id) = connect(64).await;
+        let sender = peer_map.lock().await[&peer_id].tx.clone();
+
+        let notice = ServerMessage::Notification("x".repeat(200), false);
+        let capacity = crate::config::settings().connections.send_buffer_capacity;
+        let mut sent = 0;
+        while sender.send(notice.clone()).is_ok() {
+            sent += 1;
+            assert!(sent <= capacity + 1, "queue never filled");
+        }
+
+        assert!(test_support::peer_removed(&peer_map, peer_id).await);
+        assert!(matches!(sender.send(notice), Err(TrySendError::Closed(_))));
+    }
+
+    #[tokio::test]
+    async fn an_unserializable_message_is_skipped_and_the_connection_survives() {
+        let (mut client, peer_map, peer_id) = connect(64 * 1024).await;
+        let sender = peer_map.lock().await[&peer_id].tx.clone();
+        let failures_before = MetricsService::counter(metrics_service::SERIALIZATION_FAILURES);
+
+        sender.send(ServerMessage::Notification(UNSERIALIZABLE.to_string(), false)).unwrap();
+        sender.send(ServerMessage::Notification("still here".to_string(), false)).unwrap();
+
+        assert!(matches!(
+            next_message(&mut client).await,
+            Some(ServerMessage::Notification(text, false)) if text == "still here"
+        ));
+        assert!(MetricsService::counter(metrics_service::SERIALIZATION_FAILURES) > failures_before);
+        send(&mut client, &ClientMessage::Ping).await;
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::TimeSync { .. })));
+        assert!(peer_map.lock().await.contains_key(&peer_id));
+    }
+
+    #[tokio::test]
+    async fn a_sequenced_session_skips_no_sequence_number() {
+        let (mut client, peer_map, peer_id) = connect(64 * 1024).await;
+        let sender = peer_map.lock().await[&peer_id].tx.clone();
+        send(&mut client, &ClientMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: vec!["seq".to_string()],
+            compression: Vec::new(),
+        }).await;
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::HelloAck { .. })));
+        // The clock sync that follows the ack is the first sequenced frame
+        assert!(matches!(next_message(&mut client).await, Some(ServerMessage::Sequenced { .. })));
+
+        sender.send(ServerMessage::Notification("first".to_string(), false)).unwrap();
+        sender.send(ServerMessage::Notification(UNSERIALIZABLE.to_string(), false)).unwrap();
+        sender.send(ServerMessage::Notification("second".to_string(), false)).unwrap();
+
+        let mut seqs = Vec::new();
+        for _ in 0..2 {
+            match next_message(&mut client).await {
+                Some(ServerMessage::Sequenced { seq, .. }) => seqs.push(seq),
+                other => panic!("expected a sequenced frame, got {:?}", other),
+            }
+        }
+        assert_eq!(seqs[1], seqs[0] + 1);
+    }
+
+    #[tokio::test]
+    async fn peer_is_dropped_past_the_serialization_failure_limit() {
+        let (mut client, peer_map, peer_id) = connect(64 * 1024).await;
+        let sender = peer_map.lock().await[&peer_id].tx.clone();
+
+        for _ in 0..MAX_SERIALIZATION_FAILURES {
+            sender.send(ServerMessage::Notification(UNSERIALIZABLE.to_string(), false)).unwrap();
+        }
+        sender.send(ServerMessage::Notification("at the limit".to_string(), false)).unwrap();
+        assert!(matches!(
+            next_message(&mut client).await,
+            Some(ServerMessage::Notification(text, false)) if text == "at the limit"
+        ));
+
+        sender.send(ServerMessage::Notification(UNSERIALIZABLE.to_string(), false)).unwrap();
+        assert!(test_support::peer_removed(&peer_map, peer_id).await);
+    }
+}
@@ -0,0 +1,474 @@
+// Optional IRC gateway, built with the `irc-gateway` feature.
+//
+// Plain IRC clients connect to a separate listener, authenticate with
+// PASS/NICK/USER (the nick is the Nexus username, PASS its bot token) and can
+// JOIN the channels mapped in `[irc.channels]`. Channel messages are bridged
+// both ways: PRIVMSG goes through `ChatService::send_channel_message` as the
+// authenticated user, and new messages in joined channels come back as
+// PRIVMSG. Only NICK, USER, PASS, JOIN, PRIVMSG, PING and QUIT are
+// understood; anything else gets a numeric error.
+//
+// The listener is plain TCP, so only bot accounts may log in: their token can
+// be rotated if it leaks, while a person's password is reused elsewhere. A
+// refused person gets the same reply as a wrong password, so the gateway
+// doesn't reveal which accounts are bots.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use futures::{SinkExt, StreamExt};
+use nexus_tui_common::{ChannelMessage, ServerMessage, User, UserRole};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::api::connection::{self, PeerMap};
+use crate::db;
+use crate::services::content_filter_service::FilterResult;
+use crate::services::{ChatService, ContentFilterService, ModerationService, RateLimitService, SettingsService, UserService};
+
+/// Name the gateway uses as its own prefix and as the host part of user prefixes
+const SERVER_NAME: &str = "nexus";
+/// Longest line accepted from a client; IRC allows 512, but clients splitting
+/// long messages is not worth insisting on
+const MAX_LINE_BYTES: usize = 8192;
+
+const RPL_WELCOME: &str = "001";
+const RPL_NAMREPLY: &str = "353";
+const RPL_ENDOFNAMES: &str = "366";
+const ERR_NOSUCHNICK: &str = "401";
+const ERR_NOSUCHCHANNEL: &str = "403";
+const ERR_CANNOTSENDTOCHAN: &str = "404";
+const ERR_NOTEXTTOSEND: &str = "412";
+const ERR_UNKNOWNCOMMAND: &str = "421";
+const ERR_NOTREGISTERED: &str = "451";
+const ERR_NEEDMOREPARAMS: &str = "461";
+const ERR_ALREADYREGISTRED: &str = "462";
+const ERR_PASSWDMISMATCH: &str = "464";
+const ERR_INVITEONLYCHAN: &str = "473";
+
+type Lines = Framed<TcpStream, LinesCodec>;
+
+/// Accept IRC clients until shutdown
+pub async fn run(
+    listener: TcpListener,
+    peer_map: PeerMap,
+    content_filter: Arc<ContentFilterService>,
+    rate_limiter: Arc<RateLimitService>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("IRC gateway accept failed: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown_rx.changed() => {
+                info!("IRC gateway shutting down");
+                return;
+            }
+        };
+        let peer_map = peer_map.clone();
+        let content_filter = content_filter.clone();
+        let rate_limiter = rate_limiter.clone();
+        tokio::spawn(handle_client(stream, peer_addr, peer_map, content_filter, rate_limiter));
+    }
+}
+
+/// One IRC command: verb plus parameters, the trailing one included
+struct IrcCommand {
+    verb: String,
+    params: Vec<String>,
+}
+
+fn parse_line(line: &str) -> Option<IrcCommand> {
+    let mut rest = line.trim();
+    // A client-sent prefix carries nothing we trust
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ').map(|(_, rest)| rest.trim_start()).unwrap_or("");
+    }
+    let (head, trailing) = match rest.split_once(" :") {
+        Some((head, trailing)) => (head, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = head.split_whitespace();
+    let verb = words.next()?.to_ascii_uppercase();
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    if let Some(trailing) = trailing {
+        params.push(trailing.to_string());
+    }
+    Some(IrcCommand { verb, params })
+}
+
+/// IRC nicks cannot contain spaces or the prefix separators
+fn irc_nick(username: &str) -> String {
+    username
+        .chars()
+        .map(|c| if c.is_whitespace() || c == '!' || c == '@' || c == ',' { '_' } else { c })
+        .collect()
+}
+
+fn numeric(code: &str, nick: &str, rest: &str) -> String {
+    format!(":{} {} {} {}", SERVER_NAME, code, nick, rest)
+}
+
+async fn send_line(lines: &mut Lines, line: String) -> Result<(), LinesCodecError> {
+    lines.send(format!("{}\r", line)).await
+}
+
+/// The Nexus channel an IRC channel name is mapped to, compared case-insensitively
+fn mapped_channel(name: &str) -> Option<(String, Uuid)> {
+    crate::config::settings()
+        .irc
+        .channels
+        .iter()
+        .find(|(irc_name, _)| irc_name.eq_ignore_ascii_case(name))
+        .map(|(irc_name, channel_id)| (irc_name.clone(), *channel_id))
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    peer_map: PeerMap,
+    content_filter: Arc<ContentFilterService>,
+    rate_limiter: Arc<RateLimitService>,
+) {
+    let mut lines = Framed::new(stream, LinesCodec::new_with_max_length(MAX_LINE_BYTES));
+
+    let credential_epoch = UserService::credential_epoch();
+    let Some(user) = register(&mut lines, peer_addr, &peer_map).await else {
+        return;
+    };
+    let nick = irc_nick(&user.username);
+
+    let (peer_id, sender, mut rx) = connection::register_external_peer(&peer_map, user.id).await;
+
+    // Same race as a regular login: a rotation between the password check and
+    // the peer being registered would not have revoked this session
+    if UserService::credentials_rotated_since(user.id, credential_epoch) {
+        let _ = send_line(&mut lines, numeric(ERR_PASSWDMISMATCH, &nick, ":Credentials were rotated; reconnect with the new ones")).await;
+        connection::unregister_external_peer(&peer_map, peer_id, "credentials rotated").await;
+        return;
+    }
+
+    info!("IRC gateway: {} logged in from {}", user.username, peer_addr);
+    let mut bridge = Bridge {
+        user,
+        nick,
+        joined: HashMap::new(),
+        authors: HashMap::new(),
+    };
+
+    let welcome = format!(":Welcome to the Nexus IRC gateway, {}", bridge.nick);
+    let reason = if send_line(&mut lines, numeric(RPL_WELCOME, &bridge.nick, &welcome)).await.is_err() {
+        "write failed"
+    } else {
+        loop {
+            tokio::select! {
+                line = lines.next() => match line {
+                    Some(Ok(line)) => match bridge.handle_line(&line, &mut lines, &peer_map, &content_filter, &rate_limiter).await {
+                        Ok(true) => {}
+                        Ok(false) => break "quit",
+                        Err(_) => break "write failed",
+                    },
+                    Some(Err(e)) => {
+                        warn!("IRC gateway: dropping {} ({}): {}", bridge.user.username, peer_addr, e);
+                        break "read failed";
+                    }
+                    None => break "connection closed",
+                },
+                message = rx.recv() => match message {
+                    Some(message) => {
+                        if bridge.relay(message, &mut lines).await.is_err() {
+                            break "write failed";
+                        }
+                    }
+                    None => break "queue closed",
+                },
+                _ = sender.closed() => break "closed by server",
+            }
+        }
+    };
+
+    // Pass on anything still queued, e.g. the notice of a kick, before hanging up
+    while let Ok(message) = rx.try_recv() {
+        if bridge.relay(message, &mut lines).await.is_err() {
+            break;
+        }
+    }
+    connection::unregister_external_peer(&peer_map, peer_id, reason).await;
+}
+
+/// Collect PASS, NICK and USER, then log in. Returns None if the client quit
+/// or failed to authenticate, after telling it why.
+async fn register(lines: &mut Lines, peer_addr: SocketAddr, peer_map: &PeerMap) -> Option<User> {
+    let max_lines = crate::config::settings().connections.max_pre_auth_messages;
+    let mut pass: Option<String> = None;
+    let mut nick: Option<String> = None;
+    let mut got_user = false;
+    let mut seen = 0u32;
+
+    while let Some(Ok(line)) = lines.next().await {
+        seen += 1;
+        if seen > max_lines {
+            warn!("IRC gateway: disconnecting {}: too many lines before registering", peer_addr);
+            let _ = send_line(lines, "ERROR :Too many lines before registering".to_string()).await;
+            return None;
+        }
+        let Some(command) = parse_line(&line) else {
+            continue;
+        };
+        let target = nick.as_deref().unwrap_or("*").to_string();
+        let reply = match command.verb.as_str() {
+            "PASS" | "NICK" | "USER" if command.params.is_empty() => {
+                Some(numeric(ERR_NEEDMOREPARAMS, &target, &format!("{} :Not enough parameters", command.verb)))
+            }
+            "PASS" => {
+                pass = command.params.into_iter().next();
+                None
+            }
+            "NICK" => {
+                nick = command.params.into_iter().next();
+                None
+            }
+            "USER" => {
+                got_user = true;
+                None
+            }
+            "PING" => Some(format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, command.params.join(" "))),
+            "QUIT" => return None,
+            _ => Some(numeric(ERR_NOTREGISTERED, &target, ":You have not registered")),
+        };
+        if let Some(reply) = reply {
+            if send_line(lines, reply).await.is_err() {
+                return None;
+            }
+        }
+
+        if let (Some(username), true) = (&nick, got_user) {
+            let Some(password) = &pass else {
+                let _ = send_line(lines, numeric(ERR_PASSWDMISMATCH, username, ":Password required (send PASS before NICK/USER)")).await;
+                return None;
+            };
+            let is_bot = db::users::db_get_user_by_username(username).await
+                .is_ok_and(|profile| profile.role == UserRole::Bot);
+            if !is_bot {
+                info!("IRC gateway: refused non-bot login for {} from {}", username, peer_addr);
+                let _ = send_line(lines, numeric(ERR_PASSWDMISMATCH, username, ":Password incorrect")).await;
+                return None;
+            }
+            return match UserService::login(username, password, peer_map).await {
+                Ok(user) => Some(user),
+                Err(e) => {
+                    info!("IRC gateway: login failed for {} from {}: {}", username, peer_addr, e);
+                    let _ = send_line(lines, numeric(ERR_PASSWDMISMATCH, username, ":Password incorrect")).await;
+                    None
+                }
+            };
+        }
+    }
+    None
+}
+
+/// A logged-in gateway session
+struct Bridge {
+    user: User,
+    nick: String,
+    /// Joined Nexus channels and the IRC name each was joined under
+    joined: HashMap<Uuid, String>,
+    /// Nicks of message authors, so each is looked up once per session
+    authors: HashMap<Uuid, String>,
+}
+
+impl Bridge {
+    /// Handle one client line. Ok(false) when the client quit.
+    async fn handle_line(
+        &mut self,
+        line: &str,
+        lines: &mut Lines,
+        peer_map: &PeerMap,
+        content_filter: &ContentFilterService,
+        rate_limiter: &RateLimitService,
+    ) -> Result<bool, LinesCodecError> {
+        let Some(command) = parse_line(line) else {
+            return Ok(true);
+        };
+        match command.verb.as_str() {
+            "PING" => {
+                send_line(lines, format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, command.params.join(" "))).await?;
+            }
+            "PASS" | "NICK" | "USER" => {
+                send_line(lines, numeric(ERR_ALREADYREGISTRED, &self.nick, ":You may not reregister")).await?;
+            }
+            "JOIN" => match command.params.first() {
+                Some(channels) => {
+                    for name in channels.split(',').filter(|name| !name.is_empty()) {
+                        self.join(name, lines).await?;
+                    }
+                }
+                None => {
+                    send_line(lines, numeric(ERR_NEEDMOREPARAMS, &self.nick, "JOIN :Not enough parameters")).await?;
+                }
+            },
+            "PRIVMSG" => match (command.params.first(), command.params.get(1)) {
+                (Some(target), Some(text)) if !text.is_empty() => {
+                    self.privmsg(target, text, lines, peer_map, content_filter, rate_limiter).await?;
+                }
+                (Some(_), _) => {
+                    send_line(lines, numeric(ERR_NOTEXTTOSEND, &self.nick, ":No text to send")).await?;
+                }
+                (None, _) => {
+                    send_line(lines, numeric(ERR_NEEDMOREPARAMS, &self.nick, "PRIVMSG :Not enough parameters")).await?;
+                }
+            },
+            "QUIT" => return Ok(false),
+            verb => {
+                send_line(lines, numeric(ERR_UNKNOWNCOMMAND, &self.nick, &format!("{} :Unknown command", verb))).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn join(&mut self, name: &str, lines: &mut Lines) -> Result<(), LinesCodecError> {
+        let Some((irc_name, channel_id)) = mapped_channel(name) else {
+            return send_line(lines, numeric(ERR_NOSUCHCHANNEL, &self.nick, &format!("{} :No such channel", name))).await;
+        };
+        if let Err(e) = ChatService::ensure_can_read_channel(self.user.id, channel_id).await {
+            info!("IRC gateway: {} cannot join {}: {}", self.user.username, irc_name, e);
+            return send_line(lines, numeric(ERR_INVITEONLYCHAN, &self.nick, &format!("{} :Cannot join channel", irc_name))).await;
+        }
+        self.joined.insert(channel_id, irc_name.clone());
+
+        send_line(lines, format!(":{}!{}@{} JOIN {}", self.nick, self.nick, SERVER_NAME, irc_name)).await?;
+        let names = db::channels::db_get_channel_user_list(channel_id).await
+            .map(|users| users.iter().map(|user| irc_nick(&user.username)).collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+        send_line(lines, numeric(RPL_NAMREPLY, &self.nick, &format!("= {} :{}", irc_name, names))).await?;
+        send_line(lines, numeric(RPL_ENDOFNAMES, &self.nick, &format!("{} :End of /NAMES list", irc_name))).await
+    }
+
+    async fn privmsg(
+        &mut self,
+        target: &str,
+        text: &str,
+        lines: &mut Lines,
+        peer_map: &PeerMap,
+        content_filter: &ContentFilterService,
+        rate_limiter: &RateLimitService,
+    ) -> Result<(), LinesCodecError> {
+        if !target.starts_with('#') && !target.starts_with('&') {
+            return send_line(lines, numeric(ERR_NOSUCHNICK, &self.nick, &format!("{} :Direct messages are not bridged", target))).await;
+        }
+        let channel_id = self.joined.iter()
+            .find(|(_, irc_name)| irc_name.eq_ignore_ascii_case(target))
+            .map(|(channel_id, _)| *channel_id);
+        let Some(channel_id) = channel_id else {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!("{} :Cannot send to channel (not joined)", target))).await;
+        };
+        // CTCP requests (VERSION, ACTION, ...) have no Nexus equivalent
+        if text.starts_with('\u{1}') {
+            return Ok(());
+        }
+        // Same read-only rule as the router applies to native clients
+        if SettingsService::maintenance_mode().await.unwrap_or(false) {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!(
+                "{} :Server is in maintenance mode; changes are temporarily disabled", target
+            ))).await;
+        }
+        if let Err(e) = ModerationService::enforce_slow_start(&self.user, None, rate_limiter).await {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!("{} :{}", target, e))).await;
+        }
+        if let FilterResult::Blocked(reason) = content_filter.filter_message(text) {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!("{} :{}", target, reason))).await;
+        }
+        if let Err(retry_after) = rate_limiter.check_message_rate_limit(self.user.id) {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!(
+                "{} :Too many messages, try again in {} seconds", target, retry_after
+            ))).await;
+        }
+        if let Err(e) = ChatService::send_channel_message(channel_id, &self.user, text, None, content_filter, peer_map).await {
+            return send_line(lines, numeric(ERR_CANNOTSENDTOCHAN, &self.nick, &format!("{} :{}", target, e))).await;
+        }
+        Ok(())
+    }
+
+    /// Forward a server message to the client; only what IRC can express is sent
+    async fn relay(&mut self, message: ServerMessage, lines: &mut Lines) -> Result<(), LinesCodecError> {
+        match message {
+            ServerMessage::NewChannelMessage(message) => self.relay_channel_message(message, lines).await,
+            ServerMessage::Notification(text, _) => {
+                send_line(lines, format!(":{} NOTICE {} :{}", SERVER_NAME, self.nick, text)).await
+            }
+            ServerMessage::SessionRevoked { reason } => {
+                send_line(lines, format!("ERROR :{}", reason)).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn relay_channel_message(&mut self, message: ChannelMessage, lines: &mut Lines) -> Result<(), LinesCodecError> {
+        // IRC clients echo their own messages locally
+        if message.sent_by == self.user.id {
+            return Ok(());
+        }
+        let Some(irc_name) = self.joined.get(&message.channel_id).cloned() else {
+            return Ok(());
+        };
+        let author = self.author_nick(message.sent_by).await;
+        for line in message.content.lines().filter(|line| !line.is_empty()) {
+            send_line(lines, format!(":{}!{}@{} PRIVMSG {} :{}", author, author, SERVER_NAME, irc_name, line)).await?;
+        }
+        Ok(())
+    }
+
+    async fn author_nick(&mut self, user_id: Uuid) -> String {
+        if let Some(nick) = self.authors.get(&user_id) {
+            return nick.clone();
+        }
+        let nick = match db::users::db_get_user_by_id(user_id).await {
+            Ok(profile) => irc_nick(&profile.username),
+            Err(_) => "unknown".to_string(),
+        };
+        self.authors.insert(user_id, nick.clone());
+        nick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(line: &str) -> (String, Vec<String>) {
+        let command = parse_line(line).expect("a command");
+        (command.verb, command.params)
+    }
+
+    #[test]
+    fn a_client_prefix_is_ignored() {
+        let (verb, params) = parsed(":spoofed!user@host privmsg #general hello");
+        assert_eq!(verb, "PRIVMSG");
+        assert_eq!(params, ["#general", "hello"]);
+    }
+
+    #[test]
+    fn the_trailing_parameter_keeps_its_spaces() {
+        let (verb, params) = parsed("PRIVMSG #general :hello there : world");
+        assert_eq!(verb, "PRIVMSG");
+        assert_eq!(params, ["#general", "hello there : world"]);
+
+        let (_, params) = parsed("USER bot 0 * :Real Name");
+        assert_eq!(params, ["bot", "0", "*", "Real Name"]);
+    }
+
+    #[test]
+    fn empty_lines_are_not_commands() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("   ").is_none());
+        assert!(parse_line(":prefix-only").is_none());
+    }
+}
@@ -1,2 +1,6 @@
 pub mod connection;
 pub mod routes;
+pub mod transport;
+pub mod websocket;
+#[cfg(feature = "irc-gateway")]
+pub mod irc_gateway;
@@ -1,18 +1,74 @@
-use crate::api::connection::PeerMap;
-use crate::errors::Result;
+use crate::api::connection::{PeerMap, PeerSender};
+use crate::db;
+use crate::errors::{Result, ServerError};
+use crate::services::{action_token_service, ActionTokenService, ContentFilterService, RateLimitService, SettingsService};
 use nexus_tui_common::{ClientMessage, ServerMessage, User};
-use tokio::sync::mpsc;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tracing::error;
 use uuid::Uuid;
 
 /// Message router that dispatches client messages to appropriate handlers
 pub struct MessageRouter {
     peer_map: PeerMap,
+    /// Address the connection came from, for per-IP limits before login
+    peer_ip: IpAddr,
+    content_filter: Arc<ContentFilterService>,
+    rate_limiter: Arc<RateLimitService>,
+    /// Client negotiated `lazy_servers`: send server summaries, not full servers
+    lazy_servers: AtomicBool,
+    /// The deprecated full-tree GetForums has been logged for this session
+    legacy_forums_logged: AtomicBool,
+    /// `[action_tokens] required`: sensitive operations must come wrapped in a token
+    action_tokens_required: bool,
+    /// The logged-in account still has its temporary password; nothing but
+    /// changing it is served until it does
+    password_change_required: AtomicBool,
 }
 
 impl MessageRouter {
-    pub fn new(peer_map: PeerMap) -> Self {
-        Self { peer_map }
+    pub fn new(
+        peer_map: PeerMap,
+        peer_ip: IpAddr,
+        content_filter: Arc<ContentFilterService>,
+        rate_limiter: Arc<RateLimitService>,
+    ) -> Self {
+        Self {
+            peer_map,
+            peer_ip,
+            content_filter,
+            rate_limiter,
+            lazy_servers: AtomicBool::new(false),
+            legacy_forums_logged: AtomicBool::new(false),
+            action_tokens_required: crate::config::settings().action_tokens.required,
+            password_change_required: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_action_tokens_required(mut self) -> Self {
+        self.action_tokens_required = true;
+        self
+    }
+
+    /// Apply the server-list mode negotiated in Hello
+    pub fn set_lazy_servers(&self, enabled: bool) {
+        self.lazy_servers.store(enabled, Ordering::Relaxed);
+    }
+
+    fn lazy_servers(&self) -> bool {
+        self.lazy_servers.load(Ordering::Relaxed)
+    }
+
+    /// Hold the session to changing its password (or release it)
+    fn set_password_change_required(&self, required: bool) {
+        self.password_change_required.store(required, Ordering::Relaxed);
+    }
+
+    /// True the first time this session uses the legacy full-tree GetForums
+    fn first_legacy_forums_request(&self) -> bool {
+        !self.legacy_forums_logged.swap(true, Ordering::Relaxed)
     }
 
     /// Route and handle a client message
@@ -21,9 +77,79 @@ impl MessageRouter {
         message: ClientMessage,
         current_user: &mut Option<User>,
         peer_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> Result<()> {
+        // A sensitive operation may arrive wrapped with a one-time action token.
+        // The wrapper is checked here; the token itself is only consumed once
+        // the operation has passed the checks below and is about to run.
+        let (message, action_token) = match message {
+            ClientMessage::WithActionToken { token, message } => {
+                if current_user.is_none() {
+                    self.send_error(response_sender, "Must be logged in to use an action token");
+                    return Ok(());
+                }
+                let Some(action) = sensitive_action(&message) else {
+                    self.send_error(response_sender, "This message doesn't take an action token");
+                    return Ok(());
+                };
+                (*message, Some((action, token)))
+            }
+            message => {
+                if sensitive_action(&message).is_some() && self.action_tokens_required {
+                    self.send_error(response_sender, "This operation requires an action token; request one with RequestActionToken");
+                    return Ok(());
+                }
+                (message, None)
+            }
+        };
+
+        // Before login only the handshake and authentication are served, so
+        // anonymous clients can't use the id check below to probe what exists
+        if current_user.is_none() && !allowed_before_login(&message) {
+            self.send_error(response_sender, "Must be logged in");
+            return Ok(());
+        }
+        if current_user.is_some()
+            && self.password_change_required.load(Ordering::Relaxed)
+            && !allowed_before_password_change(&message)
+        {
+            self.send_error(response_sender, "Change your temporary password before doing anything else");
+            return Ok(());
+        }
+
+        // Reject ids that don't exist before any handler touches them
+        for entity in referenced_entities(&message) {
+            if let Err(e) = entity.ensure_exists().await {
+                self.send_error(response_sender, &e.to_string());
+                return Ok(());
+            }
+        }
+
+        // Read-only maintenance mode: writes are refused, reads carry on
+        if is_write(&message) && SettingsService::maintenance_mode().await.unwrap_or(false) {
+            self.send_error(response_sender, "Server is in maintenance mode; changes are temporarily disabled");
+            return Ok(());
+        }
+
+        if let (Some((action, token)), Some(user)) = (action_token, current_user.as_ref()) {
+            if let Err(e) = ActionTokenService::consume(user.id, action, &token) {
+                self.send_error(response_sender, &e.to_string());
+                return Ok(());
+            }
+        }
+
         match message {
+            // Negotiated by the connection before routing
+            ClientMessage::Hello { .. } => Ok(()),
+            ClientMessage::GetServerInfo => {
+                self.handle_get_server_info(response_sender).await
+            }
+            // Keepalive; the reply carries the server clock so clients can correct for skew
+            ClientMessage::Ping => {
+                self.send_time_sync(response_sender);
+                Ok(())
+            }
+
             // Authentication messages
             ClientMessage::Register { username, password } => {
                 self.handle_register(username, password, current_user, peer_id, response_sender).await
@@ -34,6 +160,14 @@ impl MessageRouter {
             ClientMessage::Logout => {
                 self.handle_logout(current_user, peer_id, response_sender).await
             }
+            ClientMessage::RequestActionToken { action } => {
+                self.handle_request_action_token(current_user, action, response_sender).await
+            }
+            // Unwrapped above; a token wrapping another token is refused
+            ClientMessage::WithActionToken { .. } => {
+                self.send_error(response_sender, "This message doesn't take an action token");
+                Ok(())
+            }
 
             // User profile messages
             ClientMessage::UpdatePassword(new_password) => {
@@ -42,25 +176,91 @@ impl MessageRouter {
             ClientMessage::UpdateColor(color) => {
                 self.handle_update_color(current_user, color, response_sender).await
             }
+            ClientMessage::UpdateDisplayName { display_name } => {
+                self.handle_update_display_name(current_user, display_name, response_sender).await
+            }
             ClientMessage::UpdateProfile { bio, url1, url2, url3, location, profile_pic, cover_banner } => {
                 self.handle_update_profile(current_user, bio, url1, url2, url3, location, profile_pic, cover_banner, response_sender).await
             }
             ClientMessage::GetProfile { user_id } => {
-                self.handle_get_profile(user_id, response_sender).await
+                self.handle_get_profile(current_user, user_id, response_sender).await
+            }
+            ClientMessage::SetProfileVisibility { visibility } => {
+                self.handle_set_profile_visibility(current_user, visibility, response_sender).await
+            }
+            ClientMessage::SetAutoJoinNewChannels { enabled } => {
+                self.handle_set_auto_join_new_channels(current_user, enabled, response_sender).await
+            }
+            ClientMessage::SetProfileViewNotices { enabled } => {
+                self.handle_set_profile_view_notices(current_user, enabled, response_sender).await
             }
             ClientMessage::GetUserList => {
                 self.handle_get_user_list(response_sender).await
             }
+            ClientMessage::GetUserUpdates { since, ids } => {
+                self.handle_get_user_updates(since, ids, response_sender).await
+            }
 
             // Chat messages
-            ClientMessage::SendChannelMessage { channel_id, content } => {
-                self.handle_send_channel_message(current_user, channel_id, content, response_sender).await
+            ClientMessage::SendChannelMessage { channel_id, content, origin } => {
+                self.handle_send_channel_message(current_user, channel_id, content, origin, response_sender).await
             }
             ClientMessage::SendDirectMessage { to, content } => {
                 self.handle_send_direct_message(current_user, to, content, response_sender).await
             }
+            ClientMessage::DeleteChannelMessage { message_id } => {
+                self.handle_delete_channel_message(current_user, message_id, response_sender).await
+            }
+            ClientMessage::EditChannelMessage { message_id, content } => {
+                self.handle_edit_channel_message(current_user, message_id, content, response_sender).await
+            }
+            ClientMessage::RestoreChannelMessage { message_id } => {
+                self.handle_restore_channel_message(current_user, message_id, response_sender).await
+            }
+            ClientMessage::CreatePoll { channel_id, question, options, multi_choice, duration_secs } => {
+                self.handle_create_poll(current_user, channel_id, question, options, multi_choice, duration_secs, response_sender).await
+            }
+            ClientMessage::VotePoll { poll_id, option_indices } => {
+                self.handle_vote_poll(current_user, poll_id, option_indices, response_sender).await
+            }
+            ClientMessage::ClosePoll { poll_id } => {
+                self.handle_close_poll(current_user, poll_id, response_sender).await
+            }
+            ClientMessage::GetPollResults { poll_id } => {
+                self.handle_get_poll_results(current_user, poll_id, response_sender).await
+            }
+            ClientMessage::FollowChannel { channel_id } => {
+                self.handle_set_channel_follow(current_user, channel_id, true, response_sender).await
+            }
+            ClientMessage::UnfollowChannel { channel_id } => {
+                self.handle_set_channel_follow(current_user, channel_id, false, response_sender).await
+            }
+            ClientMessage::MuteUser { user_id, channel_id } => {
+                self.handle_set_personal_mute(current_user, user_id, channel_id, true, response_sender).await
+            }
+            ClientMessage::UnmuteUser { user_id, channel_id } => {
+                self.handle_set_personal_mute(current_user, user_id, channel_id, false, response_sender).await
+            }
+            ClientMessage::GetPersonalMutes => {
+                self.handle_get_personal_mutes(current_user, response_sender).await
+            }
+            ClientMessage::BookmarkMessage { message_id, note } => {
+                self.handle_bookmark_message(current_user, message_id, note, response_sender).await
+            }
+            ClientMessage::RemoveBookmark { message_id } => {
+                self.handle_remove_bookmark(current_user, message_id, response_sender).await
+            }
+            ClientMessage::GetBookmarks { cursor } => {
+                self.handle_get_bookmarks(current_user, cursor, response_sender).await
+            }
+            ClientMessage::MarkChannelRead { channel_id } => {
+                self.handle_mark_channel_read(current_user, channel_id, response_sender).await
+            }
+            ClientMessage::GetActiveChannels { since } => {
+                self.handle_get_active_channels(current_user, since, response_sender).await
+            }
             ClientMessage::GetChannelMessages { channel_id, before } => {
-                self.handle_get_channel_messages(channel_id, before, response_sender).await
+                self.handle_get_channel_messages(current_user, channel_id, before, response_sender).await
             }
             ClientMessage::GetDirectMessages { user_id, before } => {
                 self.handle_get_direct_messages(current_user, user_id, before, response_sender).await
@@ -68,22 +268,31 @@ impl MessageRouter {
             ClientMessage::GetChannelUserList { channel_id } => {
                 self.handle_get_channel_user_list(channel_id, response_sender).await
             }
-            ClientMessage::GetDMUserList => {
+            ClientMessage::GetDMUserList { include_archived } => {
                 if let Some(user) = current_user {
-                    self.handle_get_dm_user_list(user.id, response_sender).await
+                    self.handle_get_dm_user_list(user.id, include_archived, response_sender).await
                 } else {
                     self.send_error(response_sender, "Must be logged in to get DM user list");
                     Ok(())
                 }
             }
+            ClientMessage::ArchiveDMConversation { user_id, hide_until_new_message } => {
+                self.handle_set_dm_conversation_archived(current_user, user_id, true, hide_until_new_message, response_sender).await
+            }
+            ClientMessage::UnarchiveDMConversation { user_id } => {
+                self.handle_set_dm_conversation_archived(current_user, user_id, false, false, response_sender).await
+            }
 
             // Enhanced pagination messages
-            ClientMessage::GetChannelMessagesPaginated { channel_id, cursor, limit, direction } => {
-                self.handle_get_channel_messages_paginated(channel_id, cursor, limit, direction, response_sender).await
+            ClientMessage::GetChannelMessagesPaginated { channel_id, cursor, limit, direction, want_total } => {
+                self.handle_get_channel_messages_paginated(current_user, channel_id, cursor, limit, direction, want_total, response_sender).await
+            }
+            ClientMessage::GetMessagesAroundTimestamp { channel_id, timestamp, radius } => {
+                self.handle_get_messages_around_timestamp(current_user, channel_id, timestamp, radius, response_sender).await
             }
-            ClientMessage::GetDirectMessagesPaginated { user_id, cursor, limit, direction } => {
+            ClientMessage::GetDirectMessagesPaginated { user_id, cursor, limit, direction, want_total } => {
                 if let Some(user) = current_user {
-                    self.handle_get_direct_messages_paginated(user.id, user_id, cursor, limit, direction, response_sender).await
+                    self.handle_get_direct_messages_paginated(user.id, user_id, cursor, limit, direction, want_total, response_sender).await
                 } else {
                     self.send_error(response_sender, "Must be logged in to get direct messages");
                     Ok(())
@@ -94,8 +303,64 @@ impl MessageRouter {
             ClientMessage::GetServers => {
                 self.handle_get_servers(current_user, response_sender).await
             }
+            ClientMessage::GetServerDetail { server_id } => {
+                self.handle_get_server_detail(current_user, server_id, response_sender).await
+            }
+            ClientMessage::CreateServer { name, description, public } => {
+                self.handle_create_server(current_user, name, description, public, response_sender).await
+            }
+            ClientMessage::UpdateServer { server_id, name, description } => {
+                self.handle_update_server(current_user, server_id, name, description, response_sender).await
+            }
+            ClientMessage::CreateServerRole { server_id, name, can_read, can_write } => {
+                self.handle_create_server_role(current_user, server_id, name, can_read, can_write, response_sender).await
+            }
+            ClientMessage::AssignServerRole { role_id, user_id } => {
+                self.handle_assign_server_role(current_user, role_id, user_id, response_sender).await
+            }
+            ClientMessage::SetRoleChannelPermission { role_id, channel_id, can_read, can_write } => {
+                self.handle_set_role_channel_permission(current_user, role_id, channel_id, can_read, can_write, response_sender).await
+            }
+            ClientMessage::SetChannelLinkPolicy { channel_id, links_allowed, allowed_domains } => {
+                self.handle_set_channel_link_policy(current_user, channel_id, links_allowed, allowed_domains, response_sender).await
+            }
+            ClientMessage::SetChannelNotifyPolicy { channel_id, followers_only } => {
+                self.handle_set_channel_notify_policy(current_user, channel_id, followers_only, response_sender).await
+            }
+            ClientMessage::CreateChannel { server_id, name, description } => {
+                self.handle_create_channel(current_user, server_id, name, description, response_sender).await
+            }
+            ClientMessage::JoinChannel { channel_id } => {
+                self.handle_join_channel(current_user, channel_id, response_sender).await
+            }
+            ClientMessage::UpdateChannel { channel_id, description, topic } => {
+                self.handle_update_channel(current_user, channel_id, description, topic, response_sender).await
+            }
+            ClientMessage::GetChannelInfo { channel_id } => {
+                self.handle_get_channel_info(current_user, channel_id, response_sender).await
+            }
+            ClientMessage::SetServerSystemMessages { server_id, enabled } => {
+                self.handle_set_server_system_messages(current_user, server_id, enabled, response_sender).await
+            }
+            ClientMessage::SetServerAuditChannel { server_id, channel_id } => {
+                self.handle_set_server_audit_channel(current_user, server_id, channel_id, response_sender).await
+            }
+            ClientMessage::UpdateServerSettings { server_id, welcome_channel_id, welcome_template, welcome_dm_template } => {
+                self.handle_update_server_settings(
+                    current_user, server_id, welcome_channel_id, welcome_template, welcome_dm_template, response_sender
+                ).await
+            }
+            ClientMessage::AddServerEmoji { server_id, name, image_ref } => {
+                self.handle_add_server_emoji(current_user, server_id, name, image_ref, response_sender).await
+            }
+            ClientMessage::DeleteServerEmoji { server_id, name } => {
+                self.handle_delete_server_emoji(current_user, server_id, name, response_sender).await
+            }
+            ClientMessage::GetServerEmojis { server_id } => {
+                self.handle_get_server_emojis(current_user, server_id, response_sender).await
+            }
             ClientMessage::GetForums => {
-                self.handle_get_forums(response_sender).await
+                self.handle_get_forums(current_user, response_sender).await
             }
             ClientMessage::CreateForum { name, description } => {
                 self.handle_create_forum(current_user, name, description, response_sender).await
@@ -115,9 +380,21 @@ impl MessageRouter {
             ClientMessage::DeletePost(post_id) => {
                 self.handle_delete_post(current_user, post_id, response_sender).await
             }
+            ClientMessage::GetPostEditDiff { post_id, from_revision, to_revision } => {
+                self.handle_get_post_edit_diff(current_user, post_id, from_revision, to_revision, response_sender).await
+            }
             ClientMessage::DeleteThread(thread_id) => {
                 self.handle_delete_thread(current_user, thread_id, response_sender).await
             }
+            ClientMessage::SetForumPostingRole { forum_id, min_role } => {
+                self.handle_set_forum_posting_role(current_user, forum_id, min_role, response_sender).await
+            }
+            ClientMessage::ThreadViewed { thread_id } => {
+                self.handle_thread_viewed(current_user, thread_id).await
+            }
+            ClientMessage::GetTrendingThreads { days, limit } => {
+                self.handle_get_trending_threads(days, limit, response_sender).await
+            }
 
             // Invite messages
             ClientMessage::SendServerInvite { to_user_id, server_id } => {
@@ -132,6 +409,9 @@ impl MessageRouter {
             ClientMessage::DeclineServerInviteFromUser { from_user_id } => {
                 self.handle_decline_server_invite_from_user(current_user, from_user_id, response_sender).await
             }
+            ClientMessage::PreviewInvite { code } => {
+                self.handle_preview_invite(current_user, code, response_sender).await
+            }
 
             // Notification messages
             ClientMessage::GetNotifications { before } => {
@@ -140,8 +420,96 @@ impl MessageRouter {
             ClientMessage::MarkNotificationRead { notification_id } => {
                 self.handle_mark_notification_read(notification_id, response_sender).await
             }
+            ClientMessage::MarkNotificationsRead { ids } => {
+                self.handle_mark_notifications_read(current_user, ids, response_sender).await
+            }
+            ClientMessage::SetDigestOptOut { opt_out } => {
+                self.handle_set_digest_opt_out(current_user, opt_out, response_sender).await
+            }
+
+            // Moderation messages
+            ClientMessage::GetQuarantinedMessages => {
+                self.handle_get_quarantined_messages(current_user, response_sender).await
+            }
+            ClientMessage::ReviewQuarantinedMessage { quarantine_id, approve, warning } => {
+                self.handle_review_quarantined_message(current_user, quarantine_id, approve, warning, response_sender).await
+            }
+            ClientMessage::GetFlaggedMessages => {
+                self.handle_get_flagged_messages(current_user, response_sender).await
+            }
+            ClientMessage::DismissFlaggedMessage { flag_id } => {
+                self.handle_dismiss_flagged_message(current_user, flag_id, response_sender).await
+            }
+            ClientMessage::BanUser { user_id, server_id, reason, duration } => {
+                self.handle_ban_user(current_user, user_id, server_id, reason, duration, response_sender).await
+            }
+            ClientMessage::UnbanUser { user_id, server_id } => {
+                self.handle_unban_user(current_user, user_id, server_id, response_sender).await
+            }
+            ClientMessage::KickFromServer { server_id, user_id } => {
+                self.handle_kick_from_server(current_user, server_id, user_id, response_sender).await
+            }
+
+            // Admin messages
+            ClientMessage::SetUserRole { user_id, role } => {
+                self.handle_set_user_role(current_user, user_id, role, response_sender).await
+            }
+            ClientMessage::RenameUser { user_id, new_username } => {
+                self.handle_rename_user(current_user, user_id, new_username, response_sender).await
+            }
+            ClientMessage::SetAdminDigest { enabled, interval_minutes } => {
+                self.handle_set_admin_digest(current_user, enabled, interval_minutes, response_sender).await
+            }
 
             // Cache and performance messages
+            ClientMessage::GetServerStats => {
+                self.handle_get_server_stats(current_user, response_sender).await
+            }
+            ClientMessage::GetActiveConnections => {
+                self.handle_get_active_connections(current_user, response_sender).await
+            }
+            ClientMessage::DisconnectPeer { peer_id, reason } => {
+                self.handle_disconnect_peer(current_user, peer_id, reason, response_sender).await
+            }
+            ClientMessage::RotateBotToken { bot_user_id } => {
+                self.handle_rotate_bot_token(current_user, bot_user_id, response_sender).await
+            }
+            ClientMessage::GetUserView { user_id } => {
+                self.handle_get_user_view(current_user, user_id, response_sender).await
+            }
+            ClientMessage::GetUserMemberships { user_id } => {
+                self.handle_get_user_memberships(current_user, user_id, response_sender).await
+            }
+            ClientMessage::GetRateLimitStats => {
+                self.handle_get_rate_limit_stats(current_user, response_sender).await
+            }
+            ClientMessage::GetTopStorageUsers { limit } => {
+                self.handle_get_top_storage_users(current_user, limit, response_sender).await
+            }
+            ClientMessage::GetMyStorageUsage => {
+                self.handle_get_my_storage_usage(current_user, response_sender).await
+            }
+            ClientMessage::SetServerSetting { key, value } => {
+                self.handle_set_server_setting(current_user, key, value, response_sender).await
+            }
+            ClientMessage::SetMaintenanceMode { enabled } => {
+                self.handle_set_maintenance_mode(current_user, enabled, response_sender).await
+            }
+            ClientMessage::GetAuditLog { limit, offset, user_filter, action_filter, start_time, end_time } => {
+                self.handle_get_audit_log(
+                    current_user, limit, offset, user_filter, action_filter, start_time, end_time, response_sender
+                ).await
+            }
+            ClientMessage::GetAuditStats { user_filter, action_filter, start_time, end_time } => {
+                self.handle_get_audit_stats(
+                    current_user, user_filter, action_filter, start_time, end_time, response_sender
+                ).await
+            }
+            ClientMessage::ExportAuditLog { user_filter, action_filter, start_time, end_time, format } => {
+                self.handle_export_audit_log(
+                    current_user, user_filter, action_filter, start_time, end_time, format, response_sender
+                ).await
+            }
             ClientMessage::GetCacheStats => {
                 self.handle_get_cache_stats(response_sender).await
             }
@@ -155,27 +523,554 @@ impl MessageRouter {
     }
 
     // Helper method to send responses
-    fn send_response(&self, sender: &mpsc::UnboundedSender<ServerMessage>, message: ServerMessage) {
+    fn send_response(&self, sender: &PeerSender, message: ServerMessage) {
         if let Err(e) = sender.send(message) {
             error!("Failed to send response: {:?}", e);
         }
     }
 
+    // Helper method to send the server clock, for clients correcting for skew
+    fn send_time_sync(&self, sender: &PeerSender) {
+        self.send_response(sender, ServerMessage::TimeSync { server_time: crate::util::now_millis() });
+    }
+
     // Helper method to send error notifications
-    fn send_error(&self, sender: &mpsc::UnboundedSender<ServerMessage>, error: &str) {
+    fn send_error(&self, sender: &PeerSender, error: &str) {
         self.send_response(sender, ServerMessage::Notification(error.to_string(), true));
     }
 
     // Helper method to send success notifications
-    fn send_success(&self, sender: &mpsc::UnboundedSender<ServerMessage>, message: &str) {
+    fn send_success(&self, sender: &PeerSender, message: &str) {
         self.send_response(sender, ServerMessage::Notification(message.to_string(), false));
     }
 }
 
+/// An entity a client message refers to by id
+enum EntityRef {
+    User(Uuid),
+    Server(Uuid),
+    Channel(Uuid),
+    Forum(Uuid),
+    Thread(Uuid),
+    Post(Uuid),
+}
+
+impl EntityRef {
+    /// Check the entity exists, returning NotFound with the entity name if not
+    async fn ensure_exists(&self) -> Result<()> {
+        let (name, exists) = match *self {
+            EntityRef::User(id) => ("User", db::users::db_user_exists(id).await),
+            EntityRef::Server(id) => ("Server", db::servers::db_server_exists(id).await),
+            EntityRef::Channel(id) => ("Channel", db::channels::db_channel_exists(id).await),
+            EntityRef::Forum(id) => ("Forum", db::forums::db_forum_exists(id).await),
+            EntityRef::Thread(id) => ("Thread", db::forums::db_thread_exists(id).await),
+            EntityRef::Post(id) => ("Post", db::forums::db_post_exists(id).await),
+        };
+
+        match exists {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ServerError::NotFound(format!("{} does not exist", name))),
+            Err(e) => Err(ServerError::Database(e)),
+        }
+    }
+}
+
+/// Ids in a client message that must refer to existing rows
+fn referenced_entities(message: &ClientMessage) -> Vec<EntityRef> {
+    match message {
+        ClientMessage::GetProfile { user_id } => vec![EntityRef::User(*user_id)],
+        ClientMessage::SendChannelMessage { channel_id, .. }
+        | ClientMessage::GetChannelMessages { channel_id, .. }
+        | ClientMessage::GetChannelUserList { channel_id }
+        | ClientMessage::GetChannelMessagesPaginated { channel_id, .. }
+        | ClientMessage::GetMessagesAroundTimestamp { channel_id, .. }
+        | ClientMessage::CreatePoll { channel_id, .. }
+        | ClientMessage::MarkChannelRead { channel_id } => vec![EntityRef::Channel(*channel_id)],
+        ClientMessage::SendDirectMessage { to, .. } => vec![EntityRef::User(*to)],
+        ClientMessage::GetDirectMessages { user_id, .. }
+        | ClientMessage::GetDirectMessagesPaginated { user_id, .. }
+        | ClientMessage::ArchiveDMConversation { user_id, .. }
+        | ClientMessage::UnarchiveDMConversation { user_id } => vec![EntityRef::User(*user_id)],
+        ClientMessage::UpdateServer { server_id, .. }
+        | ClientMessage::CreateServerRole { server_id, .. }
+        | ClientMessage::SetServerSystemMessages { server_id, .. }
+        | ClientMessage::SetServerAuditChannel { server_id, .. }
+        | ClientMessage::UpdateServerSettings { server_id, .. }
+        | ClientMessage::CreateChannel { server_id, .. }
+        | ClientMessage::AddServerEmoji { server_id, .. }
+        | ClientMessage::DeleteServerEmoji { server_id, .. }
+        | ClientMessage::GetServerEmojis { server_id }
+        | ClientMessage::GetServerDetail { server_id } => vec![EntityRef::Server(*server_id)],
+        ClientMessage::AssignServerRole { user_id, .. }
+        | ClientMessage::SetUserRole { user_id, .. }
+        | ClientMessage::RenameUser { user_id, .. }
+        | ClientMessage::GetUserView { user_id }
+        | ClientMessage::GetUserMemberships { user_id } => vec![EntityRef::User(*user_id)],
+        ClientMessage::RotateBotToken { bot_user_id } => vec![EntityRef::User(*bot_user_id)],
+        ClientMessage::SetRoleChannelPermission { channel_id, .. }
+        | ClientMessage::SetChannelLinkPolicy { channel_id, .. }
+        | ClientMessage::SetChannelNotifyPolicy { channel_id, .. }
+        | ClientMessage::FollowChannel { channel_id }
+        | ClientMessage::UnfollowChannel { channel_id }
+        | ClientMessage::JoinChannel { channel_id }
+        | ClientMessage::UpdateChannel { channel_id, .. }
+        | ClientMessage::GetChannelInfo { channel_id } => vec![EntityRef::Channel(*channel_id)],
+        ClientMessage::DeleteForum { forum_id }
+        | ClientMessage::CreateThread { forum_id, .. }
+        | ClientMessage::SetForumPostingRole { forum_id, .. } => vec![EntityRef::Forum(*forum_id)],
+        ClientMessage::CreatePost { thread_id, .. }
+        | ClientMessage::ThreadViewed { thread_id } => vec![EntityRef::Thread(*thread_id)],
+        ClientMessage::CreatePostReply { thread_id, reply_to, .. } => {
+            vec![EntityRef::Thread(*thread_id), EntityRef::Post(*reply_to)]
+        }
+        ClientMessage::DeletePost(post_id)
+        | ClientMessage::GetPostEditDiff { post_id, .. } => vec![EntityRef::Post(*post_id)],
+        ClientMessage::DeleteThread(thread_id) => vec![EntityRef::Thread(*thread_id)],
+        ClientMessage::SendServerInvite { to_user_id, server_id } => {
+            vec![EntityRef::User(*to_user_id), EntityRef::Server(*server_id)]
+        }
+        ClientMessage::BanUser { user_id, server_id, .. }
+        | ClientMessage::UnbanUser { user_id, server_id }
+        | ClientMessage::KickFromServer { server_id, user_id } => {
+            vec![EntityRef::User(*user_id), EntityRef::Server(*server_id)]
+        }
+        ClientMessage::MuteUser { user_id, channel_id }
+        | ClientMessage::UnmuteUser { user_id, channel_id } => {
+            let mut refs = vec![EntityRef::User(*user_id)];
+            refs.extend(channel_id.map(EntityRef::Channel));
+            refs
+        }
+        ClientMessage::AcceptServerInviteFromUser { from_user_id }
+        | ClientMessage::DeclineServerInviteFromUser { from_user_id } => vec![EntityRef::User(*from_user_id)],
+        _ => Vec::new(),
+    }
+}
+
+/// Messages an anonymous connection may send: the handshake, server info,
+/// keepalives and the ways to authenticate
+fn allowed_before_login(message: &ClientMessage) -> bool {
+    matches!(
+        message,
+        ClientMessage::Hello { .. }
+            | ClientMessage::GetServerInfo
+            | ClientMessage::Ping
+            | ClientMessage::Register { .. }
+            | ClientMessage::Login { .. }
+    )
+}
+
+/// Messages served to an account that must replace its temporary password:
+/// keepalives, the password change (and the token it may need) and logging out
+fn allowed_before_password_change(message: &ClientMessage) -> bool {
+    matches!(
+        message,
+        ClientMessage::Hello { .. }
+            | ClientMessage::GetServerInfo
+            | ClientMessage::Ping
+            | ClientMessage::UpdatePassword(_)
+            | ClientMessage::RequestActionToken { .. }
+            | ClientMessage::Logout
+    )
+}
+
+/// The action token a sensitive operation is guarded by, if it is one
+fn sensitive_action(message: &ClientMessage) -> Option<&'static str> {
+    match message {
+        ClientMessage::UpdatePassword(_) => Some(action_token_service::UPDATE_PASSWORD),
+        ClientMessage::SetUserRole { .. } => Some(action_token_service::SET_USER_ROLE),
+        ClientMessage::RenameUser { .. } => Some(action_token_service::RENAME_USER),
+        ClientMessage::RotateBotToken { .. } => Some(action_token_service::ROTATE_BOT_TOKEN),
+        _ => None,
+    }
+}
+
+/// Messages that change state, refused while the server is in maintenance mode.
+/// `SetMaintenanceMode` is deliberately absent so admins can always switch it off.
+fn is_write(message: &ClientMessage) -> bool {
+    matches!(
+        message,
+        ClientMessage::Register { .. }
+            | ClientMessage::UpdatePassword(_)
+            | ClientMessage::UpdateColor(_)
+            | ClientMessage::UpdateDisplayName { .. }
+            | ClientMessage::UpdateProfile { .. }
+            | ClientMessage::SetProfileVisibility { .. }
+            | ClientMessage::SetAutoJoinNewChannels { .. }
+            | ClientMessage::SetProfileViewNotices { .. }
+            | ClientMessage::SendChannelMessage { .. }
+            | ClientMessage::SendDirectMessage { .. }
+            | ClientMessage::ArchiveDMConversation { .. }
+            | ClientMessage::UnarchiveDMConversation { .. }
+            | ClientMessage::DeleteChannelMessage { .. }
+            | ClientMessage::EditChannelMessage { .. }
+            | ClientMessage::RestoreChannelMessage { .. }
+            | ClientMessage::CreatePoll { .. }
+            | ClientMessage::VotePoll { .. }
+            | ClientMessage::ClosePoll { .. }
+            | ClientMessage::CreateServer { .. }
+            | ClientMessage::UpdateServer { .. }
+            | ClientMessage::CreateServerRole { .. }
+            | ClientMessage::AssignServerRole { .. }
+            | ClientMessage::SetRoleChannelPermission { .. }
+            | ClientMessage::SetChannelLinkPolicy { .. }
+            | ClientMessage::SetChannelNotifyPolicy { .. }
+            | ClientMessage::FollowChannel { .. }
+            | ClientMessage::MuteUser { .. }
+            | ClientMessage::UnmuteUser { .. }
+            | ClientMessage::BookmarkMessage { .. }
+            | ClientMessage::RemoveBookmark { .. }
+            | ClientMessage::MarkChannelRead { .. }
+            | ClientMessage::UnfollowChannel { .. }
+            | ClientMessage::SetServerSystemMessages { .. }
+            | ClientMessage::SetServerAuditChannel { .. }
+            | ClientMessage::UpdateServerSettings { .. }
+            | ClientMessage::CreateChannel { .. }
+            | ClientMessage::JoinChannel { .. }
+            | ClientMessage::UpdateChannel { .. }
+            | ClientMessage::AddServerEmoji { .. }
+            | ClientMessage::DeleteServerEmoji { .. }
+            | ClientMessage::CreateForum { .. }
+            | ClientMessage::DeleteForum { .. }
+            | ClientMessage::CreateThread { .. }
+            | ClientMessage::CreatePost { .. }
+            | ClientMessage::CreatePostReply { .. }
+            | ClientMessage::DeletePost(_)
+            | ClientMessage::DeleteThread(_)
+            | ClientMessage::SetForumPostingRole { .. }
+            | ClientMessage::SendServerInvite { .. }
+            | ClientMessage::RespondToServerInvite { .. }
+            | ClientMessage::AcceptServerInviteFromUser { .. }
+            | ClientMessage::DeclineServerInviteFromUser { .. }
+            | ClientMessage::MarkNotificationRead { .. }
+            | ClientMessage::MarkNotificationsRead { .. }
+            | ClientMessage::SetDigestOptOut { .. }
+            | ClientMessage::ReviewQuarantinedMessage { .. }
+            | ClientMessage::DismissFlaggedMessage { .. }
+            | ClientMessage::BanUser { .. }
+            | ClientMessage::UnbanUser { .. }
+            | ClientMessage::KickFromServer { .. }
+            | ClientMessage::SetUserRole { .. }
+            | ClientMessage::RenameUser { .. }
+            | ClientMessage::RotateBotToken { .. }
+            | ClientMessage::SetAdminDigest { .. }
+            | ClientMessage::SetServerSetting { .. }
+    )
+}
+
 // Import handler modules
 mod auth_handlers;
 mod chat_handlers;
 mod forum_handlers;
 mod invite_handlers;
 mod notification_handlers;
-mod cache_handlers;
\ No newline at end of file
+mod cache_handlers;
+mod moderation_handlers;
+mod server_handlers;
+mod admin_handlers;
+mod poll_handlers;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakePeer, TestDb};
+
+    /// Messages that name users, servers, channels or posts, with the given ids
+    fn messages_referencing(user_id: Uuid, server_id: Uuid, channel_id: Uuid) -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::GetProfile { user_id },
+            ClientMessage::GetUserView { user_id },
+            ClientMessage::GetUserMemberships { user_id },
+            ClientMessage::SendDirectMessage { to: user_id, content: "hi".to_string() },
+            ClientMessage::UnarchiveDMConversation { user_id },
+            ClientMessage::AcceptServerInviteFromUser { from_user_id: user_id },
+            ClientMessage::GetServerDetail { server_id },
+            ClientMessage::GetServerEmojis { server_id },
+            ClientMessage::UnbanUser { user_id, server_id },
+            ClientMessage::SendServerInvite { to_user_id: user_id, server_id },
+            ClientMessage::SendChannelMessage { channel_id, content: "hi".to_string(), origin: None },
+            ClientMessage::GetChannelMessages { channel_id, before: None },
+            ClientMessage::GetChannelUserList { channel_id },
+            ClientMessage::GetChannelInfo { channel_id },
+            ClientMessage::JoinChannel { channel_id },
+            ClientMessage::MarkChannelRead { channel_id },
+            ClientMessage::DeletePost(channel_id),
+            ClientMessage::DeleteThread(server_id),
+            ClientMessage::ThreadViewed { thread_id: server_id },
+        ]
+    }
+
+    /// Route one message and collect every reply
+    async fn replies(router: &MessageRouter, peer: &mut FakePeer, current_user: &mut Option<User>, message: ClientMessage) -> Vec<ServerMessage> {
+        router.handle_message(message, current_user, peer.peer_id, &peer.sender).await.unwrap();
+        peer.drain()
+    }
+
+    fn is_not_found(message: &ServerMessage) -> bool {
+        matches!(message, ServerMessage::Notification(text, true) if text.ends_with("does not exist"))
+    }
+
+    #[tokio::test]
+    async fn anonymous_clients_get_the_same_refusal_for_random_and_real_ids() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let mut peer = FakePeer::connect(&peer_map, None).await;
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let refusal = vec![ServerMessage::Notification("Must be logged in".to_string(), true)];
+
+        let mut batches = vec![messages_referencing(owner.id, server_id, channel_id)];
+        for _ in 0..50 {
+            batches.push(messages_referencing(Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()));
+        }
+        for message in batches.into_iter().flatten() {
+            let debug = format!("{:?}", message);
+            let got = replies(&router, &mut peer, &mut None, message).await;
+            assert_eq!(format!("{:?}", got), format!("{:?}", refusal), "anonymous {}", debug);
+        }
+    }
+
+    #[tokio::test]
+    async fn logged_in_clients_are_told_about_unknown_ids() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let alice = test_support::create_user("alice").await;
+        let mut peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut current_user = Some(alice);
+
+        for _ in 0..10 {
+            for message in messages_referencing(Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()) {
+                let debug = format!("{:?}", message);
+                let got = replies(&router, &mut peer, &mut current_user, message).await;
+                assert!(got.iter().any(is_not_found), "{} got {:?}", debug, got);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn anonymous_clients_can_still_register() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let mut peer = FakePeer::connect(&peer_map, None).await;
+        let mut current_user = None;
+
+        let message = ClientMessage::Register { username: "alice".to_string(), password: test_support::TEST_PASSWORD.to_string() };
+        let got = replies(&router, &mut peer, &mut current_user, message).await;
+
+        let success = got.iter().position(|message| matches!(message, ServerMessage::AuthSuccess(_))).expect("AuthSuccess");
+        // Followed by the server clock, so the client can correct for skew straight away
+        assert!(matches!(got.get(success + 1), Some(ServerMessage::TimeSync { .. })), "got {:?}", got);
+        assert!(current_user.is_some());
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_refuses_writes_and_serves_reads() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let server_id = test_support::create_server(&admin, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let mut peer = FakePeer::connect(&peer_map, Some(admin.id)).await;
+        let mut current_user = Some(admin.clone());
+        SettingsService::set_maintenance_mode(&admin, true).await.unwrap();
+        peer.drain();
+
+        let write = ClientMessage::SendChannelMessage { channel_id, content: "hello".to_string(), origin: None };
+        let got = replies(&router, &mut peer, &mut current_user, write).await;
+        assert!(got.iter().any(|message| matches!(
+            message,
+            ServerMessage::Notification(text, true) if text.contains("maintenance mode")
+        )), "got {:?}", got);
+        assert_eq!(db.count_rows("channel_messages"), 0);
+
+        let got = replies(&router, &mut peer, &mut current_user, ClientMessage::GetServers).await;
+        assert!(got.iter().any(|message| matches!(message, ServerMessage::Servers(_))), "got {:?}", got);
+
+        // Switching it back off goes through while maintenance is on
+        let got = replies(&router, &mut peer, &mut current_user, ClientMessage::SetMaintenanceMode { enabled: false }).await;
+        assert!(!SettingsService::maintenance_mode().await.unwrap(), "got {:?}", got);
+        let write = ClientMessage::SendChannelMessage { channel_id, content: "hello".to_string(), origin: None };
+        replies(&router, &mut peer, &mut current_user, write).await;
+        assert_eq!(db.count_rows("channel_messages"), 1);
+    }
+
+    #[tokio::test]
+    async fn a_blocked_word_never_reaches_the_channel() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = MessageRouter::new(
+            peer_map.clone(),
+            IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            Arc::new(test_support::content_filter_with(&["forbidden"], &[])),
+            Arc::new(RateLimitService::new(&crate::config::RateLimitConfig::default())),
+        );
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+        let rows_before = db.count_rows("channel_messages");
+
+        let message = ClientMessage::SendChannelMessage { channel_id, content: "say FORBIDDEN".to_string(), origin: None };
+        let got = replies(&router, &mut alice_peer, &mut Some(alice), message).await;
+
+        assert!(got.iter().any(|message| matches!(
+            message,
+            ServerMessage::Notification(text, true) if text.contains("blocked word 'forbidden'")
+        )), "got {:?}", got);
+        assert_eq!(db.count_rows("channel_messages"), rows_before);
+        assert_eq!(db.count_rows("flagged_messages"), 0);
+        assert!(bob_peer.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn blocked_messages_do_not_use_up_the_message_limit() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = MessageRouter::new(
+            peer_map.clone(),
+            IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+            Arc::new(test_support::content_filter_with(&["forbidden"], &[])),
+            Arc::new(RateLimitService::new(&crate::config::RateLimitConfig {
+                messages_per_minute: 1,
+                ..crate::config::RateLimitConfig::default()
+            })),
+        );
+        let alice = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let mut peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut current_user = Some(alice);
+        let rows_before = db.count_rows("channel_messages");
+
+        for _ in 0..2 {
+            let blocked = ClientMessage::SendChannelMessage { channel_id, content: "say forbidden".to_string(), origin: None };
+            let got = replies(&router, &mut peer, &mut current_user, blocked).await;
+            assert!(got.iter().any(|message| matches!(
+                message,
+                ServerMessage::Notification(text, true) if text.contains("blocked word")
+            )), "got {:?}", got);
+        }
+
+        let allowed = ClientMessage::SendChannelMessage { channel_id, content: "hello".to_string(), origin: None };
+        let got = replies(&router, &mut peer, &mut current_user, allowed).await;
+        assert!(!got.iter().any(|message| matches!(message, ServerMessage::Notification(_, true))), "got {:?}", got);
+        assert_eq!(db.count_rows("channel_messages"), rows_before + 1);
+
+        // The one allowed message did count
+        let over = ClientMessage::SendChannelMessage { channel_id, content: "again".to_string(), origin: None };
+        let got = replies(&router, &mut peer, &mut current_user, over).await;
+        assert!(got.iter().any(|message| matches!(
+            message,
+            ServerMessage::Notification(text, true) if text.starts_with("Too many messages")
+        )), "got {:?}", got);
+    }
+
+    #[tokio::test]
+    async fn only_members_can_read_channel_history() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let alice = test_support::create_user("alice").await;
+        let outsider = test_support::create_user("outsider").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        crate::db::channels::db_create_channel_message(channel_id, alice.id, crate::util::now_secs(), "members only", None).await.unwrap();
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut outsider_peer = FakePeer::connect(&peer_map, Some(outsider.id)).await;
+
+        let got = replies(&router, &mut outsider_peer, &mut Some(outsider), ClientMessage::GetChannelMessages { channel_id, before: None }).await;
+        assert!(got.iter().any(|message| matches!(
+            message,
+            ServerMessage::Notification(text, true) if text.contains("Not authorized to read this channel")
+        )), "got {:?}", got);
+        assert!(!got.iter().any(|message| matches!(message, ServerMessage::ChannelMessages { .. })));
+
+        let got = replies(&router, &mut alice_peer, &mut Some(alice), ClientMessage::GetChannelMessages { channel_id, before: None }).await;
+        assert!(got.iter().any(|message| matches!(
+            message,
+            ServerMessage::ChannelMessages { messages, .. } if messages.iter().any(|message| message.content == "members only")
+        )), "got {:?}", got);
+    }
+
+    #[tokio::test]
+    async fn required_action_tokens_are_spent_only_on_operations_that_run() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map).with_action_tokens_required();
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let mut peer = FakePeer::connect(&peer_map, Some(admin.id)).await;
+        let mut current_user = Some(admin.clone());
+        let update = || ClientMessage::UpdatePassword("a new password".to_string());
+
+        let got = replies(&router, &mut peer, &mut current_user, update()).await;
+        assert!(got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, true) if text.contains("requires an action token")
+        )), "{:?}", got);
+
+        // Refused by maintenance mode: the token survives for the retry
+        let (token, _) = ActionTokenService::issue(admin.id, action_token_service::UPDATE_PASSWORD).unwrap();
+        let wrapped = || ClientMessage::WithActionToken { token: token.clone(), message: Box::new(update()) };
+        SettingsService::set_maintenance_mode(&admin, true).await.unwrap();
+        peer.drain();
+        let got = replies(&router, &mut peer, &mut current_user, wrapped()).await;
+        assert!(got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, true) if text.contains("maintenance mode")
+        )), "{:?}", got);
+        SettingsService::set_maintenance_mode(&admin, false).await.unwrap();
+        peer.drain();
+
+        let got = replies(&router, &mut peer, &mut current_user, wrapped()).await;
+        assert!(got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, false) if text.contains("Password updated")
+        )), "{:?}", got);
+        // Spent now
+        let got = replies(&router, &mut peer, &mut current_user, wrapped()).await;
+        assert!(got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, true) if text.contains("action token")
+        )), "{:?}", got);
+    }
+
+    #[tokio::test]
+    async fn a_temporary_password_must_be_changed_before_anything_else() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let router = test_support::router(&peer_map);
+        let carol = test_support::create_user("carol").await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE users SET must_change_password = 1 WHERE id = ?1", rusqlite::params![carol.id.to_string()]
+        ).unwrap();
+        let mut peer = FakePeer::connect(&peer_map, None).await;
+        let mut current_user = None;
+        let login = || ClientMessage::Login { username: "carol".to_string(), password: test_support::TEST_PASSWORD.to_string() };
+        let profile = || ClientMessage::GetProfile { user_id: carol.id };
+        let held = |got: &[ServerMessage]| got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, true) if text.contains("temporary password")
+        ));
+
+        let got = replies(&router, &mut peer, &mut current_user, login()).await;
+        assert!(got.iter().any(|message| matches!(message, ServerMessage::AuthSuccess(_))), "got {:?}", got);
+        assert!(held(&replies(&router, &mut peer, &mut current_user, profile()).await));
+        let got = replies(&router, &mut peer, &mut current_user, ClientMessage::Ping).await;
+        assert!(matches!(got.as_slice(), [ServerMessage::TimeSync { .. }]), "got {:?}", got);
+
+        // Logging out and back in doesn't get around it
+        replies(&router, &mut peer, &mut current_user, ClientMessage::Logout).await;
+        replies(&router, &mut peer, &mut current_user, login()).await;
+        assert!(held(&replies(&router, &mut peer, &mut current_user, profile()).await));
+
+        let update = ClientMessage::UpdatePassword("a brand new password".to_string());
+        let got = replies(&router, &mut peer, &mut current_user, update).await;
+        assert!(got.iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, false) if text.contains("Password updated")
+        )), "got {:?}", got);
+        let got = replies(&router, &mut peer, &mut current_user, profile()).await;
+        assert!(!held(&got), "got {:?}", got);
+        assert!(!crate::db::users::db_must_change_password(carol.id).await.unwrap());
+    }
+}
@@ -0,0 +1,416 @@
+use super::MessageRouter;
+use crate::api::connection::{self, PeerSender};
+use crate::db::audit::AuditFilter;
+use crate::services::maintenance_service::MIN_ADMIN_DIGEST_MINUTES;
+use crate::services::{metrics_service, AuditService, BroadcastService, MetricsService, SettingsService, StorageService, UserService};
+use nexus_tui_common::{AuditExportFormat, ServerMessage, StorageUsage, User, UserRole};
+use tracing::info;
+use uuid::Uuid;
+
+impl MessageRouter {
+    /// Handle get server stats (Admin only)
+    pub async fn handle_get_server_stats(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                let (busiest_channels, throughput_window_secs) =
+                    MetricsService::busiest_channels(metrics_service::BUSIEST_CHANNELS_REPORTED);
+                let response = ServerMessage::ServerStats {
+                    latencies: MetricsService::latency_stats(),
+                    malformed_frames: MetricsService::counter(metrics_service::MALFORMED_FRAMES),
+                    serialization_failures: MetricsService::counter(metrics_service::SERIALIZATION_FAILURES),
+                    db_degraded: crate::db::is_degraded(),
+                    busiest_channels,
+                    throughput_window_secs,
+                };
+                self.send_response(response_sender, response);
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can view server stats");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to view server stats");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle get rate limit stats (Admin only)
+    pub async fn handle_get_rate_limit_stats(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                let stats = self.rate_limiter.stats();
+                self.send_response(response_sender, ServerMessage::RateLimitStats {
+                    uploads_allowed: stats.uploads_allowed,
+                    uploads_throttled: stats.uploads_throttled,
+                    upload_users_tracked: stats.upload_users_tracked as u64,
+                });
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can view rate limit stats");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to view rate limit stats");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle get top storage users (Admin only)
+    pub async fn handle_get_top_storage_users(
+        &self,
+        current_user: &Option<User>,
+        limit: usize,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                match StorageService::top_consumers(limit.clamp(1, 100)).await {
+                    Ok(consumers) => {
+                        let usage = consumers
+                            .into_iter()
+                            .map(|entry| StorageUsage {
+                                user_id: entry.user_id,
+                                username: entry.username,
+                                message_bytes: entry.message_bytes.max(0) as u64,
+                                media_bytes: entry.media_bytes.max(0) as u64,
+                            })
+                            .collect();
+                        self.send_response(response_sender, ServerMessage::TopStorageUsers(usage));
+                    }
+                    Err(_) => self.send_error(response_sender, "Failed to load storage usage"),
+                }
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can view storage usage");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to view storage usage");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle set user role (Admin only)
+    pub async fn handle_set_user_role(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        role: UserRole,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::set_user_role(user, user_id, role, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Role updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update role: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change roles");
+        }
+        Ok(())
+    }
+
+    /// Handle rename user (Admin only)
+    pub async fn handle_rename_user(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        new_username: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::rename_user(user, user_id, &new_username).await {
+                Ok(_) => self.send_success(response_sender, "User renamed"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to rename user: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to rename users");
+        }
+        Ok(())
+    }
+
+    /// Handle change of a runtime server setting (Admin only)
+    pub async fn handle_set_server_setting(
+        &self,
+        current_user: &Option<User>,
+        key: String,
+        value: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match SettingsService::set(user, &key, &value).await {
+                Ok(_) => self.send_success(response_sender, &format!("Setting {} updated", key)),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update setting: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change server settings");
+        }
+        Ok(())
+    }
+
+    /// Handle read-only maintenance mode switch (Admin only)
+    pub async fn handle_set_maintenance_mode(
+        &self,
+        current_user: &Option<User>,
+        enabled: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match SettingsService::set_maintenance_mode(user, enabled).await {
+                Ok(_) => {
+                    BroadcastService::broadcast_to_all(&self.peer_map, &ServerMessage::MaintenanceMode { enabled }).await;
+                    let message = if enabled { "Maintenance mode enabled" } else { "Maintenance mode disabled" };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to change maintenance mode: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change maintenance mode");
+        }
+        Ok(())
+    }
+
+    /// Handle get audit log page (Admin only)
+    pub async fn handle_get_audit_log(
+        &self,
+        current_user: &Option<User>,
+        limit: usize,
+        offset: usize,
+        user_filter: Option<Uuid>,
+        action_filter: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match AuditService::fetch_audit_entries(
+                user, limit, offset, user_filter, action_filter, start_time, end_time
+            ).await {
+                Ok((entries, has_more)) => {
+                    self.send_response(response_sender, ServerMessage::AuditLog { entries, has_more });
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to load audit log: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view the audit log");
+        }
+        Ok(())
+    }
+
+    /// Handle audit log statistics (Admin only)
+    pub async fn handle_get_audit_stats(
+        &self,
+        current_user: &Option<User>,
+        user_filter: Option<Uuid>,
+        action_filter: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            let filter = AuditFilter {
+                user_id: user_filter,
+                action: action_filter.filter(|action| !action.is_empty()),
+                start_time,
+                end_time,
+            };
+            match AuditService::calculate_audit_stats(user, filter).await {
+                Ok(stats) => self.send_response(response_sender, ServerMessage::AuditStats(stats)),
+                Err(e) => self.send_error(response_sender, &format!("Failed to load audit statistics: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view the audit log");
+        }
+        Ok(())
+    }
+
+    /// Handle audit log export (Admin only). Matching entries are streamed as
+    /// AuditExportChunk frames, waiting on the peer's queue between chunks.
+    pub async fn handle_export_audit_log(
+        &self,
+        current_user: &Option<User>,
+        user_filter: Option<Uuid>,
+        action_filter: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        format: AuditExportFormat,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to export the audit log");
+            return Ok(());
+        };
+
+        let filter = AuditFilter {
+            user_id: user_filter,
+            action: action_filter.filter(|action| !action.is_empty()),
+            start_time,
+            end_time,
+        };
+        let (mut export, total) = match AuditService::begin_export(user, filter, format).await {
+            Ok(started) => started,
+            Err(e) => {
+                self.send_error(response_sender, &format!("Failed to export audit log: {}", e));
+                return Ok(());
+            }
+        };
+
+        info!("{} exporting {} audit entries", user.username, total);
+        // The connection task drains this peer's queue, so waiting for room
+        // there would deadlock; the chunks are produced on a task of their own
+        let sender = response_sender.clone();
+        tokio::spawn(async move {
+            loop {
+                match export.next_chunk().await {
+                    Ok(Some(data)) => {
+                        let last = export.is_finished();
+                        if sender.send_wait(ServerMessage::AuditExportChunk { data, last }).await.is_err() || last {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = sender.send_wait(ServerMessage::Notification(format!("Audit log export failed: {}", e), true)).await;
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Handle admin dashboard digest opt-in (Admin only)
+    pub async fn handle_set_admin_digest(
+        &self,
+        current_user: &Option<User>,
+        enabled: bool,
+        interval_minutes: u32,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                let interval_minutes = interval_minutes.max(MIN_ADMIN_DIGEST_MINUTES);
+                match crate::db::users::db_set_admin_digest(user.id, enabled, interval_minutes).await {
+                    Ok(_) => {
+                        let message = if enabled {
+                            format!("Admin digest enabled every {} minutes", interval_minutes)
+                        } else {
+                            "Admin digest disabled".to_string()
+                        };
+                        self.send_success(response_sender, &message);
+                    }
+                    Err(_) => self.send_error(response_sender, "Failed to update admin digest preference"),
+                }
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can receive the admin digest");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to change admin digest settings");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle get active connections with the slowest consumers (Admin only)
+    pub async fn handle_get_active_connections(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                let (queued_messages, mut connections) = connection::connection_stats(&self.peer_map).await;
+                let total = connections.len();
+                connections.truncate(connection::SLOWEST_CONNECTIONS_REPORTED);
+                self.send_response(response_sender, ServerMessage::ActiveConnections {
+                    total,
+                    queued_messages,
+                    slowest: connections,
+                });
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can view active connections");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to view active connections");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle manual disconnect of a peer (Admin only)
+    pub async fn handle_disconnect_peer(
+        &self,
+        current_user: &Option<User>,
+        peer_id: Uuid,
+        reason: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match current_user {
+            Some(user) if user.role == UserRole::Admin => {
+                let reason = if reason.trim().is_empty() { "no reason given".to_string() } else { reason };
+                if connection::disconnect_peer(&self.peer_map, peer_id, &reason).await {
+                    info!("Peer {} disconnected by {}: {}", peer_id, user.username, reason);
+                    self.send_success(response_sender, "Peer disconnected");
+                } else {
+                    self.send_error(response_sender, "No such connection");
+                }
+            }
+            Some(_) => {
+                self.send_error(response_sender, "Only admins can disconnect peers");
+            }
+            None => {
+                self.send_error(response_sender, "Must be logged in to disconnect peers");
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle rotating a bot account's token (Admin only)
+    pub async fn handle_rotate_bot_token(
+        &self,
+        current_user: &Option<User>,
+        bot_user_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to rotate bot tokens");
+            return Ok(());
+        };
+
+        match UserService::rotate_bot_token(user, bot_user_id, &self.peer_map).await {
+            Ok(token) => self.send_response(response_sender, ServerMessage::BotTokenRotated { bot_user_id, token }),
+            Err(e) => self.send_error(response_sender, &format!("Failed to rotate bot token: {}", e)),
+        }
+        Ok(())
+    }
+
+    /// Handle viewing what a user sees, for debugging (Admin only)
+    pub async fn handle_get_user_view(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to view users");
+            return Ok(());
+        };
+
+        match UserService::user_view(user, user_id).await {
+            Ok((memberships, dm_partners)) => {
+                self.send_response(response_sender, ServerMessage::UserView { user_id, memberships, dm_partners });
+            }
+            Err(e) => self.send_error(response_sender, &format!("Failed to load user view: {}", e)),
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,46 @@
 use super::MessageRouter;
-use crate::services::UserService;
-use nexus_tui_common::{ServerMessage, User, UserColor};
-use tokio::sync::mpsc;
+use crate::api::connection::PeerSender;
+use crate::services::{ActionTokenService, BroadcastService, SettingsService, StorageService, UserService};
+use nexus_tui_common::{ProfileVisibility, RegistrationMode, ServerMessage, User, UserColor};
 use uuid::Uuid;
 
+/// Characters of the MOTD shown in GetServerInfo
+const MOTD_PREVIEW_CHARS: usize = 120;
+
 impl MessageRouter {
+    /// Handle public server info for server pickers; allowed before Hello or
+    /// login, rate limited per IP, and free of any per-user data
+    pub async fn handle_get_server_info(
+        &self,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Err(retry_after) = self.rate_limiter.check_server_info_rate_limit(self.peer_ip) {
+            self.send_error(response_sender, &format!("Too many requests, try again in {} seconds", retry_after));
+            return Ok(());
+        }
+
+        let info = crate::config::settings().info.clone();
+        let user_count = crate::db::users::db_count_users().await.unwrap_or(0).max(0) as u64;
+        let registration_mode = match SettingsService::registration_open().await {
+            Ok(false) => RegistrationMode::Closed,
+            _ => RegistrationMode::Open,
+        };
+        let motd_preview = SettingsService::motd().await.ok().flatten().map(|motd| {
+            let first_line = motd.lines().next().unwrap_or("");
+            first_line.chars().take(MOTD_PREVIEW_CHARS).collect::<String>()
+        });
+
+        self.send_response(response_sender, ServerMessage::ServerInfo {
+            name: info.name,
+            description: info.description,
+            registration_mode,
+            user_count,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            motd_preview,
+        });
+        Ok(())
+    }
+
     /// Handle user registration
     pub async fn handle_register(
         &self,
@@ -12,7 +48,7 @@ impl MessageRouter {
         password: String,
         current_user: &mut Option<User>,
         peer_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         match UserService::register(&username, &password, &self.peer_map).await {
             Ok(user) => {
@@ -25,6 +61,7 @@ impl MessageRouter {
                 
                 *current_user = Some(user.clone());
                 self.send_response(response_sender, ServerMessage::AuthSuccess(user));
+                self.send_time_sync(response_sender);
             }
             Err(e) => {
                 self.send_response(response_sender, ServerMessage::AuthFailure(e.to_string()));
@@ -40,8 +77,9 @@ impl MessageRouter {
         password: String,
         current_user: &mut Option<User>,
         peer_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
+        let credential_epoch = UserService::credential_epoch();
         match UserService::login(&username, &password, &self.peer_map).await {
             Ok(user) => {
                 // Update peer map
@@ -50,9 +88,40 @@ impl MessageRouter {
                     peer.user_id = Some(user.id);
                 }
                 drop(peers);
+
+                // The password was rotated while this login was being checked;
+                // the revocation sweep may have missed this session, so undo it
+                if UserService::credentials_rotated_since(user.id, credential_epoch) {
+                    if let Some(peer) = self.peer_map.lock().await.get_mut(&peer_id) {
+                        peer.user_id = None;
+                    }
+                    UserService::logout(&user, &self.peer_map).await;
+                    self.send_response(response_sender, ServerMessage::AuthFailure(
+                        "Credentials were rotated; log in with the new ones".to_string()
+                    ));
+                    return Ok(());
+                }
                 
                 *current_user = Some(user.clone());
+                let user_id = user.id;
                 self.send_response(response_sender, ServerMessage::AuthSuccess(user));
+                self.send_time_sync(response_sender);
+                if let Ok(Some(motd)) = SettingsService::motd().await {
+                    self.send_response(response_sender, ServerMessage::Notification(motd, false));
+                }
+                if let Ok(true) = SettingsService::maintenance_mode().await {
+                    self.send_response(response_sender, ServerMessage::MaintenanceMode { enabled: true });
+                }
+                // Imported accounts are held to changing their temporary password
+                let must_change = crate::db::users::db_must_change_password(user_id).await.unwrap_or(false);
+                self.set_password_change_required(must_change);
+                if must_change {
+                    self.send_response(response_sender, ServerMessage::Notification(
+                        "Your account was created with a temporary password; change it to continue".to_string(),
+                        false,
+                    ));
+                }
+                BroadcastService::replay_pending_deliveries(user_id, response_sender).await;
             }
             Err(e) => {
                 self.send_response(response_sender, ServerMessage::AuthFailure(e.to_string()));
@@ -66,7 +135,7 @@ impl MessageRouter {
         &self,
         current_user: &mut Option<User>,
         peer_id: Uuid,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        _response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             UserService::logout(user, &self.peer_map).await;
@@ -80,6 +149,7 @@ impl MessageRouter {
         drop(peers);
         
         *current_user = None;
+        self.set_password_change_required(false);
         Ok(())
     }
 
@@ -88,11 +158,12 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         new_password: String,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match UserService::update_password(user.id, &new_password).await {
                 Ok(_) => {
+                    self.set_password_change_required(false);
                     self.send_success(response_sender, "Password updated successfully!");
                 }
                 Err(e) => {
@@ -103,12 +174,32 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle a request for a one-time token guarding a sensitive operation
+    pub async fn handle_request_action_token(
+        &self,
+        current_user: &Option<User>,
+        action: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ActionTokenService::issue(user.id, &action) {
+                Ok((token, expires_at)) => {
+                    self.send_response(response_sender, ServerMessage::ActionToken { action, token, expires_at });
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to issue action token: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to request an action token");
+        }
+        Ok(())
+    }
+
     /// Handle color update
     pub async fn handle_update_color(
         &self,
         current_user: &mut Option<User>,
         color: UserColor,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        _response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             let color_str = color.0;
@@ -119,6 +210,50 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle setting or clearing the user's display name
+    pub async fn handle_update_display_name(
+        &self,
+        current_user: &Option<User>,
+        display_name: Option<String>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::update_display_name(user, display_name, &self.content_filter, &self.peer_map).await {
+                Ok(Some(name)) => self.send_success(response_sender, &format!("Display name set to {}", name)),
+                Ok(None) => self.send_success(response_sender, "Display name cleared; your username is shown"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update display name: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change your display name");
+        }
+        Ok(())
+    }
+
+    /// Handle a user asking how much storage they use
+    pub async fn handle_get_my_storage_usage(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to view storage usage");
+            return Ok(());
+        };
+
+        match StorageService::usage(user).await {
+            Ok(usage) => {
+                let quotas = &crate::config::settings().quotas;
+                self.send_response(response_sender, ServerMessage::MyStorageUsage {
+                    usage,
+                    soft_quota_bytes: quotas.soft_quota_bytes,
+                    hard_quota_bytes: quotas.hard_quota_bytes,
+                });
+            }
+            Err(_) => self.send_error(response_sender, "Failed to load storage usage"),
+        }
+        Ok(())
+    }
+
     /// Handle profile update
     pub async fn handle_update_profile(
         &self,
@@ -130,13 +265,35 @@ impl MessageRouter {
         location: Option<String>,
         profile_pic: Option<String>,
         cover_banner: Option<String>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
+            // Each new image counts as one upload
+            let images: Vec<usize> = [&profile_pic, &cover_banner]
+                .iter()
+                .filter_map(|image| image.as_ref().map(|data| data.len()))
+                .filter(|&size| size > 0)
+                .collect();
+            let image_size = |image: &Option<String>| image.as_ref().map(|data| data.len());
+            if let Err(e) = StorageService::check_upload_allowed(user.id, image_size(&profile_pic), image_size(&cover_banner)).await {
+                self.send_error(response_sender, &format!("Failed to update profile: {}", e));
+                return Ok(());
+            }
+            // All the images fit in the window or none of them is charged
+            if !images.is_empty() {
+                if let Err(retry_after) = self.rate_limiter.check_file_uploads_rate_limit(user.id, images.len() as u32) {
+                    self.send_error(response_sender, &format!(
+                        "Too many uploads, try again in {} seconds", retry_after
+                    ));
+                    return Ok(());
+                }
+            }
+
             match UserService::update_profile(
                 user.id, bio, url1, url2, url3, location, profile_pic, cover_banner, &self.peer_map
             ).await {
                 Ok(_profile) => {
+                    StorageService::record_media_change(user.id, &self.peer_map).await;
                     self.send_success(response_sender, "Profile updated successfully!");
                 }
                 Err(e) => {
@@ -149,15 +306,108 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle profile visibility change
+    pub async fn handle_set_profile_visibility(
+        &self,
+        current_user: &Option<User>,
+        visibility: ProfileVisibility,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::set_profile_visibility(user.id, visibility).await {
+                Ok(_) => self.send_success(response_sender, "Profile visibility updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update profile visibility: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change profile visibility");
+        }
+        Ok(())
+    }
+
+    /// Handle the auto-join-new-channels preference
+    pub async fn handle_set_auto_join_new_channels(
+        &self,
+        current_user: &Option<User>,
+        enabled: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::set_auto_join_new_channels(user.id, enabled).await {
+                Ok(_) => {
+                    let message = if enabled {
+                        "New channels will be joined automatically"
+                    } else {
+                        "You'll be asked before joining new channels"
+                    };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to update channel preference: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change channel preferences");
+        }
+        Ok(())
+    }
+
+    /// Handle profile view digest opt-in
+    pub async fn handle_set_profile_view_notices(
+        &self,
+        current_user: &Option<User>,
+        enabled: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match UserService::set_profile_view_notices(user.id, enabled).await {
+                Ok(_) => {
+                    let message = if enabled {
+                        "You'll get a weekly count of profile views from others who opted in"
+                    } else {
+                        "Profile views are no longer recorded"
+                    };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to update profile view preference: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change profile preferences");
+        }
+        Ok(())
+    }
+
+    /// Handle listing a user's server and channel memberships (own, or anyone's for admins)
+    pub async fn handle_get_user_memberships(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to list memberships");
+            return Ok(());
+        };
+
+        match UserService::memberships(user, user_id).await {
+            Ok(memberships) => {
+                self.send_response(response_sender, ServerMessage::UserMemberships { user_id, memberships });
+            }
+            Err(e) => self.send_error(response_sender, &format!("Failed to list memberships: {}", e)),
+        }
+        Ok(())
+    }
+
     /// Handle get profile
     pub async fn handle_get_profile(
         &self,
+        current_user: &Option<User>,
         user_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
-        match UserService::get_profile(user_id).await {
+        match UserService::get_profile(current_user.as_ref(), user_id).await {
             Ok(profile) => {
                 self.send_response(response_sender, ServerMessage::Profile(profile));
+                if let Some(viewer) = current_user {
+                    UserService::record_profile_view(viewer, user_id).await;
+                }
             }
             Err(e) => {
                 self.send_error(response_sender, &format!("Failed to load profile: {}", e));
@@ -169,7 +419,7 @@ impl MessageRouter {
     /// Handle get user list
     pub async fn handle_get_user_list(
         &self,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         match UserService::get_user_list(&self.peer_map).await {
             Ok(users) => {
@@ -182,22 +432,35 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle get user updates - only users changed since the client's last sync
+    pub async fn handle_get_user_updates(
+        &self,
+        since: i64,
+        ids: Option<Vec<Uuid>>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        // Taken before the query so a change racing with it is picked up next sync
+        let server_time = crate::util::now_secs();
+
+        match UserService::get_user_updates(since, ids, &self.peer_map).await {
+            Ok(users) => {
+                self.send_response(response_sender, ServerMessage::UserUpdates { users, server_time });
+            }
+            Err(_) => {
+                self.send_error(response_sender, "Failed to get user updates");
+            }
+        }
+        Ok(())
+    }
+
     /// Handle get servers request
     pub async fn handle_get_servers(
         &self,
         current_user: &Option<User>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            match crate::db::servers::db_get_user_servers(user.id).await {
-                Ok(servers) => {
-                    let _ = response_sender.send(ServerMessage::Servers(servers));
-                }
-                Err(e) => {
-                    let error_msg = format!("Failed to get servers: {}", e);
-                    let _ = response_sender.send(ServerMessage::Notification(error_msg, true));
-                }
-            }
+            self.send_servers_refresh(user.id, response_sender).await;
         }
         Ok(())
     }
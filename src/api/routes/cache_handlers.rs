@@ -1,14 +1,14 @@
 use super::MessageRouter;
+use crate::api::connection::PeerSender;
 use crate::services::BroadcastService;
 use nexus_tui_common::ServerMessage;
-use tokio::sync::mpsc;
 use uuid::Uuid;
 
 impl MessageRouter {
     /// Handle get cache stats
     pub async fn handle_get_cache_stats(
         &self,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         // This would typically be handled by a cache service
         // For now, return mock data
@@ -26,7 +26,7 @@ impl MessageRouter {
     pub async fn handle_invalidate_image_cache(
         &self,
         keys: Vec<String>,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        _response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         // Broadcast cache invalidation to all connected clients
         let response = ServerMessage::ImageCacheInvalidated { keys };
@@ -38,24 +38,97 @@ impl MessageRouter {
     pub async fn handle_get_user_avatars(
         &self,
         user_ids: Vec<Uuid>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
+        let limits = crate::config::settings().avatars.clone();
+
         // Limit the number of avatars requested to prevent abuse
-        let limited_user_ids = if user_ids.len() > 50 {
-            user_ids.into_iter().take(50).collect()
-        } else {
-            user_ids
-        };
+        let mut truncated = user_ids.len() > limits.max_batch_count;
+        let limited_user_ids: Vec<Uuid> = user_ids.into_iter().take(limits.max_batch_count).collect();
 
+        // Stop once the byte budget is spent; the client asks again for the rest
         let mut avatars = Vec::new();
+        let mut total_bytes = 0;
         for user_id in limited_user_ids {
-            match crate::db::users::db_get_user_avatar(user_id).await {
-                Ok(profile_pic) => avatars.push((user_id, profile_pic)),
-                Err(_) => avatars.push((user_id, None)), // User not found or no avatar
+            let profile_pic = crate::db::users::db_get_user_avatar(user_id).await.unwrap_or(None); // User not found or no avatar
+            let size = profile_pic.as_ref().map_or(0, |pic| pic.len());
+
+            // Always return at least one avatar so the client makes progress
+            if !avatars.is_empty() && total_bytes + size > limits.max_batch_bytes {
+                truncated = true;
+                break;
             }
+
+            total_bytes += size;
+            avatars.push((user_id, profile_pic));
         }
 
-        let _ = response_sender.send(ServerMessage::UserAvatars { avatars });
+        let _ = response_sender.send(ServerMessage::UserAvatars { avatars, truncated });
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::db::users;
+    use crate::test_support::{self, FakePeer, TestDb};
+    use nexus_tui_common::ServerMessage;
+    use uuid::Uuid;
+
+    /// A user whose avatar is `bytes` long
+    async fn user_with_avatar(name: &str, bytes: usize) -> Uuid {
+        let user = test_support::create_user(name).await;
+        users::db_update_user_profile(user.id, None, None, None, None, None, Some("a".repeat(bytes)), None).await.unwrap();
+        user.id
+    }
+
+    async fn request_avatars(user_ids: Vec<Uuid>) -> (Vec<(Uuid, Option<String>)>, bool) {
+        let peer_map = test_support::peer_map();
+        let mut peer = FakePeer::connect(&peer_map, None).await;
+        test_support::router(&peer_map).handle_get_user_avatars(user_ids, &peer.sender).await.unwrap();
+        match peer.drain().pop() {
+            Some(ServerMessage::UserAvatars { avatars, truncated }) => (avatars, truncated),
+            other => panic!("expected UserAvatars, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_within_the_byte_budget_is_complete() {
+        let _db = TestDb::new().await;
+        let budget = crate::config::settings().avatars.max_batch_bytes;
+        let ids = vec![user_with_avatar("a1", budget / 2).await, user_with_avatar("a2", budget / 2).await];
+
+        let (avatars, truncated) = request_avatars(ids.clone()).await;
+
+        assert!(!truncated);
+        assert_eq!(avatars.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+    }
+
+    #[tokio::test]
+    async fn batch_over_the_byte_budget_is_truncated() {
+        let _db = TestDb::new().await;
+        let chunk = crate::config::settings().avatars.max_batch_bytes * 2 / 5;
+        let ids = vec![
+            user_with_avatar("a1", chunk).await,
+            user_with_avatar("a2", chunk).await,
+            user_with_avatar("a3", chunk).await,
+        ];
+
+        let (avatars, truncated) = request_avatars(ids.clone()).await;
+
+        assert!(truncated);
+        assert_eq!(avatars.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids[..2]);
+    }
+
+    #[tokio::test]
+    async fn single_avatar_over_the_budget_is_still_sent() {
+        let _db = TestDb::new().await;
+        let budget = crate::config::settings().avatars.max_batch_bytes;
+        let ids = vec![user_with_avatar("big", budget + 1).await, user_with_avatar("small", 10).await];
+
+        let (avatars, truncated) = request_avatars(ids.clone()).await;
+
+        assert!(truncated);
+        assert_eq!(avatars.len(), 1);
+        assert_eq!(avatars[0].0, ids[0]);
+    }
+}
@@ -1,7 +1,9 @@
 use super::MessageRouter;
+use crate::api::connection::PeerSender;
 use crate::db::{channels, messages};
-use nexus_tui_common::{ServerMessage, User, PaginationCursor, PaginationDirection};
-use tokio::sync::mpsc;
+use crate::services::content_filter_service::FilterResult;
+use crate::services::{ChatService, CursorService, MetricsService, ModerationService};
+use nexus_tui_common::{MessageOrigin, ServerMessage, User, PaginationCursor, PaginationDirection};
 use uuid::Uuid;
 
 impl MessageRouter {
@@ -11,10 +13,30 @@ impl MessageRouter {
         current_user: &Option<User>,
         channel_id: Uuid,
         content: String,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        origin: Option<MessageOrigin>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            let _ = crate::services::ChatService::send_channel_message(channel_id, user, &content, &self.peer_map).await;
+            if let Err(e) = ModerationService::enforce_slow_start(user, None, &self.rate_limiter).await {
+                self.send_error(response_sender, &format!("Failed to send message: {}", e));
+                return Ok(());
+            }
+            // A message the filter refuses shouldn't use up the sender's limit
+            if let FilterResult::Blocked(reason) = self.content_filter.filter_message(&content) {
+                self.send_error(response_sender, &format!("Failed to send message: {}", reason));
+                return Ok(());
+            }
+            if let Err(retry_after) = self.rate_limiter.check_message_rate_limit(user.id) {
+                self.send_error(response_sender, &format!(
+                    "Too many messages, try again in {} seconds", retry_after
+                ));
+                return Ok(());
+            }
+            if let Err(e) = crate::services::ChatService::send_channel_message(
+                channel_id, user, &content, origin, &self.content_filter, &self.peer_map
+            ).await {
+                self.send_error(response_sender, &format!("Failed to send message: {}", e));
+            }
         }
         Ok(())
     }
@@ -25,10 +47,84 @@ impl MessageRouter {
         current_user: &Option<User>,
         to: Uuid,
         content: String,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            if let Err(e) = ModerationService::enforce_slow_start(user, Some(to), &self.rate_limiter).await {
+                self.send_error(response_sender, &format!("Failed to send message: {}", e));
+                return Ok(());
+            }
+            // A message the filter refuses shouldn't use up the sender's limit
+            if let FilterResult::Blocked(reason) = self.content_filter.filter_message(&content) {
+                self.send_error(response_sender, &format!("Failed to send message: {}", reason));
+                return Ok(());
+            }
+            if let Err(retry_after) = self.rate_limiter.check_message_rate_limit(user.id) {
+                self.send_error(response_sender, &format!(
+                    "Too many messages, try again in {} seconds", retry_after
+                ));
+                return Ok(());
+            }
+            if let Err(e) = crate::services::ChatService::send_direct_message(
+                user, to, &content, &self.content_filter, &self.peer_map
+            ).await {
+                self.send_error(response_sender, &format!("Failed to send message: {}", e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle deleting a channel message (author or moderators)
+    pub async fn handle_delete_channel_message(
+        &self,
+        current_user: &Option<User>,
+        message_id: Uuid,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            let _ = crate::services::ChatService::send_direct_message(user, to, &content, &self.peer_map).await;
+            if let Err(e) = ChatService::delete_channel_message(user, message_id, &self.peer_map).await {
+                self.send_error(response_sender, &format!("Failed to delete message: {}", e));
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to delete messages");
+        }
+        Ok(())
+    }
+
+    /// Handle editing a channel message
+    pub async fn handle_edit_channel_message(
+        &self,
+        current_user: &Option<User>,
+        message_id: Uuid,
+        content: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            if let Err(e) = ChatService::edit_channel_message(
+                user, message_id, &content, &self.content_filter, &self.peer_map
+            ).await {
+                self.send_error(response_sender, &format!("Failed to edit message: {}", e));
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to edit messages");
+        }
+        Ok(())
+    }
+
+    /// Handle restoring a deleted channel message (moderators only)
+    pub async fn handle_restore_channel_message(
+        &self,
+        current_user: &Option<User>,
+        message_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ChatService::restore_channel_message(user, message_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Message restored"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to restore message: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to restore messages");
         }
         Ok(())
     }
@@ -36,11 +132,23 @@ impl MessageRouter {
     /// Handle get channel messages (legacy)
     pub async fn handle_get_channel_messages(
         &self,
+        current_user: &Option<User>,
         channel_id: Uuid,
         before: Option<i64>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
-        match crate::services::ChatService::get_channel_messages(channel_id, before, 50).await {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to read messages");
+            return Ok(());
+        };
+        if let Err(e) = ChatService::ensure_can_read_channel(user.id, channel_id).await {
+            self.send_error(response_sender, &e.to_string());
+            return Ok(());
+        }
+
+        match crate::services::ChatService::get_channel_messages(
+            user.id, channel_id, before, crate::config::settings().pagination.channel_messages.resolve(None)
+        ).await {
             Ok((messages, history_complete)) => {
                 let _ = response_sender.send(ServerMessage::ChannelMessages { 
                     channel_id, 
@@ -64,10 +172,12 @@ impl MessageRouter {
         current_user: &Option<User>,
         user_id: Uuid,
         before: Option<i64>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            match crate::services::ChatService::get_direct_messages(user.id, user_id, before, 50).await {
+            match crate::services::ChatService::get_direct_messages(
+                user.id, user_id, before, crate::config::settings().pagination.direct_messages.resolve(None)
+            ).await {
                 Ok((messages, history_complete)) => {
                     let _ = response_sender.send(ServerMessage::DirectMessages { 
                         user_id, 
@@ -90,7 +200,7 @@ impl MessageRouter {
     pub async fn handle_get_channel_user_list(
         &self,
         channel_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         // Always use lightweight version for better performance
         match channels::db_get_channel_user_list_lightweight(channel_id).await {
@@ -129,8 +239,18 @@ impl MessageRouter {
     pub async fn handle_get_dm_user_list(
         &self,
         user_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        include_archived: bool,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
+        let archived_states = match messages::db_get_dm_conversation_states(user_id).await {
+            Ok(states) => states,
+            Err(e) => {
+                let error_msg = format!("Failed to get DM users: {}", e);
+                let _ = response_sender.send(ServerMessage::Notification(error_msg, true));
+                return Ok(());
+            }
+        };
+
         // Always use lightweight version for better performance
         match messages::db_get_dm_user_list_lightweight(user_id).await {
             Ok(mut user_infos) => {
@@ -144,17 +264,28 @@ impl MessageRouter {
                 }
 
                 // Convert UserInfo to User without profile images for better performance
-                let users = user_infos.into_iter().map(|info| User {
-                    id: info.id,
-                    username: info.username,
-                    color: info.color,
-                    role: info.role,
-                    profile_pic: None, // Exclude for performance
-                    cover_banner: None, // Exclude for performance
-                    status: info.status,
-                }).collect();
-                
-                let _ = response_sender.send(ServerMessage::DMUserList(users));
+                let mut active = Vec::new();
+                let mut archived = Vec::new();
+                for info in user_infos {
+                    let user = User {
+                        id: info.id,
+                        username: info.username,
+                        color: info.color,
+                        role: info.role,
+                        profile_pic: None, // Exclude for performance
+                        cover_banner: None, // Exclude for performance
+                        status: info.status,
+                    };
+                    match archived_states.get(&user.id) {
+                        None => active.push(user),
+                        // Hidden conversations stay out of the list until a new message arrives
+                        Some(true) => {}
+                        Some(false) if include_archived => archived.push(user),
+                        Some(false) => {}
+                    }
+                }
+
+                let _ = response_sender.send(ServerMessage::DMUserList { active, archived });
             }
             Err(e) => {
                 let error_msg = format!("Failed to get DM users: {}", e);
@@ -164,36 +295,97 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle archive or unarchive of a DM conversation
+    pub async fn handle_set_dm_conversation_archived(
+        &self,
+        current_user: &Option<User>,
+        peer_id: Uuid,
+        archived: bool,
+        hidden_until_new_message: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ChatService::set_dm_conversation_archived(user.id, peer_id, archived, hidden_until_new_message).await {
+                Ok(_) => {
+                    let message = if archived { "Conversation archived" } else { "Conversation unarchived" };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to update conversation: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to archive conversations");
+        }
+        Ok(())
+    }
+
+    /// Handle "jump to date" in a channel
+    pub async fn handle_get_messages_around_timestamp(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        timestamp: i64,
+        radius: usize,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ChatService::get_channel_messages_around(user.id, channel_id, timestamp, radius).await {
+                Ok((messages, has_more_before, has_more_after)) => {
+                    self.send_response(response_sender, ServerMessage::MessagesAroundTimestamp {
+                        channel_id,
+                        timestamp,
+                        messages,
+                        has_more_before,
+                        has_more_after,
+                    });
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to load messages: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to read messages");
+        }
+        Ok(())
+    }
+
     /// Handle channel messages with enhanced pagination
     pub async fn handle_get_channel_messages_paginated(
         &self,
+        current_user: &Option<User>,
         channel_id: Uuid,
         cursor: PaginationCursor,
         limit: Option<usize>,
         direction: PaginationDirection,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        want_total: bool,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
-        let limit = limit.unwrap_or(50).min(200); // Safety limit to prevent abuse
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to read messages");
+            return Ok(());
+        };
+        if let Err(e) = ChatService::ensure_can_read_channel(user.id, channel_id).await {
+            self.send_error(response_sender, &e.to_string());
+            return Ok(());
+        }
+
+        let limit = crate::config::settings().pagination.channel_messages.resolve(limit);
         let reverse_order = matches!(direction, PaginationDirection::Backward);
-        
-        let before = match cursor {
-            PaginationCursor::Timestamp(ts) => Some(ts),
-            PaginationCursor::Start => None,
-            PaginationCursor::Offset(_) => {
-                let _ = response_sender.send(ServerMessage::Notification(
-                    "Offset pagination not supported for messages".to_string(), 
-                    true
-                ));
+        let scope = [channel_id];
+
+        let before = match CursorService::resolve(&scope, cursor) {
+            Ok(before) => before,
+            Err(e) => {
+                let _ = response_sender.send(ServerMessage::Notification(e.to_string(), true));
                 return Ok(());
             }
         };
 
-        match channels::db_get_channel_messages_by_timestamp(channel_id, before, limit, reverse_order).await {
-            Ok((messages, has_more)) => {
+        match MetricsService::time("channel_messages", channels::db_get_channel_messages_by_timestamp(channel_id, user.id, before, limit, reverse_order)).await {
+            Ok((mut messages, has_more)) => {
                 let next_cursor = if has_more && !messages.is_empty() {
                     match direction {
-                        PaginationDirection::Forward => Some(PaginationCursor::Timestamp(messages.last().unwrap().timestamp)),
-                        PaginationDirection::Backward => Some(PaginationCursor::Timestamp(messages.first().unwrap().timestamp)),
+                        PaginationDirection::Forward => Some(CursorService::issue(&scope, messages.last().unwrap().timestamp)),
+                        PaginationDirection::Backward => Some(CursorService::issue(&scope, messages.first().unwrap().timestamp)),
                     }
                 } else {
                     None
@@ -201,19 +393,16 @@ impl MessageRouter {
 
                 let prev_cursor = if !messages.is_empty() {
                     match direction {
-                        PaginationDirection::Forward => Some(PaginationCursor::Timestamp(messages.first().unwrap().timestamp)),
-                        PaginationDirection::Backward => Some(PaginationCursor::Timestamp(messages.last().unwrap().timestamp)),
+                        PaginationDirection::Forward => Some(CursorService::issue(&scope, messages.first().unwrap().timestamp)),
+                        PaginationDirection::Backward => Some(CursorService::issue(&scope, messages.last().unwrap().timestamp)),
                     }
                 } else {
                     None
                 };
 
-                // Only get total count for small requests to avoid performance impact
-                let total_count = if limit <= 50 {
-                    channels::db_get_channel_message_count(channel_id).await.ok()
-                } else {
-                    None
-                };
+                // Cursors come from the unfiltered page so paging never stalls on muted messages
+                ChatService::hide_muted_authors(user.id, channel_id, &mut messages).await;
+                ChatService::attach_emojis(channel_id, &mut messages).await;
 
                 let _ = response_sender.send(ServerMessage::ChannelMessagesPaginated {
                     channel_id,
@@ -221,8 +410,18 @@ impl MessageRouter {
                     has_more,
                     next_cursor,
                     prev_cursor,
-                    total_count,
+                    total_count: None,
                 });
+
+                // The count is a COUNT(*) scan, so it follows in a separate message
+                if want_total {
+                    let response_sender = response_sender.clone();
+                    tokio::spawn(async move {
+                        if let Ok(total) = ChatService::get_channel_message_count(channel_id).await {
+                            let _ = response_sender.send(ServerMessage::ChannelMessageCount { channel_id, total });
+                        }
+                    });
+                }
             }
             Err(e) => {
                 let error_msg = format!("Failed to get channel messages: {}", e);
@@ -240,29 +439,27 @@ impl MessageRouter {
         cursor: PaginationCursor,
         limit: Option<usize>,
         direction: PaginationDirection,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        want_total: bool,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
-        let limit = limit.unwrap_or(50).min(200); // Safety limit to prevent abuse
+        let limit = crate::config::settings().pagination.direct_messages.resolve(limit);
         let reverse_order = matches!(direction, PaginationDirection::Backward);
-        
-        let before = match cursor {
-            PaginationCursor::Timestamp(ts) => Some(ts),
-            PaginationCursor::Start => None,
-            PaginationCursor::Offset(_) => {
-                let _ = response_sender.send(ServerMessage::Notification(
-                    "Offset pagination not supported for messages".to_string(), 
-                    true
-                ));
+        let scope = CursorService::dm_scope(current_user_id, other_user_id);
+
+        let before = match CursorService::resolve(&scope, cursor) {
+            Ok(before) => before,
+            Err(e) => {
+                let _ = response_sender.send(ServerMessage::Notification(e.to_string(), true));
                 return Ok(());
             }
         };
 
-        match messages::db_get_direct_messages_by_timestamp(current_user_id, other_user_id, before, limit, reverse_order).await {
+        match MetricsService::time("direct_messages", messages::db_get_direct_messages_by_timestamp(current_user_id, other_user_id, before, limit, reverse_order)).await {
             Ok((messages, has_more)) => {
                 let next_cursor = if has_more && !messages.is_empty() {
                     match direction {
-                        PaginationDirection::Forward => Some(PaginationCursor::Timestamp(messages.last().unwrap().timestamp)),
-                        PaginationDirection::Backward => Some(PaginationCursor::Timestamp(messages.first().unwrap().timestamp)),
+                        PaginationDirection::Forward => Some(CursorService::issue(&scope, messages.last().unwrap().timestamp)),
+                        PaginationDirection::Backward => Some(CursorService::issue(&scope, messages.first().unwrap().timestamp)),
                     }
                 } else {
                     None
@@ -270,28 +467,31 @@ impl MessageRouter {
 
                 let prev_cursor = if !messages.is_empty() {
                     match direction {
-                        PaginationDirection::Forward => Some(PaginationCursor::Timestamp(messages.first().unwrap().timestamp)),
-                        PaginationDirection::Backward => Some(PaginationCursor::Timestamp(messages.last().unwrap().timestamp)),
+                        PaginationDirection::Forward => Some(CursorService::issue(&scope, messages.first().unwrap().timestamp)),
+                        PaginationDirection::Backward => Some(CursorService::issue(&scope, messages.last().unwrap().timestamp)),
                     }
                 } else {
                     None
                 };
 
-                // Only get total count for small requests to avoid performance impact
-                let total_count = if limit <= 50 {
-                    messages::db_get_direct_message_count(current_user_id, other_user_id).await.ok()
-                } else {
-                    None
-                };
-
                 let _ = response_sender.send(ServerMessage::DirectMessagesPaginated {
                     user_id: other_user_id,
                     messages,
                     has_more,
                     next_cursor,
                     prev_cursor,
-                    total_count,
+                    total_count: None,
                 });
+
+                // The count is a COUNT(*) scan, so it follows in a separate message
+                if want_total {
+                    let response_sender = response_sender.clone();
+                    tokio::spawn(async move {
+                        if let Ok(total) = ChatService::get_direct_message_count(current_user_id, other_user_id).await {
+                            let _ = response_sender.send(ServerMessage::DirectMessageCount { user_id: other_user_id, total });
+                        }
+                    });
+                }
             }
             Err(e) => {
                 let error_msg = format!("Failed to get direct messages: {}", e);
@@ -300,4 +500,170 @@ impl MessageRouter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Handle follow or unfollow of a channel
+    pub async fn handle_set_channel_follow(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        follow: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ChatService::set_channel_follow(user.id, channel_id, follow).await {
+                Ok(_) => {
+                    let message = if follow { "Following channel" } else { "Unfollowed channel" };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to update channel follow: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to follow channels");
+        }
+        Ok(())
+    }
+
+    /// Handle muting or unmuting a user for yourself only
+    pub async fn handle_set_personal_mute(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        channel_id: Option<Uuid>,
+        muted: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ChatService::set_personal_mute(user.id, user_id, channel_id, muted).await {
+                Ok(_) => self.send_success(response_sender, if muted { "User muted" } else { "User unmuted" }),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update mute: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to mute users");
+        }
+        Ok(())
+    }
+
+    /// Handle listing the users you have muted
+    pub async fn handle_get_personal_mutes(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match crate::db::users::db_get_personal_mutes(user.id).await {
+                Ok(mutes) => self.send_response(response_sender, ServerMessage::PersonalMutes(mutes)),
+                Err(_) => self.send_error(response_sender, "Failed to load muted users"),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view muted users");
+        }
+        Ok(())
+    }
+
+    /// Handle bookmarking a channel message
+    pub async fn handle_bookmark_message(
+        &self,
+        current_user: &Option<User>,
+        message_id: Uuid,
+        note: Option<String>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to bookmark messages");
+            return Ok(());
+        };
+
+        match ChatService::bookmark_message(user.id, message_id, note).await {
+            Ok(()) => self.send_success(response_sender, "Message bookmarked"),
+            Err(e) => self.send_error(response_sender, &e.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Handle removing a bookmark
+    pub async fn handle_remove_bookmark(
+        &self,
+        current_user: &Option<User>,
+        message_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to manage bookmarks");
+            return Ok(());
+        };
+
+        match ChatService::remove_bookmark(user.id, message_id).await {
+            Ok(()) => self.send_success(response_sender, "Bookmark removed"),
+            Err(e) => self.send_error(response_sender, &e.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Handle listing the user's bookmarks, a page at a time
+    pub async fn handle_get_bookmarks(
+        &self,
+        current_user: &Option<User>,
+        cursor: PaginationCursor,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to view bookmarks");
+            return Ok(());
+        };
+
+        let scope = [user.id];
+        let before = match CursorService::resolve_keyset(&scope, cursor) {
+            Ok(before) => before,
+            Err(e) => {
+                self.send_error(response_sender, &e.to_string());
+                return Ok(());
+            }
+        };
+
+        match ChatService::get_bookmarks(user.id, before).await {
+            Ok((bookmarks, next)) => self.send_response(response_sender, ServerMessage::Bookmarks {
+                bookmarks,
+                next_cursor: next.map(|(created_at, message_id)| CursorService::issue_keyset(&scope, created_at, message_id)),
+            }),
+            Err(_) => self.send_error(response_sender, "Failed to load bookmarks"),
+        }
+        Ok(())
+    }
+
+    /// Handle marking a channel as read
+    pub async fn handle_mark_channel_read(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to mark channels read");
+            return Ok(());
+        };
+
+        if let Err(e) = ChatService::mark_channel_read(user.id, channel_id).await {
+            self.send_error(response_sender, &e.to_string());
+        }
+        Ok(())
+    }
+
+    /// Handle listing recently active channels for the home view
+    pub async fn handle_get_active_channels(
+        &self,
+        current_user: &Option<User>,
+        since: Option<i64>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to view channel activity");
+            return Ok(());
+        };
+
+        match ChatService::active_channels(user.id, since).await {
+            Ok(channels) => self.send_response(response_sender, ServerMessage::ActiveChannels(channels)),
+            Err(_) => self.send_error(response_sender, "Failed to load channel activity"),
+        }
+        Ok(())
+    }
+}
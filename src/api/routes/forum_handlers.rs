@@ -1,44 +1,69 @@
 use super::MessageRouter;
+use crate::api::connection::PeerSender;
 use crate::db;
-use nexus_tui_common::{ServerMessage, User};
-use tokio::sync::mpsc;
+use crate::services::forum_service::ForumAction;
+use crate::services::{ForumService, MetricsService};
+use nexus_tui_common::{ServerMessage, User, UserRole};
+use tracing::warn;
 use uuid::Uuid;
 
 impl MessageRouter {
     /// Handle get forums - use lightweight version by default for better performance
     pub async fn handle_get_forums(
         &self,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
-        let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-        self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+        if self.first_legacy_forums_request() {
+            warn!(
+                "Deprecated full-tree GetForums used by {} ({}); the reply is capped per forum and thread",
+                current_user.as_ref().map_or("anonymous", |u| u.username.as_str()),
+                self.peer_ip
+            );
+        }
+
+        let limits = &crate::config::settings().forums;
+        let load = db::forums::db_get_forums_lightweight(limits.legacy_max_threads_per_forum, limits.legacy_max_posts_per_thread);
+        match MetricsService::time("forum_load", load).await {
+            Ok(forums) => self.send_response(response_sender, ServerMessage::ForumsLightweight(forums)),
+            Err(e) => self.send_error(response_sender, &format!("Failed to load forums: {}", e)),
+        }
         Ok(())
     }
 
+    /// Send the refreshed forum list after a change. On failure send an error
+    /// rather than an empty list, which clients would take as "no forums".
+    async fn send_forums_refresh(&self, response_sender: &PeerSender) {
+        let limits = &crate::config::settings().forums;
+        match db::forums::db_get_forums_lightweight(limits.legacy_max_threads_per_forum, limits.legacy_max_posts_per_thread).await {
+            Ok(forums) => self.send_response(response_sender, ServerMessage::ForumsLightweight(forums)),
+            Err(e) => self.send_error(response_sender, &format!("Failed to refresh forums: {}", e)),
+        }
+    }
+
     /// Handle create forum (Admin only)
     pub async fn handle_create_forum(
         &self,
         current_user: &Option<User>,
         name: String,
         description: String,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            if user.role == nexus_tui_common::UserRole::Admin {
+            if let Err(e) = ForumService::authorize(user, ForumAction::ManageForums).await {
+                self.send_error(response_sender, &format!("Failed to create forum: {}", e));
+            } else {
                 match db::forums::db_create_forum(&name, &description).await {
                     Ok(_) => {
                         self.send_success(response_sender, "Forum created successfully");
                         
                         // Refresh forums to show new forum - use lightweight version
-                        let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                        self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                        self.send_forums_refresh(response_sender).await;
                     }
                     Err(e) => {
                         self.send_error(response_sender, &format!("Failed to create forum: {}", e));
                     }
                 }
-            } else {
-                self.send_error(response_sender, "Only admins can create forums");
             }
         } else {
             self.send_error(response_sender, "Must be logged in to create forums");
@@ -51,24 +76,23 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         forum_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
-            if user.role == nexus_tui_common::UserRole::Admin {
+            if let Err(e) = ForumService::authorize(user, ForumAction::ManageForums).await {
+                self.send_error(response_sender, &format!("Failed to delete forum: {}", e));
+            } else {
                 match db::forums::db_delete_forum(forum_id).await {
                     Ok(_) => {
                         self.send_success(response_sender, "Forum deleted successfully");
                         
                         // Refresh forums to show updated list - use lightweight version
-                        let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                        self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                        self.send_forums_refresh(response_sender).await;
                     }
                     Err(e) => {
                         self.send_error(response_sender, &format!("Failed to delete forum: {}", e));
                     }
                 }
-            } else {
-                self.send_error(response_sender, "Only admins can delete forums");
             }
         } else {
             self.send_error(response_sender, "Must be logged in to delete forums");
@@ -83,16 +107,19 @@ impl MessageRouter {
         forum_id: Uuid,
         title: String,
         content: String,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
+            if let Err(e) = ForumService::authorize(user, ForumAction::CreateThread { forum_id }).await {
+                self.send_error(response_sender, &format!("Failed to create thread: {}", e));
+                return Ok(());
+            }
             match db::forums::db_create_thread(forum_id, &title, user.id, &content).await {
                 Ok(_) => {
                     self.send_success(response_sender, "Thread created successfully");
                     
                     // Refresh forums to show new thread - use lightweight version
-                    let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                    self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                    self.send_forums_refresh(response_sender).await;
                 }
                 Err(e) => {
                     self.send_error(response_sender, &format!("Failed to create thread: {}", e));
@@ -110,16 +137,19 @@ impl MessageRouter {
         current_user: &Option<User>,
         thread_id: Uuid,
         content: String,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
+            if let Err(e) = ForumService::authorize(user, ForumAction::CreatePost { thread_id }).await {
+                self.send_error(response_sender, &format!("Failed to create post: {}", e));
+                return Ok(());
+            }
             match db::forums::db_create_post(thread_id, user.id, &content, None).await {
                 Ok(_) => {
                     self.send_success(response_sender, "Post created successfully");
                     
                     // Refresh forums to show new post - use lightweight version
-                    let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                    self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                    self.send_forums_refresh(response_sender).await;
                 }
                 Err(e) => {
                     self.send_error(response_sender, &format!("Failed to create post: {}", e));
@@ -138,9 +168,13 @@ impl MessageRouter {
         thread_id: Uuid,
         content: String,
         reply_to: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
+            if let Err(e) = ForumService::authorize(user, ForumAction::CreatePost { thread_id }).await {
+                self.send_error(response_sender, &format!("Failed to create reply: {}", e));
+                return Ok(());
+            }
             match db::forums::db_create_post(thread_id, user.id, &content, Some(reply_to)).await {
                 Ok(_) => {
                     // Don't send a success notification - it's annoying and useless
@@ -160,8 +194,7 @@ impl MessageRouter {
                     }
                     
                     // Refresh forums to show new reply - use lightweight version
-                    let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                    self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                    self.send_forums_refresh(response_sender).await;
                 }
                 Err(e) => {
                     self.send_error(response_sender, &format!("Failed to create reply: {}", e));
@@ -178,7 +211,7 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         post_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match db::forums::db_delete_post(post_id, user.id).await {
@@ -186,8 +219,7 @@ impl MessageRouter {
                     self.send_success(response_sender, "Post deleted successfully");
                     
                     // Refresh forums to show updated state - use lightweight version
-                    let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                    self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                    self.send_forums_refresh(response_sender).await;
                 }
                 Err(e) => {
                     self.send_error(response_sender, &format!("Failed to delete post: {}", e));
@@ -199,12 +231,37 @@ impl MessageRouter {
         Ok(())
     }
 
+    /// Handle get post edit diff (moderators only)
+    pub async fn handle_get_post_edit_diff(
+        &self,
+        current_user: &Option<User>,
+        post_id: Uuid,
+        from_revision: u32,
+        to_revision: u32,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ForumService::post_edit_diff(user, post_id, from_revision, to_revision).await {
+                Ok(diff) => self.send_response(response_sender, ServerMessage::PostEditDiff {
+                    post_id,
+                    from_revision,
+                    to_revision,
+                    diff,
+                }),
+                Err(e) => self.send_error(response_sender, &format!("Failed to load post diff: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view post edits");
+        }
+        Ok(())
+    }
+
     /// Handle delete thread
     pub async fn handle_delete_thread(
         &self,
         current_user: &Option<User>,
         thread_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match db::forums::db_delete_thread(thread_id, user.id).await {
@@ -212,8 +269,7 @@ impl MessageRouter {
                     self.send_success(response_sender, "Thread deleted successfully");
                     
                     // Refresh forums to show updated state - use lightweight version
-                    let forums = db::forums::db_get_forums_lightweight().await.unwrap_or_default();
-                    self.send_response(response_sender, ServerMessage::ForumsLightweight(forums));
+                    self.send_forums_refresh(response_sender).await;
                 }
                 Err(e) => {
                     self.send_error(response_sender, &format!("Failed to delete thread: {}", e));
@@ -224,4 +280,50 @@ impl MessageRouter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Handle set forum posting role (Admin only)
+    pub async fn handle_set_forum_posting_role(
+        &self,
+        current_user: &Option<User>,
+        forum_id: Uuid,
+        min_role: UserRole,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ForumService::set_posting_role(user, forum_id, min_role).await {
+                Ok(_) => self.send_success(response_sender, "Forum posting permission updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update forum: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to configure forums");
+        }
+        Ok(())
+    }
+
+    /// Handle a thread being opened. Only counts towards the thread's views;
+    /// nothing is sent back.
+    pub async fn handle_thread_viewed(
+        &self,
+        current_user: &Option<User>,
+        thread_id: Uuid,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            ForumService::record_thread_view(user.id, thread_id);
+        }
+        Ok(())
+    }
+
+    /// Handle get trending threads
+    pub async fn handle_get_trending_threads(
+        &self,
+        days: u32,
+        limit: usize,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        match ForumService::trending_threads(days, limit).await {
+            Ok(threads) => self.send_response(response_sender, ServerMessage::TrendingThreads(threads)),
+            Err(e) => self.send_error(response_sender, &format!("Failed to load trending threads: {}", e)),
+        }
+        Ok(())
+    }
+}
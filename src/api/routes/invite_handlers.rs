@@ -1,8 +1,7 @@
 use super::MessageRouter;
+use crate::api::connection::PeerSender;
 use crate::services::InviteService;
-use crate::db;
 use nexus_tui_common::{ServerMessage, User};
-use tokio::sync::mpsc;
 use uuid::Uuid;
 
 impl MessageRouter {
@@ -12,7 +11,7 @@ impl MessageRouter {
         current_user: &Option<User>,
         to_user_id: Uuid,
         server_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match InviteService::send_server_invite(user.id, to_user_id, server_id, &self.peer_map).await {
@@ -33,7 +32,7 @@ impl MessageRouter {
         current_user: &Option<User>,
         invite_id: Uuid,
         accept: bool,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match InviteService::respond_to_invite(invite_id, user.id, accept, &self.peer_map).await {
@@ -43,8 +42,7 @@ impl MessageRouter {
                     
                     // If accepted, refresh the user's server list
                     if accept {
-                        let servers = db::servers::db_get_user_servers(user.id).await.unwrap_or_default();
-                        self.send_response(response_sender, ServerMessage::Servers(servers));
+                        self.send_servers_refresh(user.id, response_sender).await;
                     }
                 }
                 Err(e) => {
@@ -60,7 +58,7 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         from_user_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match InviteService::respond_to_invite_from_user(from_user_id, user.id, true, &self.peer_map).await {
@@ -80,7 +78,7 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         from_user_id: Uuid,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match InviteService::respond_to_invite_from_user(from_user_id, user.id, false, &self.peer_map).await {
@@ -94,4 +92,22 @@ impl MessageRouter {
         }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Handle preview of the server behind an invite code
+    pub async fn handle_preview_invite(
+        &self,
+        current_user: &Option<User>,
+        code: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if current_user.is_none() {
+            self.send_error(response_sender, "Must be logged in to preview invites");
+            return Ok(());
+        }
+        match InviteService::preview_invite_code(&code).await {
+            Ok(preview) => self.send_response(response_sender, ServerMessage::InvitePreview(preview)),
+            Err(e) => self.send_error(response_sender, &format!("Failed to preview invite: {}", e)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use super::MessageRouter;
+use crate::api::connection::PeerSender;
+use crate::services::ModerationService;
+use nexus_tui_common::{ServerMessage, User};
+use uuid::Uuid;
+
+impl MessageRouter {
+    /// Handle get quarantined messages (Moderator only)
+    pub async fn handle_get_quarantined_messages(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::get_quarantined_messages(user).await {
+                Ok(messages) => {
+                    self.send_response(response_sender, ServerMessage::QuarantinedMessages { messages });
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to get quarantined messages: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to review messages");
+        }
+        Ok(())
+    }
+
+    /// Handle get flagged messages (Moderator only)
+    pub async fn handle_get_flagged_messages(
+        &self,
+        current_user: &Option<User>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::get_flagged_messages(user).await {
+                Ok(messages) => {
+                    self.send_response(response_sender, ServerMessage::FlaggedMessages { messages });
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to get flagged messages: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to review messages");
+        }
+        Ok(())
+    }
+
+    /// Handle clearing a flagged message from the review queue (Moderator only)
+    pub async fn handle_dismiss_flagged_message(
+        &self,
+        current_user: &Option<User>,
+        flag_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::dismiss_flagged_message(user, flag_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Flagged message dismissed"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to dismiss flagged message: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to review messages");
+        }
+        Ok(())
+    }
+
+    /// Handle approve/reject of a quarantined message (Moderator only)
+    pub async fn handle_review_quarantined_message(
+        &self,
+        current_user: &Option<User>,
+        quarantine_id: Uuid,
+        approve: bool,
+        warning: Option<String>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::review_quarantined_message(
+                user, quarantine_id, approve, warning, &self.peer_map
+            ).await {
+                Ok(_) => {
+                    let action = if approve { "approved" } else { "rejected" };
+                    self.send_success(response_sender, &format!("Message {}", action));
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to review message: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to review messages");
+        }
+        Ok(())
+    }
+
+    /// Handle banning a user from a server (server owner/mods or Admin)
+    pub async fn handle_ban_user(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        server_id: Uuid,
+        reason: String,
+        duration: Option<u64>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::ban_user(user, server_id, user_id, reason, duration, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "User banned"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to ban user: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to ban users");
+        }
+        Ok(())
+    }
+
+    /// Handle removing a member from a server without a ban (server owner/mods or Admin)
+    pub async fn handle_kick_from_server(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        user_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::kick_user(user, server_id, user_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "User removed from server"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to kick user: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to kick users");
+        }
+        Ok(())
+    }
+
+    /// Handle lifting a server ban (server owner/mods or Admin)
+    pub async fn handle_unban_user(
+        &self,
+        current_user: &Option<User>,
+        user_id: Uuid,
+        server_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ModerationService::unban_user(user, server_id, user_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "User unbanned; they can be invited again"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to unban user: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to unban users");
+        }
+        Ok(())
+    }
+}
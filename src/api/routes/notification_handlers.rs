@@ -1,7 +1,7 @@
 use super::MessageRouter;
+use crate::api::connection::PeerSender;
 use crate::services::NotificationService;
 use nexus_tui_common::{ServerMessage, User};
-use tokio::sync::mpsc;
 use uuid::Uuid;
 
 impl MessageRouter {
@@ -10,7 +10,7 @@ impl MessageRouter {
         &self,
         current_user: &Option<User>,
         before: Option<i64>,
-        response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         if let Some(user) = current_user {
             match NotificationService::get_notifications(user.id, before).await {
@@ -32,9 +32,53 @@ impl MessageRouter {
     pub async fn handle_mark_notification_read(
         &self,
         notification_id: Uuid,
-        _response_sender: &mpsc::UnboundedSender<ServerMessage>,
+        _response_sender: &PeerSender,
     ) -> crate::errors::Result<()> {
         let _ = NotificationService::mark_notification_read(notification_id).await;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Handle marking several notifications as read at once
+    pub async fn handle_mark_notifications_read(
+        &self,
+        current_user: &Option<User>,
+        ids: Vec<Uuid>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            if let Err(e) = NotificationService::mark_notifications_read(user.id, &ids).await {
+                self.send_error(response_sender, &format!("Failed to mark notifications read: {}", e));
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to mark notifications read");
+        }
+        Ok(())
+    }
+
+    /// Handle digest opt-out preference
+    pub async fn handle_set_digest_opt_out(
+        &self,
+        current_user: &Option<User>,
+        opt_out: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match crate::db::users::db_set_digest_opt_out(user.id, opt_out).await {
+                Ok(_) => {
+                    let message = if opt_out {
+                        "Notification digests disabled"
+                    } else {
+                        "Notification digests enabled"
+                    };
+                    self.send_success(response_sender, message);
+                }
+                Err(_) => {
+                    self.send_error(response_sender, "Failed to update digest preference");
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change notification preferences");
+        }
+        Ok(())
+    }
+}
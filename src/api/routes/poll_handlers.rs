@@ -0,0 +1,86 @@
+use super::MessageRouter;
+use crate::api::connection::PeerSender;
+use crate::services::PollService;
+use nexus_tui_common::{ServerMessage, User};
+use uuid::Uuid;
+
+impl MessageRouter {
+    /// Handle starting a poll in a channel
+    pub async fn handle_create_poll(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        question: String,
+        options: Vec<String>,
+        multi_choice: bool,
+        duration_secs: Option<u64>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match PollService::create_poll(
+                user, channel_id, &question, options, multi_choice, duration_secs, &self.content_filter, &self.peer_map
+            ).await {
+                Ok(_) => self.send_success(response_sender, "Poll created"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to create poll: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to create polls");
+        }
+        Ok(())
+    }
+
+    /// Handle casting, changing or (with no options) retracting a vote
+    pub async fn handle_vote_poll(
+        &self,
+        current_user: &Option<User>,
+        poll_id: Uuid,
+        option_indices: Vec<usize>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match PollService::vote(user, poll_id, option_indices, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Vote recorded"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to vote: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to vote");
+        }
+        Ok(())
+    }
+
+    /// Handle closing a poll early (its author or a moderator)
+    pub async fn handle_close_poll(
+        &self,
+        current_user: &Option<User>,
+        poll_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match PollService::close_poll(user, poll_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Poll closed"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to close poll: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to close polls");
+        }
+        Ok(())
+    }
+
+    /// Handle fetching a poll's current tallies
+    pub async fn handle_get_poll_results(
+        &self,
+        current_user: &Option<User>,
+        poll_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match PollService::get_results(user, poll_id).await {
+                Ok(results) => self.send_response(response_sender, ServerMessage::PollResults(results)),
+                Err(e) => self.send_error(response_sender, &format!("Failed to get poll results: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view polls");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use super::MessageRouter;
+use crate::api::connection::PeerSender;
+use crate::db;
+use crate::services::ServerService;
+use nexus_tui_common::{ServerMessage, User};
+use uuid::Uuid;
+
+impl MessageRouter {
+    /// Handle create server
+    pub async fn handle_create_server(
+        &self,
+        current_user: &Option<User>,
+        name: String,
+        description: String,
+        public: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::create_server(user.id, &name, &description, public, &self.content_filter).await {
+                Ok(_) => {
+                    self.send_success(response_sender, "Server created successfully");
+
+                    self.send_servers_refresh(user.id, response_sender).await;
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to create server: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to create servers");
+        }
+        Ok(())
+    }
+
+    /// Handle update server (owner/mods only)
+    pub async fn handle_update_server(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        name: String,
+        description: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::update_server(user.id, server_id, &name, &description, &self.content_filter, &self.peer_map).await {
+                Ok(_) => {
+                    self.send_success(response_sender, "Server updated successfully");
+
+                    self.send_servers_refresh(user.id, response_sender).await;
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to update server: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to update servers");
+        }
+        Ok(())
+    }
+
+    /// Handle create channel (owner/mods only)
+    pub async fn handle_create_channel(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        name: String,
+        description: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::create_channel(user.id, server_id, &name, &description, &self.content_filter, &self.peer_map).await {
+                Ok(_) => {
+                    self.send_success(response_sender, "Channel created successfully");
+
+                    self.send_servers_refresh(user.id, response_sender).await;
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to create channel: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to create channels");
+        }
+        Ok(())
+    }
+
+    /// Handle editing a channel's description or topic
+    pub async fn handle_update_channel(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        description: Option<String>,
+        topic: Option<String>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::update_channel(user, channel_id, description, topic, &self.content_filter, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Channel updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update channel: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to edit channels");
+        }
+        Ok(())
+    }
+
+    /// Handle fetching a channel's header details
+    pub async fn handle_get_channel_info(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::channel_info(user.id, channel_id).await {
+                Ok(info) => self.send_response(response_sender, ServerMessage::ChannelInfo(info)),
+                Err(e) => self.send_error(response_sender, &e.to_string()),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view channels");
+        }
+        Ok(())
+    }
+
+    /// Handle join channel, e.g. from a new channel notification
+    pub async fn handle_join_channel(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::join_channel(user.id, channel_id).await {
+                Ok(_) => {
+                    self.send_success(response_sender, "Joined channel");
+
+                    self.send_servers_refresh(user.id, response_sender).await;
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to join channel: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to join channels");
+        }
+        Ok(())
+    }
+
+    /// Handle create server role
+    pub async fn handle_create_server_role(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        name: String,
+        can_read: bool,
+        can_write: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::create_server_role(user.id, server_id, &name, can_read, can_write).await {
+                Ok(role_id) => {
+                    self.send_response(response_sender, ServerMessage::ServerRoleCreated { server_id, role_id, name });
+                }
+                Err(e) => {
+                    self.send_error(response_sender, &format!("Failed to create role: {}", e));
+                }
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to manage roles");
+        }
+        Ok(())
+    }
+
+    /// Handle assign server role
+    pub async fn handle_assign_server_role(
+        &self,
+        current_user: &Option<User>,
+        role_id: Uuid,
+        user_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::assign_server_role(user, role_id, user_id, &self.peer_map).await {
+                Ok(_) => self.send_success(response_sender, "Role assigned"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to assign role: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to manage roles");
+        }
+        Ok(())
+    }
+
+    /// Handle set role channel permission
+    pub async fn handle_set_role_channel_permission(
+        &self,
+        current_user: &Option<User>,
+        role_id: Uuid,
+        channel_id: Uuid,
+        can_read: bool,
+        can_write: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::set_role_channel_permission(user.id, role_id, channel_id, can_read, can_write).await {
+                Ok(_) => self.send_success(response_sender, "Role channel permissions updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update role permissions: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to manage roles");
+        }
+        Ok(())
+    }
+
+    /// Handle set channel link policy (owner/mods only)
+    pub async fn handle_set_channel_link_policy(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        links_allowed: bool,
+        allowed_domains: Option<Vec<String>>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::set_channel_link_policy(user.id, channel_id, links_allowed, allowed_domains).await {
+                Ok(_) => self.send_success(response_sender, "Channel link policy updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update link policy: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change channel settings");
+        }
+        Ok(())
+    }
+
+    /// Handle set channel notification policy (owner/mods only)
+    pub async fn handle_set_channel_notify_policy(
+        &self,
+        current_user: &Option<User>,
+        channel_id: Uuid,
+        followers_only: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::set_channel_notify_policy(user.id, channel_id, followers_only).await {
+                Ok(_) => self.send_success(response_sender, "Channel notification policy updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update notification policy: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change channel settings");
+        }
+        Ok(())
+    }
+
+    /// Handle set server system messages (owner/mods only)
+    pub async fn handle_set_server_system_messages(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        enabled: bool,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::set_system_messages(user.id, server_id, enabled).await {
+                Ok(_) => self.send_success(response_sender, "Server settings updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update server settings: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change server settings");
+        }
+        Ok(())
+    }
+
+    /// Handle update server settings - welcome channel and templates (owner/mods only)
+    pub async fn handle_update_server_settings(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        welcome_channel_id: Option<Uuid>,
+        welcome_template: Option<String>,
+        welcome_dm_template: Option<String>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::update_settings(
+                user.id, server_id, welcome_channel_id, welcome_template, welcome_dm_template, &self.content_filter
+            ).await {
+                Ok(_) => self.send_success(response_sender, "Server settings updated"),
+                Err(e) => self.send_error(response_sender, &format!("Failed to update server settings: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change server settings");
+        }
+        Ok(())
+    }
+
+    /// Handle get server detail - channels and permissions of one server, on demand
+    pub async fn handle_get_server_detail(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            if !db::servers::db_is_user_in_server(user.id, server_id).await? {
+                self.send_error(response_sender, "You are not a member of this server");
+                return Ok(());
+            }
+            match db::servers::db_get_server_detail(server_id).await {
+                Ok(server) => self.send_response(response_sender, ServerMessage::ServerDetail(server)),
+                Err(e) => self.send_error(response_sender, &format!("Failed to load server: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to view servers");
+        }
+        Ok(())
+    }
+
+    /// Send the user's server list: summaries for clients that negotiated
+    /// `lazy_servers`, full servers otherwise. On failure send an error rather
+    /// than an empty list, which clients would take as "no servers".
+    pub(super) async fn send_servers_refresh(
+        &self,
+        user_id: Uuid,
+        response_sender: &PeerSender,
+    ) {
+        let result = if self.lazy_servers() {
+            db::servers::db_get_user_server_summaries(user_id).await.map(ServerMessage::ServerSummaries)
+        } else {
+            db::servers::db_get_user_servers(user_id).await.map(ServerMessage::Servers)
+        };
+        match result {
+            Ok(message) => self.send_response(response_sender, message),
+            Err(e) => self.send_error(response_sender, &format!("Failed to load servers: {}", e)),
+        }
+    }
+
+    /// Handle set server audit channel (owner only)
+    pub async fn handle_set_server_audit_channel(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        channel_id: Option<Uuid>,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            match ServerService::set_audit_channel(user.id, server_id, channel_id).await {
+                Ok(_) => {
+                    let message = if channel_id.is_some() { "Audit channel set" } else { "Audit channel cleared" };
+                    self.send_success(response_sender, message);
+                }
+                Err(e) => self.send_error(response_sender, &format!("Failed to set audit channel: {}", e)),
+            }
+        } else {
+            self.send_error(response_sender, "Must be logged in to change server settings");
+        }
+        Ok(())
+    }
+
+    /// Handle adding a custom emoji to a server
+    pub async fn handle_add_server_emoji(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        name: String,
+        image_ref: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to manage emoji");
+            return Ok(());
+        };
+        if let Err(retry_after) = self.rate_limiter.check_file_upload_rate_limit(user.id) {
+            self.send_error(response_sender, &format!("Too many uploads, try again in {} seconds", retry_after));
+            return Ok(());
+        }
+
+        match ServerService::add_emoji(user.id, server_id, &name, &image_ref).await {
+            Ok(name) => {
+                self.send_success(response_sender, &format!("Emoji :{}: added", name));
+                self.send_server_emojis(user.id, server_id, response_sender).await;
+            }
+            Err(e) => self.send_error(response_sender, &format!("Failed to add emoji: {}", e)),
+        }
+        Ok(())
+    }
+
+    /// Handle removing a custom emoji from a server
+    pub async fn handle_delete_server_emoji(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        name: String,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        let Some(user) = current_user else {
+            self.send_error(response_sender, "Must be logged in to manage emoji");
+            return Ok(());
+        };
+
+        match ServerService::delete_emoji(user.id, server_id, &name).await {
+            Ok(_) => {
+                self.send_success(response_sender, "Emoji removed");
+                self.send_server_emojis(user.id, server_id, response_sender).await;
+            }
+            Err(e) => self.send_error(response_sender, &format!("Failed to remove emoji: {}", e)),
+        }
+        Ok(())
+    }
+
+    /// Handle listing a server's custom emoji
+    pub async fn handle_get_server_emojis(
+        &self,
+        current_user: &Option<User>,
+        server_id: Uuid,
+        response_sender: &PeerSender,
+    ) -> crate::errors::Result<()> {
+        if let Some(user) = current_user {
+            self.send_server_emojis(user.id, server_id, response_sender).await;
+        } else {
+            self.send_error(response_sender, "Must be logged in to view emoji");
+        }
+        Ok(())
+    }
+
+    async fn send_server_emojis(&self, user_id: Uuid, server_id: Uuid, response_sender: &PeerSender) {
+        match ServerService::list_emojis(user_id, server_id).await {
+            Ok(emojis) => self.send_response(response_sender, ServerMessage::ServerEmojis { server_id, emojis }),
+            Err(e) => self.send_error(response_sender, &format!("Failed to load emoji: {}", e)),
+        }
+    }
+}
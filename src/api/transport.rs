@@ -0,0 +1,37 @@
+// How protocol frames get to and from a peer.
+//
+// `handle_connection` only deals in whole frames: one serialized message per
+// frame, in either direction. A transport turns a connection into a sink and a
+// stream of such frames, so the same session loop serves every listener.
+
+use std::io;
+use std::pin::Pin;
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// Largest protocol frame accepted from a peer, on every transport
+pub const MAX_FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+pub type FrameSink = Pin<Box<dyn Sink<Bytes, Error = io::Error> + Send>>;
+pub type FrameStream = Pin<Box<dyn Stream<Item = io::Result<BytesMut>> + Send>>;
+
+/// A connection that carries whole protocol frames
+pub trait Transport: Send + 'static {
+    fn into_frames(self) -> (FrameSink, FrameStream);
+}
+
+/// The native transport: length-delimited frames over a byte stream (TLS)
+pub struct LengthDelimited<S>(pub S);
+
+impl<S> Transport for LengthDelimited<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn into_frames(self) -> (FrameSink, FrameStream) {
+        let codec = LengthDelimitedCodec::builder().max_frame_length(MAX_FRAME_BYTES).new_codec();
+        let (sink, stream) = Framed::new(self.0, codec).split();
+        (Box::pin(sink), Box::pin(stream))
+    }
+}
@@ -0,0 +1,193 @@
+// Optional websocket listener for clients that can't open a raw TLS socket
+// (browsers). Enabled with `[websocket] enabled = true`.
+//
+// Each binary websocket message carries exactly one protocol frame, the same
+// bytes a length-delimited frame would. Sessions run through
+// `handle_connection` like any other peer. Text messages are refused and end
+// the connection; pings are answered by the websocket layer itself. Messages
+// and frames are capped at the native transport's frame limit.
+
+use std::io;
+use std::sync::Arc;
+use bytes::{Bytes, BytesMut};
+use futures::{future, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+use tracing::{error, info, warn};
+
+use crate::api::connection::{handle_connection, PeerMap};
+use crate::api::transport::{FrameSink, FrameStream, Transport, MAX_FRAME_BYTES};
+use crate::services::{ContentFilterService, RateLimitService};
+
+/// Protocol frames carried in binary websocket messages
+pub struct WebSocket<S>(pub WebSocketStream<S>);
+
+impl<S> Transport for WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn into_frames(self) -> (FrameSink, FrameStream) {
+        let (sink, stream) = self.0.split();
+        let sink = sink
+            .sink_map_err(into_io_error)
+            .with(|frame: Bytes| future::ready(Ok::<_, io::Error>(Message::Binary(frame.to_vec()))));
+        let stream = stream.filter_map(|message| future::ready(match message {
+            Ok(Message::Binary(data)) => Some(Ok(BytesMut::from(&data[..]))),
+            Ok(Message::Text(_)) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "text websocket messages are not supported; send binary frames",
+            ))),
+            // Pings are answered by tungstenite; a close ends the stream after this
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_)) => None,
+            Err(e) => Some(Err(into_io_error(e))),
+        }));
+        (Box::pin(sink), Box::pin(stream))
+    }
+}
+
+/// Hold websocket clients to the same frame size as length-delimited ones
+fn websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_BYTES),
+        max_frame_size: Some(MAX_FRAME_BYTES),
+        ..WebSocketConfig::default()
+    }
+}
+
+fn into_io_error(e: tungstenite::Error) -> io::Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+/// Accept websocket clients (over TLS) until shutdown
+pub async fn run(
+    listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    peer_map: PeerMap,
+    content_filter: Arc<ContentFilterService>,
+    rate_limiter: Arc<RateLimitService>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Websocket accept failed: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown_rx.changed() => {
+                info!("Websocket listener shutting down");
+                return;
+            }
+        };
+        let tls_acceptor = tls_acceptor.clone();
+        let peer_map = peer_map.clone();
+        let content_filter = content_filter.clone();
+        let rate_limiter = rate_limiter.clone();
+        tokio::spawn(async move {
+            let tls_stream = match tls_acceptor.accept(stream).await {
+                Ok(tls_stream) => tls_stream,
+                Err(e) => {
+                    error!("TLS handshake failed: {}", e);
+                    return;
+                }
+            };
+            let websocket = match tokio_tungstenite::accept_async_with_config(tls_stream, Some(websocket_config())).await {
+                Ok(websocket) => websocket,
+                Err(e) => {
+                    warn!("Websocket handshake with {} failed: {}", peer_addr, e);
+                    return;
+                }
+            };
+            if let Err(e) = handle_connection(WebSocket(websocket), peer_addr.ip(), peer_map, content_filter, rate_limiter).await {
+                error!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimitConfig;
+    use crate::test_support;
+    use nexus_tui_common::{ClientMessage, ServerMessage};
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    type Client = WebSocketStream<DuplexStream>;
+
+    /// Serve one in-memory websocket session, skipping the HTTP upgrade
+    async fn connect() -> (Client, PeerMap) {
+        let peer_map = test_support::peer_map();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server = WebSocketStream::from_raw_socket(server, Role::Server, Some(websocket_config())).await;
+        handle_connection(
+            WebSocket(server),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            peer_map.clone(),
+            Arc::new(test_support::content_filter()),
+            Arc::new(RateLimitService::new(&RateLimitConfig::default())),
+        ).await.unwrap();
+        (WebSocketStream::from_raw_socket(client, Role::Client, None).await, peer_map)
+    }
+
+    /// Next websocket message, or None once the server hung up
+    async fn next_message(client: &mut Client) -> Option<Message> {
+        tokio::time::timeout(Duration::from_secs(5), client.next()).await
+            .expect("timed out waiting for the server")?
+            .ok()
+    }
+
+    #[tokio::test]
+    async fn a_binary_frame_gets_a_binary_reply() {
+        let (mut client, _peer_map) = connect().await;
+
+        client.send(Message::Binary(bincode::serialize(&ClientMessage::Ping).unwrap())).await.unwrap();
+
+        match next_message(&mut client).await {
+            Some(Message::Binary(frame)) => {
+                let reply: ServerMessage = bincode::deserialize(&frame).unwrap();
+                assert!(matches!(reply, ServerMessage::TimeSync { .. }));
+            }
+            other => panic!("expected a binary frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_websocket_ping_gets_a_pong() {
+        let (mut client, _peer_map) = connect().await;
+
+        client.send(Message::Ping(vec![1, 2, 3])).await.unwrap();
+
+        assert!(matches!(next_message(&mut client).await, Some(Message::Pong(data)) if data == [1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn a_text_frame_ends_the_session() {
+        let (mut client, peer_map) = connect().await;
+        let peer_id = *peer_map.lock().await.keys().next().unwrap();
+
+        client.send(Message::Text("{\"Ping\":null}".to_string())).await.unwrap();
+
+        loop {
+            match next_message(&mut client).await {
+                None | Some(Message::Close(_)) => break,
+                Some(Message::Binary(_)) => panic!("a text frame was answered"),
+                Some(_) => {}
+            }
+        }
+        assert!(test_support::peer_removed(&peer_map, peer_id).await);
+    }
+}
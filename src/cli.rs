@@ -0,0 +1,360 @@
+// Offline maintenance subcommands, run instead of the server
+
+use crate::api::connection::PeerMap;
+use crate::db::audit::AuditFilter;
+use crate::db::users::{ImportStatus, ImportedUser};
+use crate::db::{self, db_config};
+use crate::services::audit_service::AuditExport;
+use crate::services::SystemMessageService;
+use crate::util::csv_escape;
+use nexus_tui_common::config::ServerConfig;
+use nexus_tui_common::AuditExportFormat;
+use rand::distr::{Alphanumeric, SampleString};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+const MERGE_LEGACY_DMS_USAGE: &str =
+    "usage: nexus-tui-server merge-legacy-dms --from <path> [--dry-run] [--config <path>]";
+
+/// `merge-legacy-dms`: copy direct messages that older builds wrote to a
+/// hardcoded database file into the configured database
+pub async fn merge_legacy_dms(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut from = None;
+    let mut dry_run = false;
+    let mut config_path = "server_config.toml".to_string();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => from = args.next(),
+            "--dry-run" => dry_run = true,
+            "--config" => config_path = args.next().ok_or(MERGE_LEGACY_DMS_USAGE)?,
+            other => return Err(format!("unexpected argument '{}'\n{}", other, MERGE_LEGACY_DMS_USAGE).into()),
+        }
+    }
+    let from = from.ok_or(MERGE_LEGACY_DMS_USAGE)?;
+
+    let config = ServerConfig::load_or_default(&config_path);
+    crate::config::init_settings(crate::config::ServerSettings::load_or_default(&config_path));
+    db_config::init_db_path(config.database.path.clone());
+
+    if !Path::new(&from).exists() {
+        return Err(format!("legacy database {} does not exist", from).into());
+    }
+    if canonical_db_path(Path::new(&from))? == canonical_db_path(Path::new(&config.database.path))? {
+        return Err("legacy database is the configured database; nothing to merge".into());
+    }
+
+    // Holding the server's port means nothing else is serving from this database
+    let addr = format!("{}:{}", config.network.bind_address, config.network.port);
+    if TcpListener::bind(&addr).await.is_err() {
+        return Err(format!(
+            "{} is in use; stop the server before merging legacy direct messages", addr
+        ).into());
+    }
+
+    db::migrations::init_db().await?;
+    let messages = db::messages::db_merge_legacy_dms(&from, dry_run).await?;
+
+    if dry_run {
+        for message in &messages {
+            println!(
+                "would copy {} ({} -> {}, at {})",
+                message.id, message.from_user_id, message.to_user_id, message.timestamp
+            );
+        }
+        println!("{} direct message(s) would be copied from {}", messages.len(), from);
+    } else {
+        println!("Copied {} direct message(s) from {} into {}", messages.len(), from, config.database.path);
+    }
+    Ok(())
+}
+
+/// Absolute, symlink-free form of a database path. The configured database
+/// may not have been created yet, so only its directory has to exist.
+fn canonical_db_path(path: &Path) -> std::io::Result<PathBuf> {
+    if path.exists() {
+        return path.canonicalize();
+    }
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display()))
+    })?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(file_name))
+}
+
+const IMPORT_USERS_USAGE: &str =
+    "usage: nexus-tui-server import-users --file <users.csv> [--report <path>] [--config <path>]";
+
+/// Accounts created per transaction during `import-users`
+const IMPORT_BATCH_SIZE: usize = 100;
+const PLACEHOLDER_PASSWORD_LENGTH: usize = 20;
+
+/// `import-users`: create accounts from a CSV of
+/// `username,contact_note,role,force_password_reset` rows, join them to the
+/// default server, and write a report with each account's temporary password.
+/// Usernames that already exist are skipped, so the import can be re-run.
+pub async fn import_users(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = None;
+    let mut report_path = "import_report.csv".to_string();
+    let mut config_path = "server_config.toml".to_string();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => file = args.next(),
+            "--report" => report_path = args.next().ok_or(IMPORT_USERS_USAGE)?,
+            "--config" => config_path = args.next().ok_or(IMPORT_USERS_USAGE)?,
+            other => return Err(format!("unexpected argument '{}'\n{}", other, IMPORT_USERS_USAGE).into()),
+        }
+    }
+    let file = file.ok_or(IMPORT_USERS_USAGE)?;
+
+    let config = ServerConfig::load_or_default(&config_path);
+    crate::config::init_settings(crate::config::ServerSettings::load_or_default(&config_path));
+    db_config::init_db_path(config.database.path.clone());
+
+    let contents = std::fs::read_to_string(&file)
+        .map_err(|e| format!("cannot read {}: {}", file, e))?;
+    let rows = parse_import_rows(&contents)?;
+
+    db::migrations::init_db().await?;
+    db::users::ensure_system_user_exists().await?;
+    db::servers::ensure_default_server_exists().await?;
+
+    // Nobody is connected to this process; join announcements are only stored
+    let peer_map = PeerMap::new(Mutex::new(HashMap::new()));
+
+    // Written batch by batch, so a failed run still reports the accounts it created
+    let mut report = create_private_file(&report_path)
+        .map_err(|e| format!("cannot write report {}: {}", report_path, e))?;
+    writeln!(report, "requested_username,username,status,temporary_password")?;
+    let (mut created, mut renamed, mut skipped) = (0, 0, 0);
+    for batch in rows.chunks(IMPORT_BATCH_SIZE) {
+        let results = db::users::db_import_users(batch.to_vec()).await?;
+
+        for result in results {
+            let status = match result.status {
+                ImportStatus::Created => { created += 1; "created" }
+                ImportStatus::Renamed => { renamed += 1; "renamed" }
+                ImportStatus::Skipped => { skipped += 1; "skipped" }
+            };
+            // Memberships were committed with the account; only the greeting is left
+            if let (Some(user_id), Some(server_id)) = (result.user_id, result.server_id) {
+                SystemMessageService::member_joined(server_id, user_id, &result.username, &peer_map).await;
+            }
+            let password = result.password.as_deref().unwrap_or("");
+            writeln!(
+                report, "{},{},{},{}",
+                csv_escape(&result.requested_username), csv_escape(&result.username), status, password
+            )?;
+        }
+        report.sync_all()?;
+    }
+
+    println!(
+        "Imported {} user(s) ({} renamed to avoid collisions), skipped {} existing; report written to {}",
+        created + renamed, renamed, skipped, report_path
+    );
+    println!("The report contains temporary passwords; hand them out and delete it");
+    Ok(())
+}
+
+/// Create (or truncate) a file only its owner can read, for reports holding
+/// credentials. An existing file is narrowed to owner-only before anything is written.
+fn create_private_file(path: &str) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+/// Parse the import CSV. A first row starting with `username` is treated as a header.
+fn parse_import_rows(contents: &str) -> Result<Vec<ImportedUser>, String> {
+    let mut rows = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split_csv_line(line);
+        let username = fields.first().map(|f| f.trim()).unwrap_or("");
+        if line_number == 1 && username.eq_ignore_ascii_case("username") {
+            continue;
+        }
+        if username.is_empty() {
+            return Err(format!("line {}: missing username", line_number));
+        }
+
+        let contact_note = fields.get(1).map(|f| f.trim()).filter(|f| !f.is_empty()).map(str::to_string);
+        let role = match fields.get(2).map(|f| f.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("user") => "User",
+            Some("moderator") => "Moderator",
+            Some("admin") => "Admin",
+            Some("bot") => "Bot",
+            Some(other) => return Err(format!("line {}: unknown role '{}'", line_number, other)),
+        };
+        let must_change_password = match fields.get(3).map(|f| f.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("1") | Some("true") | Some("yes") => true,
+            Some("0") | Some("false") | Some("no") => false,
+            Some(other) => return Err(format!("line {}: invalid password reset flag '{}'", line_number, other)),
+        };
+
+        rows.push(ImportedUser {
+            username: username.to_string(),
+            password: Alphanumeric.sample_string(&mut rand::rng(), PLACEHOLDER_PASSWORD_LENGTH),
+            role: role.to_string(),
+            contact_note,
+            must_change_password,
+        });
+    }
+    Ok(rows)
+}
+
+/// Split one CSV line, honouring double-quoted fields with `""` escapes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+const EXPORT_AUDIT_USAGE: &str =
+    "usage: nexus-tui-server export-audit --output <path> [--since <time>] [--until <time>] [--user <name|id>] \
+     [--action <action>] [--format csv|json] [--config <path>]";
+
+/// `export-audit`: write matching audit log entries to a file, with no size
+/// limit. Times are unix seconds or YYYY-MM-DD (UTC midnight); `--until` is exclusive.
+pub async fn export_audit(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut output = None;
+    let mut since = None;
+    let mut until = None;
+    let mut user = None;
+    let mut action = None;
+    let mut format = AuditExportFormat::Csv;
+    let mut config_path = "server_config.toml".to_string();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = args.next(),
+            "--since" => since = Some(parse_export_time(&args.next().ok_or(EXPORT_AUDIT_USAGE)?)?),
+            "--until" => until = Some(parse_export_time(&args.next().ok_or(EXPORT_AUDIT_USAGE)?)?),
+            "--user" => user = args.next(),
+            "--action" => action = args.next(),
+            "--format" => format = match args.next().as_deref() {
+                Some("csv") => AuditExportFormat::Csv,
+                Some("json") => AuditExportFormat::Json,
+                _ => return Err(EXPORT_AUDIT_USAGE.into()),
+            },
+            "--config" => config_path = args.next().ok_or(EXPORT_AUDIT_USAGE)?,
+            other => return Err(format!("unexpected argument '{}'\n{}", other, EXPORT_AUDIT_USAGE).into()),
+        }
+    }
+    let output = output.ok_or(EXPORT_AUDIT_USAGE)?;
+
+    let config = ServerConfig::load_or_default(&config_path);
+    crate::config::init_settings(crate::config::ServerSettings::load_or_default(&config_path));
+    db_config::init_db_path(config.database.path.clone());
+    db::migrations::init_db().await?;
+
+    let user_id = match user {
+        Some(user) => Some(match Uuid::parse_str(&user) {
+            Ok(id) => id,
+            Err(_) => db::users::db_get_user_by_username(&user).await
+                .map_err(|_| format!("no user named {}", user))?
+                .id,
+        }),
+        None => None,
+    };
+    let filter = AuditFilter { user_id, action, start_time: since, end_time: until };
+
+    let file = std::fs::File::create(&output)
+        .map_err(|e| format!("cannot write {}: {}", output, e))?;
+    let mut writer = BufWriter::new(file);
+    let mut export = AuditExport::new(filter, format);
+    while let Some(chunk) = export.next_chunk().await? {
+        writer.write_all(chunk.as_bytes())?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    println!("Audit log exported to {}", output);
+    Ok(())
+}
+
+fn parse_export_time(value: &str) -> Result<i64, String> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+        .map_err(|_| format!("invalid time '{}': expected unix seconds or YYYY-MM-DD", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn report_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let fresh = dir.path().join("fresh.csv");
+        create_private_file(fresh.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&fresh), 0o600);
+
+        // A report left over from an earlier run with wider permissions
+        let existing = dir.path().join("existing.csv");
+        std::fs::write(&existing, "old report").unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o644)).unwrap();
+        create_private_file(existing.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&existing), 0o600);
+        assert_eq!(std::fs::read_to_string(&existing).unwrap(), "");
+    }
+
+    #[test]
+    fn a_database_path_is_compared_even_before_the_file_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("nexus.db");
+        let roundabout = dir.path().join(".").join("nexus.db");
+
+        assert_eq!(canonical_db_path(&missing).unwrap(), canonical_db_path(&roundabout).unwrap());
+        assert_eq!(canonical_db_path(&missing).unwrap(), dir.path().canonicalize().unwrap().join("nexus.db"));
+
+        std::fs::write(&missing, b"").unwrap();
+        assert_eq!(canonical_db_path(&roundabout).unwrap(), missing.canonicalize().unwrap());
+        // Without a directory to resolve there is nothing to compare
+        assert!(canonical_db_path(&dir.path().join("gone").join("nexus.db")).is_err());
+    }
+}
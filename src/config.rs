@@ -0,0 +1,552 @@
+// Server-only settings that live alongside the shared ServerConfig in the same TOML file
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+/// Settings consumed only by the server. Sections the shared `ServerConfig`
+/// owns (database, network, ...) are ignored here.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub moderation: ModerationConfig,
+    pub servers: ServerLimitsConfig,
+    pub digest: DigestConfig,
+    pub avatars: AvatarBatchConfig,
+    pub users: UserSyncConfig,
+    pub forums: ForumConfig,
+    pub sqlite: SqliteConfig,
+    pub rate_limits: RateLimitConfig,
+    pub quotas: StorageQuotaConfig,
+    pub readiness: ReadinessConfig,
+    pub messages: MessageConfig,
+    pub connections: ConnectionConfig,
+    pub audit_channel: AuditChannelConfig,
+    pub info: ServerInfoConfig,
+    pub invites: InviteConfig,
+    pub pagination: PaginationSettings,
+    pub tls: TlsConfig,
+    pub action_tokens: ActionTokenConfig,
+    pub websocket: WebSocketConfig,
+    #[cfg(feature = "irc-gateway")]
+    pub irc: IrcGatewayConfig,
+}
+
+/// Content filter and new-account moderation settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// Words that block a message outright
+    pub blocked_words: Vec<String>,
+    /// Regex patterns that flag a message for review
+    pub flagged_patterns: Vec<String>,
+    pub max_message_length: usize,
+    /// Accounts younger than this are on probation (their first channel
+    /// message is quarantined if flagged)
+    pub probation_hours: i64,
+    /// Accounts younger than this are in slow start: a tighter message rate
+    /// limit and DMs only to users they share a server with. 0 disables it.
+    pub slow_start_hours: i64,
+    /// Channel messages after which an account leaves slow start early
+    pub slow_start_graduation_messages: i64,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            blocked_words: Vec::new(),
+            flagged_patterns: Vec::new(),
+            max_message_length: 4000,
+            probation_hours: 24,
+            slow_start_hours: 72,
+            slow_start_graduation_messages: 20,
+        }
+    }
+}
+
+/// Limits on user-created servers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerLimitsConfig {
+    pub max_name_length: usize,
+    pub max_description_length: usize,
+    /// Custom emoji a single server may register
+    pub max_emojis: usize,
+    /// Largest custom emoji image, as sent by the client
+    pub max_emoji_bytes: usize,
+    /// Longest welcome message template
+    pub max_welcome_length: usize,
+    /// Longest channel topic; channel descriptions use `max_description_length`
+    pub max_channel_topic_length: usize,
+}
+
+impl Default for ServerLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_name_length: 64,
+            max_description_length: 512,
+            max_emojis: 50,
+            max_emoji_bytes: 256 * 1024,
+            max_welcome_length: 1000,
+            max_channel_topic_length: 256,
+        }
+    }
+}
+
+/// Notification digest rollup for inactive users
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DigestConfig {
+    pub enabled: bool,
+    /// Users offline longer than this get their older unread notifications rolled up
+    pub inactive_days: i64,
+    /// How often the maintenance job runs
+    pub interval_minutes: u64,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            inactive_days: 7,
+            interval_minutes: 60,
+        }
+    }
+}
+
+/// Limits on batched avatar responses
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AvatarBatchConfig {
+    pub max_batch_count: usize,
+    /// Total bytes of avatar data returned in one response
+    pub max_batch_bytes: usize,
+}
+
+impl Default for AvatarBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_count: 50,
+            max_batch_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+/// How user changes are tracked for incremental sync
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UserSyncConfig {
+    /// Treat last_seen changes as user updates (off by default to avoid churn)
+    pub bump_updated_at_on_last_seen: bool,
+    /// Let users pick a display name separate from their unique login name
+    pub display_names: bool,
+}
+
+/// Per-user request rate limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Profile pictures, banners and other images a user may upload per minute
+    pub uploads_per_minute: u32,
+    /// Pre-auth GetServerInfo requests allowed per IP address per minute
+    pub server_info_per_minute: u32,
+    /// Channel and direct messages per minute for every account
+    pub messages_per_minute: u32,
+    /// Channel and direct messages per minute for accounts in slow start
+    pub new_account_messages_per_minute: u32,
+    /// Where open rate limit windows are kept across a graceful restart;
+    /// relative paths are resolved next to the database file
+    pub state_file: String,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            uploads_per_minute: 10,
+            server_info_per_minute: 10,
+            messages_per_minute: 30,
+            new_account_messages_per_minute: 5,
+            state_file: "rate_limits.json".to_string(),
+        }
+    }
+}
+
+/// Per-user storage quotas (bytes of messages plus images); 0 means unlimited
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageQuotaConfig {
+    /// Above this the user is warned once via a notification
+    pub soft_quota_bytes: u64,
+    /// Above this new image uploads are refused
+    pub hard_quota_bytes: u64,
+    /// How often usage is recomputed from scratch to correct drift
+    pub recompute_interval_hours: u64,
+}
+
+impl Default for StorageQuotaConfig {
+    fn default() -> Self {
+        Self {
+            soft_quota_bytes: 0,
+            hard_quota_bytes: 0,
+            recompute_interval_hours: 24,
+        }
+    }
+}
+
+/// Startup self-check before accepting connections
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReadinessConfig {
+    /// How many times to run the checks before giving up
+    pub max_attempts: u32,
+    pub retry_delay_secs: u64,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            retry_delay_secs: 2,
+        }
+    }
+}
+
+/// Channel message retention
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MessageConfig {
+    /// Deleted messages stay as tombstones (hidden, but restorable by
+    /// moderators) for this long before being purged
+    pub tombstone_grace_hours: i64,
+    /// Channel messages older than this are pruned; 0 keeps them forever
+    pub retention_days: i64,
+    /// Write pruned messages to `archive_dir` before deleting them
+    pub archive_pruned: bool,
+    /// Pruned messages land in `<archive_dir>/<channel_id>/<YYYY-MM-DD>.jsonl`
+    pub archive_dir: String,
+    /// Times one message may be edited; 0 allows unlimited edits
+    pub max_edits_per_message: u32,
+    /// Let the startup integrity scan fix messages pointing at missing rows:
+    /// unknown authors become the System user, messages in missing channels
+    /// move to `orphaned_channel_messages`. Off only reports them.
+    pub repair_orphans: bool,
+}
+
+impl Default for MessageConfig {
+    fn default() -> Self {
+        Self {
+            tombstone_grace_hours: 72,
+            retention_days: 0,
+            archive_pruned: false,
+            archive_dir: "message_archive".to_string(),
+            max_edits_per_message: 20,
+            repair_orphans: false,
+        }
+    }
+}
+
+/// Mirroring of moderation events into each server's audit channel
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditChannelConfig {
+    /// Master switch; servers still need an audit channel set by their owner
+    pub enabled: bool,
+    /// Audit actions that are posted, e.g. "delete_message"
+    pub events: Vec<String>,
+}
+
+impl Default for AuditChannelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            events: vec![
+                "delete_message".to_string(),
+                "restore_message".to_string(),
+                "edit_message".to_string(),
+                "review_quarantine".to_string(),
+                "dismiss_flagged".to_string(),
+                "assign_server_role".to_string(),
+                "ban_user".to_string(),
+                "unban_user".to_string(),
+                "kick_user".to_string(),
+            ],
+        }
+    }
+}
+
+/// Per-connection limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Outgoing messages queued for one client before it is considered
+    /// stuck and disconnected
+    pub send_buffer_capacity: usize,
+    /// Compression algorithms the server will agree to in Hello, most preferred first
+    pub compression: Vec<String>,
+    /// zstd level used for outgoing frames
+    pub compression_level: i32,
+    /// Messages (other than Hello and Ping) a client may send before logging
+    /// in or registering; the connection is closed past this
+    pub max_pre_auth_messages: u32,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            send_buffer_capacity: 1024,
+            compression: vec!["zstd".to_string()],
+            compression_level: 3,
+            max_pre_auth_messages: 20,
+        }
+    }
+}
+
+/// What GetServerInfo tells clients before they log in
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerInfoConfig {
+    pub name: String,
+    pub description: String,
+}
+
+impl Default for ServerInfoConfig {
+    fn default() -> Self {
+        Self {
+            name: "Nexus".to_string(),
+            description: String::new(),
+        }
+    }
+}
+
+/// SQLite connection settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SqliteConfig {
+    /// Open heavy read paths on separate read-only connections
+    pub read_only_connections: bool,
+    /// How long a connection waits on a locked database before failing
+    pub busy_timeout_ms: u64,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            read_only_connections: true,
+            busy_timeout_ms: 5000,
+        }
+    }
+}
+
+/// What happens to a forum reply nested deeper than the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyDepthPolicy {
+    /// Refuse the reply with an error
+    Reject,
+    /// Attach the reply to the root post of its chain instead
+    Flatten,
+}
+
+/// Forum posting limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ForumConfig {
+    /// Deepest allowed reply nesting (a top-level post is depth 0); 0 disables the limit
+    pub max_reply_depth: u32,
+    pub reply_depth_policy: ReplyDepthPolicy,
+    /// Newest threads per forum sent in the legacy full-tree GetForums reply; 0 sends all
+    pub legacy_max_threads_per_forum: usize,
+    /// Newest posts per thread sent in the legacy full-tree GetForums reply; 0 sends all
+    pub legacy_max_posts_per_thread: usize,
+}
+
+impl Default for ForumConfig {
+    fn default() -> Self {
+        Self {
+            max_reply_depth: 8,
+            reply_depth_policy: ReplyDepthPolicy::Flatten,
+            legacy_max_threads_per_forum: 50,
+            legacy_max_posts_per_thread: 100,
+        }
+    }
+}
+
+/// Default and maximum page size of one kind of listing
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct PageLimits {
+    /// Used when the client doesn't ask for a size
+    pub default: usize,
+    /// Larger requests are cut down to this
+    pub max: usize,
+}
+
+impl PageLimits {
+    /// Page size for a request, falling back to the default and capped at the maximum
+    pub fn resolve(&self, requested: Option<usize>) -> usize {
+        requested.unwrap_or(self.default).clamp(1, self.max.max(1))
+    }
+}
+
+impl Default for PageLimits {
+    fn default() -> Self {
+        Self {
+            default: 50,
+            max: 200,
+        }
+    }
+}
+
+/// Page sizes per endpoint
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PaginationSettings {
+    pub channel_messages: PageLimits,
+    pub direct_messages: PageLimits,
+    pub notifications: PageLimits,
+}
+
+/// Server invite limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InviteConfig {
+    /// After a user declines an invite, the sender can't invite them again for this long; 0 disables the cooldown
+    pub decline_cooldown_hours: i64,
+}
+
+impl Default for InviteConfig {
+    fn default() -> Self {
+        Self {
+            decline_cooldown_hours: 24,
+        }
+    }
+}
+
+/// TLS hardening, applied when the listener's TLS config is built
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Oldest protocol version accepted: "1.2" or "1.3"
+    pub min_version: String,
+    /// Cipher suites allowed, by IANA name (e.g. "TLS13_AES_256_GCM_SHA384"); empty allows every suite rustls supports
+    pub cipher_suites: Vec<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            min_version: "1.2".to_string(),
+            cipher_suites: Vec::new(),
+        }
+    }
+}
+
+/// One-time tokens for sensitive operations (password change, role change, ...)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ActionTokenConfig {
+    /// Refuse sensitive operations that don't carry a token; off accepts them
+    /// bare but still checks any token that is sent
+    pub required: bool,
+    /// How long an issued token stays usable
+    pub ttl_secs: i64,
+}
+
+impl Default for ActionTokenConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            ttl_secs: 120,
+        }
+    }
+}
+
+/// Optional websocket listener (TLS, one protocol frame per binary message)
+/// for clients that can't open a raw socket, such as browsers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "0.0.0.0:8081".to_string(),
+        }
+    }
+}
+
+/// Optional IRC gateway: a plain-text listener on its own port that bridges
+/// the mapped channels for IRC clients. Built only with the `irc-gateway` feature.
+#[cfg(feature = "irc-gateway")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IrcGatewayConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    /// IRC channel name (e.g. "#general") to the Nexus channel it mirrors;
+    /// channels not listed here cannot be joined through the gateway
+    pub channels: std::collections::HashMap<String, uuid::Uuid>,
+}
+
+#[cfg(feature = "irc-gateway")]
+impl Default for IrcGatewayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "127.0.0.1:6667".to_string(),
+            channels: std::collections::HashMap::new(),
+        }
+    }
+}
+
+impl ServerSettings {
+    /// Load settings from the config file, falling back to defaults
+    pub fn load_or_default(path: &str) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Failed to parse server settings from {}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// Global server settings
+static SETTINGS: OnceCell<RwLock<Arc<ServerSettings>>> = OnceCell::new();
+
+/// Initialize the global server settings
+pub fn init_settings(settings: ServerSettings) {
+    SETTINGS.set(RwLock::new(Arc::new(settings))).ok();
+}
+
+/// Get the current server settings
+pub fn settings() -> Arc<ServerSettings> {
+    SETTINGS
+        .get()
+        .map(|s| s.read().unwrap().clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_configured_page_size_is_used_when_the_client_sends_none() {
+        let settings: ServerSettings = toml::from_str("[pagination.channel_messages]\ndefault = 20\nmax = 100\n").unwrap();
+        let limits = settings.pagination.channel_messages;
+
+        assert_eq!(limits.resolve(None), 20);
+        assert_eq!(limits.resolve(Some(30)), 30);
+        assert_eq!(limits.resolve(Some(500)), 100);
+        assert_eq!(limits.resolve(Some(0)), 1);
+        // Endpoints left out of the file keep their defaults
+        assert_eq!(settings.pagination.direct_messages.resolve(None), 50);
+    }
+}
@@ -0,0 +1,216 @@
+// Audit log DB functions
+
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use nexus_tui_common::{AuditActionCount, AuditEntry, AuditStats};
+use rusqlite::{params, params_from_iter, types::Value};
+use tokio::task;
+use uuid::Uuid;
+
+/// Optional filters for an audit log query; unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user_id: Option<Uuid>,
+    pub action: Option<String>,
+    /// Inclusive lower bound on the entry timestamp
+    pub start_time: Option<i64>,
+    /// Exclusive upper bound on the entry timestamp
+    pub end_time: Option<i64>,
+}
+
+/// Record an action taken by `user_id`
+pub async fn db_record_audit(
+    user_id: Uuid,
+    action: &str,
+    target: Option<String>,
+    details: Option<String>,
+) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let action = action.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "INSERT INTO audit_log (id, user_id, action, target, details, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![Uuid::new_v4().to_string(), user_id_str, action, target, details, now],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Page through the audit log, newest first. Only the filters that are set
+/// end up in the WHERE clause so SQLite can pick the matching index.
+/// Returns the page and whether more entries follow it.
+pub async fn db_fetch_audit_entries(
+    filter: AuditFilter,
+    limit: usize,
+    offset: usize,
+) -> Result<(Vec<AuditEntry>, bool), String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut values: Vec<Value> = Vec::new();
+        let where_clause = where_clause(filter_conditions(filter, &mut values));
+
+        // One extra row tells us whether there's another page
+        values.push(Value::Integer(limit as i64 + 1));
+        values.push(Value::Integer(offset as i64));
+        let query = format!(
+            "SELECT id, user_id, action, target, details, timestamp
+             FROM audit_log {}
+             ORDER BY timestamp DESC, id DESC LIMIT ?{} OFFSET ?{}",
+            where_clause,
+            values.len() - 1,
+            values.len()
+        );
+
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(values), audit_entry_from_row).map_err(|e| e.to_string())?;
+        let mut entries = rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+        let has_more = entries.len() > limit;
+        entries.truncate(limit);
+        Ok((entries, has_more))
+    })
+    .await
+    .unwrap()
+}
+
+/// Count the entries matching a filter
+pub async fn db_count_audit_entries(filter: AuditFilter) -> Result<usize, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut values: Vec<Value> = Vec::new();
+        let query = format!(
+            "SELECT COUNT(*) FROM audit_log {}",
+            where_clause(filter_conditions(filter, &mut values))
+        );
+        let count: i64 = conn.query_row(&query, params_from_iter(values), |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        Ok(count as usize)
+    })
+    .await
+    .unwrap()
+}
+
+/// Totals for the entries matching a filter: how many, from how many distinct
+/// users, and a per-action breakdown, most frequent first
+pub async fn db_audit_stats(filter: AuditFilter) -> Result<AuditStats, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut values: Vec<Value> = Vec::new();
+        let where_clause = where_clause(filter_conditions(filter, &mut values));
+
+        let (total_entries, unique_users): (i64, i64) = conn.query_row(
+            &format!("SELECT COUNT(*), COUNT(DISTINCT user_id) FROM audit_log {}", where_clause),
+            params_from_iter(values.iter()),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT action, COUNT(*) AS n FROM audit_log {} GROUP BY action ORDER BY n DESC, action ASC",
+            where_clause
+        )).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(values.iter()), |row| {
+            Ok(AuditActionCount {
+                action: row.get(0)?,
+                count: row.get::<_, i64>(1)? as usize,
+            })
+        }).map_err(|e| e.to_string())?;
+        let action_counts = rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+        Ok(AuditStats {
+            total_entries: total_entries as usize,
+            unique_users: unique_users as usize,
+            action_counts,
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Oldest-first batch of entries matching a filter, strictly after the
+/// `(timestamp, id)` of the previous batch's last entry. Exports walk the
+/// whole range this way instead of with ever-growing OFFSETs.
+pub async fn db_fetch_audit_entries_after(
+    filter: AuditFilter,
+    after: Option<(i64, Uuid)>,
+    limit: usize,
+) -> Result<Vec<AuditEntry>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut values: Vec<Value> = Vec::new();
+        let mut conditions = filter_conditions(filter, &mut values);
+        if let Some((timestamp, id)) = after {
+            values.push(Value::Integer(timestamp));
+            values.push(Value::Text(id.to_string()));
+            conditions.push(format!(
+                "(timestamp > ?{0} OR (timestamp = ?{0} AND id > ?{1}))",
+                values.len() - 1,
+                values.len()
+            ));
+        }
+        values.push(Value::Integer(limit as i64));
+        let query = format!(
+            "SELECT id, user_id, action, target, details, timestamp
+             FROM audit_log {}
+             ORDER BY timestamp ASC, id ASC LIMIT ?{}",
+            where_clause(conditions),
+            values.len()
+        );
+
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(values), audit_entry_from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// SQL conditions for the filters that are set, pushing their values onto `values`
+fn filter_conditions(filter: AuditFilter, values: &mut Vec<Value>) -> Vec<String> {
+    let mut conditions = Vec::new();
+    if let Some(user_id) = filter.user_id {
+        values.push(Value::Text(user_id.to_string()));
+        conditions.push(format!("user_id = ?{}", values.len()));
+    }
+    if let Some(action) = filter.action {
+        values.push(Value::Text(action));
+        conditions.push(format!("action = ?{}", values.len()));
+    }
+    if let Some(start_time) = filter.start_time {
+        values.push(Value::Integer(start_time));
+        conditions.push(format!("timestamp >= ?{}", values.len()));
+    }
+    if let Some(end_time) = filter.end_time {
+        values.push(Value::Integer(end_time));
+        conditions.push(format!("timestamp < ?{}", values.len()));
+    }
+    conditions
+}
+
+fn where_clause(conditions: Vec<String>) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+fn audit_entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+        user_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+        action: row.get(2)?,
+        target: row.get(3)?,
+        details: row.get(4)?,
+        timestamp: row.get(5)?,
+    })
+}
@@ -1,65 +1,80 @@
 // Channel DB functions
 
-use crate::db::db_config;
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
 use crate::util::parse_user_color;
-use nexus_tui_common::{ChannelMessage, User, UserRole, UserStatus, UserInfo};
-use rusqlite::{params, Connection};
+use nexus_tui_common::{ActiveChannel, ChannelInfo, ChannelMessage, ReactionSummary, User, UserRole, UserStatus, UserInfo};
+use rusqlite::{params, params_from_iter, Connection};
+use std::collections::HashMap;
 use tokio::task;
 use uuid::Uuid;
 
+/// Create a channel and add the server's members to it. Members who turned
+/// off `auto_join_new_channels` are left out and returned alongside the new
+/// channel's id, so they can be offered the channel instead.
 pub async fn db_create_channel(
     server_id: Uuid,
     name: &str,
     description: &str,
-) -> Result<Uuid, String> {
+) -> Result<(Uuid, Vec<Uuid>), String> {
     let server_id_str = server_id.to_string();
     let name = name.to_string();
     let description = description.to_string();
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let id = Uuid::new_v4();
         conn.execute(
-            "INSERT INTO channels (id, server_id, name, description) VALUES (?1, ?2, ?3, ?4)",
-            params![id.to_string(), server_id_str, name, description],
+            "INSERT INTO channels (id, server_id, name, description, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id.to_string(), server_id_str, name, description, crate::util::now_secs()],
         )
         .map_err(|e| e.to_string())?;
         let mut stmt = conn
-            .prepare("SELECT user_id FROM server_users WHERE server_id = ?1")
+            .prepare(
+                "SELECT su.user_id, COALESCE(u.auto_join_new_channels, 1) FROM server_users su
+                 LEFT JOIN users u ON u.id = su.user_id
+                 WHERE su.server_id = ?1",
+            )
             .map_err(|e| e.to_string())?;
         let user_rows = stmt
             .query_map(params![server_id_str.clone()], |row| {
-                row.get::<_, String>(0)
+                Ok((row.get::<_, String>(0)?, row.get::<_, i32>(1)?))
             })
             .map_err(|e| e.to_string())?;
+        let mut opted_out = Vec::new();
         for user_row in user_rows {
-            let user_id = user_row.map_err(|e| e.to_string())?;
+            let (user_id, auto_join) = user_row.map_err(|e| e.to_string())?;
+            if auto_join == 0 {
+                opted_out.push(Uuid::parse_str(&user_id).map_err(|e| e.to_string())?);
+                continue;
+            }
             conn.execute(
                 "INSERT OR IGNORE INTO channel_users (channel_id, user_id) VALUES (?1, ?2)",
                 params![id.to_string(), user_id],
             )
             .ok();
         }
-        Ok(id)
+        Ok((id, opted_out))
     })
     .await
     .unwrap()
 }
 
+/// Store a user's channel message; `origin` is bridge provenance as JSON
 pub async fn db_create_channel_message(
     channel_id: Uuid,
     sent_by: Uuid,
     timestamp: i64,
     content: &str,
+    origin: Option<String>,
 ) -> Result<Uuid, String> {
     let channel_id = channel_id.to_string();
     let sent_by = sent_by.to_string();
     let content = content.to_string();
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let id = Uuid::new_v4();
         conn.execute(
-            "INSERT INTO channel_messages (id, channel_id, sent_by, timestamp, content) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id.to_string(), channel_id, sent_by, timestamp, content],
+            "INSERT INTO channel_messages (id, channel_id, sent_by, timestamp, content, origin) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.to_string(), channel_id, sent_by, timestamp, content, origin],
         )
         .map_err(|e| e.to_string())?;
         Ok(id)
@@ -68,37 +83,128 @@ pub async fn db_create_channel_message(
     .unwrap()
 }
 
+/// Store a message under an id chosen by the caller. Returns false, storing
+/// nothing, if a message with that id already exists.
+pub async fn db_create_channel_message_with_id(
+    id: Uuid,
+    channel_id: Uuid,
+    sent_by: Uuid,
+    timestamp: i64,
+    content: &str,
+    origin: Option<String>,
+) -> Result<bool, String> {
+    let id = id.to_string();
+    let channel_id = channel_id.to_string();
+    let sent_by = sent_by.to_string();
+    let content = content.to_string();
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO channel_messages (id, channel_id, sent_by, timestamp, content, origin) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, channel_id, sent_by, timestamp, content, origin],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(inserted > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Store a server-generated message; `system_event` is the structured event as JSON
+pub async fn db_create_system_channel_message(
+    channel_id: Uuid,
+    timestamp: i64,
+    content: &str,
+    system_event: &str,
+) -> Result<Uuid, String> {
+    let channel_id = channel_id.to_string();
+    let sent_by = crate::db::users::SYSTEM_USER_ID.to_string();
+    let content = content.to_string();
+    let system_event = system_event.to_string();
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let id = Uuid::new_v4();
+        conn.execute(
+            "INSERT INTO channel_messages (id, channel_id, sent_by, timestamp, content, system_event) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.to_string(), channel_id, sent_by, timestamp, content, system_event],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(id)
+    })
+    .await
+    .unwrap()
+}
+
+/// Reaction summary column for channel history queries: per emoji on the row's
+/// message, the count and whether `viewer_param` reacted, as a JSON array of
+/// `[emoji, count, reacted]`. A correlated subquery on the message_id index
+/// rather than a join against all reactions, so a page stays one statement
+/// that only touches its own messages' reactions.
+fn reaction_summary_column(viewer_param: &str) -> String {
+    format!(
+        "(SELECT json_group_array(json_array(emoji, n, mine)) FROM (
+             SELECT emoji, COUNT(*) AS n, MAX(user_id = {}) AS mine, MIN(created_at) AS first_at
+             FROM message_reactions WHERE message_id = channel_messages.id
+             GROUP BY emoji ORDER BY first_at
+         ))",
+        viewer_param
+    )
+}
+
+/// Parse the reaction summary column in `column`. Emoji keep the order they
+/// were first used in; a malformed summary is a row error.
+fn parse_reactions(json: Option<String>, column: usize) -> rusqlite::Result<Vec<ReactionSummary>> {
+    let Some(json) = json else {
+        return Ok(Vec::new());
+    };
+    let rows: Vec<(String, u32, u8)> = serde_json::from_str(&json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+    Ok(rows.into_iter()
+        .map(|(emoji, count, mine)| ReactionSummary { emoji, count, reacted: mine != 0 })
+        .collect())
+}
+
+/// A page of channel history before `before`, with reaction summaries as seen by `viewer_id`
 pub async fn db_get_channel_messages(
     channel_id: Uuid,
+    viewer_id: Uuid,
     before: Option<i64>,
+    limit: usize,
 ) -> Result<(Vec<ChannelMessage>, bool), String> {
     let channel_id_str = channel_id.to_string();
+    let viewer_id_str = viewer_id.to_string();
+    let limit = limit as i64;
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
         let mut messages: Vec<ChannelMessage> = Vec::new();
         
         // Use separate if/else blocks to avoid type conflicts
         if let Some(before_ts) = before {
-            let mut stmt = conn.prepare(
-                "SELECT id, sent_by, timestamp, content
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, sent_by, timestamp, content, system_event, origin, {}
                  FROM channel_messages
-                 WHERE channel_id = ? AND timestamp < ?
-                 ORDER BY timestamp DESC LIMIT 50"
-            ).map_err(|e| e.to_string())?;
+                 WHERE channel_id = ? AND timestamp < ? AND deleted = 0
+                 ORDER BY timestamp DESC LIMIT ?",
+                reaction_summary_column("?")
+            )).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![channel_id_str, before_ts], |row| {
+            let rows = stmt.query_map(params![viewer_id_str, channel_id_str, before_ts, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, i64>(2)?,
                     row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
                 ))
             }).map_err(|e| e.to_string())?;
 
             for row in rows {
-                let (id, sent_by, timestamp, content) = row.map_err(|e| e.to_string())?;
+                let (id, sent_by, timestamp, content, system_event, origin, reactions) = row.map_err(|e| e.to_string())?;
                 
                 messages.push(ChannelMessage {
                     id: Uuid::parse_str(&id).map_err(|e| e.to_string())?,
@@ -106,27 +212,36 @@ pub async fn db_get_channel_messages(
                     sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
                     timestamp,
                     content,
+                    system_event,
+                    origin,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: parse_reactions(reactions, 6).map_err(|e| e.to_string())?,
                 });
             }
         } else {
-            let mut stmt = conn.prepare(
-                "SELECT id, sent_by, timestamp, content
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, sent_by, timestamp, content, system_event, origin, {}
                  FROM channel_messages
-                 WHERE channel_id = ?
-                 ORDER BY timestamp DESC LIMIT 50"
-            ).map_err(|e| e.to_string())?;
+                 WHERE channel_id = ? AND deleted = 0
+                 ORDER BY timestamp DESC LIMIT ?",
+                reaction_summary_column("?")
+            )).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![channel_id_str], |row| {
+            let rows = stmt.query_map(params![viewer_id_str, channel_id_str, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, i64>(2)?,
                     row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
                 ))
             }).map_err(|e| e.to_string())?;
 
             for row in rows {
-                let (id, sent_by, timestamp, content) = row.map_err(|e| e.to_string())?;
+                let (id, sent_by, timestamp, content, system_event, origin, reactions) = row.map_err(|e| e.to_string())?;
                 
                 messages.push(ChannelMessage {
                     id: Uuid::parse_str(&id).map_err(|e| e.to_string())?,
@@ -134,6 +249,11 @@ pub async fn db_get_channel_messages(
                     sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
                     timestamp,
                     content,
+                    system_event,
+                    origin,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: parse_reactions(reactions, 6).map_err(|e| e.to_string())?,
                 });
             }
         }
@@ -143,7 +263,7 @@ pub async fn db_get_channel_messages(
         // Check if we've reached the oldest message
         let history_complete = if !messages.is_empty() {
             let oldest_ts = messages.first().unwrap().timestamp;
-            let mut min_stmt = conn.prepare("SELECT MIN(timestamp) FROM channel_messages WHERE channel_id = ?")
+            let mut min_stmt = conn.prepare("SELECT MIN(timestamp) FROM channel_messages WHERE channel_id = ? AND deleted = 0")
                 .map_err(|e| e.to_string())?;
             let min_ts: i64 = min_stmt.query_row(params![channel_id_str], |row| row.get(0))
                 .unwrap_or(oldest_ts);
@@ -163,10 +283,10 @@ pub async fn db_get_channel_user_list_lightweight(channel_id: Uuid) -> Result<Ve
     let channel_id_str = channel_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
-            "SELECT u.id, u.username, u.color, u.role 
+            "SELECT u.id, u.username, u.color, u.role, u.display_name
              FROM users u 
              JOIN channel_users cu ON u.id = cu.user_id 
              WHERE cu.channel_id = ?1 
@@ -176,12 +296,14 @@ pub async fn db_get_channel_user_list_lightweight(channel_id: Uuid) -> Result<Ve
         let user_rows = stmt.query_map(params![channel_id_str], |row| {
             let role_str: String = row.get(3)?;
             Ok(UserInfo {
-                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 username: row.get(1)?,
+                display_name: crate::db::users::visible_display_name(row.get(4)?),
                 color: parse_user_color(&row.get::<_, String>(2)?),
                 role: match role_str.as_str() {
                     "Admin" => UserRole::Admin,
                     "Moderator" => UserRole::Moderator,
+                    "Bot" => UserRole::Bot,
                     _ => UserRole::User,
                 },
                 status: UserStatus::Offline, // Default to offline, will be updated by server
@@ -203,7 +325,7 @@ pub async fn db_get_channel_user_list(channel_id: Uuid) -> Result<Vec<User>, Str
     let channel_id_str = channel_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
             "SELECT u.id, u.username, u.color, u.role, u.profile_pic, u.cover_banner 
@@ -215,12 +337,13 @@ pub async fn db_get_channel_user_list(channel_id: Uuid) -> Result<Vec<User>, Str
         let user_rows = stmt.query_map(params![channel_id_str], |row| {
             let role_str: String = row.get(3)?;
             Ok(User {
-                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 username: row.get(1)?,
                 color: parse_user_color(&row.get::<_, String>(2)?),
                 role: match role_str.as_str() {
                     "Admin" => UserRole::Admin,
                     "Moderator" => UserRole::Moderator,
+                    "Bot" => UserRole::Bot,
                     _ => UserRole::User,
                 },
                 profile_pic: row.get(4)?,
@@ -243,15 +366,16 @@ pub async fn db_get_channel_user_list(channel_id: Uuid) -> Result<Vec<User>, Str
 /// Enhanced channel message retrieval with optimized profile image handling
 pub async fn db_get_channel_messages_by_timestamp(
     channel_id: Uuid,
+    viewer_id: Uuid,
     before: Option<i64>,
     limit: usize,
     reverse_order: bool,
 ) -> Result<(Vec<ChannelMessage>, bool), String> {
     let channel_id_str = channel_id.to_string();
-    let limit = limit.min(200); // Safety limit
+    let viewer_id_str = viewer_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         let mut messages = Vec::new();
         
         if let Some(before_ts) = before {
@@ -259,25 +383,28 @@ pub async fn db_get_channel_messages_by_timestamp(
             let order = if reverse_order { "DESC" } else { "ASC" };
             
             let query = format!(
-                "SELECT id, sent_by, timestamp, content
+                "SELECT id, sent_by, timestamp, content, system_event, origin, {}
                  FROM channel_messages
-                 WHERE channel_id = ? AND timestamp {} ?
+                 WHERE channel_id = ? AND timestamp {} ? AND deleted = 0
                  ORDER BY timestamp {} LIMIT ?",
-                comparison, order
+                reaction_summary_column("?"), comparison, order
             );
             
             let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
-            let rows = stmt.query_map(params![channel_id_str, before_ts, limit + 1], |row| {
+            let rows = stmt.query_map(params![viewer_id_str, channel_id_str, before_ts, limit + 1], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, i64>(2)?,
                     row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
                 ))
             }).map_err(|e| e.to_string())?;
 
             for row in rows {
-                let (id, sent_by, timestamp, content) = 
+                let (id, sent_by, timestamp, content, system_event, origin, reactions) = 
                     row.map_err(|e| e.to_string())?;
                 
                 messages.push(ChannelMessage {
@@ -286,31 +413,39 @@ pub async fn db_get_channel_messages_by_timestamp(
                     sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
                     timestamp,
                     content,
+                    system_event,
+                    origin,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: parse_reactions(reactions, 6).map_err(|e| e.to_string())?,
                 });
             }
         } else {
             let order = if reverse_order { "DESC" } else { "ASC" };
             
             let query = format!(
-                "SELECT id, sent_by, timestamp, content
+                "SELECT id, sent_by, timestamp, content, system_event, origin, {}
                  FROM channel_messages
-                 WHERE channel_id = ?
+                 WHERE channel_id = ? AND deleted = 0
                  ORDER BY timestamp {} LIMIT ?",
-                order
+                reaction_summary_column("?"), order
             );
             
             let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
-            let rows = stmt.query_map(params![channel_id_str, limit + 1], |row| {
+            let rows = stmt.query_map(params![viewer_id_str, channel_id_str, limit + 1], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, i64>(2)?,
                     row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
                 ))
             }).map_err(|e| e.to_string())?;
 
             for row in rows {
-                let (id, sent_by, timestamp, content) = 
+                let (id, sent_by, timestamp, content, system_event, origin, reactions) = 
                     row.map_err(|e| e.to_string())?;
                 
                 messages.push(ChannelMessage {
@@ -319,6 +454,11 @@ pub async fn db_get_channel_messages_by_timestamp(
                     sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
                     timestamp,
                     content,
+                    system_event,
+                    origin,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: parse_reactions(reactions, 6).map_err(|e| e.to_string())?,
                 });
             }
         }
@@ -339,15 +479,78 @@ pub async fn db_get_channel_messages_by_timestamp(
     .unwrap()
 }
 
+/// Get up to `radius` messages on each side of a moment in a channel, oldest first,
+/// with whether more exist before and after the returned window
+pub async fn db_get_channel_messages_around(
+    channel_id: Uuid,
+    viewer_id: Uuid,
+    timestamp: i64,
+    radius: usize,
+) -> Result<(Vec<ChannelMessage>, bool, bool), String> {
+    let channel_id_str = channel_id.to_string();
+    let viewer_id_str = viewer_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let fetch = |query: &str| -> Result<Vec<ChannelMessage>, String> {
+            let mut stmt = conn.prepare(query).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map(params![channel_id_str, timestamp, radius + 1, viewer_id_str], |row| {
+                Ok(ChannelMessage {
+                    id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                    channel_id,
+                    sent_by: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                    timestamp: row.get(2)?,
+                    content: row.get(3)?,
+                    system_event: row.get(4)?,
+                    origin: row.get(5)?,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: parse_reactions(row.get(6)?, 6)?,
+                })
+            }).map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+        };
+        let reactions = reaction_summary_column("?4");
+
+        // Both halves use the (channel_id, timestamp) index
+        let mut before = fetch(&format!(
+            "SELECT id, sent_by, timestamp, content, system_event, origin, {}
+             FROM channel_messages
+             WHERE channel_id = ?1 AND timestamp < ?2 AND deleted = 0
+             ORDER BY timestamp DESC LIMIT ?3",
+            reactions
+        ))?;
+        let mut after = fetch(&format!(
+            "SELECT id, sent_by, timestamp, content, system_event, origin, {}
+             FROM channel_messages
+             WHERE channel_id = ?1 AND timestamp >= ?2 AND deleted = 0
+             ORDER BY timestamp ASC LIMIT ?3",
+            reactions
+        ))?;
+
+        let has_more_before = before.len() > radius;
+        before.truncate(radius);
+        let has_more_after = after.len() > radius;
+        after.truncate(radius);
+
+        before.reverse();
+        before.extend(after);
+        Ok((before, has_more_before, has_more_after))
+    })
+    .await
+    .unwrap()
+}
+
 /// Get total message count for a channel (for pagination metadata)
 pub async fn db_get_channel_message_count(channel_id: Uuid) -> Result<usize, String> {
     let channel_id_str = channel_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare(
-            "SELECT COUNT(*) FROM channel_messages WHERE channel_id = ?"
+            "SELECT COUNT(*) FROM channel_messages WHERE channel_id = ? AND deleted = 0"
         ).map_err(|e| e.to_string())?;
         
         let count: i64 = stmt.query_row(params![channel_id_str], |row| row.get(0))
@@ -364,7 +567,7 @@ pub async fn db_get_server_channels(server_id: Uuid) -> Result<Vec<Uuid>, String
     let server_id_str = server_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare(
             "SELECT id FROM channels WHERE server_id = ?"
@@ -372,7 +575,7 @@ pub async fn db_get_server_channels(server_id: Uuid) -> Result<Vec<Uuid>, String
         
         let rows = stmt.query_map(params![server_id_str], |row| {
             let id_str: String = row.get(0)?;
-            Ok(Uuid::parse_str(&id_str).unwrap())
+            parse_uuid_column(&id_str, 0)
         }).map_err(|e| e.to_string())?;
         
         let mut channel_ids = Vec::new();
@@ -392,7 +595,7 @@ pub async fn db_add_user_to_channel(channel_id: Uuid, user_id: Uuid) -> Result<(
     let user_id_str = user_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         conn.execute(
             "INSERT OR IGNORE INTO channel_users (channel_id, user_id) VALUES (?1, ?2)",
@@ -410,7 +613,7 @@ pub async fn db_get_users_sharing_channels_with(user_id: Uuid) -> Result<Vec<Uui
     let user_id_str = user_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare(
             "SELECT DISTINCT cu2.user_id 
@@ -421,7 +624,7 @@ pub async fn db_get_users_sharing_channels_with(user_id: Uuid) -> Result<Vec<Uui
         
         let rows = stmt.query_map(params![user_id_str, user_id_str], |row| {
             let id_str: String = row.get(0)?;
-            Ok(Uuid::parse_str(&id_str).unwrap())
+            parse_uuid_column(&id_str, 0)
         }).map_err(|e| e.to_string())?;
         
         let mut user_ids = Vec::new();
@@ -434,3 +637,1163 @@ pub async fn db_get_users_sharing_channels_with(user_id: Uuid) -> Result<Vec<Uui
     .await
     .unwrap()
 }
+
+/// For each of `user_ids`, the other users they share a channel with, in one
+/// query per batch of ids rather than one per user
+pub async fn db_get_users_sharing_channels_with_many(user_ids: Vec<Uuid>) -> Result<HashMap<Uuid, Vec<Uuid>>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let mut sharing: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+
+        for chunk in user_ids.chunks(500) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT cu1.user_id, cu2.user_id
+                 FROM channel_users cu1
+                 JOIN channel_users cu2 ON cu1.channel_id = cu2.channel_id
+                 WHERE cu1.user_id IN ({}) AND cu2.user_id != cu1.user_id",
+                placeholders
+            )).map_err(|e| e.to_string())?;
+
+            let rows = stmt.query_map(params_from_iter(chunk.iter().map(|id| id.to_string())), |row| {
+                Ok((
+                    parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                    parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                ))
+            }).map_err(|e| e.to_string())?;
+
+            for row in rows {
+                let (user_id, other_id) = row.map_err(|e| e.to_string())?;
+                sharing.entry(user_id).or_default().push(other_id);
+            }
+        }
+
+        Ok(sharing)
+    })
+    .await
+    .unwrap()
+}
+
+/// Count the channel messages a user has ever sent
+pub async fn db_count_user_channel_messages(user_id: Uuid) -> Result<usize, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM channel_messages WHERE sent_by = ?1",
+            params![user_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+
+        Ok(count as usize)
+    })
+    .await
+    .unwrap()
+}
+
+/// Change a channel's description and/or topic, recording who did it. `None`
+/// leaves a field as it is; `Some(None)` clears the topic.
+pub async fn db_update_channel_details(
+    channel_id: Uuid,
+    description: Option<String>,
+    topic: Option<Option<String>>,
+    updated_by: Uuid,
+) -> Result<(), String> {
+    let channel_id_str = channel_id.to_string();
+    let updated_by_str = updated_by.to_string();
+    let now = crate::util::now_secs();
+    let (set_topic, topic) = match topic {
+        Some(topic) => (true, topic),
+        None => (false, None),
+    };
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let updated = conn.execute(
+            "UPDATE channels SET
+                 description = COALESCE(?1, description),
+                 topic = CASE WHEN ?2 THEN ?3 ELSE topic END,
+                 updated_by = ?4,
+                 updated_at = ?5
+             WHERE id = ?6",
+            params![description, set_topic, topic, updated_by_str, now, channel_id_str],
+        ).map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("Channel not found".to_string());
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Everything a client shows in a channel's header, in one query
+pub async fn db_get_channel_info(channel_id: Uuid) -> Result<ChannelInfo, String> {
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT c.id, c.server_id, c.name, c.description, c.topic,
+                    (SELECT COUNT(*) FROM channel_users WHERE channel_id = c.id),
+                    c.created_at, c.updated_by, u.username, c.updated_at
+             FROM channels c
+             LEFT JOIN users u ON u.id = c.updated_by
+             WHERE c.id = ?1",
+            params![channel_id_str],
+            |row| {
+                Ok(ChannelInfo {
+                    id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                    server_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                    name: row.get(2)?,
+                    description: row.get(3)?,
+                    topic: row.get(4)?,
+                    member_count: row.get::<_, i64>(5)? as usize,
+                    created_at: row.get(6)?,
+                    updated_by: row.get::<_, Option<String>>(7)?
+                        .map(|id| parse_uuid_column(&id, 7))
+                        .transpose()?,
+                    updated_by_name: row.get(8)?,
+                    updated_at: row.get(9)?,
+                })
+            },
+        ).map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => "Channel not found".to_string(),
+            e => e.to_string(),
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Get the server a channel belongs to
+pub async fn db_get_channel_server_id(channel_id: Uuid) -> Result<Uuid, String> {
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let server_id: String = conn.query_row(
+            "SELECT server_id FROM channels WHERE id = ?1",
+            params![channel_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Channel not found".to_string())?;
+
+        Uuid::parse_str(&server_id).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Look up channels by (lowercased) name in the same server as `channel_id`,
+/// in one query. Returns (lowercased name, channel id) for the names that exist.
+pub async fn db_resolve_channel_names(channel_id: Uuid, names: Vec<String>) -> Result<Vec<(String, Uuid)>, String> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let placeholders = vec!["?"; names.len()].join(", ");
+        let query = format!(
+            "SELECT LOWER(name), id FROM channels
+             WHERE server_id = (SELECT server_id FROM channels WHERE id = ?)
+               AND LOWER(name) IN ({})",
+            placeholders
+        );
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let args = std::iter::once(channel_id_str).chain(names);
+        let rows = stmt.query_map(params_from_iter(args), |row| {
+            Ok((row.get::<_, String>(0)?, parse_uuid_column(&row.get::<_, String>(1)?, 1)?))
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Resolve a user's effective permission on a channel for the given column.
+/// Order: explicit user row > role rows (most permissive wins) > channel default.
+fn resolve_channel_permission(conn: &Connection, channel_id: &str, user_id: &str, column: &str) -> Result<bool, String> {
+    let query = format!(
+        "SELECT COALESCE(
+             (SELECT {col} FROM channel_permissions WHERE channel_id = ?1 AND user_id = ?2),
+             (SELECT MAX(COALESCE(rcp.{col}, r.{col}))
+              FROM server_user_roles sur
+              JOIN server_roles r ON r.id = sur.role_id
+              LEFT JOIN server_role_channel_perms rcp ON rcp.role_id = r.id AND rcp.channel_id = ?1
+              WHERE sur.user_id = ?2 AND r.server_id = (SELECT server_id FROM channels WHERE id = ?1)),
+             (SELECT default_{col} FROM channels WHERE id = ?1)
+         )",
+        col = column
+    );
+
+    let allowed: Option<i64> = conn.query_row(&query, params![channel_id, user_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    match allowed {
+        Some(value) => Ok(value != 0),
+        None => Err("Channel not found".to_string()),
+    }
+}
+
+/// Check whether a user may read a channel
+pub async fn db_can_user_read_channel(user_id: Uuid, channel_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        resolve_channel_permission(&conn, &channel_id_str, &user_id_str, "can_read")
+    })
+    .await
+    .unwrap()
+}
+
+/// For each user, the channels among `channel_ids` they may read
+pub async fn db_get_readable_channels(
+    user_ids: Vec<Uuid>,
+    channel_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, Vec<Uuid>>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut readable = HashMap::new();
+        for user_id in user_ids {
+            let user_id_str = user_id.to_string();
+            let mut channels = Vec::new();
+            for channel_id in &channel_ids {
+                if resolve_channel_permission(&conn, &channel_id.to_string(), &user_id_str, "can_read")? {
+                    channels.push(*channel_id);
+                }
+            }
+            readable.insert(user_id, channels);
+        }
+        Ok(readable)
+    })
+    .await
+    .unwrap()
+}
+
+/// Check whether a user may write to a channel
+pub async fn db_can_user_write_channel(user_id: Uuid, channel_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        resolve_channel_permission(&conn, &channel_id_str, &user_id_str, "can_write")
+    })
+    .await
+    .unwrap()
+}
+
+/// Check whether a channel exists
+pub async fn db_channel_exists(channel_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("channels", channel_id).await
+}
+
+/// Get a channel's link policy: whether links are allowed at all, and the
+/// optional domain allowlist (None means any domain)
+pub async fn db_get_channel_link_policy(channel_id: Uuid) -> Result<(bool, Option<Vec<String>>), String> {
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let (links_allowed, allowlist_json): (i32, Option<String>) = conn.query_row(
+            "SELECT links_allowed, link_allowlist FROM channels WHERE id = ?1",
+            params![channel_id_str],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).map_err(|_| "Channel not found".to_string())?;
+
+        let allowlist = match allowlist_json {
+            Some(json) => Some(serde_json::from_str::<Vec<String>>(&json).map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        Ok((links_allowed != 0, allowlist))
+    })
+    .await
+    .unwrap()
+}
+
+/// Set a channel's link policy
+pub async fn db_set_channel_link_policy(
+    channel_id: Uuid,
+    links_allowed: bool,
+    allowlist: Option<Vec<String>>,
+) -> Result<(), String> {
+    let channel_id_str = channel_id.to_string();
+    let allowlist_json = match allowlist {
+        Some(domains) => Some(serde_json::to_string(&domains).map_err(|e| e.to_string())?),
+        None => None,
+    };
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE channels SET links_allowed = ?1, link_allowlist = ?2 WHERE id = ?3",
+            params![links_allowed as i32, allowlist_json, channel_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get a single channel message, live or tombstoned, with its deletion time if any
+pub async fn db_get_channel_message(message_id: Uuid) -> Result<(ChannelMessage, Option<i64>), String> {
+    let message_id_str = message_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        conn.query_row(
+            "SELECT channel_id, sent_by, timestamp, content, system_event, origin, deleted_at
+             FROM channel_messages WHERE id = ?1",
+            params![message_id_str],
+            |row| {
+                let message = ChannelMessage {
+                    id: message_id,
+                    channel_id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                    sent_by: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                    timestamp: row.get(2)?,
+                    content: row.get(3)?,
+                    system_event: row.get(4)?,
+                    origin: row.get(5)?,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: Vec::new(),
+                };
+                Ok((message, row.get::<_, Option<i64>>(6)?))
+            },
+        ).map_err(|_| "Message not found".to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Mark a channel message deleted, keeping the row until the grace window ends.
+/// Returns false if it was already deleted.
+pub async fn db_tombstone_channel_message(message_id: Uuid, deleted_by: Uuid, deleted_at: i64) -> Result<bool, String> {
+    let message_id_str = message_id.to_string();
+    let deleted_by_str = deleted_by.to_string();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let updated = tx.execute(
+            "UPDATE channel_messages SET deleted = 1, deleted_at = ?2, deleted_by = ?3
+             WHERE id = ?1 AND deleted = 0",
+            params![message_id_str, deleted_at, deleted_by_str],
+        ).map_err(|e| e.to_string())?;
+        // A deleted message has nothing left to review
+        if updated > 0 {
+            tx.execute("DELETE FROM flagged_messages WHERE message_id = ?1", params![message_id_str])
+                .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(updated > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Result of an attempt to edit a channel message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelMessageEdit {
+    Edited { channel_id: Uuid, sent_by: Uuid, edited_at: i64, previous_len: usize },
+    /// Missing or deleted
+    NotFound,
+    /// The editor is neither the author nor an Admin/Moderator
+    NotAllowed,
+    /// Already edited `[messages] max_edits_per_message` times
+    LimitReached(u32),
+}
+
+/// Replace a channel message's content. Only its author or an Admin/Moderator
+/// may edit it; `edit_count` is bumped in the same UPDATE so concurrent edits
+/// can't overshoot the configured cap.
+pub async fn db_edit_channel_message(message_id: Uuid, editor_id: Uuid, new_content: &str) -> Result<ChannelMessageEdit, String> {
+    let message_id_str = message_id.to_string();
+    let editor_id_str = editor_id.to_string();
+    let new_content = new_content.to_string();
+    let max_edits = crate::config::settings().messages.max_edits_per_message;
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let row = tx.query_row(
+            "SELECT channel_id, sent_by, content FROM channel_messages WHERE id = ?1 AND deleted = 0",
+            params![message_id_str],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)),
+        );
+        let (channel_id_str, sent_by_str, previous_content) = match row {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(ChannelMessageEdit::NotFound),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if sent_by_str != editor_id_str {
+            let is_staff: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM users WHERE id = ?1 AND role IN ('Admin', 'Moderator')",
+                params![editor_id_str],
+                |row| row.get(0),
+            ).map_err(|e| e.to_string())?;
+            if is_staff == 0 {
+                return Ok(ChannelMessageEdit::NotAllowed);
+            }
+        }
+
+        let edited_at = crate::util::now_secs();
+        let updated = tx.execute(
+            "UPDATE channel_messages SET content = ?2, edited_at = ?3, edit_count = edit_count + 1
+             WHERE id = ?1 AND deleted = 0 AND (?4 = 0 OR edit_count < ?4)",
+            params![message_id_str, new_content, edited_at, max_edits],
+        ).map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Ok(ChannelMessageEdit::LimitReached(max_edits));
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+
+        Ok(ChannelMessageEdit::Edited {
+            channel_id: Uuid::parse_str(&channel_id_str).map_err(|e| e.to_string())?,
+            sent_by: Uuid::parse_str(&sent_by_str).map_err(|e| e.to_string())?,
+            edited_at,
+            previous_len: previous_content.len(),
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Bring back a tombstoned channel message. Returns false if it wasn't deleted.
+pub async fn db_restore_channel_message(message_id: Uuid) -> Result<bool, String> {
+    let message_id_str = message_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let updated = conn.execute(
+            "UPDATE channel_messages SET deleted = 0, deleted_at = NULL, deleted_by = NULL
+             WHERE id = ?1 AND deleted = 1",
+            params![message_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(updated > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Hard-delete channel messages tombstoned before `cutoff`. Returns how many were removed.
+pub async fn db_purge_channel_message_tombstones(cutoff: i64) -> Result<usize, String> {
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        for dependent in ["message_reactions", "flagged_messages"] {
+            conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE message_id IN (
+                         SELECT id FROM channel_messages WHERE deleted = 1 AND deleted_at < ?1
+                     )",
+                    dependent
+                ),
+                params![cutoff],
+            ).map_err(|e| e.to_string())?;
+        }
+        conn.execute(
+            "DELETE FROM channel_messages WHERE deleted = 1 AND deleted_at < ?1",
+            params![cutoff],
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Oldest live channel messages sent before `cutoff`, across all channels
+pub async fn db_get_channel_messages_before(cutoff: i64, limit: usize) -> Result<Vec<ChannelMessage>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, channel_id, sent_by, timestamp, content, system_event, origin
+             FROM channel_messages
+             WHERE timestamp < ?1 AND deleted = 0
+             ORDER BY timestamp ASC LIMIT ?2"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![cutoff, limit], |row| {
+            Ok(ChannelMessage {
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                channel_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                sent_by: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
+                timestamp: row.get(3)?,
+                content: row.get(4)?,
+                system_event: row.get(5)?,
+                origin: row.get(6)?,
+                channel_refs: Vec::new(),
+                emojis: Vec::new(),
+                reactions: Vec::new(),
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Hard-delete channel messages by id. Returns how many were removed.
+pub async fn db_delete_channel_messages(message_ids: Vec<Uuid>) -> Result<usize, String> {
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let mut deleted = 0;
+        {
+            let mut reactions = tx.prepare("DELETE FROM message_reactions WHERE message_id = ?1").map_err(|e| e.to_string())?;
+            let mut flags = tx.prepare("DELETE FROM flagged_messages WHERE message_id = ?1").map_err(|e| e.to_string())?;
+            let mut stmt = tx.prepare("DELETE FROM channel_messages WHERE id = ?1").map_err(|e| e.to_string())?;
+            for message_id in &message_ids {
+                reactions.execute(params![message_id.to_string()]).map_err(|e| e.to_string())?;
+                flags.execute(params![message_id.to_string()]).map_err(|e| e.to_string())?;
+                deleted += stmt.execute(params![message_id.to_string()]).map_err(|e| e.to_string())?;
+            }
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(deleted)
+    })
+    .await
+    .unwrap()
+}
+
+/// One chunk of the channel message integrity scan
+#[derive(Debug, Clone, Default)]
+pub struct OrphanScanChunk {
+    /// Rowid to continue after; None once the table is exhausted
+    pub last_rowid: Option<i64>,
+    pub scanned: usize,
+    /// Messages whose author doesn't exist (in an existing channel)
+    pub orphaned_authors: usize,
+    /// Messages whose channel doesn't exist
+    pub orphaned_channels: usize,
+}
+
+/// Scan up to `limit` channel messages after `after_rowid` for authors or
+/// channels that don't exist (including ids that aren't valid UUIDs). With
+/// `repair`, orphaned authors are reassigned to the System user and messages
+/// in missing channels are moved to `orphaned_channel_messages`.
+pub async fn db_scan_orphaned_messages(after_rowid: i64, limit: usize, repair: bool) -> Result<OrphanScanChunk, String> {
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let rows: Vec<(i64, bool, bool)> = {
+            let mut stmt = tx.prepare(
+                "SELECT m.rowid, u.id IS NULL, c.id IS NULL
+                 FROM channel_messages m
+                 LEFT JOIN users u ON u.id = m.sent_by
+                 LEFT JOIN channels c ON c.id = m.channel_id
+                 WHERE m.rowid > ?1
+                 ORDER BY m.rowid LIMIT ?2"
+            ).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map(params![after_rowid, limit], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+        };
+
+        let mut chunk = OrphanScanChunk {
+            last_rowid: rows.last().map(|(rowid, _, _)| *rowid),
+            scanned: rows.len(),
+            ..Default::default()
+        };
+        let now = crate::util::now_secs();
+        let system_user = crate::db::users::SYSTEM_USER_ID.to_string();
+        for (rowid, missing_author, missing_channel) in rows {
+            if missing_channel {
+                chunk.orphaned_channels += 1;
+                if repair {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO orphaned_channel_messages
+                             (id, channel_id, sent_by, timestamp, content, system_event, origin, moved_at)
+                         SELECT id, channel_id, sent_by, timestamp, content, system_event, origin, ?2
+                         FROM channel_messages WHERE rowid = ?1",
+                        params![rowid, now],
+                    ).map_err(|e| e.to_string())?;
+                    tx.execute(
+                        "DELETE FROM message_reactions WHERE message_id = (SELECT id FROM channel_messages WHERE rowid = ?1)",
+                        params![rowid],
+                    ).map_err(|e| e.to_string())?;
+                    tx.execute("DELETE FROM channel_messages WHERE rowid = ?1", params![rowid])
+                        .map_err(|e| e.to_string())?;
+                }
+            } else if missing_author {
+                chunk.orphaned_authors += 1;
+                if repair {
+                    tx.execute(
+                        "UPDATE channel_messages SET sent_by = ?2 WHERE rowid = ?1",
+                        params![rowid, system_user],
+                    ).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(chunk)
+    })
+    .await
+    .unwrap()
+}
+
+/// Follow or unfollow a channel
+pub async fn db_set_channel_follow(channel_id: Uuid, user_id: Uuid, follow: bool) -> Result<(), String> {
+    let channel_id_str = channel_id.to_string();
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        if follow {
+            conn.execute(
+                "INSERT OR IGNORE INTO channel_followers (channel_id, user_id, created_at) VALUES (?1, ?2, ?3)",
+                params![channel_id_str, user_id_str, crate::util::now_secs()],
+            ).map_err(|e| e.to_string())?;
+        } else {
+            conn.execute(
+                "DELETE FROM channel_followers WHERE channel_id = ?1 AND user_id = ?2",
+                params![channel_id_str, user_id_str],
+            ).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Users to notify of every message in a channel: its followers who are
+/// still server members and can read it, if the channel's policy notifies
+/// followers at all
+pub async fn db_get_channel_followers_to_notify(channel_id: Uuid) -> Result<Vec<Uuid>, String> {
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT f.user_id FROM channel_followers f
+             JOIN channels c ON c.id = f.channel_id
+             JOIN server_users su ON su.server_id = c.server_id AND su.user_id = f.user_id
+             WHERE f.channel_id = ?1 AND c.notify_followers = 1"
+        ).map_err(|e| e.to_string())?;
+        let followers = stmt.query_map(params![channel_id_str], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let mut readers = Vec::with_capacity(followers.len());
+        for user_id in followers {
+            if resolve_channel_permission(&conn, &channel_id_str, &user_id, "can_read")? {
+                readers.push(Uuid::parse_str(&user_id).map_err(|e| e.to_string())?);
+            }
+        }
+        Ok(readers)
+    })
+    .await
+    .unwrap()
+}
+
+/// Turn follower notifications on or off for a channel
+pub async fn db_set_channel_notify_followers(channel_id: Uuid, enabled: bool) -> Result<(), String> {
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE channels SET notify_followers = ?1 WHERE id = ?2",
+            params![enabled as i32, channel_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A bookmark joined with its message. The message fields are None once the
+/// message has been purged; `deleted` is set while it is only tombstoned.
+#[derive(Debug, Clone)]
+pub struct BookmarkRow {
+    pub message_id: Uuid,
+    pub created_at: i64,
+    pub note: Option<String>,
+    pub channel_id: Option<Uuid>,
+    pub channel_name: Option<String>,
+    pub sent_by: Option<Uuid>,
+    pub timestamp: Option<i64>,
+    pub content: Option<String>,
+    pub deleted: bool,
+}
+
+/// Bookmark a message, or update the note of an existing bookmark. New
+/// bookmarks fail once the user has `max_bookmarks`.
+pub async fn db_add_bookmark(
+    user_id: Uuid,
+    message_id: Uuid,
+    note: Option<String>,
+    max_bookmarks: usize,
+) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let message_id_str = message_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let updated = tx.execute(
+            "UPDATE message_bookmarks SET note = ?1 WHERE user_id = ?2 AND message_id = ?3",
+            params![note, user_id_str, message_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        if updated == 0 {
+            let count: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM message_bookmarks WHERE user_id = ?1",
+                params![user_id_str],
+                |row| row.get(0),
+            ).map_err(|e| e.to_string())?;
+            if count as usize >= max_bookmarks {
+                return Err(format!("You can keep at most {} bookmarks", max_bookmarks));
+            }
+
+            tx.execute(
+                "INSERT INTO message_bookmarks (user_id, message_id, created_at, note) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id_str, message_id_str, now, note],
+            ).map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove a bookmark, returning false if the user didn't have it
+pub async fn db_remove_bookmark(user_id: Uuid, message_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let message_id_str = message_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let removed = conn.execute(
+            "DELETE FROM message_bookmarks WHERE user_id = ?1 AND message_id = ?2",
+            params![user_id_str, message_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(removed > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// A page of the user's bookmarks, newest first. `before` is the
+/// (created_at, message_id) of the last bookmark of the previous page, so
+/// bookmarks made in the same second are neither skipped nor repeated.
+pub async fn db_get_bookmarks(user_id: Uuid, before: Option<(i64, Uuid)>, limit: usize) -> Result<Vec<BookmarkRow>, String> {
+    let user_id_str = user_id.to_string();
+    let before_ts = before.map(|(created_at, _)| created_at);
+    let before_id = before.map(|(_, message_id)| message_id.to_string());
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT b.message_id, b.created_at, b.note, m.channel_id, c.name, m.sent_by, m.timestamp, m.content, m.deleted
+             FROM message_bookmarks b
+             LEFT JOIN channel_messages m ON m.id = b.message_id
+             LEFT JOIN channels c ON c.id = m.channel_id
+             WHERE b.user_id = ?1
+               AND (?2 IS NULL OR b.created_at < ?2 OR (b.created_at = ?2 AND b.message_id < ?3))
+             ORDER BY b.created_at DESC, b.message_id DESC
+             LIMIT ?4"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![user_id_str, before_ts, before_id, limit as i64], |row| {
+            Ok(BookmarkRow {
+                message_id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                created_at: row.get(1)?,
+                note: row.get(2)?,
+                channel_id: row.get::<_, Option<String>>(3)?
+                    .map(|id| parse_uuid_column(&id, 3))
+                    .transpose()?,
+                channel_name: row.get(4)?,
+                sent_by: row.get::<_, Option<String>>(5)?
+                    .map(|id| parse_uuid_column(&id, 5))
+                    .transpose()?,
+                timestamp: row.get(6)?,
+                content: row.get(7)?,
+                deleted: row.get::<_, Option<i32>>(8)?.unwrap_or(0) != 0,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Mark everything in a channel up to now as read by a member
+pub async fn db_mark_channel_read(user_id: Uuid, channel_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let channel_id_str = channel_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let updated = conn.execute(
+            "UPDATE channel_users SET last_read_at = ?1 WHERE channel_id = ?2 AND user_id = ?3",
+            params![now, channel_id_str, user_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(updated > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// The user's channels with a message newer than `since`, most recently
+/// active first, each with its latest message and how many messages from
+/// others arrived after the user last read it. Both lookups per channel are
+/// range scans on the (channel_id, timestamp) index.
+pub async fn db_get_active_channels_for_user(user_id: Uuid, since: i64, limit: usize) -> Result<Vec<ActiveChannel>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.server_id, c.name, m.sent_by, m.content, m.timestamp,
+                    (SELECT COUNT(*) FROM channel_messages u
+                     WHERE u.channel_id = cu.channel_id
+                       AND u.timestamp > COALESCE(cu.last_read_at, 0)
+                       AND u.deleted = 0
+                       AND u.sent_by != ?1)
+             FROM channel_users cu
+             JOIN channels c ON c.id = cu.channel_id
+             JOIN channel_messages m ON m.id = (
+                 SELECT id FROM channel_messages
+                 WHERE channel_id = cu.channel_id AND deleted = 0
+                 ORDER BY timestamp DESC
+                 LIMIT 1
+             )
+             WHERE cu.user_id = ?1 AND m.timestamp > ?2
+             ORDER BY m.timestamp DESC
+             LIMIT ?3"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![user_id_str, since, limit as i64], |row| {
+            Ok(ActiveChannel {
+                channel_id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                server_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+                name: row.get(2)?,
+                last_message_by: parse_uuid_column(&row.get::<_, String>(3)?, 3)?,
+                last_message_preview: row.get(4)?,
+                last_message_at: row.get(5)?,
+                unread_count: row.get::<_, i64>(6)? as u32,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::server_roles;
+    use crate::test_support::{self, TestDb};
+
+    /// Which write permission `user_id` ends up with on `channel_id`
+    fn can_write(db: &TestDb, user_id: Uuid, channel_id: Uuid) -> bool {
+        let conn = Connection::open(db.path()).unwrap();
+        resolve_channel_permission(&conn, &channel_id.to_string(), &user_id.to_string(), "can_write").unwrap()
+    }
+
+    fn set_channel_default(db: &TestDb, channel_id: Uuid, allowed: bool) {
+        let conn = Connection::open(db.path()).unwrap();
+        conn.execute(
+            "UPDATE channels SET default_can_write = ?1 WHERE id = ?2",
+            params![allowed, channel_id.to_string()],
+        ).unwrap();
+    }
+
+    fn set_user_override(db: &TestDb, user_id: Uuid, channel_id: Uuid, allowed: bool) {
+        let conn = Connection::open(db.path()).unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO channel_permissions (channel_id, user_id, can_read, can_write) VALUES (?1, ?2, 1, ?3)",
+            params![channel_id.to_string(), user_id.to_string(), allowed],
+        ).unwrap();
+    }
+
+    async fn give_role(server_id: Uuid, user_id: Uuid, can_write: bool) -> Uuid {
+        let name = if can_write { "writers" } else { "readers" };
+        let role_id = server_roles::db_create_server_role(server_id, name, true, can_write).await.unwrap();
+        server_roles::db_assign_server_role(role_id, user_id).await.unwrap();
+        role_id
+    }
+
+    /// A server owned by someone else with `bob` as a plain member of one channel
+    async fn member_channel() -> (Uuid, Uuid, Uuid) {
+        let owner = test_support::create_user("owner").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        (server_id, bob.id, channel_id)
+    }
+
+    #[tokio::test]
+    async fn only_members_who_can_read_are_notified_as_followers() {
+        let db = TestDb::new().await;
+        let (server_id, bob_id, channel_id) = member_channel().await;
+        let carol = test_support::create_user("carol").await;
+        let dave = test_support::create_user("dave").await;
+        test_support::join_server(server_id, carol.id).await;
+        test_support::join_server(server_id, dave.id).await;
+        for user_id in [bob_id, carol.id, dave.id] {
+            db_set_channel_follow(channel_id, user_id, true).await.unwrap();
+        }
+        db_set_channel_notify_followers(channel_id, true).await.unwrap();
+
+        crate::db::servers::db_remove_user_from_server(server_id, bob_id).await.unwrap();
+        Connection::open(db.path()).unwrap().execute(
+            "INSERT INTO channel_permissions (channel_id, user_id, can_read, can_write) VALUES (?1, ?2, 0, 0)",
+            params![channel_id.to_string(), carol.id.to_string()],
+        ).unwrap();
+
+        assert_eq!(db_get_channel_followers_to_notify(channel_id).await.unwrap(), vec![dave.id]);
+        // Leaving the server takes the follow with it
+        assert_eq!(db.count_rows("channel_followers"), 2);
+    }
+
+    #[tokio::test]
+    async fn channel_default_applies_without_roles_or_overrides() {
+        let db = TestDb::new().await;
+        let (_, bob, channel_id) = member_channel().await;
+        assert!(can_write(&db, bob, channel_id));
+
+        set_channel_default(&db, channel_id, false);
+        assert!(!can_write(&db, bob, channel_id));
+    }
+
+    #[tokio::test]
+    async fn role_beats_channel_default() {
+        let db = TestDb::new().await;
+        let (server_id, bob, channel_id) = member_channel().await;
+        set_channel_default(&db, channel_id, false);
+
+        give_role(server_id, bob, true).await;
+        assert!(can_write(&db, bob, channel_id));
+    }
+
+    #[tokio::test]
+    async fn most_permissive_role_wins() {
+        let db = TestDb::new().await;
+        let (server_id, bob, channel_id) = member_channel().await;
+
+        give_role(server_id, bob, false).await;
+        assert!(!can_write(&db, bob, channel_id));
+
+        give_role(server_id, bob, true).await;
+        assert!(can_write(&db, bob, channel_id));
+    }
+
+    #[tokio::test]
+    async fn role_channel_override_beats_the_roles_own_setting() {
+        let db = TestDb::new().await;
+        let (server_id, bob, channel_id) = member_channel().await;
+
+        let role_id = give_role(server_id, bob, true).await;
+        server_roles::db_set_role_channel_permission(role_id, channel_id, true, false).await.unwrap();
+        assert!(!can_write(&db, bob, channel_id));
+    }
+
+    #[tokio::test]
+    async fn user_override_beats_roles_and_default() {
+        let db = TestDb::new().await;
+        let (server_id, bob, channel_id) = member_channel().await;
+
+        give_role(server_id, bob, true).await;
+        set_user_override(&db, bob, channel_id, false);
+        assert!(!can_write(&db, bob, channel_id));
+
+        set_channel_default(&db, channel_id, false);
+        set_user_override(&db, bob, channel_id, true);
+        assert!(can_write(&db, bob, channel_id));
+    }
+
+    #[tokio::test]
+    async fn unknown_channel_is_an_error() {
+        let db = TestDb::new().await;
+        let bob = test_support::create_user("bob").await;
+        let conn = Connection::open(db.path()).unwrap();
+
+        let result = resolve_channel_permission(&conn, &Uuid::new_v4().to_string(), &bob.id.to_string(), "can_write");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn active_channels_are_the_recent_ones_newest_first() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let quiet = test_support::create_channel(server_id, "quiet").await;
+        let stale = test_support::create_channel(server_id, "stale").await;
+        let earlier = test_support::create_channel(server_id, "earlier").await;
+        let latest = test_support::create_channel(server_id, "latest").await;
+        let elsewhere_server = test_support::create_server(&bob, "Elsewhere").await;
+        let elsewhere = test_support::create_channel(elsewhere_server, "elsewhere").await;
+
+        let now = crate::util::now_secs();
+        db_create_channel_message(stale, bob.id, now - 10_000, "long ago", None).await.unwrap();
+        db_create_channel_message(earlier, bob.id, now - 200, "first", None).await.unwrap();
+        db_create_channel_message(earlier, alice.id, now - 100, "reply", None).await.unwrap();
+        db_create_channel_message(latest, bob.id, now - 50, "newest", None).await.unwrap();
+        db_create_channel_message(elsewhere, bob.id, now - 10, "not for alice", None).await.unwrap();
+
+        let active = db_get_active_channels_for_user(alice.id, now - 1000, 50).await.unwrap();
+        let ids: Vec<Uuid> = active.iter().map(|channel| channel.channel_id).collect();
+        assert_eq!(ids, vec![latest, earlier]);
+        assert!(!ids.contains(&quiet));
+        assert_eq!(active[0].last_message_preview, "newest");
+        assert_eq!(active[0].unread_count, 1);
+        // Alice's own reply is the latest in `earlier` and isn't unread
+        assert_eq!(active[1].last_message_by, alice.id);
+        assert_eq!(active[1].unread_count, 1);
+
+        db_mark_channel_read(alice.id, latest).await.unwrap();
+        let active = db_get_active_channels_for_user(alice.id, now - 1000, 50).await.unwrap();
+        assert_eq!(active[0].unread_count, 0);
+    }
+
+    /// Add a reaction straight to the table; nothing in the protocol adds them yet
+    fn react(db: &TestDb, message_id: Uuid, user_id: Uuid, emoji: &str, created_at: i64) {
+        Connection::open(db.path()).unwrap().execute(
+            "INSERT INTO message_reactions (message_id, user_id, emoji, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![message_id.to_string(), user_id.to_string(), emoji, created_at],
+        ).unwrap();
+    }
+
+    /// A page's reactions as (emoji, count, reacted) per message, newest message first
+    fn reactions(page: &[ChannelMessage]) -> Vec<Vec<(String, u32, bool)>> {
+        page.iter()
+            .map(|message| message.reactions.iter().map(|r| (r.emoji.clone(), r.count, r.reacted)).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reaction_summaries_hold_across_a_page_boundary() {
+        let db = TestDb::new().await;
+        let (server_id, bob, channel_id) = member_channel().await;
+        let carol = test_support::create_user("carol").await;
+        test_support::join_server(server_id, carol.id).await;
+        let mut ids = Vec::new();
+        for timestamp in 100..104 {
+            ids.push(db_create_channel_message(channel_id, carol.id, timestamp, "msg", None).await.unwrap());
+        }
+        // Newest message: two thumbs up, bob's among them, then a party
+        react(&db, ids[3], carol.id, "👍", 1);
+        react(&db, ids[3], bob, "👍", 2);
+        react(&db, ids[3], carol.id, "🎉", 3);
+        // On the second page: a party before a thumbs up, neither by bob
+        react(&db, ids[1], carol.id, "🎉", 4);
+        react(&db, ids[1], carol.id, "👍", 5);
+
+        let (first, _) = db_get_channel_messages(channel_id, bob, None, 2).await.unwrap();
+        assert_eq!(first.iter().map(|message| message.id).collect::<Vec<_>>(), vec![ids[3], ids[2]]);
+        assert_eq!(reactions(&first), vec![
+            vec![("👍".to_string(), 2, true), ("🎉".to_string(), 1, false)],
+            vec![],
+        ]);
+
+        let (second, _) = db_get_channel_messages(channel_id, bob, Some(first[1].timestamp), 2).await.unwrap();
+        assert_eq!(second.iter().map(|message| message.id).collect::<Vec<_>>(), vec![ids[1], ids[0]]);
+        assert_eq!(reactions(&second), vec![
+            vec![("🎉".to_string(), 1, false), ("👍".to_string(), 1, false)],
+            vec![],
+        ]);
+
+        // The flag follows the viewer
+        let (as_carol, _) = db_get_channel_messages(channel_id, carol.id, None, 1).await.unwrap();
+        assert_eq!(reactions(&as_carol), vec![vec![("👍".to_string(), 2, true), ("🎉".to_string(), 1, true)]]);
+    }
+
+    #[tokio::test]
+    async fn the_edit_past_the_cap_is_rejected() {
+        let _db = TestDb::new().await;
+        let (_, bob, channel_id) = member_channel().await;
+        let message_id = db_create_channel_message(channel_id, bob, crate::util::now_secs(), "draft", None).await.unwrap();
+        let max_edits = crate::config::settings().messages.max_edits_per_message;
+
+        for edit in 1..=max_edits {
+            let result = db_edit_channel_message(message_id, bob, &format!("edit {}", edit)).await.unwrap();
+            assert!(matches!(result, ChannelMessageEdit::Edited { .. }), "edit {} got {:?}", edit, result);
+        }
+        let result = db_edit_channel_message(message_id, bob, "one too many").await.unwrap();
+        assert_eq!(result, ChannelMessageEdit::LimitReached(max_edits));
+
+        let (message, _) = db_get_channel_message(message_id).await.unwrap();
+        assert_eq!(message.content, format!("edit {}", max_edits));
+    }
+
+    #[tokio::test]
+    async fn bookmarks_made_in_the_same_second_page_without_gaps() {
+        let db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        for _ in 0..5 {
+            db_add_bookmark(alice.id, Uuid::new_v4(), None, 10).await.unwrap();
+        }
+        Connection::open(db.path()).unwrap().execute("UPDATE message_bookmarks SET created_at = 1000", []).unwrap();
+
+        let mut seen = Vec::new();
+        let mut before = None;
+        loop {
+            let page = db_get_bookmarks(alice.id, before, 2).await.unwrap();
+            seen.extend(page.iter().map(|row| row.message_id));
+            if page.len() < 2 {
+                break;
+            }
+            before = page.last().map(|row| (row.created_at, row.message_id));
+        }
+
+        assert_eq!(seen.len(), 5);
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 5);
+    }
+
+    #[test]
+    fn a_malformed_reaction_summary_is_a_row_error() {
+        let parsed = parse_reactions(Some(r#"[["👍",2,1],["🎉",1,0]]"#.to_string()), 6).unwrap();
+        let parsed: Vec<_> = parsed.iter().map(|r| (r.emoji.as_str(), r.count, r.reacted)).collect();
+        assert_eq!(parsed, vec![("👍", 2, true), ("🎉", 1, false)]);
+        assert!(parse_reactions(None, 6).unwrap().is_empty());
+
+        let result = parse_reactions(Some(r#"[["👍","two",1]]"#.to_string()), 6);
+        assert!(matches!(result, Err(rusqlite::Error::FromSqlConversionFailure(6, _, _))));
+    }
+}
@@ -1,20 +1,42 @@
 use once_cell::sync::OnceCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
+use tracing::warn;
+
+const FALLBACK_DB_PATH: &str = "nexus.db";
 
 /// Global database path configuration
 static DB_CONFIG: OnceCell<RwLock<String>> = OnceCell::new();
 
+/// Whether the fallback warning has already been logged
+static FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
 /// Initialize the global database path
 pub fn init_db_path(path: String) {
     DB_CONFIG.set(RwLock::new(path)).ok();
 }
 
+/// Whether `init_db_path` (or `set_db_path`) has been called
+pub fn is_initialized() -> bool {
+    DB_CONFIG.get().is_some()
+}
+
 /// Get the current database path
 pub fn get_db_path() -> String {
-    DB_CONFIG
-        .get()
-        .map(|config| config.read().unwrap().clone())
-        .unwrap_or_else(|| "nexus.db".to_string()) // fallback to default
+    match DB_CONFIG.get() {
+        Some(config) => config.read().unwrap().clone(),
+        None => {
+            // Falling back means something touched the database before startup
+            // configured it, so it may not be the file the config points at
+            if !FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Database path requested before init_db_path; falling back to {}",
+                    FALLBACK_DB_PATH
+                );
+            }
+            FALLBACK_DB_PATH.to_string()
+        }
+    }
 }
 
 /// Update the database path at runtime (if needed)
@@ -1,14 +1,22 @@
-use crate::db::db_config;
-use crate::util::parse_user_color;
-use nexus_tui_common::{Forum, Thread, Post, User, UserRole, UserStatus, UserInfo, ForumLightweight, ThreadLightweight, PostLightweight};
-use rusqlite::{params, Connection};
+use crate::config::{ForumConfig, ReplyDepthPolicy};
+use crate::db::{get_conn, get_read_conn};
+use crate::util::{parse_role, parse_user_color};
+use nexus_tui_common::{Forum, Thread, Post, User, UserRole, UserStatus, UserInfo, ForumLightweight, ThreadLightweight, PostLightweight, TrendingThread};
+use rusqlite::{params, Connection, OptionalExtension};
 use tokio::task;
 use uuid::Uuid;
 
-/// Get forums with lightweight user info (no profile images) for better performance
-pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String> {
-    task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+/// SQL LIMIT that fetches one row past `max`, so a cut can be detected; 0 means no limit
+fn limit_with_probe(max: usize) -> i64 {
+    if max == 0 { -1 } else { max as i64 + 1 }
+}
+
+/// Get forums with lightweight user info (no profile images) for better performance.
+/// Each forum keeps its newest `max_threads` threads and each thread its newest
+/// `max_posts` posts (0 keeps all); a forum that lost any is marked `truncated`.
+pub async fn db_get_forums_lightweight(max_threads: usize, max_posts: usize) -> Result<Vec<ForumLightweight>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         let mut forums = Vec::new();
 
         let mut stmt = conn.prepare("SELECT id, name, description FROM forums")
@@ -25,56 +33,67 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
             let (forum_id, name, description) = forum_row.map_err(|e| e.to_string())?;
             let forum_uuid = Uuid::parse_str(&forum_id).map_err(|e| e.to_string())?;
 
-            // Get threads for this forum
+            // Get threads for this forum, newest first
+            let mut truncated = false;
             let mut thread_stmt = conn.prepare(
-                "SELECT id, title, author_id, timestamp FROM threads WHERE forum_id = ?1"
+                "SELECT id, title, author_id, timestamp, view_count FROM threads WHERE forum_id = ?1
+                 ORDER BY timestamp DESC LIMIT ?2"
             ).map_err(|e| e.to_string())?;
-            let thread_rows = thread_stmt.query_map(params![forum_id], |row| {
+            let thread_rows = thread_stmt.query_map(params![forum_id, limit_with_probe(max_threads)], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, String>(2)?,
                     row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
                 ))
             }).map_err(|e| e.to_string())?;
 
             let mut threads = Vec::new();
             for thread_row in thread_rows {
-                let (thread_id, title, author_id, thread_timestamp) = thread_row.map_err(|e| e.to_string())?;
+                if max_threads > 0 && threads.len() == max_threads {
+                    truncated = true;
+                    break;
+                }
+                let (thread_id, title, author_id, thread_timestamp, view_count) = thread_row.map_err(|e| e.to_string())?;
                 let thread_uuid = Uuid::parse_str(&thread_id).map_err(|e| e.to_string())?;
 
                 // Get thread author (lightweight - no profile images)
                 let mut user_stmt = conn.prepare(
-                    "SELECT id, username, color, role FROM users WHERE id = ?1"
+                    "SELECT id, username, color, role, display_name FROM users WHERE id = ?1"
                 ).map_err(|e| e.to_string())?;
-                let (user_id, username, color, role) = user_stmt.query_row(
+                let (user_id, username, color, role, display_name) = user_stmt.query_row(
                     params![author_id], |row| {
                         Ok((
                             row.get::<_, String>(0)?,
                             row.get::<_, String>(1)?,
                             row.get::<_, String>(2)?,
                             row.get::<_, String>(3)?,
+                            row.get::<_, Option<String>>(4)?,
                         ))
                     }
                 ).map_err(|e| e.to_string())?;
 
                 let author = UserInfo {
-                    id: Uuid::parse_str(&user_id).unwrap(),
+                    id: Uuid::parse_str(&user_id).map_err(|e| e.to_string())?,
                     username,
+                    display_name: crate::db::users::visible_display_name(display_name),
                     color: parse_user_color(&color),
                     role: match role.as_str() {
                         "Admin" => UserRole::Admin,
                         "Moderator" => UserRole::Moderator,
+                        "Bot" => UserRole::Bot,
                         _ => UserRole::User,
                     },
                     status: UserStatus::Offline,
                 };
 
-                // Get posts for this thread
+                // Get the newest posts for this thread
                 let mut post_stmt = conn.prepare(
-                    "SELECT id, author_id, content, timestamp, reply_to FROM posts WHERE thread_id = ?1"
+                    "SELECT id, author_id, content, timestamp, reply_to FROM posts WHERE thread_id = ?1
+                     ORDER BY timestamp DESC LIMIT ?2"
                 ).map_err(|e| e.to_string())?;
-                let post_rows = post_stmt.query_map(params![thread_id], |row| {
+                let post_rows = post_stmt.query_map(params![thread_id, limit_with_probe(max_posts)], |row| {
                     Ok((
                         row.get::<_, String>(0)?,
                         row.get::<_, String>(1)?,
@@ -86,6 +105,10 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
 
                 let mut posts = Vec::new();
                 for post_row in post_rows {
+                    if max_posts > 0 && posts.len() == max_posts {
+                        truncated = true;
+                        break;
+                    }
                     let (post_id, post_author_id, content, post_timestamp, reply_to_str) = post_row.map_err(|e| e.to_string())?;
 
                     // Parse reply_to UUID if present
@@ -96,39 +119,44 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
 
                     // Get post author (lightweight - no profile images)
                     let mut post_user_stmt = conn.prepare(
-                        "SELECT id, username, color, role FROM users WHERE id = ?1"
+                        "SELECT id, username, color, role, display_name FROM users WHERE id = ?1"
                     ).map_err(|e| e.to_string())?;
-                    let (puser_id, pusername, pcolor, prole) = post_user_stmt.query_row(
+                    let (puser_id, pusername, pcolor, prole, pdisplay_name) = post_user_stmt.query_row(
                         params![post_author_id], |row| {
                             Ok((
                                 row.get::<_, String>(0)?,
                                 row.get::<_, String>(1)?,
                                 row.get::<_, String>(2)?,
                                 row.get::<_, String>(3)?,
+                                row.get::<_, Option<String>>(4)?,
                             ))
                         }
                     ).map_err(|e| e.to_string())?;
 
                     let post_author = UserInfo {
-                        id: Uuid::parse_str(&puser_id).unwrap(),
+                        id: Uuid::parse_str(&puser_id).map_err(|e| e.to_string())?,
                         username: pusername,
+                        display_name: crate::db::users::visible_display_name(pdisplay_name),
                         color: parse_user_color(&pcolor),
                         role: match prole.as_str() {
                             "Admin" => UserRole::Admin,
                             "Moderator" => UserRole::Moderator,
+                            "Bot" => UserRole::Bot,
                             _ => UserRole::User,
                         },
                         status: UserStatus::Offline,
                     };
 
                     posts.push(PostLightweight {
-                        id: Uuid::parse_str(&post_id).unwrap(),
+                        id: Uuid::parse_str(&post_id).map_err(|e| e.to_string())?,
                         author: post_author,
                         content,
                         timestamp: post_timestamp,
                         reply_to,
                     });
                 }
+                // Fetched newest first; clients render posts oldest first
+                posts.reverse();
 
                 threads.push(ThreadLightweight {
                     id: thread_uuid,
@@ -136,6 +164,7 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
                     author,
                     posts,
                     timestamp: thread_timestamp,
+                    view_count: view_count.max(0) as u64,
                 });
             }
 
@@ -144,6 +173,7 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
                 name,
                 description,
                 threads,
+                truncated,
             });
         }
 
@@ -155,7 +185,7 @@ pub async fn db_get_forums_lightweight() -> Result<Vec<ForumLightweight>, String
 
 pub async fn db_get_forums() -> Result<Vec<Forum>, String> {
     task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         let mut forums = Vec::new();
 
         let mut stmt = conn.prepare("SELECT id, name, description FROM forums")
@@ -208,12 +238,13 @@ pub async fn db_get_forums() -> Result<Vec<Forum>, String> {
                 ).map_err(|e| e.to_string())?;
 
                 let author = User {
-                    id: Uuid::parse_str(&user_id).unwrap(),
+                    id: Uuid::parse_str(&user_id).map_err(|e| e.to_string())?,
                     username,
                     color: parse_user_color(&color),
                     role: match role.as_str() {
                         "Admin" => UserRole::Admin,
                         "Moderator" => UserRole::Moderator,
+                        "Bot" => UserRole::Bot,
                         _ => UserRole::User,
                     },
                     profile_pic,
@@ -263,12 +294,13 @@ pub async fn db_get_forums() -> Result<Vec<Forum>, String> {
                     ).map_err(|e| e.to_string())?;
 
                     let post_author = User {
-                        id: Uuid::parse_str(&puser_id).unwrap(),
+                        id: Uuid::parse_str(&puser_id).map_err(|e| e.to_string())?,
                         username: pusername,
                         color: parse_user_color(&pcolor),
                         role: match prole.as_str() {
                             "Admin" => UserRole::Admin,
                             "Moderator" => UserRole::Moderator,
+                            "Bot" => UserRole::Bot,
                             _ => UserRole::User,
                         },
                         profile_pic: pprofile_pic,
@@ -277,7 +309,7 @@ pub async fn db_get_forums() -> Result<Vec<Forum>, String> {
                     };
 
                     posts.push(Post {
-                        id: Uuid::parse_str(&post_id).unwrap(),
+                        id: Uuid::parse_str(&post_id).map_err(|e| e.to_string())?,
                         author: post_author,
                         content,
                         timestamp: post_timestamp,
@@ -318,10 +350,10 @@ pub async fn db_create_thread(
     let title = title.to_string();
     let author_id_str = author_id.to_string();
     let content = content.to_string();
-    let now = chrono::Utc::now().timestamp();
+    let now = crate::util::now_secs();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let thread_id = Uuid::new_v4();
         let post_id = Uuid::new_v4();
 
@@ -348,15 +380,22 @@ pub async fn db_create_post(thread_id: Uuid, author_id: Uuid, content: &str, rep
     let author_id_str = author_id.to_string();
     let content = content.to_string();
     let reply_to_str = reply_to.map(|id| id.to_string());
-    let now = chrono::Utc::now().timestamp();
+    let now = crate::util::now_secs();
+
+    let forum_config = crate::config::settings().forums.clone();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let post_id = Uuid::new_v4();
 
+        let (reply_to_str, depth) = match reply_to_str {
+            Some(parent_id) => resolve_reply_parent(&conn, &thread_id_str, parent_id, &forum_config)?,
+            None => (None, 0),
+        };
+
         conn.execute(
-            "INSERT INTO posts (id, thread_id, author_id, content, timestamp, reply_to) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![post_id.to_string(), thread_id_str, author_id_str, content, now, reply_to_str],
+            "INSERT INTO posts (id, thread_id, author_id, content, timestamp, reply_to, depth) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![post_id.to_string(), thread_id_str, author_id_str, content, now, reply_to_str, depth],
         ).map_err(|e| e.to_string())?;
 
         Ok(())
@@ -365,12 +404,59 @@ pub async fn db_create_post(thread_id: Uuid, author_id: Uuid, content: &str, rep
     .unwrap()
 }
 
+/// Work out where a reply attaches and its depth, applying the configured nesting limit
+fn resolve_reply_parent(
+    conn: &Connection,
+    thread_id: &str,
+    parent_id: String,
+    config: &ForumConfig,
+) -> Result<(Option<String>, u32), String> {
+    let (parent_thread_id, parent_depth): (String, u32) = conn
+        .query_row(
+            "SELECT thread_id, depth FROM posts WHERE id = ?1",
+            params![parent_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|_| "Post being replied to not found".to_string())?;
+
+    if parent_thread_id != thread_id {
+        return Err("Post being replied to is in a different thread".to_string());
+    }
+
+    let depth = parent_depth + 1;
+    if config.max_reply_depth == 0 || depth <= config.max_reply_depth {
+        return Ok((Some(parent_id), depth));
+    }
+
+    match config.reply_depth_policy {
+        ReplyDepthPolicy::Reject => Err(format!(
+            "Replies cannot be nested more than {} levels deep",
+            config.max_reply_depth
+        )),
+        ReplyDepthPolicy::Flatten => {
+            let root_id: String = conn
+                .query_row(
+                    "WITH RECURSIVE ancestors(id, reply_to) AS (
+                        SELECT id, reply_to FROM posts WHERE id = ?1
+                        UNION ALL
+                        SELECT p.id, p.reply_to FROM posts p JOIN ancestors a ON p.id = a.reply_to
+                    )
+                    SELECT id FROM ancestors WHERE reply_to IS NULL LIMIT 1",
+                    params![parent_id],
+                    |row| row.get(0),
+                )
+                .map_err(|e| e.to_string())?;
+            Ok((Some(root_id), 1))
+        }
+    }
+}
+
 pub async fn db_create_forum(name: &str, description: &str) -> Result<(), String> {
     let name = name.to_string();
     let description = description.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let forum_id = Uuid::new_v4();
 
         conn.execute(
@@ -389,7 +475,7 @@ pub async fn db_delete_post(post_id: Uuid, user_id: Uuid) -> Result<(), String>
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         // Check if the user owns the post or is an admin/moderator
         let mut stmt = conn.prepare(
@@ -431,7 +517,7 @@ pub async fn db_delete_thread(thread_id: Uuid, user_id: Uuid) -> Result<(), Stri
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         // Check if the user owns the thread or is an admin/moderator
         let mut stmt = conn.prepare(
@@ -478,7 +564,7 @@ pub async fn db_delete_forum(forum_id: Uuid) -> Result<(), String> {
     let forum_id_str = forum_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         // Delete all posts in threads of this forum first
         conn.execute(
@@ -508,7 +594,7 @@ pub async fn db_get_post_author(post_id: Uuid) -> Result<Uuid, String> {
     let post_id_str = post_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare("SELECT author_id FROM posts WHERE id = ?1").map_err(|e| e.to_string())?;
         let author_id_str: String = stmt.query_row(params![post_id_str], |row| {
@@ -520,3 +606,247 @@ pub async fn db_get_post_author(post_id: Uuid) -> Result<Uuid, String> {
     .await
     .unwrap()
 }
+
+/// Check whether a forum exists
+pub async fn db_forum_exists(forum_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("forums", forum_id).await
+}
+
+/// Check whether a thread exists
+pub async fn db_thread_exists(thread_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("threads", thread_id).await
+}
+
+/// Check whether a post exists
+pub async fn db_post_exists(post_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("posts", post_id).await
+}
+
+/// Get the minimum role allowed to post in a forum
+pub async fn db_get_forum_post_role(forum_id: Uuid) -> Result<UserRole, String> {
+    let forum_id_str = forum_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let role: String = conn.query_row(
+            "SELECT min_post_role FROM forums WHERE id = ?1",
+            params![forum_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        Ok(parse_role(&role))
+    })
+    .await
+    .unwrap()
+}
+
+/// Set the minimum role allowed to post in a forum
+pub async fn db_set_forum_post_role(forum_id: Uuid, role: &str) -> Result<(), String> {
+    let forum_id_str = forum_id.to_string();
+    let role = role.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let updated = conn.execute(
+            "UPDATE forums SET min_post_role = ?1 WHERE id = ?2",
+            params![role, forum_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        if updated == 0 {
+            return Err("Forum not found".to_string());
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get the forum a thread belongs to
+pub async fn db_get_thread_forum_id(thread_id: Uuid) -> Result<Uuid, String> {
+    let thread_id_str = thread_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let forum_id: String = conn.query_row(
+            "SELECT forum_id FROM threads WHERE id = ?1",
+            params![thread_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        Uuid::parse_str(&forum_id).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Add one view to a thread's aggregate view count
+pub async fn db_increment_thread_views(thread_id: Uuid) -> Result<(), String> {
+    let thread_id_str = thread_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "UPDATE threads SET view_count = view_count + 1 WHERE id = ?1",
+            params![thread_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Threads ranked by posts made since `since` plus their views, where each
+/// recent post counts as `post_weight` views. Only threads started or posted
+/// in since then are considered.
+pub async fn db_get_trending_threads(since: i64, post_weight: i64, limit: usize) -> Result<Vec<TrendingThread>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT t.id, t.forum_id, t.title, t.view_count, COUNT(p.id) AS recent_posts
+             FROM threads t
+             LEFT JOIN posts p ON p.thread_id = t.id AND p.timestamp >= ?1
+             GROUP BY t.id
+             HAVING recent_posts > 0 OR t.timestamp >= ?1
+             ORDER BY recent_posts * ?2 + t.view_count DESC, t.timestamp DESC
+             LIMIT ?3"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![since, post_weight, limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        }).map_err(|e| e.to_string())?;
+
+        let mut threads = Vec::new();
+        for row in rows {
+            let (id, forum_id, title, view_count, recent_posts) = row.map_err(|e| e.to_string())?;
+            threads.push(TrendingThread {
+                id: Uuid::parse_str(&id).map_err(|e| e.to_string())?,
+                forum_id: Uuid::parse_str(&forum_id).map_err(|e| e.to_string())?,
+                title,
+                view_count: view_count.max(0) as u64,
+                recent_posts: recent_posts.max(0) as u64,
+            });
+        }
+        Ok(threads)
+    })
+    .await
+    .unwrap()
+}
+
+/// Text of a post at a given revision (see the post_edits table), or None if
+/// the post has no such revision
+pub async fn db_get_post_revision(post_id: Uuid, revision: u32) -> Result<Option<String>, String> {
+    let post_id_str = post_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let stored: Option<String> = conn.query_row(
+            "SELECT content FROM post_edits WHERE post_id = ?1 AND revision = ?2",
+            params![post_id_str, revision],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+        if stored.is_some() {
+            return Ok(stored);
+        }
+
+        // The live content is the revision right after the last stored one
+        let edits: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM post_edits WHERE post_id = ?1",
+            params![post_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        if i64::from(revision) != edits {
+            return Ok(None);
+        }
+        conn.query_row(
+            "SELECT content FROM posts WHERE id = ?1",
+            params![post_id_str],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+
+    /// A thread whose posts form one reply chain: the root, then a reply to
+    /// it, then a reply to that. Returns the thread id and the post ids, root first.
+    fn reply_chain(conn: &Connection) -> (String, Vec<String>) {
+        let thread_id = Uuid::new_v4().to_string();
+        let author_id = Uuid::new_v4().to_string();
+        let mut post_ids: Vec<String> = Vec::new();
+        for depth in 0..3u32 {
+            let post_id = Uuid::new_v4().to_string();
+            conn.execute(
+                "INSERT INTO posts (id, thread_id, author_id, content, timestamp, reply_to, depth) VALUES (?1, ?2, ?3, 'post', 0, ?4, ?5)",
+                params![post_id, thread_id, author_id, post_ids.last(), depth],
+            ).unwrap();
+            post_ids.push(post_id);
+        }
+        (thread_id, post_ids)
+    }
+
+    fn config(max_reply_depth: u32, reply_depth_policy: ReplyDepthPolicy) -> ForumConfig {
+        ForumConfig { max_reply_depth, reply_depth_policy, ..ForumConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn replies_within_the_limit_attach_to_their_parent() {
+        let db = TestDb::new().await;
+        let conn = Connection::open(db.path()).unwrap();
+        let (thread_id, posts) = reply_chain(&conn);
+
+        for policy in [ReplyDepthPolicy::Reject, ReplyDepthPolicy::Flatten] {
+            let resolved = resolve_reply_parent(&conn, &thread_id, posts[1].clone(), &config(2, policy)).unwrap();
+            assert_eq!(resolved, (Some(posts[1].clone()), 2));
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_policy_refuses_replies_past_the_limit() {
+        let db = TestDb::new().await;
+        let conn = Connection::open(db.path()).unwrap();
+        let (thread_id, posts) = reply_chain(&conn);
+
+        let result = resolve_reply_parent(&conn, &thread_id, posts[2].clone(), &config(2, ReplyDepthPolicy::Reject));
+        assert_eq!(result, Err("Replies cannot be nested more than 2 levels deep".to_string()));
+    }
+
+    #[tokio::test]
+    async fn flatten_policy_moves_replies_past_the_limit_under_the_root() {
+        let db = TestDb::new().await;
+        let conn = Connection::open(db.path()).unwrap();
+        let (thread_id, posts) = reply_chain(&conn);
+
+        let resolved = resolve_reply_parent(&conn, &thread_id, posts[2].clone(), &config(2, ReplyDepthPolicy::Flatten)).unwrap();
+        assert_eq!(resolved, (Some(posts[0].clone()), 1));
+    }
+
+    #[tokio::test]
+    async fn zero_limit_allows_any_depth() {
+        let db = TestDb::new().await;
+        let conn = Connection::open(db.path()).unwrap();
+        let (thread_id, posts) = reply_chain(&conn);
+
+        let resolved = resolve_reply_parent(&conn, &thread_id, posts[2].clone(), &config(0, ReplyDepthPolicy::Reject)).unwrap();
+        assert_eq!(resolved, (Some(posts[2].clone()), 3));
+    }
+
+    #[tokio::test]
+    async fn replies_to_another_threads_post_are_refused() {
+        let db = TestDb::new().await;
+        let conn = Connection::open(db.path()).unwrap();
+        let (_, posts) = reply_chain(&conn);
+        let (other_thread_id, _) = reply_chain(&conn);
+
+        let result = resolve_reply_parent(&conn, &other_thread_id, posts[0].clone(), &config(2, ReplyDepthPolicy::Flatten));
+        assert!(result.is_err());
+    }
+}
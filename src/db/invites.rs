@@ -1,33 +1,40 @@
-use crate::db::db_config;
+use crate::db::{get_conn, parse_uuid_column};
 use crate::errors::{Result, ServerError};
 use nexus_tui_common::{ServerInvite, ServerInviteStatus, User, Server};
-use rusqlite::{params, Connection};
+use rusqlite::params;
 use uuid::Uuid;
-use std::str::FromStr;
 
+/// Create a pending invite unless the recipient is already a member or already
+/// has a pending invite to the server. The check and insert are one statement,
+/// so concurrent invites can't both get through; returns None if nothing was created.
 pub async fn db_create_server_invite(
     from_user_id: Uuid,
     to_user_id: Uuid,
     server_id: Uuid,
-) -> Result<Uuid> {
+) -> Result<Option<Uuid>> {
     let invite_id = Uuid::new_v4();
-    let timestamp = chrono::Utc::now().timestamp();
+    let timestamp = crate::util::now_secs();
     
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
-        conn.execute(
+        let conn = get_conn()?;
+        let inserted = conn.execute(
             "INSERT INTO server_invites (id, from_user_id, to_user_id, server_id, timestamp, status) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+             SELECT ?1, ?2, ?3, ?4, ?5, 'Pending'
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM server_invites WHERE to_user_id = ?3 AND server_id = ?4 AND status = 'Pending'
+             )
+             AND NOT EXISTS (
+                 SELECT 1 FROM server_users WHERE user_id = ?3 AND server_id = ?4
+             )",
             params![
                 invite_id.to_string(),
                 from_user_id.to_string(),
                 to_user_id.to_string(),
                 server_id.to_string(),
                 timestamp,
-                "Pending"
             ],
         )?;
-        Ok::<Uuid, rusqlite::Error>(invite_id)
+        Ok::<Option<Uuid>, rusqlite::Error>((inserted > 0).then_some(invite_id))
     })
     .await
     .map_err(|e| ServerError::Internal(e.to_string()))?
@@ -36,7 +43,7 @@ pub async fn db_create_server_invite(
 
 pub async fn db_get_pending_invites_for_user(user_id: Uuid) -> Result<Vec<ServerInvite>> {
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = get_conn()?;
         let mut stmt = conn.prepare(
             "SELECT si.id, si.from_user_id, si.to_user_id, si.server_id, si.timestamp, si.status,
                     u.username, u.color, u.role, u.profile_pic, u.cover_banner,
@@ -65,7 +72,7 @@ pub async fn db_get_pending_invites_for_user(user_id: Uuid) -> Result<Vec<Server
             let role = crate::util::parse_role(&role_str);
             
             let from_user = User {
-                id: Uuid::from_str(&row.get::<_, String>(1)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
                 username: row.get(6)?,
                 color: color.into(),
                 role,
@@ -75,23 +82,23 @@ pub async fn db_get_pending_invites_for_user(user_id: Uuid) -> Result<Vec<Server
             };
             
             let server = Server {
-                id: Uuid::from_str(&row.get::<_, String>(3)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(3)?, 3)?,
                 name: row.get(11)?,
                 description: row.get(12)?,
                 public: row.get::<_, i32>(13)? != 0,
                 invite_code: row.get(14)?,
                 icon: row.get(15)?,
                 banner: row.get(16)?,
-                owner: Uuid::from_str(&row.get::<_, String>(17)?).unwrap(),
+                owner: parse_uuid_column(&row.get::<_, String>(17)?, 17)?,
                 mods: vec![], // We'll populate this separately if needed
                 userlist: vec![], // We'll populate this separately if needed
                 channels: vec![], // We'll populate this separately if needed
             };
             
             Ok(ServerInvite {
-                id: Uuid::from_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 from_user,
-                to_user_id: Uuid::from_str(&row.get::<_, String>(2)?).unwrap(),
+                to_user_id: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
                 server,
                 timestamp: row.get(4)?,
                 status,
@@ -118,11 +125,13 @@ pub async fn db_update_invite_status(invite_id: Uuid, status: ServerInviteStatus
         ServerInviteStatus::Expired => "Expired",
     };
     
+    let responded_at = (status_str != "Pending").then(|| crate::util::now_secs());
+
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = get_conn()?;
         conn.execute(
-            "UPDATE server_invites SET status = ?1 WHERE id = ?2",
-            params![status_str, invite_id.to_string()],
+            "UPDATE server_invites SET status = ?1, responded_at = ?2 WHERE id = ?3",
+            params![status_str, responded_at, invite_id.to_string()],
         )?;
         Ok::<(), rusqlite::Error>(())
     })
@@ -133,7 +142,7 @@ pub async fn db_update_invite_status(invite_id: Uuid, status: ServerInviteStatus
 
 pub async fn db_get_invite_by_id(invite_id: Uuid) -> Result<Option<ServerInvite>> {
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = get_conn()?;
         let mut stmt = conn.prepare(
             "SELECT si.id, si.from_user_id, si.to_user_id, si.server_id, si.timestamp, si.status,
                     u.username, u.color, u.role, u.profile_pic, u.cover_banner,
@@ -161,7 +170,7 @@ pub async fn db_get_invite_by_id(invite_id: Uuid) -> Result<Option<ServerInvite>
             let role = crate::util::parse_role(&role_str);
             
             let from_user = User {
-                id: Uuid::from_str(&row.get::<_, String>(1)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
                 username: row.get(6)?,
                 color: color.into(),
                 role,
@@ -171,23 +180,23 @@ pub async fn db_get_invite_by_id(invite_id: Uuid) -> Result<Option<ServerInvite>
             };
             
             let server = Server {
-                id: Uuid::from_str(&row.get::<_, String>(3)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(3)?, 3)?,
                 name: row.get(11)?,
                 description: row.get(12)?,
                 public: row.get::<_, i32>(13)? != 0,
                 invite_code: row.get(14)?,
                 icon: row.get(15)?,
                 banner: row.get(16)?,
-                owner: Uuid::from_str(&row.get::<_, String>(17)?).unwrap(),
+                owner: parse_uuid_column(&row.get::<_, String>(17)?, 17)?,
                 mods: vec![],
                 userlist: vec![],
                 channels: vec![],
             };
             
             Ok(ServerInvite {
-                id: Uuid::from_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 from_user,
-                to_user_id: Uuid::from_str(&row.get::<_, String>(2)?).unwrap(),
+                to_user_id: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
                 server,
                 timestamp: row.get(4)?,
                 status,
@@ -205,21 +214,20 @@ pub async fn db_get_invite_by_id(invite_id: Uuid) -> Result<Option<ServerInvite>
     .map_err(|e| ServerError::Database(e.to_string()))
 }
 
+/// Whether the user already has a pending invite to the server, from anyone
 pub async fn db_check_existing_invite(
-    from_user_id: Uuid, 
     to_user_id: Uuid, 
     server_id: Uuid
 ) -> Result<bool> {
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = get_conn()?;
         let mut stmt = conn.prepare(
             "SELECT COUNT(*) FROM server_invites 
-             WHERE from_user_id = ?1 AND to_user_id = ?2 AND server_id = ?3 AND status = 'Pending'"
+             WHERE to_user_id = ?1 AND server_id = ?2 AND status = 'Pending'"
         )?;
         
         let count: i64 = stmt.query_row(
             params![
-                from_user_id.to_string(),
                 to_user_id.to_string(),
                 server_id.to_string()
             ],
@@ -233,12 +241,32 @@ pub async fn db_check_existing_invite(
     .map_err(|e| ServerError::Database(e.to_string()))
 }
 
+/// When the recipient last declined an invite from this sender, to any server.
+/// Invites declined before responded_at was recorded fall back to their send time.
+pub async fn db_last_declined_invite_at(
+    from_user_id: Uuid,
+    to_user_id: Uuid,
+) -> Result<Option<i64>> {
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn()?;
+        conn.query_row(
+            "SELECT MAX(COALESCE(responded_at, timestamp)) FROM server_invites
+             WHERE from_user_id = ?1 AND to_user_id = ?2 AND status = 'Declined'",
+            params![from_user_id.to_string(), to_user_id.to_string()],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+    })
+    .await
+    .map_err(|e| ServerError::Internal(e.to_string()))?
+    .map_err(|e| ServerError::Database(e.to_string()))
+}
+
 pub async fn db_get_pending_invite_from_user(
     from_user_id: Uuid,
     to_user_id: Uuid,
 ) -> Result<Option<ServerInvite>> {
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = get_conn()?;
         let mut stmt = conn.prepare(
             "SELECT si.id, si.from_user_id, si.to_user_id, si.server_id, si.timestamp, si.status,
                     u.username, u.color, u.role, u.profile_pic, u.cover_banner,
@@ -268,7 +296,7 @@ pub async fn db_get_pending_invite_from_user(
             let role = crate::util::parse_role(&role_str);
             
             let from_user = User {
-                id: Uuid::from_str(&row.get::<_, String>(1)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
                 username: row.get(6)?,
                 color: color.into(),
                 role,
@@ -278,23 +306,23 @@ pub async fn db_get_pending_invite_from_user(
             };
             
             let server = Server {
-                id: Uuid::from_str(&row.get::<_, String>(3)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(3)?, 3)?,
                 name: row.get(11)?,
                 description: row.get(12)?,
                 public: row.get::<_, i32>(13)? != 0,
                 invite_code: row.get(14)?,
                 icon: row.get(15)?,
                 banner: row.get(16)?,
-                owner: Uuid::from_str(&row.get::<_, String>(17)?).unwrap(),
+                owner: parse_uuid_column(&row.get::<_, String>(17)?, 17)?,
                 mods: vec![],
                 userlist: vec![],
                 channels: vec![],
             };
             
             Ok(ServerInvite {
-                id: Uuid::from_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 from_user,
-                to_user_id: Uuid::from_str(&row.get::<_, String>(2)?).unwrap(),
+                to_user_id: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
                 server,
                 timestamp: row.get(4)?,
                 status,
@@ -310,4 +338,75 @@ pub async fn db_get_pending_invite_from_user(
     .await
     .map_err(|e| ServerError::Internal(e.to_string()))?
     .map_err(|e| ServerError::Database(e.to_string()))
-}
\ No newline at end of file
+}
+/// Remove an invite that could not be announced
+pub async fn db_delete_server_invite(invite_id: Uuid) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn()?;
+        conn.execute(
+            "DELETE FROM server_invites WHERE id = ?1",
+            params![invite_id.to_string()],
+        )?;
+        Ok::<(), rusqlite::Error>(())
+    })
+    .await
+    .map_err(|e| ServerError::Internal(e.to_string()))?
+    .map_err(|e| ServerError::Database(e.to_string()))
+}
+
+/// Remember which DM announced an invite
+pub async fn db_set_invite_dm(invite_id: Uuid, dm_id: Uuid) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn()?;
+        conn.execute(
+            "UPDATE server_invites SET dm_id = ?1 WHERE id = ?2",
+            params![dm_id.to_string(), invite_id.to_string()],
+        )?;
+        Ok::<(), rusqlite::Error>(())
+    })
+    .await
+    .map_err(|e| ServerError::Internal(e.to_string()))?
+    .map_err(|e| ServerError::Database(e.to_string()))
+}
+
+/// The DM that announced an invite, if one was recorded
+pub async fn db_get_invite_dm(invite_id: Uuid) -> Result<Option<Uuid>> {
+    tokio::task::spawn_blocking(move || {
+        let conn = get_conn()?;
+        let dm_id: Option<String> = conn.query_row(
+            "SELECT dm_id FROM server_invites WHERE id = ?1",
+            params![invite_id.to_string()],
+            |row| row.get(0),
+        )?;
+        dm_id.map(|id| parse_uuid_column(&id, 0)).transpose()
+    })
+    .await
+    .map_err(|e| ServerError::Internal(e.to_string()))?
+    .map_err(|e| ServerError::Database(e.to_string()))
+}
+
+/// Mark pending invites sent before `cutoff` as expired, returning their ids
+pub async fn db_expire_pending_invites(cutoff: i64) -> Result<Vec<Uuid>> {
+    tokio::task::spawn_blocking(move || {
+        let mut conn = get_conn()?;
+        let tx = conn.transaction()?;
+
+        let ids = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM server_invites WHERE status = 'Pending' AND timestamp < ?1"
+            )?;
+            let rows = stmt.query_map(params![cutoff], |row| parse_uuid_column(&row.get::<_, String>(0)?, 0))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        tx.execute(
+            "UPDATE server_invites SET status = 'Expired', responded_at = ?2 WHERE status = 'Pending' AND timestamp < ?1",
+            params![cutoff, crate::util::now_secs()],
+        )?;
+        tx.commit()?;
+        Ok::<Vec<Uuid>, rusqlite::Error>(ids)
+    })
+    .await
+    .map_err(|e| ServerError::Internal(e.to_string()))?
+    .map_err(|e| ServerError::Database(e.to_string()))
+}
@@ -1,6 +1,7 @@
-use crate::db::db_config;
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
 use nexus_tui_common::{DirectMessage, User, UserInfo, UserRole, UserStatus};
-use rusqlite::{params, Connection};
+use rusqlite::params;
+use std::collections::HashMap;
 use tokio::task;
 use uuid::Uuid;
 
@@ -15,7 +16,7 @@ pub async fn db_store_direct_message(
     let content = content.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let id = Uuid::new_v4();
 
         conn.execute(
@@ -23,6 +24,13 @@ pub async fn db_store_direct_message(
             params![id.to_string(), from_user_id_str, to_user_id_str, content, timestamp],
         ).map_err(|e| e.to_string())?;
 
+        // New activity brings an archived or hidden conversation back for both sides
+        conn.execute(
+            "DELETE FROM dm_conversation_state
+             WHERE (user_id = ?1 AND peer_id = ?2) OR (user_id = ?2 AND peer_id = ?1)",
+            params![to_user_id_str, from_user_id_str],
+        ).map_err(|e| e.to_string())?;
+
         Ok(id)
     })
     .await
@@ -33,13 +41,14 @@ pub async fn db_get_direct_messages(
     user1_id: Uuid,
     user2_id: Uuid,
     before: Option<i64>,
-    _limit: usize,
+    limit: usize,
 ) -> Result<(Vec<DirectMessage>, bool), String> {
     let user1_id_str = user1_id.to_string();
     let user2_id_str = user2_id.to_string();
+    let limit = limit as i64;
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
         let mut messages: Vec<DirectMessage> = Vec::new();
         
@@ -49,10 +58,10 @@ pub async fn db_get_direct_messages(
                  FROM direct_messages 
                  WHERE ((from_user_id = ? AND to_user_id = ?) OR (from_user_id = ? AND to_user_id = ?)) 
                  AND timestamp < ?
-                 ORDER BY timestamp DESC LIMIT 50"
+                 ORDER BY timestamp DESC LIMIT ?"
             ).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![user1_id_str, user2_id_str, user2_id_str, user1_id_str, before_ts], |row| {
+            let rows = stmt.query_map(params![user1_id_str, user2_id_str, user2_id_str, user1_id_str, before_ts, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
@@ -79,10 +88,10 @@ pub async fn db_get_direct_messages(
                 "SELECT id, from_user_id, to_user_id, content, timestamp
                  FROM direct_messages 
                  WHERE ((from_user_id = ? AND to_user_id = ?) OR (from_user_id = ? AND to_user_id = ?))
-                 ORDER BY timestamp DESC LIMIT 50"
+                 ORDER BY timestamp DESC LIMIT ?"
             ).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![user1_id_str, user2_id_str, user2_id_str, user1_id_str], |row| {
+            let rows = stmt.query_map(params![user1_id_str, user2_id_str, user2_id_str, user1_id_str, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
@@ -133,7 +142,7 @@ pub async fn db_get_dm_user_list_lightweight(user_id: Uuid) -> Result<Vec<UserIn
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
 
         // Get users we've had conversations with
         let mut stmt = conn.prepare(
@@ -158,25 +167,28 @@ pub async fn db_get_dm_user_list_lightweight(user_id: Uuid) -> Result<Vec<UserIn
             
             // Get lightweight user profile
             let mut user_stmt = conn.prepare(
-                "SELECT username, color, role FROM users WHERE id = ?"
+                "SELECT username, color, role, display_name FROM users WHERE id = ?"
             ).map_err(|e| e.to_string())?;
             
-            if let Ok((username, color, role)) = user_stmt.query_row(
+            if let Ok((username, color, role, display_name)) = user_stmt.query_row(
                 params![other_user_id_str], |row| {
                     Ok((
                         row.get::<_, String>(0)?,
                         row.get::<_, String>(1)?,
                         row.get::<_, String>(2)?,
+                        row.get::<_, Option<String>>(3)?,
                     ))
                 }
             ) {
                 users.push(UserInfo {
                     id: other_user_id,
                     username,
+                    display_name: crate::db::users::visible_display_name(display_name),
                     color: nexus_tui_common::UserColor::new(color),
                     role: match role.as_str() {
                         "Admin" => UserRole::Admin,
                         "Moderator" => UserRole::Moderator,
+                        "Bot" => UserRole::Bot,
                         _ => UserRole::User,
                     },
                     status: UserStatus::Offline, // Default to offline, will be updated by server
@@ -194,7 +206,7 @@ pub async fn db_get_dm_user_list(user_id: Uuid) -> Result<Vec<User>, String> {
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
 
         // Get users we've had conversations with
         let mut stmt = conn.prepare(
@@ -240,6 +252,7 @@ pub async fn db_get_dm_user_list(user_id: Uuid) -> Result<Vec<User>, String> {
                     role: match role.as_str() {
                         "Admin" => UserRole::Admin,
                         "Moderator" => UserRole::Moderator,
+                        "Bot" => UserRole::Bot,
                         _ => UserRole::User,
                     },
                     profile_pic,
@@ -265,10 +278,9 @@ pub async fn db_get_direct_messages_by_timestamp(
 ) -> Result<(Vec<DirectMessage>, bool), String> {
     let user1_id_str = user1_id.to_string();
     let user2_id_str = user2_id.to_string();
-    let limit = limit.min(200); // Safety limit
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         let mut messages = Vec::new();
         
         let base_query = 
@@ -340,7 +352,7 @@ pub async fn db_get_direct_message_count(user1_id: Uuid, user2_id: Uuid) -> Resu
     let user2_id_str = user2_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare(
             "SELECT COUNT(*) FROM direct_messages 
@@ -355,3 +367,132 @@ pub async fn db_get_direct_message_count(user1_id: Uuid, user2_id: Uuid) -> Resu
     .await
     .unwrap()
 }
+
+/// Replace the text of a stored direct message
+pub async fn db_update_direct_message_content(message_id: Uuid, content: &str) -> Result<(), String> {
+    let message_id_str = message_id.to_string();
+    let content = content.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE direct_messages SET content = ?1 WHERE id = ?2",
+            params![content, message_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A direct message found in a legacy database but missing from the configured one
+#[derive(Debug, Clone)]
+pub struct LegacyDirectMessage {
+    pub id: String,
+    pub from_user_id: String,
+    pub to_user_id: String,
+    pub timestamp: i64,
+}
+
+/// Copy direct messages from a legacy database file into the configured one,
+/// skipping ids that already exist. With `dry_run` nothing is written.
+/// Returns the messages that were (or would be) copied.
+pub async fn db_merge_legacy_dms(legacy_path: &str, dry_run: bool) -> Result<Vec<LegacyDirectMessage>, String> {
+    let legacy_path = legacy_path.to_string();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute("ATTACH DATABASE ?1 AS legacy", params![legacy_path])
+            .map_err(|e| format!("Cannot attach {}: {}", legacy_path, e))?;
+
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let missing = {
+            let mut stmt = tx.prepare(
+                "SELECT id, from_user_id, to_user_id, timestamp FROM legacy.direct_messages
+                 WHERE id NOT IN (SELECT id FROM main.direct_messages)
+                 ORDER BY timestamp ASC"
+            ).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| {
+                Ok(LegacyDirectMessage {
+                    id: row.get(0)?,
+                    from_user_id: row.get(1)?,
+                    to_user_id: row.get(2)?,
+                    timestamp: row.get(3)?,
+                })
+            }).map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+        };
+
+        if !dry_run {
+            tx.execute(
+                "INSERT OR IGNORE INTO main.direct_messages (id, from_user_id, to_user_id, content, timestamp)
+                 SELECT id, from_user_id, to_user_id, content, timestamp FROM legacy.direct_messages",
+                [],
+            ).map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+
+        conn.execute("DETACH DATABASE legacy", []).map_err(|e| e.to_string())?;
+        Ok(missing)
+    })
+    .await
+    .unwrap()
+}
+
+/// Archive (optionally hiding until the next message) or unarchive a DM conversation, for `user_id` only
+pub async fn db_set_dm_conversation_state(
+    user_id: Uuid,
+    peer_id: Uuid,
+    archived: bool,
+    hidden_until_new_message: bool,
+) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let peer_id_str = peer_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        if archived {
+            conn.execute(
+                "INSERT INTO dm_conversation_state (user_id, peer_id, archived, hidden_until_new_message, updated_at)
+                 VALUES (?1, ?2, 1, ?3, ?4)
+                 ON CONFLICT(user_id, peer_id) DO UPDATE SET
+                     archived = 1,
+                     hidden_until_new_message = excluded.hidden_until_new_message,
+                     updated_at = excluded.updated_at",
+                params![user_id_str, peer_id_str, hidden_until_new_message as i32, crate::util::now_secs()],
+            ).map_err(|e| e.to_string())?;
+        } else {
+            conn.execute(
+                "DELETE FROM dm_conversation_state WHERE user_id = ?1 AND peer_id = ?2",
+                params![user_id_str, peer_id_str],
+            ).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A user's archived DM conversations: peer id -> hidden until the next message
+pub async fn db_get_dm_conversation_states(user_id: Uuid) -> Result<HashMap<Uuid, bool>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT peer_id, hidden_until_new_message FROM dm_conversation_state
+             WHERE user_id = ?1 AND archived = 1"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![user_id_str], |row| {
+            Ok((parse_uuid_column(&row.get::<_, String>(0)?, 0)?, row.get::<_, i32>(1)? != 0))
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<HashMap<_, _>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
@@ -1,11 +1,13 @@
-use crate::db::db_config;
 use crate::errors::{Result, ServerError};
-use rusqlite::{Connection, Result as SqlResult};
+use crate::util::normalize_username;
+use rusqlite::{params, Connection, Result as SqlResult};
 use tracing::info;
 
 pub async fn init_db() -> Result<()> {
     tokio::task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path())?;
+        let conn = super::get_conn()?;
+        // WAL lets read-only connections run alongside the writer
+        conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
         create_tables(&conn)?;
         add_missing_columns(&conn)?;
         Ok::<(), rusqlite::Error>(())
@@ -33,7 +35,11 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
             url3 TEXT,
             location TEXT,
             profile_pic TEXT,
-            cover_banner TEXT
+            cover_banner TEXT,
+            created_at INTEGER,
+            last_seen INTEGER,
+            digest_opt_out INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER
         )",
         [],
     )?;
@@ -49,6 +55,7 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
             icon TEXT,
             banner TEXT,
             owner TEXT NOT NULL,
+            system_messages INTEGER NOT NULL DEFAULT 1,
             FOREIGN KEY(owner) REFERENCES users(id)
         )",
         [],
@@ -85,6 +92,11 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
             server_id TEXT NOT NULL,
             name TEXT NOT NULL,
             description TEXT NOT NULL,
+            default_can_read INTEGER NOT NULL DEFAULT 1,
+            default_can_write INTEGER NOT NULL DEFAULT 1,
+            links_allowed INTEGER NOT NULL DEFAULT 1,
+            link_allowlist TEXT,
+            notify_followers INTEGER NOT NULL DEFAULT 0,
             FOREIGN KEY(server_id) REFERENCES servers(id)
         )",
         [],
@@ -116,6 +128,46 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
         [],
     )?;
 
+    // Server roles (permission groups with default channel permissions)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_roles (
+            id TEXT PRIMARY KEY,
+            server_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            can_read INTEGER NOT NULL DEFAULT 1,
+            can_write INTEGER NOT NULL DEFAULT 1,
+            UNIQUE(server_id, name),
+            FOREIGN KEY(server_id) REFERENCES servers(id)
+        )",
+        [],
+    )?;
+
+    // Per-channel overrides of a role's default permissions
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_role_channel_perms (
+            role_id TEXT NOT NULL,
+            channel_id TEXT NOT NULL,
+            can_read INTEGER NOT NULL DEFAULT 1,
+            can_write INTEGER NOT NULL DEFAULT 1,
+            PRIMARY KEY(role_id, channel_id),
+            FOREIGN KEY(role_id) REFERENCES server_roles(id),
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+
+    // Server role membership
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_user_roles (
+            role_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            PRIMARY KEY(role_id, user_id),
+            FOREIGN KEY(role_id) REFERENCES server_roles(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
     // Channel messages
     conn.execute(
         "CREATE TABLE IF NOT EXISTS channel_messages (
@@ -124,6 +176,7 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
             sent_by TEXT NOT NULL,
             timestamp INTEGER NOT NULL,
             content TEXT NOT NULL,
+            system_event TEXT,
             FOREIGN KEY(channel_id) REFERENCES channels(id),
             FOREIGN KEY(sent_by) REFERENCES users(id)
         )",
@@ -213,6 +266,271 @@ fn create_tables(conn: &Connection) -> SqlResult<()> {
         [],
     )?;
 
+    // Flagged first messages from new accounts awaiting moderator review
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS quarantined_messages (
+            id TEXT PRIMARY KEY,
+            channel_id TEXT NOT NULL,
+            sent_by TEXT NOT NULL,
+            content TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(sent_by) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Important real-time messages held for users who were offline when they were sent
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pending_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            payload BLOB NOT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Settings admins change at runtime (MOTD, registration, ...)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at INTEGER NOT NULL,
+            updated_by TEXT
+        )",
+        [],
+    )?;
+
+    // Record of privileged actions, browsable by admins
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            action TEXT NOT NULL,
+            target TEXT,
+            details TEXT,
+            timestamp INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Running per-user totals of stored message and media bytes
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS user_storage (
+            user_id TEXT PRIMARY KEY,
+            message_bytes INTEGER NOT NULL DEFAULT 0,
+            media_bytes INTEGER NOT NULL DEFAULT 0,
+            soft_quota_warned INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Channel follows, separate from membership: who wants notifications for a channel
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channel_followers (
+            channel_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY(channel_id, user_id),
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Per-user DM list tidying; only ever read by `user_id`, never by the peer
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dm_conversation_state (
+            user_id TEXT NOT NULL,
+            peer_id TEXT NOT NULL,
+            archived INTEGER NOT NULL DEFAULT 0,
+            hidden_until_new_message INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY(user_id, peer_id),
+            FOREIGN KEY(user_id) REFERENCES users(id),
+            FOREIGN KEY(peer_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Custom emoji, referenced as :name: in messages of the server
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_emojis (
+            server_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            image_ref TEXT NOT NULL,
+            created_by TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY(server_id, name),
+            FOREIGN KEY(server_id) REFERENCES servers(id),
+            FOREIGN KEY(created_by) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Personal mutes: the muter stops seeing the muted user's channel messages.
+    // A NULL channel_id mutes them everywhere.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS personal_mutes (
+            muter_id TEXT NOT NULL,
+            muted_id TEXT NOT NULL,
+            channel_id TEXT,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY(muter_id) REFERENCES users(id),
+            FOREIGN KEY(muted_id) REFERENCES users(id),
+            FOREIGN KEY(channel_id) REFERENCES channels(id)
+        )",
+        [],
+    )?;
+
+    // Forum post edit history: the content a post had before each edit.
+    // Revision 0 is the original text; the live posts.content is the revision
+    // after the last stored one.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS post_edits (
+            post_id TEXT NOT NULL,
+            revision INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            edited_by TEXT NOT NULL,
+            edited_at INTEGER NOT NULL,
+            FOREIGN KEY(post_id) REFERENCES posts(id),
+            FOREIGN KEY(edited_by) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Personal message bookmarks, private to the user. Kept when the message
+    // is deleted; the fetch shows a placeholder instead.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_bookmarks (
+            user_id TEXT NOT NULL,
+            message_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            note TEXT,
+            PRIMARY KEY(user_id, message_id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Server-level bans. expires_at is NULL for a permanent ban; an expired
+    // row no longer counts as banned.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS server_bans (
+            server_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            banned_by TEXT NOT NULL,
+            reason TEXT,
+            banned_at INTEGER NOT NULL,
+            expires_at INTEGER,
+            PRIMARY KEY(server_id, user_id),
+            FOREIGN KEY(server_id) REFERENCES servers(id),
+            FOREIGN KEY(user_id) REFERENCES users(id),
+            FOREIGN KEY(banned_by) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Who opened whose profile, for the weekly profile view digest. At most
+    // one row per viewer and profile per day; only written when both opted in.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS profile_views (
+            viewed_id TEXT NOT NULL,
+            viewer_id TEXT NOT NULL,
+            viewed_at INTEGER NOT NULL,
+            FOREIGN KEY(viewed_id) REFERENCES users(id),
+            FOREIGN KEY(viewer_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Emoji reactions on channel messages, one per user and emoji. History
+    // pages carry a per-emoji summary of these.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_reactions (
+            message_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            emoji TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY(message_id, user_id, emoji),
+            FOREIGN KEY(message_id) REFERENCES channel_messages(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Channel messages whose channel no longer exists, moved aside by the
+    // startup integrity scan when [messages] repair_orphans is on
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS orphaned_channel_messages (
+            id TEXT PRIMARY KEY,
+            channel_id TEXT NOT NULL,
+            sent_by TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            system_event TEXT,
+            origin TEXT,
+            moved_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Messages the content filter flagged but let through, kept for moderator
+    // review. Exactly one of channel_id (channel message) or dm_to (DM) is set.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS flagged_messages (
+            id TEXT PRIMARY KEY,
+            message_id TEXT NOT NULL,
+            channel_id TEXT,
+            dm_to TEXT,
+            sent_by TEXT NOT NULL,
+            content TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            flagged_at INTEGER NOT NULL,
+            FOREIGN KEY(sent_by) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // Channel polls. options is a JSON array of the choices; closed_at is set
+    // when the author or a moderator closes it, or closes_at passes.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS polls (
+            id TEXT PRIMARY KEY,
+            channel_id TEXT NOT NULL,
+            author_id TEXT NOT NULL,
+            question TEXT NOT NULL,
+            options TEXT NOT NULL,
+            multi_choice INTEGER NOT NULL DEFAULT 0,
+            closes_at INTEGER,
+            closed_at INTEGER,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY(channel_id) REFERENCES channels(id),
+            FOREIGN KEY(author_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    // One row per chosen option; a single-choice vote is one row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS poll_votes (
+            poll_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            option_index INTEGER NOT NULL,
+            voted_at INTEGER NOT NULL,
+            PRIMARY KEY(poll_id, user_id, option_index),
+            FOREIGN KEY(poll_id) REFERENCES polls(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
     info!("Database tables created/verified");
     Ok(())
 }
@@ -227,6 +545,26 @@ fn add_missing_columns(conn: &Connection) -> SqlResult<()> {
         ("location", "TEXT"),
         ("profile_pic", "TEXT"),
         ("cover_banner", "TEXT"),
+        ("created_at", "INTEGER"),
+        ("last_seen", "INTEGER"),
+        ("digest_opt_out", "INTEGER NOT NULL DEFAULT 0"),
+        ("updated_at", "INTEGER"),
+        ("admin_digest_enabled", "INTEGER NOT NULL DEFAULT 0"),
+        ("admin_digest_interval", "INTEGER NOT NULL DEFAULT 60"),
+        ("profile_visibility", "TEXT NOT NULL DEFAULT 'Public'"),
+        ("username_normalized", "TEXT"),
+        ("must_change_password", "INTEGER NOT NULL DEFAULT 0"),
+        // Set by `import-users`: contact note and the lowercased username from the import file
+        ("contact_note", "TEXT"),
+        ("imported_as", "TEXT"),
+        // Off: new channels in the user's servers send a join prompt instead of subscribing them
+        ("auto_join_new_channels", "INTEGER NOT NULL DEFAULT 1"),
+        // Opt-in: profile views are only recorded between two users who both enabled this
+        ("profile_view_notices", "INTEGER NOT NULL DEFAULT 0"),
+        ("profile_view_digest_at", "INTEGER"),
+        // Shown instead of the username when [users] display_names is on; NULL uses the username
+        ("display_name", "TEXT"),
+        ("display_name_normalized", "TEXT"),
     ];
 
     for (col, col_type) in user_columns.iter() {
@@ -241,6 +579,34 @@ fn add_missing_columns(conn: &Connection) -> SqlResult<()> {
         }
     }
 
+    // Lookalike-safe usernames for accounts created before normalization existed
+    let unnormalized: Vec<(String, String)> = {
+        let mut stmt = conn.prepare("SELECT id, username FROM users WHERE username_normalized IS NULL")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<SqlResult<_>>()?
+    };
+    for (id, username) in unnormalized {
+        conn.execute(
+            "UPDATE users SET username_normalized = ?1 WHERE id = ?2",
+            params![normalize_username(&username), id],
+        )?;
+    }
+
+    // Lookalike-safe display names set before they were normalized
+    let unnormalized: Vec<(String, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, display_name FROM users WHERE display_name IS NOT NULL AND display_name_normalized IS NULL"
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<SqlResult<_>>()?
+    };
+    for (id, display_name) in unnormalized {
+        conn.execute(
+            "UPDATE users SET display_name_normalized = ?1 WHERE id = ?2",
+            params![normalize_username(&display_name), id],
+        )?;
+    }
+
     // Add reply_to column to posts table for post replies feature
     let sql = "ALTER TABLE posts ADD COLUMN reply_to TEXT";
     let result = conn.execute(sql, []);
@@ -251,13 +617,135 @@ fn add_missing_columns(conn: &Connection) -> SqlResult<()> {
         }
     }
 
+    // Minimum role allowed to start threads and post in a forum
+    let sql = "ALTER TABLE forums ADD COLUMN min_post_role TEXT NOT NULL DEFAULT 'User'";
+    if let Err(e) = conn.execute(sql, []) {
+        // Ignore duplicate column errors
+        if !e.to_string().contains("duplicate column name") {
+            return Err(e);
+        }
+    }
+
+    // Cached reply depth so the nesting limit check doesn't walk the chain
+    match conn.execute("ALTER TABLE posts ADD COLUMN depth INTEGER NOT NULL DEFAULT 0", []) {
+        Ok(_) => {
+            // Backfill depth for replies that existed before the column
+            conn.execute(
+                "WITH RECURSIVE chain(id, depth) AS (
+                    SELECT id, 0 FROM posts WHERE reply_to IS NULL
+                    UNION ALL
+                    SELECT p.id, c.depth + 1 FROM posts p JOIN chain c ON p.reply_to = c.id
+                )
+                UPDATE posts
+                SET depth = COALESCE((SELECT depth FROM chain WHERE chain.id = posts.id), 1)
+                WHERE reply_to IS NOT NULL",
+                [],
+            )?;
+        }
+        Err(e) => {
+            // Ignore duplicate column errors
+            if !e.to_string().contains("duplicate column name") {
+                return Err(e);
+            }
+        }
+    }
+
+    // Channel-wide default permissions and link policy
+    let channel_columns = [
+        ("default_can_read", "INTEGER NOT NULL DEFAULT 1"),
+        ("default_can_write", "INTEGER NOT NULL DEFAULT 1"),
+        ("links_allowed", "INTEGER NOT NULL DEFAULT 1"),
+        ("link_allowlist", "TEXT"),
+        // Followers are notified of every message, not just mentions
+        ("notify_followers", "INTEGER NOT NULL DEFAULT 0"),
+    ];
+
+    for (col, col_type) in channel_columns.iter() {
+        let sql = format!("ALTER TABLE channels ADD COLUMN {} {}", col, col_type);
+        let result = conn.execute(&sql, []);
+
+        if let Err(e) = result {
+            // Ignore duplicate column errors
+            if !e.to_string().contains("duplicate column name") {
+                return Err(e);
+            }
+        }
+    }
+
+    // Server-generated join/rename events in channels
+    let alterations = [
+        "ALTER TABLE servers ADD COLUMN system_messages INTEGER NOT NULL DEFAULT 1",
+        "ALTER TABLE channel_messages ADD COLUMN system_event TEXT",
+        // Provenance of bridged/bot content, as JSON
+        "ALTER TABLE channel_messages ADD COLUMN origin TEXT",
+        // Tombstones for deleted messages, purged after the grace window
+        "ALTER TABLE channel_messages ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE channel_messages ADD COLUMN deleted_at INTEGER",
+        "ALTER TABLE channel_messages ADD COLUMN deleted_by TEXT",
+        // Times the message has been edited, capped by [messages] max_edits_per_message
+        "ALTER TABLE channel_messages ADD COLUMN edit_count INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE channel_messages ADD COLUMN edited_at INTEGER",
+        // The DM announcing an invite, resolved once the invite is answered or expires
+        "ALTER TABLE server_invites ADD COLUMN dm_id TEXT",
+        // When the invite was accepted, declined or expired
+        "ALTER TABLE server_invites ADD COLUMN responded_at INTEGER",
+        // Channel that mirrors the server's moderation events, set by the owner
+        "ALTER TABLE servers ADD COLUMN audit_channel_id TEXT",
+        // Greeting posted and/or DMed when someone joins the server
+        "ALTER TABLE servers ADD COLUMN welcome_channel_id TEXT",
+        "ALTER TABLE servers ADD COLUMN welcome_template TEXT",
+        "ALTER TABLE servers ADD COLUMN welcome_dm_template TEXT",
+        // Aggregate view count, bumped at most once per user per hour
+        "ALTER TABLE threads ADD COLUMN view_count INTEGER NOT NULL DEFAULT 0",
+        // Newest message the member has seen, for unread counts
+        "ALTER TABLE channel_users ADD COLUMN last_read_at INTEGER",
+        // Channel header details and who last changed them
+        "ALTER TABLE channels ADD COLUMN topic TEXT",
+        "ALTER TABLE channels ADD COLUMN created_at INTEGER",
+        "ALTER TABLE channels ADD COLUMN updated_by TEXT",
+        "ALTER TABLE channels ADD COLUMN updated_at INTEGER",
+    ];
+    for sql in alterations {
+        if let Err(e) = conn.execute(sql, []) {
+            // Ignore duplicate column errors
+            if !e.to_string().contains("duplicate column name") {
+                return Err(e);
+            }
+        }
+    }
+
     // Create indexes for better performance
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_messages_channel_timestamp ON channel_messages(channel_id, timestamp)", []);
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_direct_messages_users_timestamp ON direct_messages(from_user_id, to_user_id, timestamp)", []);
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_notifications_user_created ON notifications(user_id, created_at)", []);
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_server_users_server ON server_users(server_id)", []);
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_users_channel ON channel_users(channel_id)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_users_user ON channel_users(user_id)", []);
     let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_posts_reply_to ON posts(reply_to)", []); // Index for reply lookups
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_posts_thread_timestamp ON posts(thread_id, timestamp)", []);
+    let _ = conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_post_edits_post_revision ON post_edits(post_id, revision)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_message_bookmarks_user_created ON message_bookmarks(user_id, created_at)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_messages_sent_by ON channel_messages(sent_by)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_users_updated_at ON users(updated_at)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_server_user_roles_user ON server_user_roles(user_id)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_pending_deliveries_user ON pending_deliveries(user_id, id)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_users_username_normalized ON users(username_normalized)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_users_imported_as ON users(imported_as)", []);
+    // NULLs never collide in a plain unique index, so global mutes are keyed on ''
+    let _ = conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_personal_mutes_unique ON personal_mutes(muter_id, muted_id, IFNULL(channel_id, ''))", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_personal_mutes_muted ON personal_mutes(muted_id)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_server_invites_status_timestamp ON server_invites(status, timestamp)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_server_invites_pair ON server_invites(from_user_id, to_user_id, status)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp ON audit_log(timestamp)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_audit_log_user ON audit_log(user_id, timestamp)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action, timestamp)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_messages_deleted_at ON channel_messages(deleted_at) WHERE deleted = 1", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_channel_messages_timestamp ON channel_messages(timestamp)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_profile_views_viewed ON profile_views(viewed_id, viewed_at)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_message_reactions_message ON message_reactions(message_id)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_flagged_messages_channel ON flagged_messages(channel_id, flagged_at)", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_polls_closes_at ON polls(closes_at) WHERE closed_at IS NULL", []);
+    let _ = conn.execute("CREATE INDEX IF NOT EXISTS idx_poll_votes_poll ON poll_votes(poll_id)", []);
 
     info!("Database migration completed");
     Ok(())
@@ -6,5 +6,145 @@ pub mod notifications;
 pub mod servers;
 pub mod forums;
 pub mod invites;
+pub mod quarantine;
+pub mod server_roles;
+pub mod pending_deliveries;
+pub mod storage;
+pub mod audit;
+pub mod settings;
 pub mod db_config;
+pub mod polls;
 
+
+use rusqlite::{params, Connection, ErrorCode, OpenFlags};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Attempts made to open the database before giving up
+const OPEN_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry; doubles on each further attempt
+const OPEN_RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Set while opens are failing and being retried, cleared by the next successful open
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the database was unreachable on the last attempt to open it
+pub fn is_degraded() -> bool {
+    DEGRADED.load(Ordering::Relaxed)
+}
+
+/// Errors worth retrying: the file is missing or unreadable for a moment,
+/// e.g. network storage blipping. Anything else fails straight away.
+fn is_transient(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(err, _)
+            if matches!(err.code, ErrorCode::CannotOpen | ErrorCode::SystemIoFailure)
+    )
+}
+
+/// Run `open` with backoff on transient errors. Called from blocking tasks,
+/// so sleeping the thread is fine.
+fn open_with_retry(open: impl Fn() -> rusqlite::Result<Connection>) -> rusqlite::Result<Connection> {
+    let mut attempt = 1;
+    loop {
+        match open() {
+            Ok(conn) => {
+                DEGRADED.store(false, Ordering::Relaxed);
+                return Ok(conn);
+            }
+            Err(e) if attempt < OPEN_ATTEMPTS && is_transient(&e) => {
+                DEGRADED.store(true, Ordering::Relaxed);
+                let delay = OPEN_RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+                warn!("Database unavailable (attempt {}/{}), retrying in {:?}: {}", attempt, OPEN_ATTEMPTS, delay, e);
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => {
+                if is_transient(&e) {
+                    DEGRADED.store(true, Ordering::Relaxed);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Open a read-write connection to the database
+pub fn get_conn() -> rusqlite::Result<Connection> {
+    open_with_retry(|| {
+        let conn = Connection::open(db_config::get_db_path())?;
+        conn.busy_timeout(Duration::from_millis(crate::config::settings().sqlite.busy_timeout_ms))?;
+        Ok(conn)
+    })
+}
+
+/// Open a connection for queries that only read. With WAL these don't
+/// contend with writers; falls back to `get_conn` when disabled in config.
+pub fn get_read_conn() -> rusqlite::Result<Connection> {
+    let sqlite = crate::config::settings().sqlite.clone();
+    if !sqlite.read_only_connections {
+        return get_conn();
+    }
+
+    open_with_retry(|| {
+        let conn = Connection::open_with_flags(
+            db_config::get_db_path(),
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+        )?;
+        conn.busy_timeout(Duration::from_millis(sqlite.busy_timeout_ms))?;
+        Ok(conn)
+    })
+}
+
+/// Parse a UUID stored in a column, turning corrupt values into a row error instead of a panic
+pub fn parse_uuid_column(value: &str, column: usize) -> rusqlite::Result<Uuid> {
+    Uuid::parse_str(value).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+/// Cheap existence check for a row keyed by `id` in one of our tables
+async fn db_row_exists(table: &'static str, id: Uuid) -> Result<bool, String> {
+    let id_str = id.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let query = format!("SELECT EXISTS(SELECT 1 FROM {} WHERE id = ?1)", table);
+        let exists: bool = conn.query_row(&query, params![id_str], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+
+        Ok(exists)
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    #[tokio::test]
+    async fn read_connections_refuse_writes() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+
+        let conn = get_read_conn().unwrap();
+        let username: String = conn.query_row(
+            "SELECT username FROM users WHERE id = ?1", params![alice.id.to_string()], |row| row.get(0)
+        ).unwrap();
+        assert_eq!(username, "alice");
+
+        let write = conn.execute("UPDATE users SET username = 'mallory' WHERE id = ?1", params![alice.id.to_string()]);
+        assert!(matches!(
+            write,
+            Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == ErrorCode::ReadOnly
+        ));
+        assert!(get_conn().unwrap().execute("UPDATE users SET color = 'Red' WHERE id = ?1", params![alice.id.to_string()]).is_ok());
+    }
+}
@@ -1,6 +1,6 @@
-use crate::db::db_config;
+use crate::db::{get_conn, get_read_conn};
 use nexus_tui_common::{Notification, NotificationType};
-use rusqlite::{params, Connection};
+use rusqlite::{params, params_from_iter};
 use tokio::task;
 use uuid::Uuid;
 
@@ -13,10 +13,10 @@ pub async fn db_insert_notification(
     let user_id_str = user_id.to_string();
     let notif_type = notif_type.to_string();
     let related_id_str = related_id.to_string();
-    let now = chrono::Utc::now().timestamp();
+    let now = crate::util::now_secs();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let id = Uuid::new_v4();
 
         conn.execute(
@@ -33,11 +33,13 @@ pub async fn db_insert_notification(
 pub async fn db_get_notifications(
     user_id: Uuid,
     before: Option<i64>,
+    limit: usize,
 ) -> Result<(Vec<Notification>, bool), String> {
     let user_id_str = user_id.to_string();
+    let limit = limit as i64;
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
         let mut notifications = Vec::new();
         
@@ -47,10 +49,10 @@ pub async fn db_get_notifications(
                 "SELECT id, type, related_id, created_at, read, extra 
                  FROM notifications 
                  WHERE user_id = ? AND created_at < ? 
-                 ORDER BY created_at DESC LIMIT 50"
+                 ORDER BY created_at DESC LIMIT ?"
             ).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![user_id_str, before_ts], |row| {
+            let rows = stmt.query_map(params![user_id_str, before_ts, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
@@ -87,10 +89,10 @@ pub async fn db_get_notifications(
                 "SELECT id, type, related_id, created_at, read, extra 
                  FROM notifications 
                  WHERE user_id = ? 
-                 ORDER BY created_at DESC LIMIT 50"
+                 ORDER BY created_at DESC LIMIT ?"
             ).map_err(|e| e.to_string())?;
             
-            let rows = stmt.query_map(params![user_id_str], |row| {
+            let rows = stmt.query_map(params![user_id_str, limit], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
@@ -125,7 +127,7 @@ pub async fn db_get_notifications(
         }
 
         notifications.reverse(); // Oldest first
-        let history_complete = notifications.len() < 50;
+        let history_complete = (notifications.len() as i64) < limit;
 
         Ok((notifications, history_complete))
     })
@@ -133,11 +135,35 @@ pub async fn db_get_notifications(
     .unwrap()
 }
 
+/// Mark several of a user's notifications read in one statement. Ids that
+/// belong to other users are ignored. Returns how many rows changed.
+pub async fn db_mark_notifications_read(ids: &[Uuid], user_id: Uuid) -> Result<usize, String> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let user_id_str = user_id.to_string();
+    let id_strs: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let placeholders = vec!["?"; id_strs.len()].join(", ");
+        let query = format!(
+            "UPDATE notifications SET read = 1 WHERE user_id = ? AND id IN ({})",
+            placeholders
+        );
+        let args = std::iter::once(user_id_str).chain(id_strs);
+        conn.execute(&query, params_from_iter(args)).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
 pub async fn db_mark_notification_read(notification_id: Uuid) -> Result<(), String> {
     let notification_id_str = notification_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         conn.execute(
             "UPDATE notifications SET read = 1 WHERE id = ?1",
@@ -149,3 +175,177 @@ pub async fn db_mark_notification_read(notification_id: Uuid) -> Result<(), Stri
     .await
     .unwrap()
 }
+
+/// Collapse old unread notifications of inactive users into a single "Digest" row.
+/// Users last seen before `cutoff`, or never seen at all, who aren't in
+/// `online_user_ids` and haven't opted out get one digest per run; the originals
+/// are deleted. Returns how many digests were created.
+pub async fn db_rollup_notifications(cutoff: i64, online_user_ids: Vec<Uuid>) -> Result<usize, String> {
+    let online: Vec<String> = online_user_ids.iter().map(|id| id.to_string()).collect();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let candidates: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT DISTINCT n.user_id
+                 FROM notifications n
+                 JOIN users u ON u.id = n.user_id
+                 WHERE n.read = 0 AND n.type != 'Digest' AND n.created_at < ?1
+                   AND u.digest_opt_out = 0 AND (u.last_seen IS NULL OR u.last_seen < ?1)"
+            ).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map(params![cutoff], |row| row.get::<_, String>(0))
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+        };
+
+        let mut digests = 0;
+        for user_id in candidates.into_iter().filter(|id| !online.contains(id)) {
+            let counts: Vec<(String, i64)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT type, COUNT(*) FROM notifications
+                     WHERE user_id = ?1 AND read = 0 AND type != 'Digest' AND created_at < ?2
+                     GROUP BY type ORDER BY type"
+                ).map_err(|e| e.to_string())?;
+                let rows = stmt.query_map(params![user_id, cutoff], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                }).map_err(|e| e.to_string())?;
+                rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+            };
+
+            let summary = counts.iter()
+                .map(|(notif_type, count)| format!("{} {}", count, digest_label(notif_type, *count)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let counts_json: serde_json::Map<String, serde_json::Value> = counts.iter()
+                .map(|(notif_type, count)| (notif_type.clone(), serde_json::Value::from(*count)))
+                .collect();
+            let extra = serde_json::json!({
+                "summary": format!("While you were away: {}", summary),
+                "counts": counts_json,
+            }).to_string();
+
+            tx.execute(
+                "DELETE FROM notifications WHERE user_id = ?1 AND read = 0 AND type != 'Digest' AND created_at < ?2",
+                params![user_id, cutoff],
+            ).map_err(|e| e.to_string())?;
+            tx.execute(
+                "INSERT INTO notifications (id, user_id, type, related_id, created_at, read, extra) VALUES (?1, ?2, 'Digest', ?2, ?3, 0, ?4)",
+                params![Uuid::new_v4().to_string(), user_id, now, extra],
+            ).map_err(|e| e.to_string())?;
+            digests += 1;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(digests)
+    })
+    .await
+    .unwrap()
+}
+
+/// Human-readable label for a notification type in a digest summary
+fn digest_label(notif_type: &str, count: i64) -> String {
+    let label = match notif_type {
+        "ThreadReply" => "reply",
+        "Mention" => "mention",
+        "DM" => "DM",
+        "Announcement" => "announcement",
+        other => return other.to_string(),
+    };
+    match (label, count) {
+        (label, 1) => label.to_string(),
+        ("reply", _) => "replies".to_string(),
+        (label, _) => format!("{}s", label),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    /// Give `user_id` `count` unread notifications and return their ids
+    async fn unread(user_id: Uuid, count: usize) -> Vec<Uuid> {
+        for _ in 0..count {
+            db_insert_notification(user_id, "Mention", Uuid::new_v4(), None).await.unwrap();
+        }
+        let (notifications, _) = db_get_notifications(user_id, None, 50).await.unwrap();
+        notifications.into_iter().map(|notification| notification.id).collect()
+    }
+
+    async fn read_ids(user_id: Uuid) -> Vec<Uuid> {
+        let (notifications, _) = db_get_notifications(user_id, None, 50).await.unwrap();
+        notifications.into_iter().filter(|notification| notification.read).map(|notification| notification.id).collect()
+    }
+
+    #[tokio::test]
+    async fn a_batch_marks_exactly_the_listed_notifications() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let ids = unread(alice.id, 4).await;
+
+        assert_eq!(db_mark_notifications_read(&ids[..2], alice.id).await.unwrap(), 2);
+
+        let mut read = read_ids(alice.id).await;
+        let mut expected = ids[..2].to_vec();
+        read.sort();
+        expected.sort();
+        assert_eq!(read, expected);
+    }
+
+    #[tokio::test]
+    async fn a_batch_ignores_other_users_notifications() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let alice_ids = unread(alice.id, 1).await;
+        let bob_ids = unread(bob.id, 2).await;
+
+        let batch = [alice_ids[0], bob_ids[0], bob_ids[1]];
+        assert_eq!(db_mark_notifications_read(&batch, alice.id).await.unwrap(), 1);
+
+        assert_eq!(read_ids(alice.id).await, alice_ids);
+        assert!(read_ids(bob.id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_is_complete_only_when_a_page_comes_back_short() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        unread(alice.id, 5).await;
+
+        let (page, complete) = db_get_notifications(alice.id, None, 3).await.unwrap();
+        assert_eq!(page.len(), 3);
+        assert!(!complete);
+
+        let (page, complete) = db_get_notifications(alice.id, None, 10).await.unwrap();
+        assert_eq!(page.len(), 5);
+        assert!(complete);
+    }
+
+    #[tokio::test]
+    async fn users_never_seen_count_as_inactive_for_digests() {
+        let db = TestDb::new().await;
+        let never_seen = test_support::create_user("never_seen").await;
+        let active = test_support::create_user("active").await;
+        let online = test_support::create_user("online").await;
+        for user in [&never_seen, &active, &online] {
+            unread(user.id, 2).await;
+        }
+        let cutoff = crate::util::now_secs() + 60;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE users SET last_seen = ?1 WHERE id = ?2",
+            rusqlite::params![cutoff + 60, active.id.to_string()],
+        ).unwrap();
+
+        assert_eq!(db_rollup_notifications(cutoff, vec![online.id]).await.unwrap(), 1);
+
+        let (rolled_up, _) = db_get_notifications(never_seen.id, None, 50).await.unwrap();
+        assert_eq!(rolled_up.len(), 1);
+        for user in [&active, &online] {
+            assert_eq!(db_get_notifications(user.id, None, 50).await.unwrap().0.len(), 2);
+        }
+    }
+}
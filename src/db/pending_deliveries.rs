@@ -0,0 +1,89 @@
+// Pending delivery DB functions
+
+use crate::db::get_conn;
+use rusqlite::{params, params_from_iter};
+use tokio::task;
+use uuid::Uuid;
+
+/// Queue a serialized message for a user, evicting their oldest entries beyond `max_per_user`
+pub async fn db_queue_pending_delivery(
+    user_id: Uuid,
+    kind: &str,
+    payload: Vec<u8>,
+    max_per_user: usize,
+) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let kind = kind.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "INSERT INTO pending_deliveries (user_id, kind, payload, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![user_id_str, kind, payload, now],
+        ).map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "DELETE FROM pending_deliveries WHERE user_id = ?1 AND id NOT IN (
+                SELECT id FROM pending_deliveries WHERE user_id = ?1 ORDER BY id DESC LIMIT ?2
+            )",
+            params![user_id_str, max_per_user as i64],
+        ).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A user's queued payloads with their row ids, oldest first. They stay
+/// queued until removed with `db_delete_pending_deliveries`.
+pub async fn db_get_pending_deliveries(user_id: Uuid) -> Result<Vec<(i64, Vec<u8>)>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT id, payload FROM pending_deliveries WHERE user_id = ?1 ORDER BY id ASC"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![user_id_str], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove queued payloads by row id, once they have been handed over
+pub async fn db_delete_pending_deliveries(ids: Vec<i64>) -> Result<usize, String> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        conn.execute(
+            &format!("DELETE FROM pending_deliveries WHERE id IN ({})", placeholders),
+            params_from_iter(ids.iter()),
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Drop queued messages created before `cutoff`, returning how many were removed
+pub async fn db_purge_pending_deliveries(cutoff: i64) -> Result<usize, String> {
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "DELETE FROM pending_deliveries WHERE created_at < ?1",
+            params![cutoff],
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
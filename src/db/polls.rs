@@ -0,0 +1,214 @@
+// Channel poll DB functions
+
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use nexus_tui_common::{Poll, PollResults};
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::task;
+use uuid::Uuid;
+
+/// Outcome of casting or changing a vote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PollVote {
+    Recorded,
+    NotFound,
+    Closed,
+    /// The voter isn't a member of the poll's channel
+    NotMember,
+}
+
+const POLL_COLUMNS: &str = "id, channel_id, author_id, question, options, multi_choice, closes_at, closed_at, created_at";
+
+fn poll_from_row(row: &rusqlite::Row) -> rusqlite::Result<Poll> {
+    let options: String = row.get(4)?;
+    Ok(Poll {
+        id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+        channel_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+        author_id: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
+        question: row.get(3)?,
+        options: serde_json::from_str(&options).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        multi_choice: row.get(5)?,
+        closes_at: row.get(6)?,
+        closed: row.get::<_, Option<i64>>(7)?.is_some(),
+        created_at: row.get(8)?,
+    })
+}
+
+/// Create a poll in a channel
+pub async fn db_create_poll(
+    channel_id: Uuid,
+    author_id: Uuid,
+    question: &str,
+    options: Vec<String>,
+    multi_choice: bool,
+    closes_at: Option<i64>,
+) -> Result<Poll, String> {
+    let poll = Poll {
+        id: Uuid::new_v4(),
+        channel_id,
+        author_id,
+        question: question.to_string(),
+        options,
+        multi_choice,
+        closes_at,
+        closed: false,
+        created_at: crate::util::now_secs(),
+    };
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let options = serde_json::to_string(&poll.options).map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "INSERT INTO polls (id, channel_id, author_id, question, options, multi_choice, closes_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                poll.id.to_string(), poll.channel_id.to_string(), poll.author_id.to_string(),
+                poll.question, options, poll.multi_choice, poll.closes_at, poll.created_at
+            ],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(poll)
+    })
+    .await
+    .unwrap()
+}
+
+pub async fn db_get_poll(poll_id: Uuid) -> Result<Option<Poll>, String> {
+    let poll_id_str = poll_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            &format!("SELECT {} FROM polls WHERE id = ?1", POLL_COLUMNS),
+            params![poll_id_str],
+            poll_from_row,
+        ).optional().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Replace a user's votes on a poll with `option_indices` (empty retracts
+/// them). Indices must already be validated against the poll's options.
+pub async fn db_vote_poll(poll_id: Uuid, user_id: Uuid, option_indices: Vec<usize>) -> Result<PollVote, String> {
+    let poll_id_str = poll_id.to_string();
+    let user_id_str = user_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let poll = tx.query_row(
+            "SELECT p.closed_at IS NOT NULL OR (p.closes_at IS NOT NULL AND p.closes_at <= ?2),
+                    EXISTS(SELECT 1 FROM channel_users cu WHERE cu.channel_id = p.channel_id AND cu.user_id = ?3)
+             FROM polls p WHERE p.id = ?1",
+            params![poll_id_str, now, user_id_str],
+            |row| Ok((row.get::<_, bool>(0)?, row.get::<_, bool>(1)?)),
+        ).optional().map_err(|e| e.to_string())?;
+        match poll {
+            None => return Ok(PollVote::NotFound),
+            Some((true, _)) => return Ok(PollVote::Closed),
+            Some((false, false)) => return Ok(PollVote::NotMember),
+            Some((false, true)) => {}
+        }
+
+        tx.execute(
+            "DELETE FROM poll_votes WHERE poll_id = ?1 AND user_id = ?2",
+            params![poll_id_str, user_id_str],
+        ).map_err(|e| e.to_string())?;
+        for index in option_indices {
+            tx.execute(
+                "INSERT OR IGNORE INTO poll_votes (poll_id, user_id, option_index, voted_at) VALUES (?1, ?2, ?3, ?4)",
+                params![poll_id_str, user_id_str, index as i64, now],
+            ).map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(PollVote::Recorded)
+    })
+    .await
+    .unwrap()
+}
+
+/// Close a poll. Returns false if it was already closed.
+pub async fn db_close_poll(poll_id: Uuid) -> Result<bool, String> {
+    let poll_id_str = poll_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let updated = conn.execute(
+            "UPDATE polls SET closed_at = ?2 WHERE id = ?1 AND closed_at IS NULL",
+            params![poll_id_str, now],
+        ).map_err(|e| e.to_string())?;
+        Ok(updated > 0)
+    })
+    .await
+    .unwrap()
+}
+
+fn poll_results(conn: &Connection, poll: &Poll) -> Result<PollResults, String> {
+    let poll_id_str = poll.id.to_string();
+    let mut counts = vec![0u32; poll.options.len()];
+
+    let mut stmt = conn.prepare(
+        "SELECT option_index, COUNT(*) FROM poll_votes WHERE poll_id = ?1 GROUP BY option_index"
+    ).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map(params![poll_id_str], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        let (index, count) = row.map_err(|e| e.to_string())?;
+        if let Some(slot) = usize::try_from(index).ok().and_then(|index| counts.get_mut(index)) {
+            *slot = count as u32;
+        }
+    }
+
+    let voters: i64 = conn.query_row(
+        "SELECT COUNT(DISTINCT user_id) FROM poll_votes WHERE poll_id = ?1",
+        params![poll_id_str],
+        |row| row.get(0),
+    ).map_err(|e| e.to_string())?;
+
+    Ok(PollResults {
+        poll_id: poll.id,
+        channel_id: poll.channel_id,
+        counts,
+        voters: voters as u32,
+        closed: poll.closed,
+    })
+}
+
+/// Current tallies for a poll
+pub async fn db_get_poll_results(poll_id: Uuid) -> Result<Option<PollResults>, String> {
+    let poll_id_str = poll_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let poll = conn.query_row(
+            &format!("SELECT {} FROM polls WHERE id = ?1", POLL_COLUMNS),
+            params![poll_id_str],
+            poll_from_row,
+        ).optional().map_err(|e| e.to_string())?;
+        poll.map(|poll| poll_results(&conn, &poll)).transpose()
+    })
+    .await
+    .unwrap()
+}
+
+/// Open polls whose closing time has passed
+pub async fn db_get_expired_poll_ids(now: i64) -> Result<Vec<Uuid>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM polls WHERE closed_at IS NULL AND closes_at IS NOT NULL AND closes_at <= ?1"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![now], |row| parse_uuid_column(&row.get::<_, String>(0)?, 0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
@@ -0,0 +1,303 @@
+// Quarantined and flagged message DB functions
+
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use nexus_tui_common::{ChannelMessage, FlaggedMessage};
+use rusqlite::{params, OptionalExtension};
+use tokio::task;
+use uuid::Uuid;
+
+/// Hold a message back for moderator review, returning the quarantine entry id
+pub async fn db_quarantine_message(
+    channel_id: Uuid,
+    sent_by: Uuid,
+    content: &str,
+    timestamp: i64,
+    reason: &str,
+) -> Result<Uuid, String> {
+    let channel_id_str = channel_id.to_string();
+    let sent_by_str = sent_by.to_string();
+    let content = content.to_string();
+    let reason = reason.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let id = Uuid::new_v4();
+
+        conn.execute(
+            "INSERT INTO quarantined_messages (id, channel_id, sent_by, content, timestamp, reason) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.to_string(), channel_id_str, sent_by_str, content, timestamp, reason],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(id)
+    })
+    .await
+    .unwrap()
+}
+
+/// Get a quarantined message. The returned message's `id` is the quarantine entry id.
+pub async fn db_get_quarantined_message(quarantine_id: Uuid) -> Result<(ChannelMessage, String), String> {
+    let quarantine_id_str = quarantine_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let (channel_id, sent_by, content, timestamp, reason) = conn.query_row(
+            "SELECT channel_id, sent_by, content, timestamp, reason FROM quarantined_messages WHERE id = ?1",
+            params![quarantine_id_str],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        ).map_err(|_| "Quarantined message not found".to_string())?;
+
+        let message = ChannelMessage {
+            id: quarantine_id,
+            channel_id: Uuid::parse_str(&channel_id).map_err(|e| e.to_string())?,
+            sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
+            timestamp,
+            content,
+            system_event: None,
+            origin: None,
+            channel_refs: Vec::new(),
+            emojis: Vec::new(),
+            reactions: Vec::new(),
+        };
+
+        Ok((message, reason))
+    })
+    .await
+    .unwrap()
+}
+
+/// List quarantined messages awaiting review, oldest first. When `server_mod_id` is
+/// given, only messages from servers that user moderates are returned.
+pub async fn db_get_quarantined_messages(server_mod_id: Option<Uuid>) -> Result<Vec<(ChannelMessage, String)>, String> {
+    let server_mod_id_str = server_mod_id.map(|id| id.to_string());
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, channel_id, sent_by, content, timestamp, reason
+             FROM quarantined_messages
+             WHERE ?1 IS NULL OR channel_id IN (
+                 SELECT c.id FROM channels c
+                 JOIN server_mods sm ON c.server_id = sm.server_id
+                 WHERE sm.user_id = ?1
+             )
+             ORDER BY timestamp ASC"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![server_mod_id_str], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        }).map_err(|e| e.to_string())?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            let (id, channel_id, sent_by, content, timestamp, reason) = row.map_err(|e| e.to_string())?;
+            messages.push((
+                ChannelMessage {
+                    id: Uuid::parse_str(&id).map_err(|e| e.to_string())?,
+                    channel_id: Uuid::parse_str(&channel_id).map_err(|e| e.to_string())?,
+                    sent_by: Uuid::parse_str(&sent_by).map_err(|e| e.to_string())?,
+                    timestamp,
+                    content,
+                    system_event: None,
+                    origin: None,
+                    channel_refs: Vec::new(),
+                    emojis: Vec::new(),
+                    reactions: Vec::new(),
+                },
+                reason,
+            ));
+        }
+
+        Ok(messages)
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove a quarantine entry once it has been reviewed
+pub async fn db_delete_quarantined_message(quarantine_id: Uuid) -> Result<(), String> {
+    let quarantine_id_str = quarantine_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let deleted = conn.execute(
+            "DELETE FROM quarantined_messages WHERE id = ?1",
+            params![quarantine_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        if deleted == 0 {
+            return Err("Quarantined message not found".to_string());
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Put a reviewed entry back under its original id, e.g. when releasing it failed
+pub async fn db_restore_quarantined_message(message: &ChannelMessage, reason: &str) -> Result<(), String> {
+    let id_str = message.id.to_string();
+    let channel_id_str = message.channel_id.to_string();
+    let sent_by_str = message.sent_by.to_string();
+    let content = message.content.clone();
+    let timestamp = message.timestamp;
+    let reason = reason.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT INTO quarantined_messages (id, channel_id, sent_by, content, timestamp, reason) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id_str, channel_id_str, sent_by_str, content, timestamp, reason],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Count messages still waiting for review
+pub async fn db_count_quarantined_messages() -> Result<i64, String> {
+    task::spawn_blocking(|| {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.query_row("SELECT COUNT(*) FROM quarantined_messages", [], |row| row.get(0))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Record a delivered message the content filter flagged, for moderator
+/// review. `channel_id` is None for a DM, `dm_to` None for a channel message.
+pub async fn db_flag_message(
+    message_id: Uuid,
+    channel_id: Option<Uuid>,
+    dm_to: Option<Uuid>,
+    sent_by: Uuid,
+    content: &str,
+    reason: &str,
+    flagged_at: i64,
+) -> Result<Uuid, String> {
+    let content = content.to_string();
+    let reason = reason.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let id = Uuid::new_v4();
+
+        conn.execute(
+            "INSERT INTO flagged_messages (id, message_id, channel_id, dm_to, sent_by, content, reason, flagged_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                id.to_string(), message_id.to_string(), channel_id.map(|id| id.to_string()),
+                dm_to.map(|id| id.to_string()), sent_by.to_string(), content, reason, flagged_at
+            ],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(id)
+    })
+    .await
+    .unwrap()
+}
+
+const FLAGGED_COLUMNS: &str = "id, message_id, channel_id, dm_to, sent_by, content, reason, flagged_at";
+
+fn flagged_from_row(row: &rusqlite::Row) -> rusqlite::Result<FlaggedMessage> {
+    let optional_uuid = |index: usize| -> rusqlite::Result<Option<Uuid>> {
+        row.get::<_, Option<String>>(index)?
+            .map(|value| parse_uuid_column(&value, index))
+            .transpose()
+    };
+    Ok(FlaggedMessage {
+        id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+        message_id: parse_uuid_column(&row.get::<_, String>(1)?, 1)?,
+        channel_id: optional_uuid(2)?,
+        dm_to: optional_uuid(3)?,
+        sent_by: parse_uuid_column(&row.get::<_, String>(4)?, 4)?,
+        content: row.get(5)?,
+        reason: row.get(6)?,
+        flagged_at: row.get(7)?,
+    })
+}
+
+pub async fn db_get_flagged_message(flag_id: Uuid) -> Result<Option<FlaggedMessage>, String> {
+    let flag_id_str = flag_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            &format!("SELECT {} FROM flagged_messages WHERE id = ?1", FLAGGED_COLUMNS),
+            params![flag_id_str],
+            flagged_from_row,
+        ).optional().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// List flagged messages awaiting review, oldest first. When `server_mod_id` is
+/// given, only channel messages from servers that user moderates are returned;
+/// flagged DMs are left to global staff.
+pub async fn db_get_flagged_messages(server_mod_id: Option<Uuid>) -> Result<Vec<FlaggedMessage>, String> {
+    let server_mod_id_str = server_mod_id.map(|id| id.to_string());
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM flagged_messages
+             WHERE ?1 IS NULL OR channel_id IN (
+                 SELECT c.id FROM channels c
+                 JOIN server_mods sm ON c.server_id = sm.server_id
+                 WHERE sm.user_id = ?1
+             )
+             ORDER BY flagged_at ASC",
+            FLAGGED_COLUMNS
+        )).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![server_mod_id_str], flagged_from_row)
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove a flagged message entry once it has been reviewed
+pub async fn db_delete_flagged_message(flag_id: Uuid) -> Result<(), String> {
+    let flag_id_str = flag_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let deleted = conn.execute(
+            "DELETE FROM flagged_messages WHERE id = ?1",
+            params![flag_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        if deleted == 0 {
+            return Err("Flagged message not found".to_string());
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
@@ -0,0 +1,101 @@
+// Server role DB functions (permission groups scoped to a server)
+
+use crate::db::get_conn;
+use rusqlite::params;
+use tokio::task;
+use uuid::Uuid;
+
+/// Create a role in a server with its default channel permissions
+pub async fn db_create_server_role(
+    server_id: Uuid,
+    name: &str,
+    can_read: bool,
+    can_write: bool,
+) -> Result<Uuid, String> {
+    let server_id_str = server_id.to_string();
+    let name = name.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let id = Uuid::new_v4();
+
+        conn.execute(
+            "INSERT INTO server_roles (id, server_id, name, can_read, can_write) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id.to_string(), server_id_str, name, can_read as i32, can_write as i32],
+        ).map_err(|e| {
+            if e.to_string().contains("UNIQUE constraint failed") {
+                "A role with that name already exists".to_string()
+            } else {
+                e.to_string()
+            }
+        })?;
+
+        Ok(id)
+    })
+    .await
+    .unwrap()
+}
+
+/// Get the server a role belongs to
+pub async fn db_get_role_server_id(role_id: Uuid) -> Result<Uuid, String> {
+    let role_id_str = role_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let server_id: String = conn.query_row(
+            "SELECT server_id FROM server_roles WHERE id = ?1",
+            params![role_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Role not found".to_string())?;
+
+        Uuid::parse_str(&server_id).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Give a user a role
+pub async fn db_assign_server_role(role_id: Uuid, user_id: Uuid) -> Result<(), String> {
+    let role_id_str = role_id.to_string();
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "INSERT OR IGNORE INTO server_user_roles (role_id, user_id) VALUES (?1, ?2)",
+            params![role_id_str, user_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Override a role's permissions for a single channel
+pub async fn db_set_role_channel_permission(
+    role_id: Uuid,
+    channel_id: Uuid,
+    can_read: bool,
+    can_write: bool,
+) -> Result<(), String> {
+    let role_id_str = role_id.to_string();
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "INSERT INTO server_role_channel_perms (role_id, channel_id, can_read, can_write)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(role_id, channel_id) DO UPDATE SET can_read = excluded.can_read, can_write = excluded.can_write",
+            params![role_id_str, channel_id_str, can_read as i32, can_write as i32],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
@@ -1,6 +1,6 @@
-use crate::db::db_config;
-use nexus_tui_common::Server;
-use rusqlite::{params, Connection};
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use nexus_tui_common::{ChannelMembership, Server, ServerEmoji, ServerMembership, ServerPreview, ServerSummary};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
 use tokio::task;
 use uuid::Uuid;
 
@@ -18,7 +18,7 @@ pub async fn db_create_server(
     let banner = banner.map(|s| s.to_string());
     let owner = owner.to_string();
     tokio::task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let id = Uuid::new_v4();
         conn.execute(
             "INSERT INTO servers (id, name, description, public, owner, icon, banner) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
@@ -36,132 +36,263 @@ pub async fn db_create_server(
     }).await.unwrap()
 }
 
+/// Server columns selected by the queries that build full `Server` values
+const SERVER_COLUMNS: &str = "s.id, s.name, s.description, s.public, s.invite_code, s.icon, s.banner, s.owner";
+
+/// Build a full `Server` (mods, members, channels) from a row selecting `SERVER_COLUMNS`
+fn load_server(conn: &Connection, row: &rusqlite::Row) -> Result<Server, String> {
+    let id: String = row.get(0).map_err(|e| e.to_string())?;
+    let server_id = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
+
+    // Get moderators
+    let mut mods_stmt = conn.prepare("SELECT user_id FROM server_mods WHERE server_id = ?1")
+        .map_err(|e| e.to_string())?;
+    let mods: Vec<Uuid> = mods_stmt.query_map(params![id], |row| {
+        let user_id_str: String = row.get(0)?;
+        parse_uuid_column(&user_id_str, 0)
+    }).map_err(|e| e.to_string())?
+    .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+    // Get userlist
+    let mut users_stmt = conn.prepare("SELECT user_id FROM server_users WHERE server_id = ?1")
+        .map_err(|e| e.to_string())?;
+    let userlist: Vec<Uuid> = users_stmt.query_map(params![id], |row| {
+        let user_id_str: String = row.get(0)?;
+        parse_uuid_column(&user_id_str, 0)
+    }).map_err(|e| e.to_string())?
+    .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+    let owner: String = row.get(7).map_err(|e| e.to_string())?;
+    Ok(Server {
+        id: server_id,
+        name: row.get(1).map_err(|e| e.to_string())?,
+        description: row.get(2).map_err(|e| e.to_string())?,
+        public: row.get::<_, i32>(3).map_err(|e| e.to_string())? != 0,
+        invite_code: row.get(4).map_err(|e| e.to_string())?,
+        icon: row.get(5).map_err(|e| e.to_string())?,
+        banner: row.get(6).map_err(|e| e.to_string())?,
+        owner: Uuid::parse_str(&owner).map_err(|e| e.to_string())?,
+        mods,
+        userlist,
+        channels: load_server_channels(conn, server_id)?,
+    })
+}
+
+/// Channel metadata (members, permissions, no messages) for one server
+fn load_server_channels(conn: &Connection, server_id: Uuid) -> Result<Vec<nexus_tui_common::Channel>, String> {
+    // Get channels (simplified - just metadata without messages)
+    let mut channels_stmt = conn.prepare(
+        "SELECT id, name, description FROM channels WHERE server_id = ?1"
+    ).map_err(|e| e.to_string())?;
+    let channel_rows = channels_stmt.query_map(params![server_id.to_string()], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    }).map_err(|e| e.to_string())?;
+
+    let mut channels = Vec::new();
+    for channel_row in channel_rows {
+        let (chan_id, chan_name, chan_desc) = channel_row.map_err(|e| e.to_string())?;
+        let channel_id = Uuid::parse_str(&chan_id).map_err(|e| e.to_string())?;
+        
+        // Get channel userlist
+        let mut cu_stmt = conn.prepare("SELECT user_id FROM channel_users WHERE channel_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let channel_userlist: Vec<Uuid> = cu_stmt.query_map(params![chan_id], |row| {
+            let user_id_str: String = row.get(0)?;
+            parse_uuid_column(&user_id_str, 0)
+        }).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+
+        // Get permissions (simplified)
+        let mut perm_stmt = conn.prepare(
+            "SELECT user_id, can_read, can_write FROM channel_permissions WHERE channel_id = ?1"
+        ).map_err(|e| e.to_string())?;
+        let mut can_read = Vec::new();
+        let mut can_write = Vec::new();
+        
+        let perm_rows = perm_stmt.query_map(params![chan_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i32>(1)?,
+                row.get::<_, i32>(2)?,
+            ))
+        }).map_err(|e| e.to_string())?;
+
+        for perm_row in perm_rows {
+            let (uid, read, write) = perm_row.map_err(|e| e.to_string())?;
+            let uuid = Uuid::parse_str(&uid).map_err(|e| e.to_string())?;
+            if read != 0 { can_read.push(uuid); }
+            if write != 0 { can_write.push(uuid); }
+        }
+
+        channels.push(nexus_tui_common::Channel {
+            id: channel_id,
+            server_id,
+            name: chan_name,
+            description: chan_desc,
+            permissions: nexus_tui_common::ChannelPermissions { can_read, can_write },
+            userlist: channel_userlist,
+            messages: Vec::new(), // Always empty in server list
+        });
+    }
+
+    Ok(channels)
+}
+
+/// Every server a user belongs to, with channels. Heavy for users in many
+/// servers; clients that negotiate `lazy_servers` use the summaries instead.
 pub async fn db_get_user_servers(user_id: Uuid) -> Result<Vec<Server>, String> {
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
         
+        let query = format!(
+            "SELECT {} FROM servers s
+             INNER JOIN server_users su ON s.id = su.server_id
+             WHERE su.user_id = ?1",
+            SERVER_COLUMNS
+        );
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let mut rows = stmt.query(params![user_id_str]).map_err(|e| e.to_string())?;
+
+        let mut servers = Vec::new();
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            servers.push(load_server(&conn, row)?);
+        }
+
+        Ok(servers)
+    })
+    .await
+    .unwrap()
+}
+
+/// One server with its channels and permissions, for opening it on demand
+pub async fn db_get_server_detail(server_id: Uuid) -> Result<Server, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let query = format!("SELECT {} FROM servers s WHERE s.id = ?1", SERVER_COLUMNS);
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let mut rows = stmt.query(params![server_id_str]).map_err(|e| e.to_string())?;
+
+        match rows.next().map_err(|e| e.to_string())? {
+            Some(row) => load_server(&conn, row),
+            None => Err("Server not found".to_string()),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Lightweight summaries of a user's servers: no channels or member lists.
+/// `last_message_at` lets clients flag servers with activity they haven't seen.
+pub async fn db_get_user_server_summaries(user_id: Uuid) -> Result<Vec<ServerSummary>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
         let mut stmt = conn.prepare(
-            "SELECT s.id, s.name, s.description, s.public, s.invite_code, s.icon, s.banner, s.owner 
-             FROM servers s 
-             INNER JOIN server_users su ON s.id = su.server_id 
-             WHERE su.user_id = ?1"
+            "SELECT s.id, s.name, s.icon,
+                    (SELECT COUNT(*) FROM server_users WHERE server_id = s.id),
+                    (SELECT MAX(cm.timestamp) FROM channel_messages cm
+                     JOIN channels c ON c.id = cm.channel_id
+                     WHERE c.server_id = s.id AND cm.deleted = 0)
+             FROM servers s
+             INNER JOIN server_users su ON s.id = su.server_id
+             WHERE su.user_id = ?1
+             ORDER BY s.name"
         ).map_err(|e| e.to_string())?;
 
-        let server_rows = stmt.query_map(params![user_id_str], |row| {
+        let rows = stmt.query_map(params![user_id_str], |row| {
+            Ok(ServerSummary {
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                name: row.get(1)?,
+                icon: row.get(2)?,
+                member_count: row.get::<_, i64>(3)? as usize,
+                last_message_at: row.get(4)?,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Every server a user belongs to with the channels they're a member of, ids and names only
+pub async fn db_get_user_memberships(user_id: Uuid) -> Result<Vec<ServerMembership>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT s.id, s.name, c.id, c.name
+             FROM server_users su
+             JOIN servers s ON s.id = su.server_id
+             LEFT JOIN (channel_users cu JOIN channels c ON c.id = cu.channel_id)
+                 ON c.server_id = s.id AND cu.user_id = su.user_id
+             WHERE su.user_id = ?1
+             ORDER BY s.name, s.id, c.name"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![user_id_str], |row| {
             Ok((
-                row.get::<_, String>(0)?,
+                parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 row.get::<_, String>(1)?,
-                row.get::<_, String>(2)?,
-                row.get::<_, i32>(3)?,
-                row.get::<_, Option<String>>(4)?,
-                row.get::<_, Option<String>>(5)?,
-                row.get::<_, Option<String>>(6)?,
-                row.get::<_, String>(7)?,
+                row.get::<_, Option<String>>(2)?
+                    .map(|id| parse_uuid_column(&id, 2))
+                    .transpose()?,
+                row.get::<_, Option<String>>(3)?,
             ))
         }).map_err(|e| e.to_string())?;
 
-        let mut servers = Vec::new();
-        for server_row in server_rows {
-            let (id, name, description, public, invite_code, icon, banner, owner) = 
-                server_row.map_err(|e| e.to_string())?;
-            
-            let server_id = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
-            
-            // Get moderators
-            let mut mods_stmt = conn.prepare("SELECT user_id FROM server_mods WHERE server_id = ?1")
-                .map_err(|e| e.to_string())?;
-            let mods: Vec<Uuid> = mods_stmt.query_map(params![id], |row| {
-                let user_id_str: String = row.get(0)?;
-                Ok(Uuid::parse_str(&user_id_str).unwrap())
-            }).map_err(|e| e.to_string())?
-            .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
-
-            // Get userlist
-            let mut users_stmt = conn.prepare("SELECT user_id FROM server_users WHERE server_id = ?1")
-                .map_err(|e| e.to_string())?;
-            let userlist: Vec<Uuid> = users_stmt.query_map(params![id], |row| {
-                let user_id_str: String = row.get(0)?;
-                Ok(Uuid::parse_str(&user_id_str).unwrap())
-            }).map_err(|e| e.to_string())?
-            .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
-
-            // Get channels (simplified - just metadata without messages)
-            let mut channels_stmt = conn.prepare(
-                "SELECT id, name, description FROM channels WHERE server_id = ?1"
-            ).map_err(|e| e.to_string())?;
-            let channel_rows = channels_stmt.query_map(params![id], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                ))
-            }).map_err(|e| e.to_string())?;
-
-            let mut channels = Vec::new();
-            for channel_row in channel_rows {
-                let (chan_id, chan_name, chan_desc) = channel_row.map_err(|e| e.to_string())?;
-                let channel_id = Uuid::parse_str(&chan_id).map_err(|e| e.to_string())?;
-                
-                // Get channel userlist
-                let mut cu_stmt = conn.prepare("SELECT user_id FROM channel_users WHERE channel_id = ?1")
-                    .map_err(|e| e.to_string())?;
-                let channel_userlist: Vec<Uuid> = cu_stmt.query_map(params![chan_id], |row| {
-                    let user_id_str: String = row.get(0)?;
-                    Ok(Uuid::parse_str(&user_id_str).unwrap())
-                }).map_err(|e| e.to_string())?
-                .collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
-
-                // Get permissions (simplified)
-                let mut perm_stmt = conn.prepare(
-                    "SELECT user_id, can_read, can_write FROM channel_permissions WHERE channel_id = ?1"
-                ).map_err(|e| e.to_string())?;
-                let mut can_read = Vec::new();
-                let mut can_write = Vec::new();
-                
-                let perm_rows = perm_stmt.query_map(params![chan_id], |row| {
-                    Ok((
-                        row.get::<_, String>(0)?,
-                        row.get::<_, i32>(1)?,
-                        row.get::<_, i32>(2)?,
-                    ))
-                }).map_err(|e| e.to_string())?;
-
-                for perm_row in perm_rows {
-                    let (uid, read, write) = perm_row.map_err(|e| e.to_string())?;
-                    let uuid = Uuid::parse_str(&uid).map_err(|e| e.to_string())?;
-                    if read != 0 { can_read.push(uuid); }
-                    if write != 0 { can_write.push(uuid); }
-                }
-
-                channels.push(nexus_tui_common::Channel {
-                    id: channel_id,
-                    server_id,
-                    name: chan_name,
-                    description: chan_desc,
-                    permissions: nexus_tui_common::ChannelPermissions { can_read, can_write },
-                    userlist: channel_userlist,
-                    messages: Vec::new(), // Always empty in server list
-                });
+        let mut memberships: Vec<ServerMembership> = Vec::new();
+        for row in rows {
+            let (server_id, server_name, channel_id, channel_name) = row.map_err(|e| e.to_string())?;
+            if memberships.last().is_none_or(|m| m.server_id != server_id) {
+                memberships.push(ServerMembership { server_id, server_name, channels: Vec::new() });
+            }
+            if let (Some(channel_id), Some(name)) = (channel_id, channel_name) {
+                memberships.last_mut().unwrap().channels.push(ChannelMembership { channel_id, name });
             }
-
-            servers.push(Server {
-                id: server_id,
-                name,
-                description,
-                public: public != 0,
-                invite_code,
-                icon,
-                banner,
-                owner: Uuid::parse_str(&owner).map_err(|e| e.to_string())?,
-                mods,
-                userlist,
-                channels,
-            });
         }
+        Ok(memberships)
+    })
+    .await
+    .unwrap()
+}
 
-        Ok(servers)
+/// Public-facing info about the server an invite code belongs to, without joining it
+pub async fn db_get_server_by_invite_code(code: &str) -> Result<Option<ServerPreview>, String> {
+    let code = code.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        conn.query_row(
+            "SELECT s.id, s.name, s.description, s.icon, s.public,
+                    (SELECT COUNT(*) FROM server_users WHERE server_id = s.id)
+             FROM servers s WHERE s.invite_code = ?1",
+            params![code],
+            |row| {
+                Ok(ServerPreview {
+                    id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                    name: row.get(1)?,
+                    description: row.get(2)?,
+                    icon: row.get(3)?,
+                    public: row.get::<_, i32>(4)? != 0,
+                    member_count: row.get::<_, i64>(5)? as usize,
+                })
+            },
+        ).optional().map_err(|e| e.to_string())
     })
     .await
     .unwrap()
@@ -169,7 +300,7 @@ pub async fn db_get_user_servers(user_id: Uuid) -> Result<Vec<Server>, String> {
 
 pub async fn get_default_server_id() -> Result<Option<Uuid>, String> {
     task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare("SELECT id FROM servers ORDER BY rowid ASC LIMIT 1")
             .map_err(|e| e.to_string())?;
@@ -186,7 +317,7 @@ pub async fn get_default_server_id() -> Result<Option<Uuid>, String> {
 /// Get all servers (simplified for user registration)
 pub async fn db_get_servers() -> Result<Vec<nexus_tui_common::Server>, String> {
     task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare(
             "SELECT id, name, description, owner FROM servers ORDER BY id LIMIT 1"
@@ -228,7 +359,7 @@ pub async fn db_add_user_to_server(server_id: Uuid, user_id: Uuid) -> Result<(),
     let user_id_str = user_id.to_string();
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         conn.execute(
             "INSERT OR IGNORE INTO server_users (server_id, user_id) VALUES (?1, ?2)",
@@ -246,7 +377,7 @@ pub async fn db_is_user_in_server(user_id: Uuid, server_id: Uuid) -> Result<bool
     let server_id_str = server_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         let mut stmt = conn.prepare("SELECT COUNT(*) FROM server_users WHERE user_id = ?1 AND server_id = ?2")
             .map_err(|e| e.to_string())?;
@@ -260,9 +391,163 @@ pub async fn db_is_user_in_server(user_id: Uuid, server_id: Uuid) -> Result<bool
     .unwrap()
 }
 
+/// Whether the user has an unexpired ban from the server
+pub async fn db_is_user_banned(user_id: Uuid, server_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let server_id_str = server_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM server_bans
+             WHERE user_id = ?1 AND server_id = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+            params![user_id_str, server_id_str, now],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+
+        Ok(count > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Drop a user's membership of a server: its channels, follows, roles, mod
+/// status and the membership itself
+fn remove_server_member(conn: &Connection, server_id: &str, user_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM channel_users WHERE user_id = ?1 AND channel_id IN (SELECT id FROM channels WHERE server_id = ?2)",
+        params![user_id, server_id],
+    )?;
+    conn.execute(
+        "DELETE FROM server_user_roles WHERE user_id = ?1 AND role_id IN (SELECT id FROM server_roles WHERE server_id = ?2)",
+        params![user_id, server_id],
+    )?;
+    conn.execute(
+        "DELETE FROM channel_followers WHERE user_id = ?1 AND channel_id IN (SELECT id FROM channels WHERE server_id = ?2)",
+        params![user_id, server_id],
+    )?;
+    conn.execute("DELETE FROM server_mods WHERE user_id = ?1 AND server_id = ?2", params![user_id, server_id])?;
+    conn.execute("DELETE FROM server_users WHERE user_id = ?1 AND server_id = ?2", params![user_id, server_id])?;
+    Ok(())
+}
+
+/// Ban a user from a server and remove them from it. A repeated ban replaces
+/// the earlier one. `expires_at` None is permanent.
+pub async fn db_ban_user(
+    server_id: Uuid,
+    user_id: Uuid,
+    banned_by: Uuid,
+    reason: Option<String>,
+    expires_at: Option<i64>,
+) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+    let user_id_str = user_id.to_string();
+    let banned_by_str = banned_by.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO server_bans (server_id, user_id, banned_by, reason, banned_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![server_id_str, user_id_str, banned_by_str, reason, now, expires_at],
+        ).map_err(|e| e.to_string())?;
+        remove_server_member(&tx, &server_id_str, &user_id_str).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Lift a ban. Returns false if the user wasn't banned.
+pub async fn db_unban_user(server_id: Uuid, user_id: Uuid) -> Result<bool, String> {
+    let server_id_str = server_id.to_string();
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let deleted = conn.execute(
+            "DELETE FROM server_bans WHERE server_id = ?1 AND user_id = ?2",
+            params![server_id_str, user_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(deleted > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove a member from a server and its channels without banning them.
+/// Returns false if they weren't a member.
+pub async fn db_remove_user_from_server(server_id: Uuid, user_id: Uuid) -> Result<bool, String> {
+    let server_id_str = server_id.to_string();
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let is_member: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM server_users WHERE server_id = ?1 AND user_id = ?2",
+            params![server_id_str, user_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        if is_member == 0 {
+            return Ok(false);
+        }
+        remove_server_member(&tx, &server_id_str, &user_id_str).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(true)
+    })
+    .await
+    .unwrap()
+}
+
+/// IDs of every member of a server
+pub async fn db_get_server_member_ids(server_id: Uuid) -> Result<Vec<Uuid>, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare("SELECT user_id FROM server_users WHERE server_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![server_id_str], |row| parse_uuid_column(&row.get::<_, String>(0)?, 0))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Check whether two users are members of at least one common server
+pub async fn db_users_share_server(user_a: Uuid, user_b: Uuid) -> Result<bool, String> {
+    let user_a_str = user_a.to_string();
+    let user_b_str = user_b.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT EXISTS(
+                SELECT 1 FROM server_users a
+                JOIN server_users b ON a.server_id = b.server_id
+                WHERE a.user_id = ?1 AND b.user_id = ?2
+            )",
+            params![user_a_str, user_b_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
 pub async fn ensure_default_server_exists() -> Result<(), String> {
     task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         
         // Check if any servers exist
         let count: i64 = conn.query_row("SELECT COUNT(*) FROM servers", [], |row| row.get(0))
@@ -323,3 +608,379 @@ pub async fn ensure_default_server_exists() -> Result<(), String> {
     .await
     .unwrap()
 }
+
+/// Update a server's name and description, returning the previous name
+pub async fn db_update_server(server_id: Uuid, name: &str, description: &str) -> Result<String, String> {
+    let server_id_str = server_id.to_string();
+    let name = name.to_string();
+    let description = description.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let old_name: String = conn.query_row(
+            "SELECT name FROM servers WHERE id = ?1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Server not found".to_string())?;
+
+        conn.execute(
+            "UPDATE servers SET name = ?1, description = ?2 WHERE id = ?3",
+            params![name, description, server_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(old_name)
+    })
+    .await
+    .unwrap()
+}
+
+/// Check if a user is the owner or a moderator of a server
+pub async fn db_is_user_server_mod(user_id: Uuid, server_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM servers s
+             WHERE s.id = ?2 AND (s.owner = ?1 OR EXISTS (
+                 SELECT 1 FROM server_mods sm WHERE sm.server_id = s.id AND sm.user_id = ?1
+             ))",
+            params![user_id_str, server_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+
+        Ok(count > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Check whether a server exists
+pub async fn db_server_exists(server_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("servers", server_id).await
+}
+
+/// Get the channel a server posts system messages in (its oldest channel),
+/// or None if the server has system messages turned off
+pub async fn db_get_server_system_channel(server_id: Uuid) -> Result<Option<Uuid>, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let enabled: i32 = conn.query_row(
+            "SELECT system_messages FROM servers WHERE id = ?1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Server not found".to_string())?;
+        if enabled == 0 {
+            return Ok(None);
+        }
+
+        let channel_id: Option<String> = conn.query_row(
+            "SELECT id FROM channels WHERE server_id = ?1 ORDER BY rowid ASC LIMIT 1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).ok();
+
+        match channel_id {
+            Some(id) => Ok(Some(Uuid::parse_str(&id).map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// The channel a server mirrors moderation events into, if its owner set one
+pub async fn db_get_server_audit_channel(server_id: Uuid) -> Result<Option<Uuid>, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let channel_id: Option<String> = conn.query_row(
+            "SELECT audit_channel_id FROM servers WHERE id = ?1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Server not found".to_string())?;
+
+        channel_id.map(|id| Uuid::parse_str(&id).map_err(|e| e.to_string())).transpose()
+    })
+    .await
+    .unwrap()
+}
+
+/// Set or clear a server's audit channel
+pub async fn db_set_server_audit_channel(server_id: Uuid, channel_id: Option<Uuid>) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+    let channel_id_str = channel_id.map(|id| id.to_string());
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE servers SET audit_channel_id = ?1 WHERE id = ?2",
+            params![channel_id_str, server_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A server's greeting for new members. The channel message needs both the
+/// channel and the template; the DM only its template.
+#[derive(Debug, Clone, Default)]
+pub struct WelcomeSettings {
+    pub channel_id: Option<Uuid>,
+    pub template: Option<String>,
+    pub dm_template: Option<String>,
+}
+
+/// A change to a server's welcome settings: `None` leaves a field as it is,
+/// `Some(None)` clears it
+#[derive(Debug, Clone, Default)]
+pub struct WelcomeUpdate {
+    pub channel_id: Option<Option<Uuid>>,
+    pub template: Option<Option<String>>,
+    pub dm_template: Option<Option<String>>,
+}
+
+/// Get a server's welcome settings
+pub async fn db_get_server_welcome(server_id: Uuid) -> Result<WelcomeSettings, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let (channel_id, template, dm_template) = conn.query_row(
+            "SELECT welcome_channel_id, welcome_template, welcome_dm_template FROM servers WHERE id = ?1",
+            params![server_id_str],
+            |row| Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            )),
+        ).map_err(|_| "Server not found".to_string())?;
+
+        Ok(WelcomeSettings {
+            channel_id: channel_id.map(|id| Uuid::parse_str(&id).map_err(|e| e.to_string())).transpose()?,
+            template,
+            dm_template,
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Replace a server's welcome settings
+pub async fn db_set_server_welcome(server_id: Uuid, welcome: WelcomeSettings) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE servers SET welcome_channel_id = ?1, welcome_template = ?2, welcome_dm_template = ?3 WHERE id = ?4",
+            params![
+                welcome.channel_id.map(|id| id.to_string()),
+                welcome.template,
+                welcome.dm_template,
+                server_id_str
+            ],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Change only the welcome settings named in `update`, in one statement so
+/// concurrent edits of different fields don't undo each other
+pub async fn db_update_server_welcome(server_id: Uuid, update: WelcomeUpdate) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE servers SET
+                welcome_channel_id = CASE WHEN ?1 THEN ?2 ELSE welcome_channel_id END,
+                welcome_template = CASE WHEN ?3 THEN ?4 ELSE welcome_template END,
+                welcome_dm_template = CASE WHEN ?5 THEN ?6 ELSE welcome_dm_template END
+             WHERE id = ?7",
+            params![
+                update.channel_id.is_some(),
+                update.channel_id.flatten().map(|id| id.to_string()),
+                update.template.is_some(),
+                update.template.flatten(),
+                update.dm_template.is_some(),
+                update.dm_template.flatten(),
+                server_id_str
+            ],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// The user who owns a server
+pub async fn db_get_server_owner(server_id: Uuid) -> Result<Uuid, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let owner: String = conn.query_row(
+            "SELECT owner FROM servers WHERE id = ?1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).map_err(|_| "Server not found".to_string())?;
+
+        Uuid::parse_str(&owner).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Turn system messages on or off for a server
+pub async fn db_set_server_system_messages(server_id: Uuid, enabled: bool) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE servers SET system_messages = ?1 WHERE id = ?2",
+            params![enabled as i32, server_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Register a custom emoji for a server. Fails if the name is already taken
+/// or the server already has `max_emojis`; both checks run in one transaction.
+pub async fn db_add_server_emoji(
+    server_id: Uuid,
+    name: &str,
+    image_ref: &str,
+    created_by: Uuid,
+    max_emojis: usize,
+) -> Result<(), String> {
+    let server_id_str = server_id.to_string();
+    let name = name.to_string();
+    let image_ref = image_ref.to_string();
+    let created_by_str = created_by.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM server_emojis WHERE server_id = ?1",
+            params![server_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        if count as usize >= max_emojis {
+            return Err(format!("This server already has the maximum of {} custom emoji", max_emojis));
+        }
+
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO server_emojis (server_id, name, image_ref, created_by, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![server_id_str, name, image_ref, created_by_str, now],
+        ).map_err(|e| e.to_string())?;
+        if inserted == 0 {
+            return Err(format!("An emoji named :{}: already exists", name));
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// All custom emoji of a server, by name
+pub async fn db_get_server_emojis(server_id: Uuid) -> Result<Vec<ServerEmoji>, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT name, image_ref, created_by FROM server_emojis WHERE server_id = ?1 ORDER BY name"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![server_id_str], |row| {
+            Ok(ServerEmoji {
+                name: row.get(0)?,
+                image_ref: row.get(1)?,
+                created_by: parse_uuid_column(&row.get::<_, String>(2)?, 2)?,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Remove a custom emoji; returns whether it existed
+pub async fn db_delete_server_emoji(server_id: Uuid, name: &str) -> Result<bool, String> {
+    let server_id_str = server_id.to_string();
+    let name = name.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let deleted = conn.execute(
+            "DELETE FROM server_emojis WHERE server_id = ?1 AND name = ?2",
+            params![server_id_str, name],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(deleted > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Look up emoji names in the server owning `channel_id`, in one query.
+/// Names without a matching emoji are left out.
+pub async fn db_resolve_channel_emojis(channel_id: Uuid, names: Vec<String>) -> Result<Vec<(String, String)>, String> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let placeholders = vec!["?"; names.len()].join(", ");
+        let query = format!(
+            "SELECT e.name, e.image_ref FROM server_emojis e
+             JOIN channels c ON c.server_id = e.server_id
+             WHERE c.id = ? AND e.name IN ({})",
+            placeholders
+        );
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let args = std::iter::once(channel_id_str).chain(names);
+        let rows = stmt.query_map(params_from_iter(args), |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
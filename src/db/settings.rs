@@ -0,0 +1,53 @@
+// Runtime server settings DB functions
+
+use crate::db::{get_conn, get_read_conn};
+use rusqlite::{params, OptionalExtension};
+use tokio::task;
+use uuid::Uuid;
+
+/// Get a runtime setting, if it has ever been set
+pub async fn db_get_setting(key: &str) -> Result<Option<String>, String> {
+    let key = key.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        conn.query_row(
+            "SELECT value FROM server_settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Set a runtime setting, returning its previous value
+pub async fn db_set_setting(key: &str, value: &str, updated_by: Uuid) -> Result<Option<String>, String> {
+    let key = key.to_string();
+    let value = value.to_string();
+    let updated_by = updated_by.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let old: Option<String> = tx.query_row(
+            "SELECT value FROM server_settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "INSERT INTO server_settings (key, value, updated_at, updated_by) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(key) DO UPDATE SET value = ?2, updated_at = ?3, updated_by = ?4",
+            params![key, value, now, updated_by],
+        ).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(old)
+    })
+    .await
+    .unwrap()
+}
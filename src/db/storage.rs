@@ -0,0 +1,169 @@
+// Per-user storage accounting DB functions
+
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use crate::db::users::SYSTEM_USER_ID;
+use rusqlite::params;
+use tokio::task;
+use uuid::Uuid;
+
+/// Stored bytes attributed to one user
+#[derive(Debug, Clone)]
+pub struct UserStorage {
+    pub user_id: Uuid,
+    pub username: String,
+    pub message_bytes: i64,
+    pub media_bytes: i64,
+}
+
+impl UserStorage {
+    pub fn total_bytes(&self) -> i64 {
+        self.message_bytes + self.media_bytes
+    }
+}
+
+/// Add the size of a newly stored message to a user's total
+pub async fn db_add_message_bytes(user_id: Uuid, bytes: i64) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT INTO user_storage (user_id, message_bytes, media_bytes, updated_at) VALUES (?1, ?2, 0, ?3)
+             ON CONFLICT(user_id) DO UPDATE SET message_bytes = message_bytes + ?2, updated_at = ?3",
+            params![user_id_str, bytes, now],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Recount a user's media bytes from their current profile images
+pub async fn db_refresh_media_bytes(user_id: Uuid) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT INTO user_storage (user_id, message_bytes, media_bytes, updated_at)
+             SELECT id, 0, COALESCE(LENGTH(profile_pic), 0) + COALESCE(LENGTH(cover_banner), 0), ?2
+             FROM users WHERE id = ?1
+             ON CONFLICT(user_id) DO UPDATE SET media_bytes = excluded.media_bytes, updated_at = ?2",
+            params![user_id_str, now],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get a user's current storage usage and whether they've been warned about the soft quota
+pub async fn db_get_user_storage(user_id: Uuid) -> Result<(i64, i64, bool), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let usage = conn.query_row(
+            "SELECT message_bytes, media_bytes, soft_quota_warned FROM user_storage WHERE user_id = ?1",
+            params![user_id_str],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i32>(2)? != 0)),
+        );
+        match usage {
+            Ok(usage) => Ok(usage),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok((0, 0, false)),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Sizes of a user's current profile picture and cover banner
+pub async fn db_get_profile_image_bytes(user_id: Uuid) -> Result<(i64, i64), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT COALESCE(LENGTH(profile_pic), 0), COALESCE(LENGTH(cover_banner), 0) FROM users WHERE id = ?1",
+            params![user_id_str],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Remember whether a user has been told they're over the soft quota
+pub async fn db_set_soft_quota_warned(user_id: Uuid, warned: bool) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.execute(
+            "UPDATE user_storage SET soft_quota_warned = ?1 WHERE user_id = ?2",
+            params![warned as i32, user_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Users using the most storage, largest first
+pub async fn db_get_top_storage_users(limit: usize) -> Result<Vec<UserStorage>, String> {
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare(
+            "SELECT s.user_id, u.username, s.message_bytes, s.media_bytes
+             FROM user_storage s JOIN users u ON u.id = s.user_id
+             ORDER BY s.message_bytes + s.media_bytes DESC
+             LIMIT ?1"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![limit as i64], |row| {
+            Ok(UserStorage {
+                user_id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                username: row.get(1)?,
+                message_bytes: row.get(2)?,
+                media_bytes: row.get(3)?,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Rebuild every user's totals from the stored messages and images, correcting any drift
+pub async fn db_recompute_user_storage() -> Result<usize, String> {
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let updated = tx.execute(
+            "INSERT INTO user_storage (user_id, message_bytes, media_bytes, updated_at)
+             SELECT u.id,
+                    COALESCE((SELECT SUM(LENGTH(content)) FROM channel_messages WHERE sent_by = u.id), 0)
+                    + COALESCE((SELECT SUM(LENGTH(content)) FROM direct_messages WHERE from_user_id = u.id), 0),
+                    COALESCE(LENGTH(u.profile_pic), 0) + COALESCE(LENGTH(u.cover_banner), 0),
+                    ?1
+             FROM users u WHERE u.id != ?2
+             ON CONFLICT(user_id) DO UPDATE SET
+                message_bytes = excluded.message_bytes,
+                media_bytes = excluded.media_bytes,
+                updated_at = excluded.updated_at",
+            params![now, SYSTEM_USER_ID.to_string()],
+        ).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(updated)
+    })
+    .await
+    .unwrap()
+}
@@ -1,17 +1,41 @@
 use crate::auth::{hash_password, verify_password};
-use crate::db::db_config;
-use crate::util::parse_user_color;
-use nexus_tui_common::{UserProfile, UserRole, UserInfo, UserStatus};
-use rusqlite::{params, Connection};
+use crate::db::{get_conn, get_read_conn, parse_uuid_column};
+use crate::util::{normalize_username, parse_user_color};
+use nexus_tui_common::{PersonalMute, ProfileVisibility, UserProfile, UserRole, UserInfo, UserStatus};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
 use tokio::task;
 use tracing::info;
 use uuid::Uuid;
 
+/// Id of the built-in account that authors server-generated messages
+pub const SYSTEM_USER_ID: Uuid = Uuid::nil();
+
+/// Create the built-in System account if it doesn't exist yet. Its password hash
+/// is not a valid hash, so it can never log in.
+pub async fn ensure_system_user_exists() -> Result<(), String> {
+    task::spawn_blocking(|| {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let now = crate::util::now_secs();
+
+        conn.execute(
+            "INSERT OR IGNORE INTO users (id, username, username_normalized, password_hash, color, role, created_at, updated_at)
+             VALUES (?1, 'System', ?2, '!', 'Gray', 'User', ?3, ?3)",
+            params![SYSTEM_USER_ID.to_string(), normalize_username("System"), now],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Count real (non-system) users
 pub async fn db_count_users() -> Result<i64, String> {
     task::spawn_blocking(|| {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let count: i64 = conn
-            .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+            .query_row("SELECT COUNT(*) FROM users WHERE id != ?1", params![SYSTEM_USER_ID.to_string()], |row| row.get(0))
             .map_err(|e| e.to_string())?;
         Ok(count)
     })
@@ -24,21 +48,23 @@ pub async fn db_get_user_info_by_id(user_id: Uuid) -> Result<UserInfo, String> {
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, username, color, role FROM users WHERE id = ?1"
+            "SELECT id, username, color, role, display_name FROM users WHERE id = ?1"
         ).map_err(|e| e.to_string())?;
 
         let user_info = stmt.query_row(params![user_id_str], |row| {
             let role_str: String = row.get(3)?;
             Ok(UserInfo {
-                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 username: row.get(1)?,
+                display_name: visible_display_name(row.get(4)?),
                 color: parse_user_color(&row.get::<_, String>(2)?),
                 role: match role_str.as_str() {
                     "Admin" => UserRole::Admin,
                     "Moderator" => UserRole::Moderator,
+                    "Bot" => UserRole::Bot,
                     _ => UserRole::User,
                 },
                 status: UserStatus::Offline,
@@ -61,10 +87,10 @@ pub async fn db_get_users_info_by_ids(user_ids: &[Uuid]) -> Result<Vec<UserInfo>
     let placeholders = user_ids_str.iter().map(|_| "?").collect::<Vec<_>>().join(",");
     
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let query = format!(
-            "SELECT id, username, color, role FROM users WHERE id IN ({})", 
+            "SELECT id, username, color, role, display_name FROM users WHERE id IN ({})", 
             placeholders
         );
         
@@ -74,12 +100,14 @@ pub async fn db_get_users_info_by_ids(user_ids: &[Uuid]) -> Result<Vec<UserInfo>
         let rows = stmt.query_map(&params[..], |row| {
             let role_str: String = row.get(3)?;
             Ok(UserInfo {
-                id: Uuid::parse_str(&row.get::<_, String>(0)?).unwrap(),
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
                 username: row.get(1)?,
+                display_name: visible_display_name(row.get(4)?),
                 color: parse_user_color(&row.get::<_, String>(2)?),
                 role: match role_str.as_str() {
                     "Admin" => UserRole::Admin,
                     "Moderator" => UserRole::Moderator,
+                    "Bot" => UserRole::Bot,
                     _ => UserRole::User,
                 },
                 status: UserStatus::Connected,
@@ -105,19 +133,25 @@ pub async fn db_register_user(
 ) -> Result<UserProfile, String> {
     let username = username.to_string();
     let username_lower = username.to_lowercase();
+    let username_normalized = normalize_username(&username);
     let password = password.to_string();
     let color = color.to_string();
     let role = role.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
-        // Check if username exists (case insensitive)
+        // Check if username exists (case insensitive, or a lookalike of an existing
+        // username or of someone's display name)
         let mut stmt = conn
-            .prepare("SELECT COUNT(*) FROM users WHERE LOWER(username) = ?1")
+            .prepare(
+                "SELECT COUNT(*) FROM users
+                 WHERE LOWER(username) = ?1 OR username_normalized = ?2
+                    OR LOWER(display_name) = ?1 OR display_name_normalized = ?2"
+            )
             .map_err(|e| e.to_string())?;
         let exists: i64 = stmt
-            .query_row(params![username_lower], |row| row.get(0))
+            .query_row(params![username_lower, username_normalized], |row| row.get(0))
             .map_err(|e| e.to_string())?;
 
         if exists > 0 {
@@ -126,10 +160,11 @@ pub async fn db_register_user(
 
         let id = Uuid::new_v4();
         let hash = hash_password(&password).map_err(|e| e.to_string())?;
+        let now = crate::util::now_secs();
 
         conn.execute(
-            "INSERT INTO users (id, username, password_hash, color, role) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id.to_string(), username, hash, color, role],
+            "INSERT INTO users (id, username, username_normalized, password_hash, color, role, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+            params![id.to_string(), username, username_normalized, hash, color, role, now],
         )
         .map_err(|e| e.to_string())?;
 
@@ -143,6 +178,7 @@ pub async fn db_register_user(
             role: match role.as_str() {
                 "Admin" => UserRole::Admin,
                 "Moderator" => UserRole::Moderator,
+                "Bot" => UserRole::Bot,
                 _ => UserRole::User,
             },
             bio: None,
@@ -163,7 +199,7 @@ pub async fn db_login_user(username: &str, password: &str) -> Result<UserProfile
     let password = password.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn
             .prepare("SELECT id, username, password_hash, color, role, bio, url1, url2, url3, location, profile_pic, cover_banner FROM users WHERE LOWER(username) = ?1")
@@ -193,13 +229,14 @@ pub async fn db_login_user(username: &str, password: &str) -> Result<UserProfile
         }
 
         Ok(UserProfile {
-            id: Uuid::parse_str(&user.0).unwrap(),
+            id: Uuid::parse_str(&user.0).map_err(|e| e.to_string())?,
             username: user.1,
             hash: String::new(), // Don't return password hash
             color: parse_user_color(&user.3),
             role: match user.4.as_str() {
                 "Admin" => UserRole::Admin,
                 "Moderator" => UserRole::Moderator,
+                "Bot" => UserRole::Bot,
                 _ => UserRole::User,
             },
             bio: user.5,
@@ -219,7 +256,7 @@ pub async fn db_get_user_by_id(user_id: Uuid) -> Result<UserProfile, String> {
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
             "SELECT id, username, password_hash, color, role, bio, url1, url2, url3, location, profile_pic, cover_banner 
@@ -246,13 +283,14 @@ pub async fn db_get_user_by_id(user_id: Uuid) -> Result<UserProfile, String> {
             .map_err(|_| "User not found".to_string())?;
 
         Ok(UserProfile {
-            id: Uuid::parse_str(&user.0).unwrap(),
+            id: Uuid::parse_str(&user.0).map_err(|e| e.to_string())?,
             username: user.1,
             hash: String::new(), // Don't return password hash
             color: parse_user_color(&user.3),
             role: match user.4.as_str() {
                 "Admin" => UserRole::Admin,
                 "Moderator" => UserRole::Moderator,
+                "Bot" => UserRole::Bot,
                 _ => UserRole::User,
             },
             bio: user.5,
@@ -272,7 +310,7 @@ pub async fn db_get_user_by_username(username: &str) -> Result<UserProfile, Stri
     let username_lower = username.to_lowercase();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
             "SELECT id, username, password_hash, color, role, bio, url1, url2, url3, location, profile_pic, cover_banner 
@@ -299,13 +337,14 @@ pub async fn db_get_user_by_username(username: &str) -> Result<UserProfile, Stri
             .map_err(|_| "User not found".to_string())?;
 
         Ok(UserProfile {
-            id: Uuid::parse_str(&user.0).unwrap(),
+            id: Uuid::parse_str(&user.0).map_err(|e| e.to_string())?,
             username: user.1,
             hash: String::new(), // Don't return password hash
             color: parse_user_color(&user.3),
             role: match user.4.as_str() {
                 "Admin" => UserRole::Admin,
                 "Moderator" => UserRole::Moderator,
+                "Bot" => UserRole::Bot,
                 _ => UserRole::User,
             },
             bio: user.5,
@@ -321,19 +360,39 @@ pub async fn db_get_user_by_username(username: &str) -> Result<UserProfile, Stri
     .unwrap()
 }
 
+/// Update columns on a user row and bump `updated_at` in the same statement.
+/// Every mutation visible to other users goes through here so incremental sync
+/// never misses a change. `set_clause` uses ?1..?N for `values`.
+fn update_user_row(
+    conn: &Connection,
+    user_id: &str,
+    set_clause: &str,
+    values: &[&dyn rusqlite::ToSql],
+) -> Result<usize, String> {
+    let now = crate::util::now_secs();
+    let query = format!(
+        "UPDATE users SET {}, updated_at = ?{} WHERE id = ?{}",
+        set_clause,
+        values.len() + 1,
+        values.len() + 2
+    );
+
+    let mut all_values: Vec<&dyn rusqlite::ToSql> = values.to_vec();
+    all_values.push(&now);
+    all_values.push(&user_id);
+
+    conn.execute(&query, &all_values[..]).map_err(|e| e.to_string())
+}
+
 pub async fn db_update_user_password(user_id: Uuid, new_password: &str) -> Result<(), String> {
     let user_id_str = user_id.to_string();
     let new_password = new_password.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
         let hash = hash_password(&new_password).map_err(|e| e.to_string())?;
 
-        conn.execute(
-            "UPDATE users SET password_hash = ?1 WHERE id = ?2",
-            params![hash, user_id_str],
-        )
-        .map_err(|e| e.to_string())?;
+        update_user_row(&conn, &user_id_str, "password_hash = ?1, must_change_password = 0", params![hash])?;
 
         Ok(())
     })
@@ -341,18 +400,77 @@ pub async fn db_update_user_password(user_id: Uuid, new_password: &str) -> Resul
     .unwrap()
 }
 
+/// Replace a bot account's password in a single statement, so the old one
+/// stops working the moment the new one is stored. False if `bot_id` isn't a bot.
+pub async fn db_rotate_bot_password(bot_id: Uuid, new_password: &str) -> Result<bool, String> {
+    let bot_id_str = bot_id.to_string();
+    let new_password = new_password.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let hash = hash_password(&new_password).map_err(|e| e.to_string())?;
+
+        let updated = conn.execute(
+            "UPDATE users SET password_hash = ?1, must_change_password = 0, updated_at = ?2
+             WHERE id = ?3 AND role = 'Bot'",
+            params![hash, crate::util::now_secs(), bot_id_str],
+        ).map_err(|e| e.to_string())?;
+        Ok(updated > 0)
+    })
+    .await
+    .unwrap()
+}
+
 pub async fn db_update_user_color(user_id: Uuid, color: &str) -> Result<(), String> {
     let user_id_str = user_id.to_string();
     let color = color.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
-        conn.execute(
-            "UPDATE users SET color = ?1 WHERE id = ?2",
-            params![color, user_id_str],
-        )
-        .map_err(|e| e.to_string())?;
+        update_user_row(&conn, &user_id_str, "color = ?1", params![color])?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// A stored display name as clients get to see it: none while
+/// `[users] display_names` is off
+pub(crate) fn visible_display_name(stored: Option<String>) -> Option<String> {
+    if crate::config::settings().users.display_names { stored } else { None }
+}
+
+/// Set or clear (None) a user's display name. It may match their own
+/// username but not, even as a lookalike, anyone else's.
+pub async fn db_update_display_name(user_id: Uuid, display_name: Option<String>) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let display_name_normalized = display_name.as_deref().map(normalize_username);
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        if let (Some(name), Some(normalized)) = (&display_name, &display_name_normalized) {
+            let taken: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM users
+                 WHERE (LOWER(username) = LOWER(?1) OR username_normalized = ?2) AND id != ?3",
+                params![name, normalized, user_id_str],
+                |row| row.get(0),
+            ).map_err(|e| e.to_string())?;
+            if taken > 0 {
+                return Err("Display name matches another user's username".to_string());
+            }
+        }
+
+        if update_user_row(
+            &conn,
+            &user_id_str,
+            "display_name = ?1, display_name_normalized = ?2",
+            params![display_name, display_name_normalized],
+        )? == 0 {
+            return Err("User not found".to_string());
+        }
 
         Ok(())
     })
@@ -373,13 +491,14 @@ pub async fn db_update_user_profile(
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
-        conn.execute(
-            "UPDATE users SET bio = ?1, url1 = ?2, url2 = ?3, url3 = ?4, location = ?5, profile_pic = ?6, cover_banner = ?7 WHERE id = ?8",
-            params![bio, url1, url2, url3, location, profile_pic, cover_banner, user_id_str],
-        )
-        .map_err(|e| e.to_string())?;
+        update_user_row(
+            &conn,
+            &user_id_str,
+            "bio = ?1, url1 = ?2, url2 = ?3, url3 = ?4, location = ?5, profile_pic = ?6, cover_banner = ?7",
+            params![bio, url1, url2, url3, location, profile_pic, cover_banner],
+        )?;
 
         Ok(())
     })
@@ -391,7 +510,7 @@ pub async fn db_get_user_profile(user_id: Uuid) -> Result<UserProfile, String> {
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
             "SELECT id, username, bio, url1, url2, url3, location, profile_pic, cover_banner, color, role 
@@ -417,13 +536,14 @@ pub async fn db_get_user_profile(user_id: Uuid) -> Result<UserProfile, String> {
             .map_err(|_| "User not found".to_string())?;
 
         Ok(UserProfile {
-            id: Uuid::parse_str(&user.0).unwrap(),
+            id: Uuid::parse_str(&user.0).map_err(|e| e.to_string())?,
             username: user.1,
             hash: String::new(), // Don't return password hash
             color: parse_user_color(&user.9),
             role: match user.10.as_str() {
                 "Admin" => UserRole::Admin,
                 "Moderator" => UserRole::Moderator,
+                "Bot" => UserRole::Bot,
                 _ => UserRole::User,
             },
             bio: user.2,
@@ -444,7 +564,7 @@ pub async fn db_get_user_avatar(user_id: Uuid) -> Result<Option<String>, String>
     let user_id_str = user_id.to_string();
 
     task::spawn_blocking(move || {
-        let conn = Connection::open(db_config::get_db_path()).map_err(|e| e.to_string())?;
+        let conn = get_conn().map_err(|e| e.to_string())?;
 
         let mut stmt = conn.prepare(
             "SELECT profile_pic FROM users WHERE id = ?1"
@@ -459,3 +579,799 @@ pub async fn db_get_user_avatar(user_id: Uuid) -> Result<Option<String>, String>
     .await
     .unwrap()
 }
+
+/// Get when a user registered. Accounts created before this was tracked return None.
+pub async fn db_get_user_created_at(user_id: Uuid) -> Result<Option<i64>, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let created_at = conn.query_row(
+            "SELECT created_at FROM users WHERE id = ?1",
+            params![user_id_str],
+            |row| row.get::<_, Option<i64>>(0),
+        ).map_err(|_| "User not found".to_string())?;
+
+        Ok(created_at)
+    })
+    .await
+    .unwrap()
+}
+
+/// Get IDs of users who moderate a server: global Admins/Moderators plus the server's mods
+pub async fn db_get_moderator_ids(server_id: Uuid) -> Result<Vec<Uuid>, String> {
+    let server_id_str = server_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id FROM users WHERE role IN ('Admin', 'Moderator')
+             UNION
+             SELECT user_id FROM server_mods WHERE server_id = ?1"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(params![server_id_str], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+
+        let mut ids = Vec::new();
+        for row in rows {
+            let id_str = row.map_err(|e| e.to_string())?;
+            ids.push(Uuid::parse_str(&id_str).map_err(|e| e.to_string())?);
+        }
+
+        Ok(ids)
+    })
+    .await
+    .unwrap()
+}
+
+/// Record that a user was just active (login, logout or disconnect)
+pub async fn db_touch_user_last_seen(user_id: Uuid) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let now = crate::util::now_secs();
+    let bump_updated_at = crate::config::settings().users.bump_updated_at_on_last_seen;
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        // Presence churns constantly, so by default it doesn't count as a profile change
+        if bump_updated_at {
+            update_user_row(&conn, &user_id_str, "last_seen = ?1", params![now])?;
+        } else {
+            conn.execute(
+                "UPDATE users SET last_seen = ?1 WHERE id = ?2",
+                params![now, user_id_str],
+            ).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Opt a user in or out of notification digests
+pub async fn db_set_digest_opt_out(user_id: Uuid, opt_out: bool) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE users SET digest_opt_out = ?1 WHERE id = ?2",
+            params![opt_out as i32, user_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get who may see a user's full profile
+pub async fn db_get_profile_visibility(user_id: Uuid) -> Result<ProfileVisibility, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        let visibility: String = conn.query_row(
+            "SELECT profile_visibility FROM users WHERE id = ?1",
+            params![user_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+
+        Ok(match visibility.as_str() {
+            "ServerMembersOnly" => ProfileVisibility::ServerMembersOnly,
+            "Private" => ProfileVisibility::Private,
+            _ => ProfileVisibility::Public,
+        })
+    })
+    .await
+    .unwrap()
+}
+
+/// Set who may see a user's full profile
+pub async fn db_set_profile_visibility(user_id: Uuid, visibility: ProfileVisibility) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let visibility_str = match visibility {
+        ProfileVisibility::Public => "Public",
+        ProfileVisibility::ServerMembersOnly => "ServerMembersOnly",
+        ProfileVisibility::Private => "Private",
+    };
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        update_user_row(&conn, &user_id_str, "profile_visibility = ?1", &[&visibility_str])?;
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Choose whether the user is subscribed to new channels in their servers automatically
+pub async fn db_set_auto_join_new_channels(user_id: Uuid, enabled: bool) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        let updated = conn.execute(
+            "UPDATE users SET auto_join_new_channels = ?1 WHERE id = ?2",
+            params![enabled as i32, user_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        if updated == 0 {
+            return Err("User not found".to_string());
+        }
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Opt in or out of profile view notices. Opting in starts the digest period;
+/// opting out forgets the views recorded for the user's profile.
+pub async fn db_set_profile_view_notices(user_id: Uuid, enabled: bool) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let updated = tx.execute(
+            "UPDATE users SET profile_view_notices = ?1, profile_view_digest_at = CASE WHEN ?1 = 1 THEN ?3 ELSE NULL END
+             WHERE id = ?2",
+            params![enabled as i32, user_id_str, now],
+        ).map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("User not found".to_string());
+        }
+        if !enabled {
+            tx.execute("DELETE FROM profile_views WHERE viewed_id = ?1", params![user_id_str])
+                .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Record that `viewer_id` opened `viewed_id`'s profile. Nothing is written
+/// unless both users opted in to profile view notices, or if the same viewer
+/// was already recorded after `dedupe_since`. Returns whether a row was written.
+pub async fn db_record_profile_view(viewer_id: Uuid, viewed_id: Uuid, dedupe_since: i64) -> Result<bool, String> {
+    let viewer_id_str = viewer_id.to_string();
+    let viewed_id_str = viewed_id.to_string();
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let inserted = conn.execute(
+            "INSERT INTO profile_views (viewed_id, viewer_id, viewed_at)
+             SELECT ?1, ?2, ?3
+             WHERE (SELECT COUNT(*) FROM users WHERE id IN (?1, ?2) AND profile_view_notices = 1) = 2
+               AND NOT EXISTS (
+                   SELECT 1 FROM profile_views WHERE viewed_id = ?1 AND viewer_id = ?2 AND viewed_at > ?4
+               )",
+            params![viewed_id_str, viewer_id_str, now, dedupe_since],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(inserted > 0)
+    })
+    .await
+    .unwrap()
+}
+
+/// Close the profile view digest period of every opted-in user whose period
+/// started at or before `period_start`. Returns the users with views in their
+/// closed period and how many, and drops view rows no digest needs any more.
+pub async fn db_take_profile_view_digests(period_start: i64) -> Result<Vec<(Uuid, i64)>, String> {
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let digests = {
+            let mut stmt = tx.prepare(
+                "SELECT u.id, COUNT(v.viewer_id) FROM users u
+                 JOIN profile_views v ON v.viewed_id = u.id AND v.viewed_at > u.profile_view_digest_at
+                 WHERE u.profile_view_notices = 1 AND u.profile_view_digest_at <= ?1
+                 GROUP BY u.id"
+            ).map_err(|e| e.to_string())?;
+            let rows = stmt.query_map(params![period_start], |row| {
+                Ok((parse_uuid_column(&row.get::<_, String>(0)?, 0)?, row.get::<_, i64>(1)?))
+            }).map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+        };
+
+        tx.execute(
+            "UPDATE users SET profile_view_digest_at = ?1
+             WHERE profile_view_notices = 1 AND profile_view_digest_at <= ?2",
+            params![now, period_start],
+        ).map_err(|e| e.to_string())?;
+        // Every view up to period_start has now been counted in some digest
+        tx.execute(
+            "DELETE FROM profile_views WHERE viewed_at <= ?1",
+            params![period_start],
+        ).map_err(|e| e.to_string())?;
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(digests)
+    })
+    .await
+    .unwrap()
+}
+
+/// Save an admin's dashboard digest preference (interval in minutes)
+pub async fn db_set_admin_digest(user_id: Uuid, enabled: bool, interval_minutes: u32) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        conn.execute(
+            "UPDATE users SET admin_digest_enabled = ?1, admin_digest_interval = ?2 WHERE id = ?3",
+            params![enabled as i32, interval_minutes, user_id_str],
+        ).map_err(|e| e.to_string())?;
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get admins who opted in to the dashboard digest, with their interval in minutes
+pub async fn db_get_admin_digest_subscribers() -> Result<Vec<(Uuid, u32)>, String> {
+    task::spawn_blocking(|| {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, admin_digest_interval FROM users WHERE role = 'Admin' AND admin_digest_enabled = 1"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok((parse_uuid_column(&row.get::<_, String>(0)?, 0)?, row.get::<_, u32>(1)?))
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Count users registered at or after `since`
+pub async fn db_count_users_created_since(since: i64) -> Result<i64, String> {
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT COUNT(*) FROM users WHERE created_at >= ?1 AND id != ?2",
+            params![since, SYSTEM_USER_ID.to_string()],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Get users whose profile, color, role or username changed at or after `since`
+/// (inclusive, since timestamps are in seconds), optionally restricted to the given ids.
+/// The System account is not a user anyone lists, so it's left out.
+pub async fn db_get_users_updated_since(since: i64, user_ids: Option<Vec<Uuid>>) -> Result<Vec<UserInfo>, String> {
+    let user_ids_str: Option<Vec<String>> = user_ids.map(|ids| ids.iter().map(|id| id.to_string()).collect());
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let system_user_id = SYSTEM_USER_ID.to_string();
+        let mut query = "SELECT id, username, color, role, display_name FROM users WHERE COALESCE(updated_at, 0) >= ? AND id != ?".to_string();
+        let mut params: Vec<&dyn rusqlite::ToSql> = vec![&since, &system_user_id];
+        if let Some(ids) = &user_ids_str {
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+            query.push_str(&format!(" AND id IN ({})", placeholders));
+            params.extend(ids.iter().map(|s| s as &dyn rusqlite::ToSql));
+        }
+
+        let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(&params[..], |row| {
+            let role_str: String = row.get(3)?;
+            Ok(UserInfo {
+                id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                username: row.get(1)?,
+                display_name: visible_display_name(row.get(4)?),
+                color: parse_user_color(&row.get::<_, String>(2)?),
+                role: match role_str.as_str() {
+                    "Admin" => UserRole::Admin,
+                    "Moderator" => UserRole::Moderator,
+                    "Bot" => UserRole::Bot,
+                    _ => UserRole::User,
+                },
+                status: UserStatus::Offline, // Filled in by the caller
+            })
+        }).map_err(|e| e.to_string())?;
+
+        let mut users = Vec::new();
+        for row in rows {
+            users.push(row.map_err(|e| e.to_string())?);
+        }
+
+        Ok(users)
+    })
+    .await
+    .unwrap()
+}
+
+/// Check whether a user exists
+pub async fn db_user_exists(user_id: Uuid) -> Result<bool, String> {
+    super::db_row_exists("users", user_id).await
+}
+
+/// Change a user's role
+pub async fn db_update_user_role(user_id: Uuid, role: &str) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let role = role.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        if update_user_row(&conn, &user_id_str, "role = ?1", params![role])? == 0 {
+            return Err("User not found".to_string());
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Rename a user, keeping usernames unique (case insensitive and lookalike-safe)
+/// and clear of other users' display names
+pub async fn db_update_username(user_id: Uuid, username: &str) -> Result<(), String> {
+    let user_id_str = user_id.to_string();
+    let username = username.to_string();
+    let username_normalized = normalize_username(&username);
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        let taken: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM users
+             WHERE (LOWER(username) = LOWER(?1) OR username_normalized = ?2
+                    OR LOWER(display_name) = LOWER(?1) OR display_name_normalized = ?2)
+               AND id != ?3",
+            params![username, username_normalized, user_id_str],
+            |row| row.get(0),
+        ).map_err(|e| e.to_string())?;
+        if taken > 0 {
+            return Err("Username already taken".to_string());
+        }
+
+        if update_user_row(
+            &conn, &user_id_str, "username = ?1, username_normalized = ?2", params![username, username_normalized]
+        )? == 0 {
+            return Err("User not found".to_string());
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Whether the user still has to replace a placeholder password
+pub async fn db_must_change_password(user_id: Uuid) -> Result<bool, String> {
+    let user_id_str = user_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+        conn.query_row(
+            "SELECT must_change_password FROM users WHERE id = ?1",
+            params![user_id_str],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|flag| flag != 0)
+        .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// One account to create from a bulk import file
+#[derive(Debug, Clone)]
+pub struct ImportedUser {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+    pub contact_note: Option<String>,
+    pub must_change_password: bool,
+}
+
+/// What happened to one import row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportStatus {
+    Created,
+    /// Created under a suffixed name because the requested one (or a lookalike) was taken
+    Renamed,
+    /// Already imported or registered under this username
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportResult {
+    pub requested_username: String,
+    pub username: String,
+    pub user_id: Option<Uuid>,
+    pub status: ImportStatus,
+    /// The row's temporary password, for accounts that were created
+    pub password: Option<String>,
+    /// The default server a created account was joined to
+    pub server_id: Option<Uuid>,
+}
+
+/// Create a batch of imported accounts in one transaction. A row is skipped
+/// when its username already exists or was imported before, so re-running an
+/// import is harmless; lookalike collisions get a numeric suffix instead.
+/// Created accounts join the default server and its channels in the same
+/// transaction, so an account never exists without its memberships.
+pub async fn db_import_users(batch: Vec<ImportedUser>) -> Result<Vec<ImportResult>, String> {
+    task::spawn_blocking(move || {
+        let mut conn = get_conn().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        let now = crate::util::now_secs();
+        // The server registration joins (see `db_get_servers`)
+        let default_server: Option<String> = tx.query_row(
+            "SELECT id FROM servers ORDER BY id LIMIT 1",
+            [],
+            |row| row.get(0),
+        ).optional().map_err(|e| e.to_string())?;
+        let server_id = default_server.as_deref()
+            .map(|id| Uuid::parse_str(id).map_err(|e| e.to_string()))
+            .transpose()?;
+
+        let mut results = Vec::with_capacity(batch.len());
+        for entry in batch {
+            let requested_lower = entry.username.to_lowercase();
+            let already_present: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM users WHERE LOWER(username) = ?1 OR imported_as = ?1",
+                params![requested_lower],
+                |row| row.get(0),
+            ).map_err(|e| e.to_string())?;
+            if already_present > 0 {
+                results.push(ImportResult {
+                    requested_username: entry.username.clone(),
+                    username: entry.username,
+                    user_id: None,
+                    status: ImportStatus::Skipped,
+                    password: None,
+                    server_id: None,
+                });
+                continue;
+            }
+
+            let mut username = entry.username.clone();
+            let mut suffix = 1;
+            loop {
+                let taken: i64 = tx.query_row(
+                    "SELECT COUNT(*) FROM users
+                     WHERE LOWER(username) = ?1 OR username_normalized = ?2
+                        OR LOWER(display_name) = ?1 OR display_name_normalized = ?2",
+                    params![username.to_lowercase(), normalize_username(&username)],
+                    |row| row.get(0),
+                ).map_err(|e| e.to_string())?;
+                if taken == 0 {
+                    break;
+                }
+                suffix += 1;
+                username = format!("{}_{}", entry.username, suffix);
+            }
+
+            let id = Uuid::new_v4();
+            let hash = hash_password(&entry.password).map_err(|e| e.to_string())?;
+            tx.execute(
+                "INSERT INTO users (id, username, username_normalized, password_hash, color, role,
+                                    created_at, updated_at, must_change_password, contact_note, imported_as)
+                 VALUES (?1, ?2, ?3, ?4, 'Green', ?5, ?6, ?6, ?7, ?8, ?9)",
+                params![
+                    id.to_string(), username, normalize_username(&username), hash, entry.role, now,
+                    entry.must_change_password as i64, entry.contact_note, requested_lower
+                ],
+            ).map_err(|e| e.to_string())?;
+
+            if let Some(server_id) = &default_server {
+                tx.execute(
+                    "INSERT OR IGNORE INTO server_users (server_id, user_id) VALUES (?1, ?2)",
+                    params![server_id, id.to_string()],
+                ).map_err(|e| e.to_string())?;
+                tx.execute(
+                    "INSERT OR IGNORE INTO channel_users (channel_id, user_id)
+                     SELECT id, ?2 FROM channels WHERE server_id = ?1",
+                    params![server_id, id.to_string()],
+                ).map_err(|e| e.to_string())?;
+            }
+
+            let status = if suffix > 1 { ImportStatus::Renamed } else { ImportStatus::Created };
+            results.push(ImportResult {
+                requested_username: entry.username,
+                username,
+                user_id: Some(id),
+                status,
+                password: Some(entry.password),
+                server_id,
+            });
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(results)
+    })
+    .await
+    .unwrap()
+}
+
+/// Mute or unmute a user for `muter_id` only, in one channel or (with no
+/// channel) everywhere
+pub async fn db_set_personal_mute(
+    muter_id: Uuid,
+    muted_id: Uuid,
+    channel_id: Option<Uuid>,
+    muted: bool,
+) -> Result<(), String> {
+    let muter_id_str = muter_id.to_string();
+    let muted_id_str = muted_id.to_string();
+    let channel_id_str = channel_id.map(|id| id.to_string());
+    let now = crate::util::now_secs();
+
+    task::spawn_blocking(move || {
+        let conn = get_conn().map_err(|e| e.to_string())?;
+
+        if muted {
+            conn.execute(
+                "INSERT OR IGNORE INTO personal_mutes (muter_id, muted_id, channel_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![muter_id_str, muted_id_str, channel_id_str, now],
+            ).map_err(|e| e.to_string())?;
+        } else {
+            conn.execute(
+                "DELETE FROM personal_mutes WHERE muter_id = ?1 AND muted_id = ?2 AND channel_id IS ?3",
+                params![muter_id_str, muted_id_str, channel_id_str],
+            ).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    })
+    .await
+    .unwrap()
+}
+
+/// Everyone `muter_id` has muted
+pub async fn db_get_personal_mutes(muter_id: Uuid) -> Result<Vec<PersonalMute>, String> {
+    let muter_id_str = muter_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT muted_id, channel_id FROM personal_mutes WHERE muter_id = ?1 ORDER BY created_at"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![muter_id_str], |row| {
+            Ok(PersonalMute {
+                user_id: parse_uuid_column(&row.get::<_, String>(0)?, 0)?,
+                channel_id: row.get::<_, Option<String>>(1)?
+                    .map(|id| parse_uuid_column(&id, 1))
+                    .transpose()?,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Authors `muter_id` has muted in a channel, counting global mutes
+pub async fn db_get_muted_authors(muter_id: Uuid, channel_id: Uuid) -> Result<HashSet<Uuid>, String> {
+    let muter_id_str = muter_id.to_string();
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT muted_id FROM personal_mutes
+             WHERE muter_id = ?1 AND (channel_id IS NULL OR channel_id = ?2)"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![muter_id_str, channel_id_str], |row| {
+            parse_uuid_column(&row.get::<_, String>(0)?, 0)
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<HashSet<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+/// Users who muted `author_id` in a channel, counting global mutes
+pub async fn db_get_users_muting(author_id: Uuid, channel_id: Uuid) -> Result<HashSet<Uuid>, String> {
+    let author_id_str = author_id.to_string();
+    let channel_id_str = channel_id.to_string();
+
+    task::spawn_blocking(move || {
+        let conn = get_read_conn().map_err(|e| e.to_string())?;
+
+        let mut stmt = conn.prepare(
+            "SELECT muter_id FROM personal_mutes
+             WHERE muted_id = ?1 AND (channel_id IS NULL OR channel_id = ?2)"
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![author_id_str, channel_id_str], |row| {
+            parse_uuid_column(&row.get::<_, String>(0)?, 0)
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<HashSet<_>, _>>().map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    /// Backdate `updated_at` on every user, or just `user_id`
+    fn set_updated_at(db: &TestDb, user_id: Option<Uuid>, updated_at: i64) {
+        let conn = Connection::open(db.path()).unwrap();
+        match user_id {
+            Some(id) => conn.execute("UPDATE users SET updated_at = ?1 WHERE id = ?2", params![updated_at, id.to_string()]),
+            None => conn.execute("UPDATE users SET updated_at = ?1", params![updated_at]),
+        }.unwrap();
+    }
+
+    fn ids(users: &[UserInfo]) -> Vec<Uuid> {
+        users.iter().map(|user| user.id).collect()
+    }
+
+    #[tokio::test]
+    async fn only_users_modified_after_the_cursor_are_returned() {
+        let db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        set_updated_at(&db, None, 100);
+        set_updated_at(&db, Some(bob.id), 200);
+
+        assert_eq!(ids(&db_get_users_updated_since(150, None).await.unwrap()), vec![bob.id]);
+        // The cursor is inclusive, so a change in the same second isn't skipped
+        assert_eq!(ids(&db_get_users_updated_since(200, None).await.unwrap()), vec![bob.id]);
+        assert!(db_get_users_updated_since(201, None).await.unwrap().is_empty());
+
+        let mut everyone = ids(&db_get_users_updated_since(0, None).await.unwrap());
+        everyone.sort();
+        let mut expected = vec![alice.id, bob.id];
+        expected.sort();
+        assert_eq!(everyone, expected);
+        // Not even when asked for by id
+        assert!(db_get_users_updated_since(0, Some(vec![SYSTEM_USER_ID])).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_can_be_limited_to_known_ids() {
+        let db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        set_updated_at(&db, None, 100);
+        set_updated_at(&db, Some(alice.id), 200);
+        set_updated_at(&db, Some(bob.id), 200);
+
+        assert_eq!(ids(&db_get_users_updated_since(150, Some(vec![alice.id])).await.unwrap()), vec![alice.id]);
+        assert!(db_get_users_updated_since(150, Some(Vec::new())).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_user_mutation_advances_updated_at() {
+        let db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+
+        let mutations: Vec<(&str, std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), String>>>>)> = vec![
+            ("color", Box::pin(db_update_user_color(alice.id, "Red"))),
+            ("profile", Box::pin(db_update_user_profile(alice.id, Some("bio".to_string()), None, None, None, None, None, None))),
+            ("role", Box::pin(db_update_user_role(alice.id, "Moderator"))),
+            ("rename", Box::pin(db_update_username(alice.id, "alicia"))),
+            ("password", Box::pin(db_update_user_password(alice.id, "new password"))),
+        ];
+        for (name, mutation) in mutations {
+            set_updated_at(&db, None, 100);
+            mutation.await.unwrap();
+            assert_eq!(
+                ids(&db_get_users_updated_since(101, None).await.unwrap()),
+                vec![alice.id],
+                "{} did not advance updated_at",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn lookalikes_of_an_existing_username_are_taken() {
+        let _db = TestDb::new().await;
+        test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+
+        let cyrillic = "\u{0430}lice";
+        let taken = Some("Username already taken".to_string());
+        assert_eq!(db_register_user(cyrillic, "password", "Green", "User").await.err(), taken);
+        assert_eq!(db_register_user("Alice", "password", "Green", "User").await.err(), taken);
+        assert_eq!(db_update_username(bob.id, cyrillic).await.err(), taken);
+        assert!(db_register_user("alicia", "password", "Green", "User").await.is_ok());
+    }
+
+    fn import_row(username: &str, password: &str) -> ImportedUser {
+        ImportedUser {
+            username: username.to_string(),
+            password: password.to_string(),
+            role: "User".to_string(),
+            contact_note: None,
+            must_change_password: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_duplicate_import_row_keeps_the_first_rows_password() {
+        let _db = TestDb::new().await;
+        let results = db_import_users(vec![
+            import_row("carol", "first password"),
+            import_row("Carol", "second password"),
+        ]).await.unwrap();
+
+        assert_eq!(results[0].status, ImportStatus::Created);
+        assert_eq!(results[0].password.as_deref(), Some("first password"));
+        assert_eq!(results[1].status, ImportStatus::Skipped);
+        assert_eq!(results[1].password, None);
+        assert!(db_login_user("carol", "first password").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn imported_accounts_join_the_default_server_with_the_account() {
+        let _db = TestDb::new().await;
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Home").await;
+        let channel_ids = [
+            test_support::create_channel(server_id, "general").await,
+            test_support::create_channel(server_id, "random").await,
+        ];
+
+        let results = db_import_users(vec![import_row("dana", "temporary password")]).await.unwrap();
+
+        let dana = results[0].user_id.expect("created");
+        assert_eq!(results[0].server_id, Some(server_id));
+        assert!(crate::db::servers::db_is_user_in_server(dana, server_id).await.unwrap());
+        for channel_id in channel_ids {
+            let members = crate::db::channels::db_get_channel_user_list(channel_id).await.unwrap();
+            assert!(members.iter().any(|member| member.id == dana));
+        }
+        assert!(db_must_change_password(dana).await.unwrap());
+    }
+}
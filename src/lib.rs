@@ -0,0 +1,184 @@
+pub mod api;
+pub mod db;
+pub mod util;
+pub mod auth;
+pub mod services;
+pub mod errors;
+pub mod config;
+pub mod readiness;
+pub mod cli;
+#[cfg(test)]
+mod test_support;
+
+use api::connection::{handle_connection, PeerMap};
+use api::transport::LengthDelimited;
+use services::{rate_limit_service, BroadcastService, ContentFilterService, MaintenanceService, RateLimitService};
+use std::collections::HashMap;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex};
+use tracing::{error, info};
+use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::{version, ServerConfig as RustlsServerConfig, SupportedProtocolVersion};
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use std::fs::File;
+use std::io::BufReader;
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certfile = File::open(path).map_err(|e| format!("Cannot open certificate file {}: {}", path, e))?;
+    let mut reader = BufReader::new(certfile);
+    let certs: Vec<_> = certs(&mut reader)
+        .filter_map(|res| res.ok())
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &str) -> Result<PrivatePkcs8KeyDer<'static>, String> {
+    let keyfile = File::open(path).map_err(|e| format!("Cannot open private key file {}: {}", path, e))?;
+    let mut reader = BufReader::new(keyfile);
+    let keys: Vec<_> = pkcs8_private_keys(&mut reader)
+        .filter_map(|res| res.ok())
+        .collect();
+    keys.into_iter().next().ok_or_else(|| format!("No private key found in {}", path))
+}
+
+/// Protocol versions and crypto provider allowed by the [tls] settings. Versions
+/// older than 1.2 and suites that can't be used with the allowed versions are rejected.
+fn tls_parameters(tls: &config::TlsConfig) -> Result<(Vec<&'static SupportedProtocolVersion>, CryptoProvider), String> {
+    let versions: Vec<&'static SupportedProtocolVersion> = match tls.min_version.trim() {
+        "1.2" => vec![&version::TLS13, &version::TLS12],
+        "1.3" => vec![&version::TLS13],
+        other => return Err(format!("Unsupported tls.min_version '{}': use \"1.2\" or \"1.3\"", other)),
+    };
+    let version_allowed = |v: &SupportedProtocolVersion| versions.iter().any(|allowed| allowed.version == v.version);
+
+    let mut provider = ring::default_provider();
+    if tls.cipher_suites.is_empty() {
+        provider.cipher_suites.retain(|suite| version_allowed(suite.version()));
+    } else {
+        let mut suites = Vec::with_capacity(tls.cipher_suites.len());
+        for name in &tls.cipher_suites {
+            let suite = provider.cipher_suites.iter()
+                .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| format!("Unknown or unsupported TLS cipher suite '{}'", name))?;
+            if !version_allowed(suite.version()) {
+                return Err(format!("TLS cipher suite '{}' can't be used with tls.min_version {}", name, tls.min_version));
+            }
+            suites.push(*suite);
+        }
+        provider.cipher_suites = suites;
+    }
+    Ok((versions, provider))
+}
+
+/// Build the TLS server config from PEM files and the [tls] settings
+pub fn load_tls_config(cert_path: &str, key_path: &str, tls: &config::TlsConfig) -> Result<RustlsServerConfig, String> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let (versions, provider) = tls_parameters(tls)?;
+    let suite_names: Vec<String> = provider.cipher_suites.iter().map(|suite| format!("{:?}", suite.suite())).collect();
+
+    let tls_config = RustlsServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&versions)
+        .map_err(|e| format!("Invalid TLS settings: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, tokio_rustls::rustls::pki_types::PrivateKeyDer::Pkcs8(key))
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+
+    let version_names: Vec<String> = versions.iter().map(|v| format!("{:?}", v.version)).collect();
+    info!("TLS versions: {}; cipher suites: {}", version_names.join(", "), suite_names.join(", "));
+    Ok(tls_config)
+}
+
+/// Serve connections from a bound listener until `shutdown_rx` flips to true.
+/// Expects the database and settings to be initialized already.
+pub async fn run_server(
+    listener: TcpListener,
+    tls_acceptor: TlsAcceptor,
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize peer map for connection management
+    let peer_map = PeerMap::new(Mutex::new(HashMap::new()));
+
+    // Build the content filter once and share it across connections
+    let content_filter = Arc::new(ContentFilterService::new(&config::settings().moderation));
+    let rate_limiter = Arc::new(RateLimitService::new(&config::settings().rate_limits));
+    let rate_limit_state = rate_limit_service::state_file_path(&config::settings().rate_limits);
+    rate_limiter.load_state(&rate_limit_state);
+
+    // Background jobs (notification digests, rate limit sweeps, ...)
+    MaintenanceService::spawn(peer_map.clone(), rate_limiter.clone());
+    BroadcastService::spawn_presence_flusher(&peer_map);
+
+    #[cfg(feature = "irc-gateway")]
+    if config::settings().irc.enabled {
+        let irc = config::settings().irc.clone();
+        match TcpListener::bind(&irc.bind_address).await {
+            Ok(irc_listener) => {
+                info!("IRC gateway listening on {} ({} mapped channels)", irc.bind_address, irc.channels.len());
+                tokio::spawn(api::irc_gateway::run(
+                    irc_listener,
+                    peer_map.clone(),
+                    content_filter.clone(),
+                    rate_limiter.clone(),
+                    shutdown_rx.clone(),
+                ));
+            }
+            Err(e) => error!("IRC gateway cannot bind {}: {}", irc.bind_address, e),
+        }
+    }
+
+    if config::settings().websocket.enabled {
+        let websocket = config::settings().websocket.clone();
+        match TcpListener::bind(&websocket.bind_address).await {
+            Ok(websocket_listener) => {
+                info!("Websocket listener on {}", websocket.bind_address);
+                tokio::spawn(api::websocket::run(
+                    websocket_listener,
+                    tls_acceptor.clone(),
+                    peer_map.clone(),
+                    content_filter.clone(),
+                    rate_limiter.clone(),
+                    shutdown_rx.clone(),
+                ));
+            }
+            Err(e) => error!("Websocket listener cannot bind {}: {}", websocket.bind_address, e),
+        }
+    }
+
+    // Accept connections
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown_rx.changed() => {
+                info!("Shutting down, no longer accepting connections");
+                match rate_limiter.save_state(&rate_limit_state) {
+                    Ok(saved) => info!("Saved {} rate limit windows to {}", saved, rate_limit_state.display()),
+                    Err(e) => error!("Failed to save rate limit state to {}: {}", rate_limit_state.display(), e),
+                }
+                return Ok(());
+            }
+        };
+        let peer_map = peer_map.clone();
+        let tls_acceptor = tls_acceptor.clone();
+        let content_filter = content_filter.clone();
+        let rate_limiter = rate_limiter.clone();
+        tokio::spawn(async move {
+            match tls_acceptor.accept(stream).await {
+                Ok(tls_stream) => {
+                    if let Err(e) = handle_connection(LengthDelimited(tls_stream), peer_addr.ip(), peer_map, content_filter, rate_limiter).await {
+                        error!("Connection error: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("TLS handshake failed: {}", e);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,154 @@
+// Startup self-check run before the server accepts connections
+
+use crate::db;
+use std::time::Duration;
+use tokio_rustls::rustls::ServerConfig as RustlsServerConfig;
+use tracing::{error, info, warn};
+
+/// Result of one readiness check
+#[derive(Debug, Clone)]
+pub struct ReadinessCheck {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of a full readiness run
+#[derive(Debug, Clone)]
+pub struct ReadinessReport {
+    pub checks: Vec<ReadinessCheck>,
+}
+
+impl ReadinessReport {
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    /// Log one line per check plus an overall verdict
+    pub fn log_summary(&self) {
+        for check in &self.checks {
+            if check.passed {
+                info!("  [ok]   {}: {}", check.name, check.detail);
+            } else {
+                error!("  [fail] {}: {}", check.name, check.detail);
+            }
+        }
+        if self.is_ready() {
+            info!("Readiness: all {} checks passed", self.checks.len());
+        } else {
+            let failed = self.checks.iter().filter(|check| !check.passed).count();
+            error!("Readiness: {} of {} checks failed", failed, self.checks.len());
+        }
+    }
+}
+
+fn check(name: &'static str, result: Result<String, String>) -> ReadinessCheck {
+    match result {
+        Ok(detail) => ReadinessCheck { name, passed: true, detail },
+        Err(detail) => ReadinessCheck { name, passed: false, detail },
+    }
+}
+
+/// Database answers queries on both read-write and read-only connections
+async fn check_database() -> Result<String, String> {
+    tokio::task::spawn_blocking(|| {
+        let conn = db::get_conn().map_err(|e| format!("cannot open {}: {}", db::db_config::get_db_path(), e))?;
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+            .map_err(|e| format!("query failed: {}", e))?;
+
+        let read_conn = db::get_read_conn().map_err(|e| format!("cannot open read connection: {}", e))?;
+        read_conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+            .map_err(|e| format!("read query failed: {}", e))?;
+
+        Ok(format!("connected to {}", db::db_config::get_db_path()))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// At least one server exists for new users to join
+async fn check_default_server() -> Result<String, String> {
+    let servers = db::servers::db_get_servers().await?;
+    if servers.is_empty() {
+        return Err("no servers exist".to_string());
+    }
+    Ok(format!("{} server(s) present", servers.len()))
+}
+
+/// The certificate and key loaded into a usable TLS config
+fn check_tls(tls_config: &Result<RustlsServerConfig, String>) -> Result<String, String> {
+    tls_config
+        .as_ref()
+        .map(|_| "certificate and key loaded".to_string())
+        .map_err(|e| e.clone())
+}
+
+/// Run every readiness check once
+pub async fn run_checks(tls_config: &Result<RustlsServerConfig, String>) -> ReadinessReport {
+    ReadinessReport {
+        checks: vec![
+            check("database", check_database().await),
+            check("default server", check_default_server().await),
+            check("tls", check_tls(tls_config)),
+        ],
+    }
+}
+
+/// Run the checks until they pass or the configured attempts run out
+pub async fn wait_until_ready(tls_config: &Result<RustlsServerConfig, String>) -> Result<(), String> {
+    let settings = crate::config::settings().readiness.clone();
+    let attempts = settings.max_attempts.max(1);
+
+    for attempt in 1..=attempts {
+        info!("Running readiness checks (attempt {}/{})", attempt, attempts);
+        let report = run_checks(tls_config).await;
+        report.log_summary();
+        if report.is_ready() {
+            return Ok(());
+        }
+        if attempt < attempts {
+            warn!("Not ready, retrying in {}s", settings.retry_delay_secs);
+            tokio::time::sleep(Duration::from_secs(settings.retry_delay_secs)).await;
+        }
+    }
+
+    Err(format!("server failed readiness checks after {} attempt(s)", attempts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+    use std::time::Instant;
+
+    fn failed_checks(report: &ReadinessReport) -> Vec<&'static str> {
+        report.checks.iter().filter(|check| !check.passed).map(|check| check.name).collect()
+    }
+
+    #[tokio::test]
+    async fn a_migrated_database_with_a_server_is_ready() {
+        let _db = TestDb::new().await;
+        test_support::create_user_with_role("admin", "Admin").await;
+        db::servers::ensure_default_server_exists().await.unwrap();
+
+        let report = run_checks(&Err("no certificate".to_string())).await;
+
+        assert_eq!(failed_checks(&report), vec!["tls"]);
+    }
+
+    #[tokio::test]
+    async fn a_broken_database_path_fails_quickly() {
+        let db = TestDb::new().await;
+        let missing = db.path().parent().unwrap().join("missing").join("nexus.db");
+        db::db_config::set_db_path(missing.to_string_lossy().into_owned());
+
+        let started = Instant::now();
+        let report = run_checks(&Err("no certificate".to_string())).await;
+
+        assert!(!report.is_ready());
+        let database = report.checks.iter().find(|check| check.name == "database").unwrap();
+        assert!(!database.passed);
+        assert!(database.detail.starts_with("cannot open"), "{}", database.detail);
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+    }
+}
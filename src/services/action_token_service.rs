@@ -0,0 +1,119 @@
+use crate::errors::{Result, ServerError};
+use once_cell::sync::Lazy;
+use rand::distr::{Alphanumeric, SampleString};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Length of an issued action token
+const ACTION_TOKEN_LENGTH: usize = 32;
+
+// Sensitive actions that take a token
+pub const UPDATE_PASSWORD: &str = "update_password";
+pub const SET_USER_ROLE: &str = "set_user_role";
+pub const RENAME_USER: &str = "rename_user";
+pub const ROTATE_BOT_TOKEN: &str = "rotate_bot_token";
+
+pub const SENSITIVE_ACTIONS: [&str; 4] = [UPDATE_PASSWORD, SET_USER_ROLE, RENAME_USER, ROTATE_BOT_TOKEN];
+
+struct IssuedToken {
+    user_id: Uuid,
+    action: &'static str,
+    expires_at: i64,
+}
+
+/// Outstanding tokens by value. Kept in memory: a restart invalidates them,
+/// which only costs the client a new RequestActionToken.
+static TOKENS: Lazy<Mutex<HashMap<String, IssuedToken>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// One-time tokens that guard sensitive operations against a replayed frame.
+/// A token is bound to the user and action it was issued for and is consumed
+/// by the first attempt to use it, successful or not.
+pub struct ActionTokenService;
+
+impl ActionTokenService {
+    /// Issue a token for `action`. Returns the token and when it expires.
+    /// The user's previous token for the same action stops working, so each
+    /// user holds at most one per action.
+    pub fn issue(user_id: Uuid, action: &str) -> Result<(String, i64)> {
+        let Some(action) = SENSITIVE_ACTIONS.iter().copied().find(|known| *known == action) else {
+            return Err(ServerError::Validation(format!(
+                "Unknown action '{}'; expected one of: {}", action, SENSITIVE_ACTIONS.join(", ")
+            )));
+        };
+
+        let now = crate::util::now_secs();
+        let expires_at = now + crate::config::settings().action_tokens.ttl_secs.max(1);
+        let token = Alphanumeric.sample_string(&mut rand::rng(), ACTION_TOKEN_LENGTH);
+
+        let mut tokens = TOKENS.lock().unwrap();
+        tokens.retain(|_, issued| {
+            issued.expires_at > now && !(issued.user_id == user_id && issued.action == action)
+        });
+        tokens.insert(token.clone(), IssuedToken { user_id, action, expires_at });
+        Ok((token, expires_at))
+    }
+
+    /// Use up a token for `action`. Fails if it was never issued, already
+    /// used, expired, or belongs to another user or action.
+    pub fn consume(user_id: Uuid, action: &str, token: &str) -> Result<()> {
+        let issued = TOKENS.lock().unwrap().remove(token);
+        match issued {
+            Some(issued) if issued.user_id == user_id && issued.action == action => {
+                if issued.expires_at <= crate::util::now_secs() {
+                    return Err(ServerError::Authorization("Action token has expired".to_string()));
+                }
+                Ok(())
+            }
+            _ => Err(ServerError::Authorization("Invalid or already used action token".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_rejected(result: Result<()>) -> bool {
+        matches!(result, Err(ServerError::Authorization(_)))
+    }
+
+    #[test]
+    fn a_fresh_token_works_once() {
+        let user_id = Uuid::new_v4();
+        let (token, _) = ActionTokenService::issue(user_id, RENAME_USER).unwrap();
+        assert!(ActionTokenService::consume(user_id, RENAME_USER, &token).is_ok());
+        assert!(is_rejected(ActionTokenService::consume(user_id, RENAME_USER, &token)));
+    }
+
+    #[test]
+    fn a_token_is_bound_to_its_user_and_action() {
+        let user_id = Uuid::new_v4();
+        let (token, _) = ActionTokenService::issue(user_id, SET_USER_ROLE).unwrap();
+        assert!(is_rejected(ActionTokenService::consume(Uuid::new_v4(), SET_USER_ROLE, &token)));
+
+        // The failed attempt used the token up
+        let (token, _) = ActionTokenService::issue(user_id, SET_USER_ROLE).unwrap();
+        assert!(is_rejected(ActionTokenService::consume(user_id, UPDATE_PASSWORD, &token)));
+        assert!(is_rejected(ActionTokenService::consume(user_id, SET_USER_ROLE, &token)));
+    }
+
+    #[test]
+    fn a_new_token_replaces_the_previous_one_for_the_action() {
+        let user_id = Uuid::new_v4();
+        let (first, _) = ActionTokenService::issue(user_id, UPDATE_PASSWORD).unwrap();
+        let (other_action, _) = ActionTokenService::issue(user_id, ROTATE_BOT_TOKEN).unwrap();
+        let (second, _) = ActionTokenService::issue(user_id, UPDATE_PASSWORD).unwrap();
+
+        let outstanding = TOKENS.lock().unwrap().values().filter(|issued| issued.user_id == user_id).count();
+        assert_eq!(outstanding, 2);
+        assert!(is_rejected(ActionTokenService::consume(user_id, UPDATE_PASSWORD, &first)));
+        assert!(ActionTokenService::consume(user_id, UPDATE_PASSWORD, &second).is_ok());
+        assert!(ActionTokenService::consume(user_id, ROTATE_BOT_TOKEN, &other_action).is_ok());
+    }
+
+    #[test]
+    fn unknown_actions_get_no_token() {
+        assert!(matches!(ActionTokenService::issue(Uuid::new_v4(), "drop_database"), Err(ServerError::Validation(_))));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::api::connection::PeerMap;
+use crate::db::audit::{self, AuditFilter};
+use crate::db::{servers, users};
+use crate::errors::{Result, ServerError};
+use crate::services::system_message_service::SystemEvent;
+use crate::services::SystemMessageService;
+use crate::util::csv_escape;
+use nexus_tui_common::{AuditEntry, AuditExportFormat, AuditStats, User, UserRole};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Most audit entries returned per page
+const MAX_AUDIT_PAGE: usize = 200;
+/// Largest export served over the protocol; bigger ones go through `export-audit`
+pub const MAX_PROTOCOL_EXPORT_ROWS: usize = 100_000;
+/// Entries read and sent per export chunk
+const EXPORT_BATCH_SIZE: usize = 500;
+
+// Audit action names
+pub const SET_USER_ROLE: &str = "set_user_role";
+pub const RENAME_USER: &str = "rename_user";
+pub const REVIEW_QUARANTINE: &str = "review_quarantine";
+pub const DISMISS_FLAGGED: &str = "dismiss_flagged";
+pub const DELETE_MESSAGE: &str = "delete_message";
+pub const RESTORE_MESSAGE: &str = "restore_message";
+pub const EDIT_MESSAGE: &str = "edit_message";
+pub const MESSAGE_MODERATED: &str = "message_moderated";
+pub const SET_FORUM_POSTING_ROLE: &str = "set_forum_posting_role";
+pub const CONFIGURATION_CHANGED: &str = "configuration_changed";
+pub const ASSIGN_SERVER_ROLE: &str = "assign_server_role";
+pub const EXPORT_AUDIT_LOG: &str = "export_audit_log";
+pub const ROTATE_BOT_TOKEN: &str = "rotate_bot_token";
+pub const VIEW_USER_AS: &str = "view_user_as";
+pub const USER_BANNED: &str = "ban_user";
+pub const USER_UNBANNED: &str = "unban_user";
+pub const USER_KICKED: &str = "kick_user";
+
+pub struct AuditService;
+
+impl AuditService {
+    /// Record an action in the audit log. Failures are logged, never surfaced:
+    /// the action itself already happened.
+    pub async fn record(actor: &User, action: &str, target: Option<String>, details: Option<String>) {
+        if let Err(e) = audit::db_record_audit(actor.id, action, target, details).await {
+            warn!("Failed to record audit entry {} by {}: {}", action, actor.username, e);
+        }
+    }
+
+    /// Post a human-readable line about a server-scoped action into the server's
+    /// audit channel, if it has one and the action type is enabled. Like
+    /// `record`, failures are logged and never fail the action.
+    pub async fn mirror_to_server(server_id: Uuid, actor: &User, action: &str, summary: String, peer_map: &PeerMap) {
+        let config = &crate::config::settings().audit_channel;
+        if !config.enabled || !config.events.iter().any(|event| event == action) {
+            return;
+        }
+
+        let channel_id = match servers::db_get_server_audit_channel(server_id).await {
+            Ok(Some(channel_id)) => channel_id,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to look up audit channel of server {}: {}", server_id, e);
+                return;
+            }
+        };
+
+        let event = SystemEvent::ModerationAction { action: action.to_string(), actor_id: actor.id, summary };
+        if let Err(e) = SystemMessageService::post(channel_id, &event, peer_map).await {
+            warn!("Failed to mirror {} by {} to server {}: {}", action, actor.username, server_id, e);
+        }
+    }
+
+    /// Username for an audit summary, falling back to the id
+    pub async fn display_name(user_id: Uuid) -> String {
+        users::db_get_user_by_id(user_id).await
+            .map(|profile| profile.username)
+            .unwrap_or_else(|_| user_id.to_string())
+    }
+
+    /// Page through the audit log with optional filters (Admin only)
+    pub async fn fetch_audit_entries(
+        admin: &User,
+        limit: usize,
+        offset: usize,
+        user_filter: Option<Uuid>,
+        action_filter: Option<String>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+    ) -> Result<(Vec<AuditEntry>, bool)> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can view the audit log".to_string()));
+        }
+
+        let filter = AuditFilter {
+            user_id: user_filter,
+            action: action_filter.filter(|action| !action.is_empty()),
+            start_time,
+            end_time,
+        };
+        audit::db_fetch_audit_entries(filter, limit.clamp(1, MAX_AUDIT_PAGE), offset).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Summarize the audit log with optional filters (Admin only)
+    pub async fn calculate_audit_stats(admin: &User, filter: AuditFilter) -> Result<AuditStats> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can view the audit log".to_string()));
+        }
+        audit::db_audit_stats(filter).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Start an in-protocol export (Admin only). Refuses exports larger than
+    /// MAX_PROTOCOL_EXPORT_ROWS, pointing at the CLI instead. Returns the
+    /// export and how many entries it will contain.
+    pub async fn begin_export(admin: &User, filter: AuditFilter, format: AuditExportFormat) -> Result<(AuditExport, usize)> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can export the audit log".to_string()));
+        }
+
+        let total = audit::db_count_audit_entries(filter.clone()).await
+            .map_err(|e| ServerError::Database(e))?;
+        if total > MAX_PROTOCOL_EXPORT_ROWS {
+            return Err(ServerError::Validation(format!(
+                "{} entries match; exports over {} must be run on the server with `nexus-tui-server export-audit`",
+                total, MAX_PROTOCOL_EXPORT_ROWS
+            )));
+        }
+
+        Self::record(admin, EXPORT_AUDIT_LOG, None, Some(format!("{} entries", total))).await;
+        Ok((AuditExport::new(filter, format), total))
+    }
+}
+
+/// An audit log export in progress, read oldest first a batch at a time.
+/// The first chunk carries the CSV header or opening bracket, the last one
+/// the closing bracket, so the chunks concatenate into a complete document.
+pub struct AuditExport {
+    filter: AuditFilter,
+    format: AuditExportFormat,
+    cursor: Option<(i64, Uuid)>,
+    rows_written: usize,
+    started: bool,
+    finished: bool,
+}
+
+impl AuditExport {
+    pub fn new(filter: AuditFilter, format: AuditExportFormat) -> Self {
+        Self { filter, format, cursor: None, rows_written: 0, started: false, finished: false }
+    }
+
+    /// Whether the last chunk has been produced
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The next chunk of the document, or None once it is complete
+    pub async fn next_chunk(&mut self) -> Result<Option<String>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let entries = audit::db_fetch_audit_entries_after(self.filter.clone(), self.cursor, EXPORT_BATCH_SIZE).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let mut chunk = String::new();
+        if !self.started {
+            self.started = true;
+            chunk.push_str(match self.format {
+                AuditExportFormat::Csv => "id,timestamp,time,user_id,action,target,details\n",
+                AuditExportFormat::Json => "[",
+            });
+        }
+        for entry in &entries {
+            self.push_entry(&mut chunk, entry)?;
+        }
+        if let Some(last) = entries.last() {
+            self.cursor = Some((last.timestamp, last.id));
+        }
+        if entries.len() < EXPORT_BATCH_SIZE {
+            self.finished = true;
+            if let AuditExportFormat::Json = self.format {
+                chunk.push_str("\n]\n");
+            }
+        }
+        Ok(Some(chunk))
+    }
+
+    fn push_entry(&mut self, chunk: &mut String, entry: &AuditEntry) -> Result<()> {
+        match self.format {
+            AuditExportFormat::Csv => {
+                let time = chrono::DateTime::from_timestamp(entry.timestamp, 0)
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default();
+                chunk.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    entry.id,
+                    entry.timestamp,
+                    time,
+                    entry.user_id,
+                    csv_escape(&entry.action),
+                    csv_escape(entry.target.as_deref().unwrap_or("")),
+                    csv_escape(entry.details.as_deref().unwrap_or("")),
+                ));
+            }
+            AuditExportFormat::Json => {
+                let json = serde_json::to_string(entry)
+                    .map_err(|e| ServerError::Internal(format!("Failed to serialize audit entry: {}", e)))?;
+                chunk.push_str(if self.rows_written == 0 { "\n" } else { ",\n" });
+                chunk.push_str(&json);
+            }
+        }
+        self.rows_written += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    /// Record `action` by `actor`, dated `timestamp`
+    async fn record_at(db: &TestDb, actor: &User, action: &str, timestamp: i64) {
+        AuditService::record(actor, action, Some(action.to_string()), None).await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE audit_log SET timestamp = ?1 WHERE target = ?2",
+            rusqlite::params![timestamp, action],
+        ).unwrap();
+    }
+
+    fn actions(entries: &[AuditEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.action.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn entries_can_be_filtered_by_action_and_time() {
+        let db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        record_at(&db, &admin, RENAME_USER, 100).await;
+        record_at(&db, &admin, SET_USER_ROLE, 200).await;
+        record_at(&db, &admin, DELETE_MESSAGE, 300).await;
+
+        let (by_action, more) = AuditService::fetch_audit_entries(
+            &admin, 50, 0, None, Some(SET_USER_ROLE.to_string()), None, None
+        ).await.unwrap();
+        assert_eq!(actions(&by_action), vec![SET_USER_ROLE]);
+        assert!(!more);
+
+        // Start inclusive, end exclusive, newest first
+        let (in_range, _) = AuditService::fetch_audit_entries(&admin, 50, 0, None, None, Some(100), Some(300)).await.unwrap();
+        assert_eq!(actions(&in_range), vec![SET_USER_ROLE, RENAME_USER]);
+
+        let (first_page, more) = AuditService::fetch_audit_entries(&admin, 2, 0, None, None, None, None).await.unwrap();
+        assert_eq!(actions(&first_page), vec![DELETE_MESSAGE, SET_USER_ROLE]);
+        assert!(more);
+    }
+
+    #[tokio::test]
+    async fn only_admins_read_the_audit_log() {
+        let _db = TestDb::new().await;
+        let moderator = test_support::create_user_with_role("moderator", "Moderator").await;
+
+        let result = AuditService::fetch_audit_entries(&moderator, 50, 0, None, None, None, None).await;
+
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+    }
+
+    /// Every chunk of an export, concatenated
+    async fn export_all(admin: &User, format: AuditExportFormat) -> String {
+        let (mut export, _) = AuditService::begin_export(admin, AuditFilter::default(), format).await.unwrap();
+        let mut document = String::new();
+        while let Some(chunk) = export.next_chunk().await.unwrap() {
+            document.push_str(&chunk);
+        }
+        document
+    }
+
+    #[tokio::test]
+    async fn csv_exports_quote_metadata_json() {
+        let _db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let details = "{\"reason\": \"spam, \\\"ads\\\"\",\n \"count\": 2}";
+        AuditService::record(&admin, RENAME_USER, Some("bob".to_string()), Some(details.to_string())).await;
+
+        let document = export_all(&admin, AuditExportFormat::Csv).await;
+
+        // Quoted once, inner quotes doubled, the newline kept inside the field
+        let quoted = concat!(r#""{""reason"": ""spam, \""ads\"""","#, "\n", r#" ""count"": 2}""#);
+        assert!(document.starts_with("id,timestamp,time,user_id,action,target,details\n"));
+        assert!(document.contains(&format!(",{},bob,{}\n", RENAME_USER, quoted)), "{}", document);
+    }
+
+    #[tokio::test]
+    async fn exports_over_the_protocol_limit_are_refused() {
+        let db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let mut conn = rusqlite::Connection::open(db.path()).unwrap();
+        let tx = conn.transaction().unwrap();
+        for timestamp in 0..=MAX_PROTOCOL_EXPORT_ROWS as i64 {
+            tx.execute(
+                "INSERT INTO audit_log (id, user_id, action, timestamp) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![Uuid::new_v4().to_string(), admin.id.to_string(), RENAME_USER, timestamp],
+            ).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let refused = AuditService::begin_export(&admin, AuditFilter::default(), AuditExportFormat::Json).await;
+        assert!(matches!(refused, Err(ServerError::Validation(_))));
+
+        // Narrowed below the limit, the same log exports
+        let filter = AuditFilter { start_time: Some(1), ..AuditFilter::default() };
+        let (_, total) = AuditService::begin_export(&admin, filter, AuditExportFormat::Json).await.unwrap();
+        assert_eq!(total, MAX_PROTOCOL_EXPORT_ROWS);
+    }
+}
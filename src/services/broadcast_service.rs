@@ -1,9 +1,57 @@
-use crate::api::connection::PeerMap;
+use crate::api::connection::{schedule_dead_peer_cleanup, PeerMap, PeerSender};
+use crate::db::pending_deliveries;
 use nexus_tui_common::{ServerMessage, User};
-use std::collections::HashSet;
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
 use tracing::{error, info};
 use uuid::Uuid;
 
+/// Most undelivered messages kept per offline user; older ones are evicted first
+pub const MAX_PENDING_PER_USER: usize = 100;
+/// How long undelivered messages are kept before maintenance drops them
+pub const PENDING_DELIVERY_TTL_DAYS: i64 = 14;
+/// Presence changes are collected for this long and then sent as one batch per recipient
+const PRESENCE_BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+/// Presence changes waiting for the next flush of one peer map
+struct PresenceBuffer {
+    /// Tells this flusher's buffer apart from a later one at the same address
+    generation: u64,
+    /// Latest state per user within the window: the user and whether they're online
+    pending: HashMap<Uuid, (User, bool)>,
+    /// Bumped after every flush, so callers can wait for one
+    flushed: watch::Sender<u64>,
+}
+
+/// Buffers of the peer maps that have a presence flusher running, keyed by
+/// the peer map's address
+static PRESENCE_BUFFERS: Lazy<Mutex<HashMap<usize, PresenceBuffer>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static PRESENCE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn presence_key(peer_map: &PeerMap) -> usize {
+    Arc::as_ptr(peer_map) as usize
+}
+
+/// Unregisters a flusher's buffer when its task ends, including when the
+/// runtime drops it mid-sleep
+struct PresenceFlusherGuard {
+    key: usize,
+    generation: u64,
+}
+
+impl Drop for PresenceFlusherGuard {
+    fn drop(&mut self) {
+        let mut buffers = PRESENCE_BUFFERS.lock().unwrap();
+        if buffers.get(&self.key).is_some_and(|buffer| buffer.generation == self.generation) {
+            buffers.remove(&self.key);
+        }
+    }
+}
+
 pub struct BroadcastService;
 
 impl BroadcastService {
@@ -11,19 +59,22 @@ impl BroadcastService {
     pub async fn broadcast_to_all(peer_map: &PeerMap, message: &ServerMessage) {
         let peers = peer_map.lock().await;
         let mut success_count = 0;
-        let mut error_count = 0;
+        let mut dead_peers = Vec::new();
 
-        for peer in peers.values() {
+        for (peer_id, peer) in peers.iter() {
             if peer.user_id.is_some() {
                 match peer.tx.send(message.clone()) {
                     Ok(_) => success_count += 1,
                     Err(e) => {
-                        error_count += 1;
+                        dead_peers.push(*peer_id);
                         error!("Failed to broadcast message: {}", e);
                     }
                 }
             }
         }
+        drop(peers);
+        let error_count = dead_peers.len();
+        schedule_dead_peer_cleanup(peer_map, dead_peers);
 
         info!(
             "Broadcasted message to {} users ({} errors)",
@@ -40,17 +91,23 @@ impl BroadcastService {
         let peers = peer_map.lock().await;
         let user_ids_set: HashSet<Uuid> = user_ids.iter().copied().collect();
         let mut success_count = 0;
+        let mut dead_peers = Vec::new();
 
-        for peer in peers.values() {
+        for (peer_id, peer) in peers.iter() {
             if let Some(uid) = peer.user_id {
                 if user_ids_set.contains(&uid) {
                     match peer.tx.send(message.clone()) {
                         Ok(_) => success_count += 1,
-                        Err(e) => error!("Failed to send message to user {}: {}", uid, e),
+                        Err(e) => {
+                            dead_peers.push(*peer_id);
+                            error!("Failed to send message to user {}: {}", uid, e);
+                        }
                     }
                 }
             }
         }
+        drop(peers);
+        schedule_dead_peer_cleanup(peer_map, dead_peers);
 
         info!("Sent message to {} users", success_count);
     }
@@ -60,39 +117,125 @@ impl BroadcastService {
         let peers = peer_map.lock().await;
         let user_ids_set: HashSet<Uuid> = user_ids.iter().copied().collect();
         let mut success_count = 0;
+        let mut dead_peers = Vec::new();
 
-        for peer in peers.values() {
+        for (peer_id, peer) in peers.iter() {
             if let Some(uid) = peer.user_id {
                 if user_ids_set.contains(&uid) {
                     match peer.tx.send(message.clone()) {
                         Ok(_) => success_count += 1,
-                        Err(e) => error!("Failed to send message to user {}: {}", uid, e),
+                        Err(e) => {
+                            dead_peers.push(*peer_id);
+                            error!("Failed to send message to user {}: {}", uid, e);
+                        }
                     }
                 }
             }
         }
+        drop(peers);
+        schedule_dead_peer_cleanup(peer_map, dead_peers);
 
         info!("Sent message to {} users", success_count);
     }
 
-    /// Broadcast user status change to users who share channels
+    /// Broadcast user status change to users who share channels. Changes are
+    /// buffered briefly so a wave of reconnects (e.g. after a restart) costs one
+    /// lookup and one message per recipient instead of one per change.
+    /// Without a flusher running for the peer map the change is sent at once.
     pub async fn broadcast_user_status_change(peer_map: &PeerMap, user: &User, joined: bool) {
-        // Get users who share channels with this user
-        let shared_users = match crate::db::channels::db_get_users_sharing_channels_with(user.id).await {
-            Ok(users) => users,
+        let buffered = {
+            let mut buffers = PRESENCE_BUFFERS.lock().unwrap();
+            match buffers.get_mut(&presence_key(peer_map)) {
+                Some(buffer) => {
+                    buffer.pending.insert(user.id, (user.clone(), joined));
+                    true
+                }
+                None => false,
+            }
+        };
+
+        if !buffered {
+            Self::send_presence(peer_map, HashMap::from([(user.id, (user.clone(), joined))])).await;
+        }
+    }
+
+    /// Start the task that flushes `peer_map`'s presence changes every
+    /// PRESENCE_BATCH_WINDOW. It stops once the peer map is dropped.
+    pub fn spawn_presence_flusher(peer_map: &PeerMap) -> tokio::task::JoinHandle<()> {
+        let key = presence_key(peer_map);
+        let generation = PRESENCE_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let (flushed, _) = watch::channel(0);
+        PRESENCE_BUFFERS.lock().unwrap().insert(key, PresenceBuffer { generation, pending: HashMap::new(), flushed });
+
+        let peer_map = Arc::downgrade(peer_map);
+        tokio::spawn(async move {
+            let _guard = PresenceFlusherGuard { key, generation };
+            let mut interval = tokio::time::interval(PRESENCE_BATCH_WINDOW);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(peer_map) = peer_map.upgrade() else { break };
+                let changes = match PRESENCE_BUFFERS.lock().unwrap().get_mut(&key) {
+                    Some(buffer) => std::mem::take(&mut buffer.pending),
+                    None => break,
+                };
+                Self::send_presence(&peer_map, changes).await;
+                if let Some(buffer) = PRESENCE_BUFFERS.lock().unwrap().get(&key) {
+                    buffer.flushed.send_modify(|count| *count += 1);
+                }
+            }
+        })
+    }
+
+    /// Send presence changes to everyone sharing a channel with the changed
+    /// users. A recipient with a single change gets the plain
+    /// UserJoined/UserLeft; more than one is sent as a PresenceBatch.
+    async fn send_presence(peer_map: &PeerMap, changes: HashMap<Uuid, (User, bool)>) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let changed_ids: Vec<Uuid> = changes.keys().copied().collect();
+        let sharing = match crate::db::channels::db_get_users_sharing_channels_with_many(changed_ids).await {
+            Ok(sharing) => sharing,
             Err(e) => {
                 error!("Failed to get shared channel users: {}", e);
                 return;
             }
         };
 
-        let message = if joined {
-            ServerMessage::UserJoined(user.clone())
-        } else {
-            ServerMessage::UserLeft(user.id)
-        };
+        // recipient -> (came online, went offline)
+        let mut batches: HashMap<Uuid, (Vec<User>, Vec<Uuid>)> = HashMap::new();
+        for (changed_id, recipients) in sharing {
+            let Some((user, online)) = changes.get(&changed_id) else { continue };
+            for recipient in recipients {
+                let batch = batches.entry(recipient).or_default();
+                if *online {
+                    batch.0.push(user.clone());
+                } else {
+                    batch.1.push(user.id);
+                }
+            }
+        }
 
-        Self::send_to_users(peer_map, &shared_users, message).await;
+        let peers = peer_map.lock().await;
+        let mut dead_peers = Vec::new();
+        for (peer_id, peer) in peers.iter() {
+            let Some((online, offline)) = peer.user_id.and_then(|uid| batches.get(&uid)) else { continue };
+            let message = match (online.as_slice(), offline.as_slice()) {
+                ([user], []) => ServerMessage::UserJoined(user.clone()),
+                ([], [user_id]) => ServerMessage::UserLeft(*user_id),
+                _ => ServerMessage::PresenceBatch { online: online.clone(), offline: offline.clone() },
+            };
+            if let Err(e) = peer.tx.send(message) {
+                dead_peers.push(*peer_id);
+                error!("Failed to send presence update to peer {}: {}", peer_id, e);
+            }
+        }
+        drop(peers);
+        schedule_dead_peer_cleanup(peer_map, dead_peers);
+
+        info!("Flushed {} presence changes to {} users", changes.len(), batches.len());
     }
 
     /// Broadcast user profile update to users who share channels
@@ -119,30 +262,95 @@ impl BroadcastService {
         Self::broadcast_to_users(peer_map, channel_user_ids, message).await;
     }
 
-    /// Send a direct message to a specific user if they're online
+    /// Send a direct message to a specific user if they're online. Important
+    /// messages that can't be delivered are kept and replayed on next login.
     pub async fn send_to_user(peer_map: &PeerMap, user_id: Uuid, message: &ServerMessage) -> bool {
         let peers = peer_map.lock().await;
+        let mut dead_peers = Vec::new();
         
-        for peer in peers.values() {
+        for (peer_id, peer) in peers.iter() {
             if peer.user_id == Some(user_id) {
                 match peer.tx.send(message.clone()) {
                     Ok(_) => return true,
                     Err(e) => {
+                        dead_peers.push(*peer_id);
                         error!("Failed to send message to user {}: {}", user_id, e);
-                        return false;
                     }
                 }
             }
         }
+        drop(peers);
+        schedule_dead_peer_cleanup(peer_map, dead_peers);
+
+        if let Some(kind) = Self::pending_delivery_kind(message) {
+            Self::queue_pending_delivery(user_id, kind, message).await;
+        }
         
         false // User not online
     }
 
+    /// Message kinds worth holding for a user who is offline right now
+    fn pending_delivery_kind(message: &ServerMessage) -> Option<&'static str> {
+        match message {
+            ServerMessage::ServerInviteReceived(_) => Some("ServerInviteReceived"),
+            ServerMessage::ServerInviteResponse { .. } => Some("ServerInviteResponse"),
+            _ => None,
+        }
+    }
+
+    async fn queue_pending_delivery(user_id: Uuid, kind: &str, message: &ServerMessage) {
+        let payload = match bincode::serialize(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize pending {} for user {}: {}", kind, user_id, e);
+                return;
+            }
+        };
+
+        match pending_deliveries::db_queue_pending_delivery(user_id, kind, payload, MAX_PENDING_PER_USER).await {
+            Ok(_) => info!("Queued {} for offline user {}", kind, user_id),
+            Err(e) => error!("Failed to queue pending {} for user {}: {}", kind, user_id, e),
+        }
+    }
+
+    /// Send a freshly authenticated user everything queued while they were away, oldest first
+    pub async fn replay_pending_deliveries(user_id: Uuid, sender: &PeerSender) {
+        let pending = match pending_deliveries::db_get_pending_deliveries(user_id).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!("Failed to load pending deliveries for user {}: {}", user_id, e);
+                return;
+            }
+        };
+
+        // Only entries handed to the sender (or unreadable ones) are removed;
+        // if the peer goes away midway the rest wait for the next login
+        let mut handled = Vec::new();
+        for (id, payload) in pending {
+            match bincode::deserialize::<ServerMessage>(&payload) {
+                Ok(message) => {
+                    // Not send_wait: this runs on the connection task that drains the queue
+                    if let Err(e) = sender.send(message) {
+                        info!("Stopping replay for user {} ({}); keeping the rest queued", user_id, e);
+                        break;
+                    }
+                }
+                Err(e) => error!("Dropping unreadable pending delivery for user {}: {}", user_id, e),
+            }
+            handled.push(id);
+        }
+
+        if let Err(e) = pending_deliveries::db_delete_pending_deliveries(handled).await {
+            error!("Failed to clear replayed deliveries for user {}: {}", user_id, e);
+        }
+    }
+
     /// Get list of online user IDs
     pub async fn get_online_users(peer_map: &PeerMap) -> HashSet<Uuid> {
         let peers = peer_map.lock().await;
         peers
             .values()
+            .filter(|peer| !peer.disconnected.load(Ordering::SeqCst))
             .filter_map(|peer| peer.user_id)
             .collect()
     }
@@ -150,6 +358,200 @@ impl BroadcastService {
     /// Check if a user is online
     pub async fn is_user_online(peer_map: &PeerMap, user_id: Uuid) -> bool {
         let peers = peer_map.lock().await;
-        peers.values().any(|peer| peer.user_id == Some(user_id))
+        peers
+            .values()
+            .any(|peer| peer.user_id == Some(user_id) && !peer.disconnected.load(Ordering::SeqCst))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakePeer, TestDb};
+    use nexus_tui_common::ClientMessage;
+
+    fn invite_response(invite_id: Uuid, from: &User) -> ServerMessage {
+        ServerMessage::ServerInviteResponse { invite_id, accepted: true, user: from.clone() }
+    }
+
+    fn invite_ids(messages: &[ServerMessage]) -> Vec<Uuid> {
+        messages.iter().filter_map(|message| match message {
+            ServerMessage::ServerInviteResponse { invite_id, .. } => Some(*invite_id),
+            _ => None,
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn peers_with_a_dropped_receiver_are_evicted_after_a_broadcast() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let bob_peer_id = FakePeer::connect(&peer_map, Some(bob.id)).await.hang_up();
+
+        let notice = ServerMessage::Notification("hello".to_string(), false);
+        BroadcastService::broadcast_to_all(&peer_map, &notice).await;
+
+        assert!(test_support::peer_removed(&peer_map, bob_peer_id).await);
+        assert!(peer_map.lock().await.contains_key(&alice_peer.peer_id));
+        assert!(!BroadcastService::is_user_online(&peer_map, bob.id).await);
+        assert!(alice_peer.drain().iter().any(|message| matches!(
+            message, ServerMessage::Notification(text, false) if text == "hello"
+        )));
+    }
+
+    #[tokio::test]
+    async fn targeted_sends_evict_dead_peers_too() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let bob = test_support::create_user("bob").await;
+        let dead_peer_id = FakePeer::connect(&peer_map, Some(bob.id)).await.hang_up();
+        let live_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        let notice = ServerMessage::Notification("hello".to_string(), false);
+        BroadcastService::broadcast_to_users(&peer_map, &[bob.id], &notice).await;
+
+        assert!(test_support::peer_removed(&peer_map, dead_peer_id).await);
+        assert!(peer_map.lock().await.contains_key(&live_peer.peer_id));
+    }
+
+    #[tokio::test]
+    async fn messages_for_an_offline_user_are_replayed_in_order_on_login() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let invites: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        for &invite_id in &invites {
+            assert!(!BroadcastService::send_to_user(&peer_map, bob.id, &invite_response(invite_id, &alice)).await);
+        }
+        assert_eq!(db.count_rows("pending_deliveries"), 3);
+
+        let mut bob_peer = FakePeer::connect(&peer_map, None).await;
+        let login = ClientMessage::Login { username: "bob".to_string(), password: test_support::TEST_PASSWORD.to_string() };
+        test_support::router(&peer_map)
+            .handle_message(login, &mut None, bob_peer.peer_id, &bob_peer.sender).await.unwrap();
+
+        let received = bob_peer.drain();
+        assert!(received.iter().any(|message| matches!(message, ServerMessage::AuthSuccess(_))));
+        assert_eq!(invite_ids(&received), invites);
+        assert_eq!(db.count_rows("pending_deliveries"), 0);
+    }
+
+    #[tokio::test]
+    async fn undelivered_messages_stay_queued_when_the_peer_is_gone() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        for _ in 0..2 {
+            BroadcastService::send_to_user(&peer_map, bob.id, &invite_response(Uuid::new_v4(), &alice)).await;
+        }
+
+        let bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+        let sender = bob_peer.sender.clone();
+        bob_peer.hang_up();
+        BroadcastService::replay_pending_deliveries(bob.id, &sender).await;
+
+        assert_eq!(db.count_rows("pending_deliveries"), 2);
+    }
+
+    /// Everything the peer receives once the flusher has sent every change
+    /// buffered so far
+    async fn presence_updates(peer_map: &PeerMap, peer: &mut FakePeer) -> Vec<ServerMessage> {
+        let mut flushed = PRESENCE_BUFFERS.lock().unwrap()[&presence_key(peer_map)].flushed.subscribe();
+        let settled = async {
+            loop {
+                flushed.changed().await.expect("presence flusher stopped");
+                let buffers = PRESENCE_BUFFERS.lock().unwrap();
+                if buffers[&presence_key(peer_map)].pending.is_empty() {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), settled).await.expect("presence flush");
+        peer.drain()
+    }
+
+    /// `alice`, online, sharing a channel with `count` other users
+    async fn shared_channel(peer_map: &PeerMap, count: usize) -> (FakePeer, Vec<User>) {
+        let alice = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        let mut others = Vec::new();
+        for i in 0..count {
+            let user = test_support::create_user(&format!("user{}", i)).await;
+            test_support::join_server(server_id, user.id).await;
+            others.push(user);
+        }
+        test_support::create_channel(server_id, "general").await;
+        (FakePeer::connect(peer_map, Some(alice.id)).await, others)
+    }
+
+    #[tokio::test]
+    async fn rapid_presence_changes_reach_each_recipient_as_one_batch() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (mut alice_peer, others) = shared_channel(&peer_map, 6).await;
+        let _flusher = BroadcastService::spawn_presence_flusher(&peer_map);
+
+        for user in &others[..5] {
+            BroadcastService::broadcast_user_status_change(&peer_map, user, true).await;
+        }
+        BroadcastService::broadcast_user_status_change(&peer_map, &others[5], false).await;
+
+        let received = presence_updates(&peer_map, &mut alice_peer).await;
+        assert_eq!(received.len(), 1, "{:?}", received);
+        match &received[0] {
+            ServerMessage::PresenceBatch { online, offline } => {
+                let mut online: Vec<Uuid> = online.iter().map(|user| user.id).collect();
+                let mut expected: Vec<Uuid> = others[..5].iter().map(|user| user.id).collect();
+                online.sort();
+                expected.sort();
+                assert_eq!(online, expected);
+                assert_eq!(offline, &vec![others[5].id]);
+            }
+            other => panic!("expected a PresenceBatch, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_lone_presence_change_is_sent_plain() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (mut alice_peer, others) = shared_channel(&peer_map, 1).await;
+        let _flusher = BroadcastService::spawn_presence_flusher(&peer_map);
+
+        // Only the latest state within the window counts
+        BroadcastService::broadcast_user_status_change(&peer_map, &others[0], false).await;
+        BroadcastService::broadcast_user_status_change(&peer_map, &others[0], true).await;
+
+        let received = presence_updates(&peer_map, &mut alice_peer).await;
+        assert_eq!(received.len(), 1, "{:?}", received);
+        assert!(matches!(&received[0], ServerMessage::UserJoined(user) if user.id == others[0].id));
+    }
+
+    #[tokio::test]
+    async fn without_a_flusher_presence_is_sent_at_once() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (mut alice_peer, others) = shared_channel(&peer_map, 1).await;
+
+        BroadcastService::broadcast_user_status_change(&peer_map, &others[0], false).await;
+
+        let received = alice_peer.drain();
+        assert!(matches!(&received[..], [ServerMessage::UserLeft(user_id)] if *user_id == others[0].id), "{:?}", received);
+    }
+
+    #[tokio::test]
+    async fn a_stopped_flusher_unregisters_its_buffer() {
+        let peer_map = test_support::peer_map();
+        let flusher = BroadcastService::spawn_presence_flusher(&peer_map);
+        assert!(PRESENCE_BUFFERS.lock().unwrap().contains_key(&presence_key(&peer_map)));
+
+        flusher.abort();
+        let _ = flusher.await;
+
+        assert!(!PRESENCE_BUFFERS.lock().unwrap().contains_key(&presence_key(&peer_map)));
     }
-}
\ No newline at end of file
+}
@@ -1,11 +1,65 @@
-use crate::db::{channels, messages};
+use crate::config::PageLimits;
+use crate::db::{channels, messages, servers, users};
 use crate::errors::{Result, ServerError};
-use crate::services::{BroadcastService, NotificationService};
+use crate::services::{audit_service, metrics_service, AuditService, BroadcastService, MetricsService, ModerationService, NotificationService, StorageService};
+use crate::services::content_filter_service::{ContentFilterService, FilterResult};
 use crate::api::connection::PeerMap;
-use nexus_tui_common::{ChannelMessage, DirectMessage, ServerMessage, User};
-use tracing::info;
+use nexus_tui_common::{ActiveChannel, ChannelMessage, DirectMessage, MessageBookmark, MessageOrigin, ServerMessage, User, UserRole};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
 use uuid::Uuid;
 
+/// Largest serialized `origin` payload accepted on a bridged message
+const MAX_ORIGIN_BYTES: usize = 512;
+
+/// Most #channel references resolved in one message
+const MAX_CHANNEL_REFS: usize = 20;
+
+/// Most custom :emoji: resolved in one message
+const MAX_EMOJI_REFS: usize = 50;
+
+/// Most messages returned on each side of a "jump to date" anchor
+const MAX_AROUND_RADIUS: usize = 100;
+
+/// Most bookmarks one user may keep
+const MAX_BOOKMARKS: usize = 500;
+
+/// Longest note attached to a bookmark
+const MAX_BOOKMARK_NOTE_CHARS: usize = 280;
+
+/// Bookmarks returned per GetBookmarks page
+const BOOKMARK_PAGE_SIZE: usize = 50;
+
+/// Characters of a deleted message kept in its audit entry
+const AUDIT_PREVIEW_CHARS: usize = 80;
+
+/// Shown in place of a bookmarked message that has since been deleted
+const DELETED_PLACEHOLDER: &str = "[deleted]";
+
+/// How far back the home view looks for activity when the client doesn't say
+const DEFAULT_ACTIVITY_WINDOW_SECS: i64 = 7 * 24 * 3600;
+
+/// Channels listed in the home view
+const MAX_ACTIVE_CHANNELS: usize = 50;
+
+/// Characters of the latest message shown in the home view
+const ACTIVITY_PREVIEW_CHARS: usize = 100;
+
+/// How long a message count stays cached. Counts are approximate anyway.
+const MESSAGE_COUNT_TTL: Duration = Duration::from_secs(60);
+
+/// Cache key for message counts: a channel or a DM conversation (ordered user pair)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum MessageCountKey {
+    Channel(Uuid),
+    Direct(Uuid, Uuid),
+}
+
+static MESSAGE_COUNTS: Lazy<Mutex<HashMap<MessageCountKey, (usize, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 /// Configuration for pagination
 #[derive(Debug, Clone)]
 pub struct PaginationConfig {
@@ -14,11 +68,11 @@ pub struct PaginationConfig {
     pub prefetch_threshold: usize, // How many messages to prefetch
 }
 
-impl Default for PaginationConfig {
-    fn default() -> Self {
+impl From<PageLimits> for PaginationConfig {
+    fn from(limits: PageLimits) -> Self {
         Self {
-            default_page_size: 50,
-            max_page_size: 100,
+            default_page_size: limits.default,
+            max_page_size: limits.max,
             prefetch_threshold: 10,
         }
     }
@@ -181,46 +235,247 @@ impl ChatService {
         })
     }
 
+    /// Reject links the channel's link policy doesn't allow
+    async fn check_link_policy(channel_id: Uuid, content: &str) -> Result<()> {
+        let urls = crate::util::extract_urls(content);
+        if urls.is_empty() {
+            return Ok(());
+        }
+
+        let (links_allowed, allowlist) = channels::db_get_channel_link_policy(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if !links_allowed {
+            return Err(ServerError::Validation("Links are not allowed in this channel".to_string()));
+        }
+
+        if let Some(allowlist) = allowlist {
+            for url in &urls {
+                let domain = crate::util::url_domain(url).unwrap_or_default();
+                if !allowlist.iter().any(|allowed| crate::util::domain_allowed(&domain, allowed)) {
+                    return Err(ServerError::Validation(format!(
+                        "Links to {} are not allowed in this channel", domain
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serialize a message's bridge provenance. Only bot accounts may set it;
+    /// anyone else's is dropped without complaint.
+    fn origin_json(user: &User, origin: Option<MessageOrigin>) -> Result<Option<String>> {
+        let Some(origin) = origin.filter(|_| user.role == UserRole::Bot) else {
+            return Ok(None);
+        };
+        let json = serde_json::to_string(&origin)
+            .map_err(|e| ServerError::Internal(e.to_string()))?;
+        if json.len() > MAX_ORIGIN_BYTES {
+            return Err(ServerError::Validation(format!(
+                "Message origin must be at most {} bytes", MAX_ORIGIN_BYTES
+            )));
+        }
+        Ok(Some(json))
+    }
+
     /// Send a channel message
     pub async fn send_channel_message(
         channel_id: Uuid,
         user: &User,
         content: &str,
+        origin: Option<MessageOrigin>,
+        content_filter: &ContentFilterService,
         peer_map: &PeerMap,
     ) -> Result<()> {
-        let timestamp = chrono::Utc::now().timestamp();
-        
-        // Store message in database
-        let message_id = channels::db_create_channel_message(
-            channel_id, user.id, timestamp, content
-        ).await.map_err(|e| ServerError::Database(e))?;
+        if !channels::db_can_user_write_channel(user.id, channel_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("You don't have permission to write in this channel".to_string()));
+        }
+
+        let origin = Self::origin_json(user, origin)?;
+
+        Self::check_link_policy(channel_id, content).await?;
+
+        let timestamp = crate::util::now_secs();
+
+        let flagged = match content_filter.filter_message(content) {
+            FilterResult::Allowed => None,
+            FilterResult::Blocked(reason) => return Err(ServerError::Validation(reason)),
+            FilterResult::Flagged(reason) => Some(reason),
+        };
+
+        if let Some(reason) = &flagged {
+            // A new account's first message is held for review if the filter flags it
+            if ModerationService::is_on_probation(user.id).await?
+                && channels::db_count_user_channel_messages(user.id).await.map_err(|e| ServerError::Database(e))? == 0
+            {
+                return ModerationService::quarantine_message(
+                    channel_id, user, content, timestamp, reason, peer_map
+                ).await;
+            }
+        }
+
+        let message_id = Self::deliver_channel_message(channel_id, user, content, origin, timestamp, None, peer_map).await?;
+
+        // Flagged messages from established accounts go out, but leave a trail
+        // and land in the moderators' review queue
+        if let Some(reason) = flagged {
+            ModerationService::flag_message(user, message_id, Some(channel_id), None, content, &reason, timestamp).await;
+            AuditService::record(
+                user, audit_service::MESSAGE_MODERATED, Some(channel_id.to_string()), Some(reason)
+            ).await;
+        }
+        Ok(())
+    }
+
+    /// Store and broadcast a channel message that has already passed any checks,
+    /// returning the stored message's id
+    pub async fn deliver_channel_message(
+        channel_id: Uuid,
+        user: &User,
+        content: &str,
+        origin: Option<String>,
+        timestamp: i64,
+        message_id: Option<Uuid>,
+        peer_map: &PeerMap,
+    ) -> Result<Uuid> {
+        // Store message in database. A message that already has an id (one
+        // released from quarantine) is stored at most once under it.
+        let message_id = match message_id {
+            Some(message_id) => {
+                let stored = channels::db_create_channel_message_with_id(
+                    message_id, channel_id, user.id, timestamp, content, origin.clone()
+                ).await.map_err(|e| ServerError::Database(e))?;
+                if !stored {
+                    info!("Channel message {} was already delivered", message_id);
+                    return Ok(message_id);
+                }
+                message_id
+            }
+            None => channels::db_create_channel_message(
+                channel_id, user.id, timestamp, content, origin.clone()
+            ).await.map_err(|e| ServerError::Database(e))?,
+        };
+        MetricsService::increment(metrics_service::MESSAGES_SENT);
+        MetricsService::record_channel_message(channel_id, content.len());
+        StorageService::record_message(user.id, content, peer_map).await;
 
         // Create message object - no redundant author fields
-        let channel_msg = ChannelMessage {
+        let mut channel_msg = ChannelMessage {
             id: message_id,
             channel_id,
             sent_by: user.id,
             timestamp,
             content: content.to_string(),
+            system_event: None,
+            origin,
+            channel_refs: Self::resolve_channel_refs(channel_id, content).await,
+            emojis: Vec::new(),
+            reactions: Vec::new(),
         };
+        Self::attach_emojis(channel_id, std::slice::from_mut(&mut channel_msg)).await;
 
-        // Get channel users for broadcasting
+        // Get channel users for broadcasting, minus anyone who muted the author
+        let muters = Self::users_muting(user.id, channel_id).await;
         let channel_users = channels::db_get_channel_user_list(channel_id).await
             .map_err(|e| ServerError::Database(e))?;
-        let user_ids: Vec<Uuid> = channel_users.iter().map(|u| u.id).collect();
+        let user_ids: Vec<Uuid> = channel_users.iter()
+            .map(|u| u.id)
+            .filter(|id| !muters.contains(id))
+            .collect();
 
         // Broadcast to channel users
-        let message = ServerMessage::NewChannelMessage(channel_msg);
-        BroadcastService::broadcast_to_channel_users(peer_map, &user_ids, &message).await;
+        Self::broadcast_with_readable_refs(channel_msg, &user_ids, peer_map).await;
 
         // Handle mentions
         let mentioned_users = crate::util::extract_mentions(content);
-        if !mentioned_users.is_empty() {
-            Self::handle_mentions(user, content, &mentioned_users, peer_map).await;
-        }
+        let mentioned_ids = if mentioned_users.is_empty() {
+            Vec::new()
+        } else {
+            Self::handle_mentions(user, content, &mentioned_users, &muters, peer_map).await
+        };
+
+        Self::notify_followers(channel_id, user, &mentioned_ids, &muters, peer_map).await;
 
         info!("Channel message sent by {} in channel {}", user.username, channel_id);
-        Ok(())
+        Ok(message_id)
+    }
+
+    /// Resolve #channel-name references against the channels of the message's
+    /// server. Names that don't match a channel are left out.
+    async fn resolve_channel_refs(channel_id: Uuid, content: &str) -> Vec<(String, Uuid)> {
+        let mut names = crate::util::extract_channel_references(content);
+        names.truncate(MAX_CHANNEL_REFS);
+
+        match channels::db_resolve_channel_names(channel_id, names).await {
+            Ok(refs) => refs,
+            Err(e) => {
+                error!("Failed to resolve channel references: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Broadcast a new channel message, keeping in each recipient's copy only
+    /// the #channel references that recipient may read
+    async fn broadcast_with_readable_refs(channel_msg: ChannelMessage, user_ids: &[Uuid], peer_map: &PeerMap) {
+        if channel_msg.channel_refs.is_empty() {
+            let message = ServerMessage::NewChannelMessage(channel_msg);
+            BroadcastService::broadcast_to_channel_users(peer_map, user_ids, &message).await;
+            return;
+        }
+
+        let ref_ids: Vec<Uuid> = channel_msg.channel_refs.iter().map(|(_, id)| *id).collect();
+        let readable = match channels::db_get_readable_channels(user_ids.to_vec(), ref_ids).await {
+            Ok(readable) => readable,
+            Err(e) => {
+                error!("Failed to check access to referenced channels: {}", e);
+                HashMap::new()
+            }
+        };
+
+        // Recipients who see the same references share one copy
+        let mut groups: HashMap<Vec<Uuid>, Vec<Uuid>> = HashMap::new();
+        for user_id in user_ids {
+            let visible = readable.get(user_id).cloned().unwrap_or_default();
+            groups.entry(visible).or_default().push(*user_id);
+        }
+        for (visible, recipients) in groups {
+            let mut copy = channel_msg.clone();
+            copy.channel_refs.retain(|(_, id)| visible.contains(id));
+            let message = ServerMessage::NewChannelMessage(copy);
+            BroadcastService::broadcast_to_channel_users(peer_map, &recipients, &message).await;
+        }
+    }
+
+    /// Fill in the image refs of the server's custom :emoji: used in each
+    /// message, with one lookup for the whole batch. Unknown names are left out.
+    pub async fn attach_emojis(channel_id: Uuid, messages: &mut [ChannelMessage]) {
+        let per_message: Vec<Vec<String>> = messages.iter()
+            .map(|message| {
+                let mut names = crate::util::extract_emoji_names(&message.content);
+                names.truncate(MAX_EMOJI_REFS);
+                names
+            })
+            .collect();
+        let mut all_names: Vec<String> = per_message.iter().flatten().cloned().collect();
+        all_names.sort();
+        all_names.dedup();
+        if all_names.is_empty() {
+            return;
+        }
+
+        let known: HashMap<String, String> = match servers::db_resolve_channel_emojis(channel_id, all_names).await {
+            Ok(emojis) => emojis.into_iter().collect(),
+            Err(e) => {
+                error!("Failed to resolve custom emoji: {}", e);
+                return;
+            }
+        };
+        for (message, names) in messages.iter_mut().zip(per_message) {
+            message.emojis = names.into_iter()
+                .filter_map(|name| known.get(&name).map(|image_ref| (name, image_ref.clone())))
+                .collect();
+        }
     }
 
     /// Send a direct message
@@ -228,14 +483,23 @@ impl ChatService {
         from_user: &User,
         to_user_id: Uuid,
         content: &str,
+        content_filter: &ContentFilterService,
         peer_map: &PeerMap,
     ) -> Result<()> {
-        let timestamp = chrono::Utc::now().timestamp();
+        let flagged = match content_filter.filter_message(content) {
+            FilterResult::Allowed => None,
+            FilterResult::Blocked(reason) => return Err(ServerError::Validation(reason)),
+            FilterResult::Flagged(reason) => Some(reason),
+        };
+
+        let timestamp = crate::util::now_secs();
         
         // Store DM in database
         let dm_id = messages::db_store_direct_message(
             from_user.id, to_user_id, content, timestamp
         ).await.map_err(|e| ServerError::Database(e))?;
+        MetricsService::increment(metrics_service::MESSAGES_SENT);
+        StorageService::record_message(from_user.id, content, peer_map).await;
 
         // Create DM object - no redundant author fields
         let dm = DirectMessage {
@@ -254,6 +518,13 @@ impl ChatService {
         // Create notification for recipient
         NotificationService::create_dm_notification(to_user_id, dm_id, &from_user.username, peer_map).await;
 
+        if let Some(reason) = flagged {
+            ModerationService::flag_message(from_user, dm_id, None, Some(to_user_id), content, &reason, timestamp).await;
+            AuditService::record(
+                from_user, audit_service::MESSAGE_MODERATED, Some(dm_id.to_string()), Some(reason)
+            ).await;
+        }
+
         info!("Direct message sent from {} to {}", from_user.username, to_user_id);
         Ok(())
     }
@@ -261,10 +532,23 @@ impl ChatService {
     /// Get channel messages with enhanced pagination
     pub async fn get_channel_messages_paginated(
         channel_id: Uuid,
+        viewer_id: Uuid,
+        request: PaginationRequest,
+        config: Option<PaginationConfig>,
+    ) -> Result<PaginationResponse<ChannelMessage>> {
+        MetricsService::time(
+            "channel_messages",
+            Self::fetch_channel_messages_paginated(channel_id, viewer_id, request, config),
+        ).await
+    }
+
+    async fn fetch_channel_messages_paginated(
+        channel_id: Uuid,
+        viewer_id: Uuid,
         request: PaginationRequest,
         config: Option<PaginationConfig>,
     ) -> Result<PaginationResponse<ChannelMessage>> {
-        let config = config.unwrap_or_default();
+        let config = config.unwrap_or_else(|| crate::config::settings().pagination.channel_messages.into());
         let limit = request.limit.min(config.max_page_size).max(1);
         
         match request.cursor {
@@ -274,13 +558,14 @@ impl ChatService {
                     limit,
                     Some(before_ts),
                     |before, lim, reverse| async move {
-                        channels::db_get_channel_messages_by_timestamp(channel_id, before, lim, reverse).await
+                        channels::db_get_channel_messages_by_timestamp(channel_id, viewer_id, before, lim, reverse).await
                     }
                 ).await
             }
             PaginationCursor::Start => {
                 let (messages, has_more) = channels::db_get_channel_messages_by_timestamp(
                     channel_id, 
+                    viewer_id,
                     None, 
                     limit,
                     request.direction == PaginationDirection::Backward
@@ -290,12 +575,226 @@ impl ChatService {
             }
             PaginationCursor::Offset(_) => {
                 // Fallback to existing implementation for compatibility
-                let (messages, has_more) = Self::get_channel_messages(channel_id, None, limit).await?;
+                let (messages, has_more) = channels::db_get_channel_messages(channel_id, viewer_id, None, limit).await
+                    .map_err(|e| ServerError::Database(e))?;
                 Ok(Self::create_fallback_pagination_response(messages, has_more))
             }
         }
     }
 
+    /// Get messages around a moment in a channel for "jump to date"
+    pub async fn get_channel_messages_around(
+        user_id: Uuid,
+        channel_id: Uuid,
+        timestamp: i64,
+        radius: usize,
+    ) -> Result<(Vec<ChannelMessage>, bool, bool)> {
+        Self::ensure_can_read_channel(user_id, channel_id).await?;
+
+        let radius = radius.clamp(1, MAX_AROUND_RADIUS);
+        let (mut messages, more_before, more_after) = channels::db_get_channel_messages_around(channel_id, user_id, timestamp, radius).await
+            .map_err(|e| ServerError::Database(e))?;
+        Self::hide_muted_authors(user_id, channel_id, &mut messages).await;
+        Self::attach_emojis(channel_id, &mut messages).await;
+        Ok((messages, more_before, more_after))
+    }
+
+    /// Require that a user belongs to the channel's server and may read the channel
+    pub async fn ensure_can_read_channel(user_id: Uuid, channel_id: Uuid) -> Result<()> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        let is_member = servers::db_is_user_in_server(user_id, server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let can_read = is_member && channels::db_can_user_read_channel(user_id, channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if !can_read {
+            return Err(ServerError::Forbidden("Not authorized to read this channel".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Whether a user moderates the server a channel belongs to
+    async fn moderates_channel(user_id: Uuid, channel_id: Uuid) -> Result<bool> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        let moderator_ids = users::db_get_moderator_ids(server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(moderator_ids.contains(&user_id))
+    }
+
+    /// Mirror a moderation action on a channel to its server's audit channel
+    pub async fn mirror_moderation(channel_id: Uuid, actor: &User, action: &str, summary: String, peer_map: &PeerMap) {
+        match channels::db_get_channel_server_id(channel_id).await {
+            Ok(server_id) => AuditService::mirror_to_server(server_id, actor, action, summary, peer_map).await,
+            Err(e) => error!("Failed to find server of channel {} for audit mirroring: {}", channel_id, e),
+        }
+    }
+
+    /// Delete a channel message (author or moderators). The message becomes a
+    /// tombstone hidden from history and is purged after the grace window.
+    pub async fn delete_channel_message(user: &User, message_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        let (message, deleted_at) = channels::db_get_channel_message(message_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if deleted_at.is_some() {
+            return Err(ServerError::NotFound("Message not found".to_string()));
+        }
+        if message.sent_by != user.id && !Self::moderates_channel(user.id, message.channel_id).await? {
+            return Err(ServerError::Forbidden("You can't delete this message".to_string()));
+        }
+
+        let now = crate::util::now_secs();
+        if !channels::db_tombstone_channel_message(message_id, user.id, now).await
+            .map_err(|e| ServerError::Database(e))?
+        {
+            // Lost a race with another delete
+            return Err(ServerError::NotFound("Message not found".to_string()));
+        }
+
+        let channel_users = channels::db_get_channel_user_list(message.channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let user_ids: Vec<Uuid> = channel_users.iter().map(|u| u.id).collect();
+        let deleted = ServerMessage::ChannelMessageDeleted { channel_id: message.channel_id, message_id };
+        BroadcastService::broadcast_to_channel_users(peer_map, &user_ids, &deleted).await;
+
+        let mut preview: String = message.content.chars().take(AUDIT_PREVIEW_CHARS).collect();
+        if preview.len() < message.content.len() {
+            preview.push('…');
+        }
+        AuditService::record(
+            user,
+            audit_service::DELETE_MESSAGE,
+            Some(message_id.to_string()),
+            Some(format!("author={} channel={} preview={}", message.sent_by, message.channel_id, preview)),
+        ).await;
+        // Authors removing their own messages aren't moderation
+        if message.sent_by != user.id {
+            let summary = format!(
+                "{} deleted a message by {}", user.username, AuditService::display_name(message.sent_by).await
+            );
+            Self::mirror_moderation(message.channel_id, user, audit_service::DELETE_MESSAGE, summary, peer_map).await;
+        }
+        info!("Channel message {} deleted by {}", message_id, user.username);
+        Ok(())
+    }
+
+    /// Edit a channel message (author or Admins/Moderators). The new content
+    /// goes through the same write check, filter, link policy and storage
+    /// accounting as a fresh message.
+    pub async fn edit_channel_message(
+        user: &User,
+        message_id: Uuid,
+        content: &str,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        let content = content.trim();
+        if content.is_empty() {
+            return Err(ServerError::Validation("Message cannot be empty".to_string()));
+        }
+        let flagged = match content_filter.filter_message(content) {
+            FilterResult::Allowed => None,
+            FilterResult::Blocked(reason) => return Err(ServerError::Validation(reason)),
+            FilterResult::Flagged(reason) => Some(reason),
+        };
+        let (message, _) = channels::db_get_channel_message(message_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        // Authors who lost write access can't reword what they already sent
+        if message.sent_by == user.id
+            && !channels::db_can_user_write_channel(user.id, message.channel_id).await.map_err(|e| ServerError::Database(e))?
+        {
+            return Err(ServerError::Forbidden("You don't have permission to write in this channel".to_string()));
+        }
+        Self::check_link_policy(message.channel_id, content).await?;
+
+        let (channel_id, sent_by, edited_at, previous_len) = match channels::db_edit_channel_message(message_id, user.id, content).await
+            .map_err(|e| ServerError::Database(e))?
+        {
+            channels::ChannelMessageEdit::Edited { channel_id, sent_by, edited_at, previous_len } => {
+                (channel_id, sent_by, edited_at, previous_len)
+            }
+            channels::ChannelMessageEdit::NotFound => {
+                return Err(ServerError::NotFound("Message not found".to_string()));
+            }
+            channels::ChannelMessageEdit::NotAllowed => {
+                return Err(ServerError::Forbidden("You can't edit this message".to_string()));
+            }
+            channels::ChannelMessageEdit::LimitReached(max) => {
+                return Err(ServerError::Validation(format!("Message has already been edited {} times", max)));
+            }
+        };
+
+        let channel_users = channels::db_get_channel_user_list(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let user_ids: Vec<Uuid> = channel_users.iter().map(|u| u.id).collect();
+        let edited = ServerMessage::ChannelMessageEdited {
+            message_id,
+            channel_id,
+            content: content.to_string(),
+            edited_at,
+        };
+        BroadcastService::broadcast_to_channel_users(peer_map, &user_ids, &edited).await;
+        StorageService::record_message_edit(sent_by, previous_len, content.len(), peer_map).await;
+
+        if let Some(reason) = flagged {
+            ModerationService::flag_message(user, message_id, Some(channel_id), None, content, &reason, edited_at).await;
+            AuditService::record(
+                user, audit_service::MESSAGE_MODERATED, Some(channel_id.to_string()), Some(reason)
+            ).await;
+        }
+
+        // Staff rewording someone else's message is a moderation action
+        if sent_by != user.id {
+            AuditService::record(
+                user, audit_service::EDIT_MESSAGE, Some(message_id.to_string()), Some(sent_by.to_string())
+            ).await;
+            let summary = format!(
+                "{} edited a message by {}", user.username, AuditService::display_name(sent_by).await
+            );
+            Self::mirror_moderation(channel_id, user, audit_service::EDIT_MESSAGE, summary, peer_map).await;
+        }
+        info!("Channel message {} edited by {}", message_id, user.username);
+        Ok(())
+    }
+
+    /// Restore a deleted channel message that is still inside the grace window (moderators only)
+    pub async fn restore_channel_message(moderator: &User, message_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        let (message, deleted_at) = channels::db_get_channel_message(message_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if !Self::moderates_channel(moderator.id, message.channel_id).await? {
+            return Err(ServerError::Forbidden("Not a moderator of this server".to_string()));
+        }
+        let Some(deleted_at) = deleted_at else {
+            return Err(ServerError::Validation("Message is not deleted".to_string()));
+        };
+        let grace_secs = crate::config::settings().messages.tombstone_grace_hours * 3600;
+        if crate::util::now_secs() - deleted_at >= grace_secs {
+            return Err(ServerError::Validation("Message is past its restore window".to_string()));
+        }
+
+        if !channels::db_restore_channel_message(message_id).await
+            .map_err(|e| ServerError::Database(e))?
+        {
+            return Ok(());
+        }
+
+        AuditService::record(
+            moderator, audit_service::RESTORE_MESSAGE, Some(message_id.to_string()), Some(message.sent_by.to_string())
+        ).await;
+        let summary = format!(
+            "{} restored a message by {}", moderator.username, AuditService::display_name(message.sent_by).await
+        );
+        Self::mirror_moderation(message.channel_id, moderator, audit_service::RESTORE_MESSAGE, summary, peer_map).await;
+
+        let channel_users = channels::db_get_channel_user_list(message.channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let user_ids: Vec<Uuid> = channel_users.iter().map(|u| u.id).collect();
+        let restored = ServerMessage::NewChannelMessage(message);
+        BroadcastService::broadcast_to_channel_users(peer_map, &user_ids, &restored).await;
+
+        info!("Channel message {} restored by {}", message_id, moderator.username);
+        Ok(())
+    }
+
     /// Get direct messages with enhanced pagination
     pub async fn get_direct_messages_paginated(
         user1_id: Uuid,
@@ -303,7 +802,19 @@ impl ChatService {
         request: PaginationRequest,
         config: Option<PaginationConfig>,
     ) -> Result<PaginationResponse<DirectMessage>> {
-        let config = config.unwrap_or_default();
+        MetricsService::time(
+            "direct_messages",
+            Self::fetch_direct_messages_paginated(user1_id, user2_id, request, config),
+        ).await
+    }
+
+    async fn fetch_direct_messages_paginated(
+        user1_id: Uuid,
+        user2_id: Uuid,
+        request: PaginationRequest,
+        config: Option<PaginationConfig>,
+    ) -> Result<PaginationResponse<DirectMessage>> {
+        let config = config.unwrap_or_else(|| crate::config::settings().pagination.direct_messages.into());
         let limit = request.limit.min(config.max_page_size).max(1);
         
         match request.cursor {
@@ -330,7 +841,8 @@ impl ChatService {
             }
             PaginationCursor::Offset(_) => {
                 // Fallback to existing implementation
-                let (messages, has_more) = Self::get_direct_messages(user1_id, user2_id, None, limit).await?;
+                let (messages, has_more) = messages::db_get_direct_messages(user1_id, user2_id, None, limit).await
+                    .map_err(|e| ServerError::Database(e))?;
                 Ok(Self::create_fallback_pagination_response(messages, has_more))
             }
         }
@@ -338,12 +850,16 @@ impl ChatService {
 
     /// Get channel messages with pagination
     pub async fn get_channel_messages(
+        viewer_id: Uuid,
         channel_id: Uuid,
         before: Option<i64>,
-        _limit: usize,
+        limit: usize,
     ) -> Result<(Vec<ChannelMessage>, bool)> {
-        channels::db_get_channel_messages(channel_id, before).await
-            .map_err(|e| ServerError::Database(e))
+        let (mut messages, history_complete) = MetricsService::time("channel_messages", channels::db_get_channel_messages(channel_id, viewer_id, before, limit)).await
+            .map_err(|e| ServerError::Database(e))?;
+        Self::hide_muted_authors(viewer_id, channel_id, &mut messages).await;
+        Self::attach_emojis(channel_id, &mut messages).await;
+        Ok((messages, history_complete))
     }
 
     /// Get direct messages between two users
@@ -353,7 +869,60 @@ impl ChatService {
         before: Option<i64>,
         limit: usize,
     ) -> Result<(Vec<DirectMessage>, bool)> {
-        messages::db_get_direct_messages(user1_id, user2_id, before, limit).await
+        MetricsService::time("direct_messages", messages::db_get_direct_messages(user1_id, user2_id, before, limit)).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Look up a cached count, or compute and cache it
+    async fn cached_message_count<F, Fut>(key: MessageCountKey, compute: F) -> Result<usize>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = std::result::Result<usize, String>>,
+    {
+        {
+            let counts = MESSAGE_COUNTS.lock().unwrap();
+            if let Some((count, computed_at)) = counts.get(&key) {
+                if computed_at.elapsed() < MESSAGE_COUNT_TTL {
+                    return Ok(*count);
+                }
+            }
+        }
+
+        let count = compute().await.map_err(|e| ServerError::Database(e))?;
+
+        let mut counts = MESSAGE_COUNTS.lock().unwrap();
+        counts.retain(|_, (_, computed_at)| computed_at.elapsed() < MESSAGE_COUNT_TTL);
+        counts.insert(key, (count, Instant::now()));
+        Ok(count)
+    }
+
+    /// Total messages in a channel (cached for a minute)
+    pub async fn get_channel_message_count(channel_id: Uuid) -> Result<usize> {
+        Self::cached_message_count(MessageCountKey::Channel(channel_id), || {
+            channels::db_get_channel_message_count(channel_id)
+        }).await
+    }
+
+    /// Total messages between two users (cached for a minute)
+    pub async fn get_direct_message_count(user1_id: Uuid, user2_id: Uuid) -> Result<usize> {
+        let key = MessageCountKey::Direct(user1_id.min(user2_id), user1_id.max(user2_id));
+        Self::cached_message_count(key, || {
+            messages::db_get_direct_message_count(user1_id, user2_id)
+        }).await
+    }
+
+    /// Archive or unarchive a DM conversation in the user's own list. The other
+    /// participant never sees this state.
+    pub async fn set_dm_conversation_archived(
+        user_id: Uuid,
+        peer_id: Uuid,
+        archived: bool,
+        hidden_until_new_message: bool,
+    ) -> Result<()> {
+        if user_id == peer_id {
+            return Err(ServerError::BadRequest("That conversation doesn't exist".to_string()));
+        }
+        messages::db_set_dm_conversation_state(user_id, peer_id, archived, archived && hidden_until_new_message).await
             .map_err(|e| ServerError::Database(e))
     }
 
@@ -392,15 +961,23 @@ impl ChatService {
     }
 
     /// Handle mention notifications
+    /// Notify users mentioned in a message, returning who was notified
     async fn handle_mentions(
         from_user: &User,
         content: &str,
         mentioned_usernames: &[String],
+        muters: &HashSet<Uuid>,
         peer_map: &PeerMap,
-    ) {
+    ) -> Vec<Uuid> {
+        let mut notified = Vec::new();
         for username in mentioned_usernames {
             // Find the mentioned user
             if let Ok(mentioned_user) = crate::db::users::db_get_user_by_username(username).await {
+                // Nobody reads the System account's notifications
+                if mentioned_user.id == crate::db::users::SYSTEM_USER_ID || muters.contains(&mentioned_user.id) {
+                    continue;
+                }
+                notified.push(mentioned_user.id);
                 // Send mention notification
                 let message = ServerMessage::MentionNotification {
                     from: from_user.clone(),
@@ -420,5 +997,719 @@ impl ChatService {
                 }
             }
         }
+        notified
+    }
+
+    /// Notify a channel's followers of a message when the channel's policy asks
+    /// for it. Mentioned users already got a mention notification and are skipped,
+    /// as are followers who muted the author.
+    async fn notify_followers(
+        channel_id: Uuid,
+        from_user: &User,
+        mentioned_ids: &[Uuid],
+        muters: &HashSet<Uuid>,
+        peer_map: &PeerMap,
+    ) {
+        let followers = match channels::db_get_channel_followers_to_notify(channel_id).await {
+            Ok(followers) => followers,
+            Err(e) => {
+                error!("Failed to load followers of channel {}: {}", channel_id, e);
+                return;
+            }
+        };
+
+        for follower_id in followers {
+            if follower_id == from_user.id || mentioned_ids.contains(&follower_id) || muters.contains(&follower_id) {
+                continue;
+            }
+            NotificationService::create_channel_message_notification(
+                follower_id, channel_id, &from_user.username, peer_map
+            ).await;
+        }
+    }
+
+    /// Users who personally muted `author_id` in this channel. Failures are
+    /// logged and treated as nobody, so a lookup error never drops a message.
+    async fn users_muting(author_id: Uuid, channel_id: Uuid) -> HashSet<Uuid> {
+        users::db_get_users_muting(author_id, channel_id).await.unwrap_or_else(|e| {
+            error!("Failed to load personal mutes of {}: {}", author_id, e);
+            HashSet::new()
+        })
+    }
+
+    /// Drop messages by authors the viewer muted in this channel or globally
+    pub async fn hide_muted_authors(viewer_id: Uuid, channel_id: Uuid, messages: &mut Vec<ChannelMessage>) {
+        match users::db_get_muted_authors(viewer_id, channel_id).await {
+            Ok(muted) if !muted.is_empty() => messages.retain(|message| !muted.contains(&message.sent_by)),
+            Ok(_) => {}
+            Err(e) => error!("Failed to load personal mutes of {}: {}", viewer_id, e),
+        }
+    }
+
+    /// Mute or unmute another user for yourself, in one channel or everywhere.
+    /// Nobody else, including the muted user, is told.
+    pub async fn set_personal_mute(muter_id: Uuid, muted_id: Uuid, channel_id: Option<Uuid>, muted: bool) -> Result<()> {
+        if muter_id == muted_id {
+            return Err(ServerError::BadRequest("You can't mute yourself".to_string()));
+        }
+        if let Some(channel_id) = channel_id {
+            Self::ensure_can_read_channel(muter_id, channel_id).await?;
+        }
+
+        users::db_set_personal_mute(muter_id, muted_id, channel_id, muted).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("User {} {} {} in {:?}", muter_id, if muted { "muted" } else { "unmuted" }, muted_id, channel_id);
+        Ok(())
+    }
+
+    /// Follow or unfollow a channel the user can read
+    pub async fn set_channel_follow(user_id: Uuid, channel_id: Uuid, follow: bool) -> Result<()> {
+        if follow {
+            Self::ensure_can_read_channel(user_id, channel_id).await?;
+        }
+
+        channels::db_set_channel_follow(channel_id, user_id, follow).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("User {} {} channel {}", user_id, if follow { "followed" } else { "unfollowed" }, channel_id);
+        Ok(())
+    }
+
+    /// Bookmark a channel message the user can read, or change the note on an existing bookmark
+    pub async fn bookmark_message(user_id: Uuid, message_id: Uuid, note: Option<String>) -> Result<()> {
+        let (message, deleted_at) = channels::db_get_channel_message(message_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if deleted_at.is_some() {
+            return Err(ServerError::NotFound("Message not found".to_string()));
+        }
+        Self::ensure_can_read_channel(user_id, message.channel_id).await?;
+
+        let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+        if note.as_ref().is_some_and(|note| note.chars().count() > MAX_BOOKMARK_NOTE_CHARS) {
+            return Err(ServerError::Validation(format!(
+                "Bookmark notes must be at most {} characters", MAX_BOOKMARK_NOTE_CHARS
+            )));
+        }
+
+        channels::db_add_bookmark(user_id, message_id, note, MAX_BOOKMARKS).await
+            .map_err(|e| ServerError::Validation(e))
+    }
+
+    /// Remove one of the user's bookmarks
+    pub async fn remove_bookmark(user_id: Uuid, message_id: Uuid) -> Result<()> {
+        if !channels::db_remove_bookmark(user_id, message_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound("Bookmark not found".to_string()));
+        }
+        Ok(())
+    }
+
+    /// A page of the user's bookmarks with their messages, and the cursor for
+    /// the next page. Bookmarks in channels the user can no longer read are
+    /// left out; deleted messages show a placeholder.
+    pub async fn get_bookmarks(
+        user_id: Uuid,
+        before: Option<(i64, Uuid)>,
+    ) -> Result<(Vec<MessageBookmark>, Option<(i64, Uuid)>)> {
+        let rows = channels::db_get_bookmarks(user_id, before, BOOKMARK_PAGE_SIZE).await
+            .map_err(|e| ServerError::Database(e))?;
+        // The cursor follows the unfiltered page so paging never stalls on hidden bookmarks
+        let next_cursor = if rows.len() == BOOKMARK_PAGE_SIZE {
+            rows.last().map(|row| (row.created_at, row.message_id))
+        } else {
+            None
+        };
+
+        let mut readable: HashMap<Uuid, bool> = HashMap::new();
+        let mut bookmarks = Vec::with_capacity(rows.len());
+        for row in rows {
+            if let Some(channel_id) = row.channel_id {
+                let can_read = match readable.get(&channel_id) {
+                    Some(can_read) => *can_read,
+                    None => {
+                        let can_read = Self::ensure_can_read_channel(user_id, channel_id).await.is_ok();
+                        readable.insert(channel_id, can_read);
+                        can_read
+                    }
+                };
+                if !can_read {
+                    continue;
+                }
+            }
+
+            let content = match row.content {
+                Some(content) if !row.deleted => content,
+                _ => DELETED_PLACEHOLDER.to_string(),
+            };
+            bookmarks.push(MessageBookmark {
+                message_id: row.message_id,
+                channel_id: row.channel_id,
+                channel_name: row.channel_name,
+                sent_by: row.sent_by,
+                timestamp: row.timestamp,
+                content,
+                note: row.note,
+                bookmarked_at: row.created_at,
+            });
+        }
+        Ok((bookmarks, next_cursor))
+    }
+
+    /// Mark a channel read up to now for one of its members
+    pub async fn mark_channel_read(user_id: Uuid, channel_id: Uuid) -> Result<()> {
+        if !channels::db_mark_channel_read(user_id, channel_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound("You are not a member of this channel".to_string()));
+        }
+        Ok(())
+    }
+
+    /// The user's readable channels with messages since `since` (default a
+    /// week ago), most recent first, for the client's home view
+    pub async fn active_channels(user_id: Uuid, since: Option<i64>) -> Result<Vec<ActiveChannel>> {
+        let since = since.unwrap_or_else(|| crate::util::now_secs() - DEFAULT_ACTIVITY_WINDOW_SECS);
+        let rows = channels::db_get_active_channels_for_user(user_id, since, MAX_ACTIVE_CHANNELS).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let mut active = Vec::with_capacity(rows.len());
+        for mut channel in rows {
+            if Self::ensure_can_read_channel(user_id, channel.channel_id).await.is_err() {
+                continue;
+            }
+            if channel.last_message_preview.chars().count() > ACTIVITY_PREVIEW_CHARS {
+                channel.last_message_preview = channel.last_message_preview
+                    .chars()
+                    .take(ACTIVITY_PREVIEW_CHARS)
+                    .chain(std::iter::once('…'))
+                    .collect();
+            }
+            active.push(channel);
+        }
+        Ok(active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::notifications;
+    use crate::test_support::{self, FakePeer, TestDb};
+
+    /// A server with `alice` (owner) and `bob` in its one channel
+    async fn two_member_channel() -> (User, User, Uuid) {
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        (alice, bob, channel_id)
+    }
+
+    #[tokio::test]
+    async fn channel_message_is_stored_and_broadcast_to_members() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "hello there", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        assert_eq!(channels::db_get_channel_message_count(channel_id).await.unwrap(), 1);
+        let received = bob_peer.drain();
+        assert!(received.iter().any(|message| matches!(
+            message,
+            ServerMessage::NewChannelMessage(msg)
+                if msg.channel_id == channel_id && msg.sent_by == alice.id && msg.content == "hello there"
+        )));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn channel_message_is_refused_to_non_members() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (_, _, channel_id) = two_member_channel().await;
+        let outsider = test_support::create_user("outsider").await;
+
+        let result = ChatService::send_channel_message(
+            channel_id, &outsider, "let me in", None, &test_support::content_filter(), &peer_map
+        ).await;
+
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+        assert_eq!(channels::db_get_channel_message_count(channel_id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn mentioning_the_system_account_notifies_nobody() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = two_member_channel().await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "hey @System", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        assert_eq!(channels::db_get_channel_message_count(channel_id).await.unwrap(), 1);
+        assert_eq!(db.count_rows("notifications"), 0);
+    }
+
+    #[tokio::test]
+    async fn mentions_reach_online_users_live_and_offline_users_as_notifications() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let carol = test_support::create_user("carol").await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "ping @bob and @carol", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        assert!(bob_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::MentionNotification { from, .. } if from.id == alice.id
+        )));
+        let (bob_notifications, _) = notifications::db_get_notifications(bob.id, None, 10).await.unwrap();
+        assert!(bob_notifications.is_empty(), "online users get the live mention only");
+        let (carol_notifications, _) = notifications::db_get_notifications(carol.id, None, 10).await.unwrap();
+        assert_eq!(carol_notifications.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn followers_only_channels_notify_other_members_only_when_mentioned() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let carol = test_support::create_user("carol").await;
+        test_support::join_server(server_id, carol.id).await;
+        crate::services::ServerService::set_channel_notify_policy(alice.id, channel_id, true).await.unwrap();
+        ChatService::set_channel_follow(carol.id, channel_id, true).await.unwrap();
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "just chatting", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let (bob_notifications, _) = notifications::db_get_notifications(bob.id, None, 10).await.unwrap();
+        assert!(bob_notifications.is_empty(), "members who don't follow aren't notified");
+        let (carol_notifications, _) = notifications::db_get_notifications(carol.id, None, 10).await.unwrap();
+        assert_eq!(carol_notifications.len(), 1);
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "over to you @bob", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let (bob_notifications, _) = notifications::db_get_notifications(bob.id, None, 10).await.unwrap();
+        assert_eq!(bob_notifications.len(), 1, "a mention still notifies");
+    }
+
+    #[tokio::test]
+    async fn a_personal_mute_hides_messages_from_the_muter_only() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let carol = test_support::create_user("carol").await;
+        test_support::join_server(server_id, carol.id).await;
+        ChatService::set_personal_mute(bob.id, alice.id, Some(channel_id), true).await.unwrap();
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+        let mut carol_peer = FakePeer::connect(&peer_map, Some(carol.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "you can't see me", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        ChatService::send_channel_message(
+            channel_id, &carol, "but you can see me", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        let (bob_history, _) = ChatService::get_channel_messages(bob.id, channel_id, None, 50).await.unwrap();
+        assert_eq!(bob_history.len(), 1);
+        assert_eq!(bob_history[0].sent_by, carol.id);
+        let (carol_history, _) = ChatService::get_channel_messages(carol.id, channel_id, None, 50).await.unwrap();
+        assert_eq!(carol_history.len(), 2);
+
+        let from_alice = |messages: Vec<ServerMessage>| messages.iter().any(|message| matches!(
+            message,
+            ServerMessage::NewChannelMessage(msg) if msg.sent_by == alice.id
+        ));
+        assert!(!from_alice(bob_peer.drain()), "muted messages aren't delivered live");
+        assert!(from_alice(carol_peer.drain()));
+    }
+
+    #[tokio::test]
+    async fn sending_counts_towards_the_channels_throughput() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = two_member_channel().await;
+
+        for content in ["one", "three"] {
+            ChatService::send_channel_message(
+                channel_id, &alice, content, None, &test_support::content_filter(), &peer_map
+            ).await.unwrap();
+        }
+
+        // The counters are process-wide, so look for this test's channel only
+        let (busiest, _) = MetricsService::busiest_channels(usize::MAX);
+        let throughput = busiest.iter().find(|channel| channel.channel_id == channel_id).expect("channel is counted");
+        assert_eq!(throughput.messages, 2);
+        assert_eq!(throughput.bytes, 8);
+    }
+
+    #[tokio::test]
+    async fn direct_message_is_stored_and_sent_to_both_users() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ChatService::send_direct_message(&alice, bob.id, "psst", &test_support::content_filter(), &peer_map)
+            .await.unwrap();
+
+        assert_eq!(messages::db_get_direct_message_count(alice.id, bob.id).await.unwrap(), 1);
+        for received in [alice_peer.drain(), bob_peer.drain()] {
+            assert!(received.iter().any(|message| matches!(
+                message,
+                ServerMessage::DirectMessage(dm) if dm.from == alice.id && dm.to == bob.id && dm.content == "psst"
+            )));
+        }
+    }
+
+    #[tokio::test]
+    async fn channel_references_resolve_within_the_server() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let dev_ops = test_support::create_channel(server_id, "Dev-Ops").await;
+        let elsewhere = test_support::create_server(&alice, "Elsewhere").await;
+        test_support::create_channel(elsewhere, "lounge").await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "try #DEV-OPS or #General, not #lounge or #nowhere", None,
+            &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        let refs = bob_peer.drain().into_iter().find_map(|message| match message {
+            ServerMessage::NewChannelMessage(message) => Some(message.channel_refs),
+            _ => None,
+        }).expect("bob got the message");
+        let mut refs = refs;
+        refs.sort();
+        let mut expected = vec![("dev-ops".to_string(), dev_ops), ("general".to_string(), channel_id)];
+        expected.sort();
+        assert_eq!(refs, expected);
+    }
+
+    #[tokio::test]
+    async fn channel_references_leave_out_channels_the_reader_cannot_read() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let staff = test_support::create_channel(server_id, "staff").await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "INSERT INTO channel_permissions (channel_id, user_id, can_read, can_write) VALUES (?1, ?2, 0, 0)",
+            rusqlite::params![staff.to_string(), bob.id.to_string()],
+        ).unwrap();
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &alice, "see #staff and #general", None,
+            &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        let refs_for = |messages: Vec<ServerMessage>| messages.into_iter().find_map(|message| match message {
+            ServerMessage::NewChannelMessage(message) => Some(message.channel_refs),
+            _ => None,
+        });
+        let mut alice_refs = refs_for(alice_peer.drain()).expect("alice got the message");
+        alice_refs.sort();
+        let mut expected = vec![("general".to_string(), channel_id), ("staff".to_string(), staff)];
+        expected.sort();
+        assert_eq!(alice_refs, expected);
+        assert_eq!(refs_for(bob_peer.drain()).expect("bob got the message"), vec![("general".to_string(), channel_id)]);
+    }
+
+    /// The one live message in the database
+    async fn only_live_message() -> ChannelMessage {
+        let mut live = channels::db_get_channel_messages_before(i64::MAX, 10).await.unwrap();
+        assert_eq!(live.len(), 1);
+        live.remove(0)
+    }
+
+    #[tokio::test]
+    async fn only_bots_carry_a_message_origin() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let filter = test_support::content_filter();
+        let alice = test_support::create_user("alice").await;
+        let bridge = test_support::create_user_with_role("bridge", "Bot").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bridge.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let origin = |author: &str| Some(MessageOrigin {
+            network: "irc".to_string(),
+            author: author.to_string(),
+            timestamp: Some(1_700_000_000_000),
+        });
+
+        ChatService::send_channel_message(channel_id, &bridge, "relayed", origin("carol"), &filter, &peer_map).await.unwrap();
+        ChatService::send_channel_message(channel_id, &alice, "spoofed", origin("carol"), &filter, &peer_map).await.unwrap();
+        let oversized = ChatService::send_channel_message(
+            channel_id, &bridge, "too much", origin(&"x".repeat(MAX_ORIGIN_BYTES)), &filter, &peer_map
+        ).await;
+
+        assert!(matches!(oversized, Err(ServerError::Validation(_))));
+        let history = channels::db_get_channel_messages_before(i64::MAX, 10).await.unwrap();
+        assert_eq!(history.len(), 2);
+        let relayed = history.iter().find(|message| message.content == "relayed").unwrap();
+        let stored: MessageOrigin = serde_json::from_str(relayed.origin.as_deref().unwrap()).unwrap();
+        assert_eq!(stored.network, "irc");
+        assert_eq!(stored.author, "carol");
+        let spoofed = history.iter().find(|message| message.content == "spoofed").unwrap();
+        assert!(spoofed.origin.is_none());
+    }
+
+    #[tokio::test]
+    async fn bookmarks_keep_one_note_and_outlive_the_message() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "keep this", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+
+        ChatService::bookmark_message(alice.id, message.id, Some("  later ".to_string())).await.unwrap();
+        ChatService::bookmark_message(alice.id, message.id, Some("reread".to_string())).await.unwrap();
+        let too_long = ChatService::bookmark_message(alice.id, message.id, Some("x".repeat(MAX_BOOKMARK_NOTE_CHARS + 1))).await;
+
+        assert!(matches!(too_long, Err(ServerError::Validation(_))));
+        let (bookmarks, next_cursor) = ChatService::get_bookmarks(alice.id, None).await.unwrap();
+        assert_eq!(bookmarks.len(), 1);
+        assert!(next_cursor.is_none());
+        assert_eq!(bookmarks[0].note.as_deref(), Some("reread"));
+        assert_eq!(bookmarks[0].content, "keep this");
+        assert_eq!(bookmarks[0].channel_name.as_deref(), Some("general"));
+        assert!(ChatService::get_bookmarks(bob.id, None).await.unwrap().0.is_empty());
+
+        ChatService::delete_channel_message(&bob, message.id, &peer_map).await.unwrap();
+        let (bookmarks, _) = ChatService::get_bookmarks(alice.id, None).await.unwrap();
+        assert_eq!(bookmarks[0].content, DELETED_PLACEHOLDER);
+
+        ChatService::remove_bookmark(alice.id, message.id).await.unwrap();
+        assert!(ChatService::get_bookmarks(alice.id, None).await.unwrap().0.is_empty());
+        assert!(matches!(
+            ChatService::remove_bookmark(alice.id, message.id).await,
+            Err(ServerError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_deleted_message_is_hidden_until_restored() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "oops", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+
+        ChatService::delete_channel_message(&bob, message.id, &peer_map).await.unwrap();
+
+        assert_eq!(channels::db_get_channel_message_count(channel_id).await.unwrap(), 0);
+        assert!(alice_peer.drain().iter().any(|sent| matches!(
+            sent,
+            ServerMessage::ChannelMessageDeleted { message_id, .. } if *message_id == message.id
+        )));
+        // Only moderators bring messages back
+        assert!(matches!(
+            ChatService::restore_channel_message(&bob, message.id, &peer_map).await,
+            Err(ServerError::Forbidden(_))
+        ));
+
+        ChatService::restore_channel_message(&alice, message.id, &peer_map).await.unwrap();
+
+        assert_eq!(only_live_message().await.content, "oops");
+        assert!(alice_peer.drain().iter().any(|sent| matches!(
+            sent,
+            ServerMessage::NewChannelMessage(restored) if restored.id == message.id
+        )));
+    }
+
+    #[tokio::test]
+    async fn a_tombstone_past_the_grace_window_cannot_be_restored_and_is_purged() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &alice, "old news", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+        ChatService::delete_channel_message(&alice, message.id, &peer_map).await.unwrap();
+
+        let grace_secs = crate::config::settings().messages.tombstone_grace_hours * 3600;
+        let deleted_at = crate::util::now_secs() - grace_secs - 1;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE channel_messages SET deleted_at = ?1", rusqlite::params![deleted_at]
+        ).unwrap();
+
+        assert!(matches!(
+            ChatService::restore_channel_message(&alice, message.id, &peer_map).await,
+            Err(ServerError::Validation(_))
+        ));
+        assert_eq!(crate::services::MaintenanceService::purge_message_tombstones().await.unwrap(), 1);
+        assert_eq!(db.count_rows("channel_messages"), 0);
+    }
+
+    /// Details of every DELETE_MESSAGE audit entry
+    fn delete_audits(db: &TestDb) -> Vec<String> {
+        let conn = rusqlite::Connection::open(db.path()).unwrap();
+        let mut stmt = conn.prepare("SELECT details FROM audit_log WHERE action = ?1").unwrap();
+        stmt.query_map(rusqlite::params![audit_service::DELETE_MESSAGE], |row| row.get(0))
+            .unwrap()
+            .collect::<std::result::Result<Vec<String>, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_message_is_deleted_once_even_when_deletes_race() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "twice", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+
+        let (by_author, by_owner) = tokio::join!(
+            ChatService::delete_channel_message(&bob, message.id, &peer_map),
+            ChatService::delete_channel_message(&alice, message.id, &peer_map),
+        );
+        let deleted = [&by_author, &by_owner].iter().filter(|result| result.is_ok()).count();
+        assert_eq!(deleted, 1, "author: {:?}, owner: {:?}", by_author, by_owner);
+        assert!([by_author, by_owner].into_iter().any(|result| matches!(result, Err(ServerError::NotFound(_)))));
+        assert_eq!(delete_audits(&db).len(), 1);
+
+        // Later deletes are refused whichever check sees them first
+        assert!(matches!(
+            ChatService::delete_channel_message(&alice, message.id, &peer_map).await,
+            Err(ServerError::NotFound(_))
+        ));
+        assert!(!channels::db_tombstone_channel_message(message.id, alice.id, crate::util::now_secs()).await.unwrap());
+        assert_eq!(delete_audits(&db).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_delete_audit_previews_the_first_characters() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = two_member_channel().await;
+        let long = "é".repeat(AUDIT_PREVIEW_CHARS + 20);
+        let exact = "a".repeat(AUDIT_PREVIEW_CHARS);
+        for content in [&long, &exact] {
+            ChatService::send_channel_message(
+                channel_id, &alice, content, None, &test_support::content_filter(), &peer_map
+            ).await.unwrap();
+            let message = only_live_message().await;
+            ChatService::delete_channel_message(&alice, message.id, &peer_map).await.unwrap();
+        }
+
+        let audits = delete_audits(&db);
+        assert_eq!(audits.len(), 2);
+        // Cut on characters, not bytes, and marked as cut
+        let truncated = format!("preview={}…", "é".repeat(AUDIT_PREVIEW_CHARS));
+        assert!(audits.iter().any(|details| details.ends_with(&truncated)), "{:?}", audits);
+        // A message that fits is quoted whole, without the marker
+        assert!(audits.iter().any(|details| details.ends_with(&format!("preview={}", exact))), "{:?}", audits);
+    }
+
+    #[tokio::test]
+    async fn an_author_without_write_access_cannot_edit() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (_, bob, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "first draft", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "INSERT INTO channel_permissions (channel_id, user_id, can_read, can_write) VALUES (?1, ?2, 1, 0)",
+            rusqlite::params![channel_id.to_string(), bob.id.to_string()],
+        ).unwrap();
+
+        let result = ChatService::edit_channel_message(
+            &bob, message.id, "second draft", &test_support::content_filter(), &peer_map
+        ).await;
+
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+        assert_eq!(only_live_message().await.content, "first draft");
+    }
+
+    #[tokio::test]
+    async fn an_edit_charges_the_author_for_the_size_change() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (_, bob, channel_id) = two_member_channel().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "hi", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+
+        ChatService::edit_channel_message(
+            &bob, message.id, "hello there", &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        assert_eq!(StorageService::usage(&bob).await.unwrap().message_bytes, 11);
+
+        // A moderator's edit still counts against the author
+        ChatService::edit_channel_message(
+            &moderator, message.id, "hello", &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        assert_eq!(StorageService::usage(&bob).await.unwrap().message_bytes, 5);
+        assert_eq!(StorageService::usage(&moderator).await.unwrap().message_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn a_flagged_edit_is_recorded_for_review() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (_, bob, channel_id) = two_member_channel().await;
+        ChatService::send_channel_message(
+            channel_id, &bob, "nice shoes", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+        let message = only_live_message().await;
+        let filter = test_support::content_filter_with(&[], &["cheap"]);
+
+        ChatService::edit_channel_message(&bob, message.id, "cheap shoes here", &filter, &peer_map).await.unwrap();
+
+        assert_eq!(only_live_message().await.content, "cheap shoes here");
+        assert_eq!(db.count_rows("flagged_messages"), 1);
+        let moderated: i64 = rusqlite::Connection::open(db.path()).unwrap().query_row(
+            "SELECT COUNT(*) FROM audit_log WHERE action = ?1",
+            rusqlite::params![audit_service::MESSAGE_MODERATED],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(moderated, 1);
+    }
+
+    #[tokio::test]
+    async fn blocked_direct_message_is_neither_stored_nor_sent() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+        let filter = test_support::content_filter_with(&["forbidden"], &[]);
+
+        let result = ChatService::send_direct_message(&alice, bob.id, "a Forbidden word", &filter, &peer_map).await;
+
+        assert!(matches!(result, Err(ServerError::Validation(reason)) if reason.contains("forbidden")));
+        assert_eq!(db.count_rows("direct_messages"), 0);
+        assert_eq!(db.count_rows("notifications"), 0);
+        assert!(bob_peer.drain().is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::ModerationConfig;
+use regex::Regex;
+use tracing::{info, warn};
+
+/// Outcome of running content through the filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterResult {
+    Allowed,
+    /// Allowed through, but worth a moderator's attention
+    Flagged(String),
+    /// Must not be stored or delivered
+    Blocked(String),
+}
+
+pub struct ContentFilterService {
+    blocked_words: Vec<String>,
+    flagged_patterns: Vec<Regex>,
+    max_message_length: usize,
+}
+
+impl ContentFilterService {
+    /// Build the filter from the moderation config. Blocked words are
+    /// trimmed, lowercased and deduplicated; empty ones are dropped since they
+    /// would match every message. Invalid patterns are logged and skipped so
+    /// one typo doesn't take the rest of the filter down with it.
+    pub fn new(config: &ModerationConfig) -> Self {
+        let mut blocked_words: Vec<String> = Vec::new();
+        for word in &config.blocked_words {
+            let word = word.trim().to_lowercase();
+            if word.is_empty() {
+                warn!("Ignoring empty blocked word in moderation config");
+            } else if !blocked_words.contains(&word) {
+                blocked_words.push(word);
+            }
+        }
+
+        let mut flagged_patterns = Vec::new();
+        let mut invalid_patterns = 0;
+        for pattern in &config.flagged_patterns {
+            match Regex::new(pattern) {
+                Ok(regex) => flagged_patterns.push(regex),
+                Err(e) => {
+                    invalid_patterns += 1;
+                    warn!("Skipping invalid flagged pattern '{}': {}", pattern, e);
+                }
+            }
+        }
+
+        info!(
+            "Content filter loaded: {} blocked words ({} duplicate or empty dropped), {} valid and {} invalid flagged patterns",
+            blocked_words.len(),
+            config.blocked_words.len() - blocked_words.len(),
+            flagged_patterns.len(),
+            invalid_patterns
+        );
+
+        Self {
+            blocked_words,
+            flagged_patterns,
+            max_message_length: config.max_message_length,
+        }
+    }
+
+    /// Check a chat message against the configured rules
+    pub fn filter_message(&self, content: &str) -> FilterResult {
+        if content.chars().count() > self.max_message_length {
+            return FilterResult::Blocked(format!(
+                "Message exceeds the maximum length of {} characters",
+                self.max_message_length
+            ));
+        }
+
+        let lowered = content.to_lowercase();
+        if let Some(word) = self.blocked_words.iter().find(|w| lowered.contains(w.as_str())) {
+            return FilterResult::Blocked(format!("Message contains blocked word '{}'", word));
+        }
+
+        if let Some(pattern) = self.flagged_patterns.iter().find(|p| p.is_match(content)) {
+            return FilterResult::Flagged(format!("Matched pattern '{}'", pattern.as_str()));
+        }
+
+        FilterResult::Allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(blocked_words: &[&str], flagged_patterns: &[&str], max_message_length: usize) -> ContentFilterService {
+        ContentFilterService::new(&ModerationConfig {
+            blocked_words: blocked_words.iter().map(|word| word.to_string()).collect(),
+            flagged_patterns: flagged_patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            max_message_length,
+            ..ModerationConfig::default()
+        })
+    }
+
+    #[test]
+    fn ordinary_messages_are_allowed() {
+        let filter = filter(&["spam"], &[r"https?://"], 100);
+        assert_eq!(filter.filter_message("hello there"), FilterResult::Allowed);
+        assert_eq!(filter.filter_message(""), FilterResult::Allowed);
+    }
+
+    #[test]
+    fn blocked_words_match_regardless_of_case() {
+        let filter = filter(&["  Spam "], &[], 100);
+        assert!(matches!(filter.filter_message("buy SPAM now"), FilterResult::Blocked(reason) if reason.contains("'spam'")));
+        assert!(matches!(filter.filter_message("spammer"), FilterResult::Blocked(_)));
+    }
+
+    #[test]
+    fn the_length_limit_counts_characters() {
+        let filter = filter(&[], &[], 5);
+        assert_eq!(filter.filter_message("héllo"), FilterResult::Allowed);
+        assert!(matches!(filter.filter_message("hello!"), FilterResult::Blocked(reason) if reason.contains("maximum length")));
+    }
+
+    #[test]
+    fn flagged_patterns_let_the_message_through() {
+        let filter = filter(&[], &[r"https?://", "(unclosed"], 100);
+        assert!(matches!(filter.filter_message("see http://example.com"), FilterResult::Flagged(_)));
+        // The invalid pattern was skipped rather than matching everything
+        assert_eq!(filter.filter_message("(unclosed"), FilterResult::Allowed);
+    }
+
+    #[test]
+    fn empty_blocked_words_are_dropped() {
+        let filter = filter(&["", "   "], &[], 100);
+        assert_eq!(filter.filter_message("anything at all"), FilterResult::Allowed);
+    }
+
+    #[test]
+    fn duplicate_blocked_words_are_collapsed() {
+        let filter = filter(&["spam", " SPAM", "Spam ", "scam"], &[], 100);
+        assert_eq!(filter.blocked_words, vec!["spam".to_string(), "scam".to_string()]);
+    }
+
+    #[test]
+    fn a_bad_pattern_leaves_the_other_patterns_working() {
+        let filter = filter(&["spam"], &["[unclosed", r"\bfree money\b", "*"], 100);
+        assert_eq!(filter.flagged_patterns.len(), 1);
+        assert!(matches!(filter.filter_message("get free money here"), FilterResult::Flagged(_)));
+        assert!(matches!(filter.filter_message("spam"), FilterResult::Blocked(_)));
+    }
+}
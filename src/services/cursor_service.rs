@@ -0,0 +1,208 @@
+use crate::errors::{Result, ServerError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use nexus_tui_common::PaginationCursor;
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Layout version, the first byte of every token
+const CURSOR_VERSION: u8 = 1;
+
+/// Bytes of the HMAC kept in a token
+const TAG_LEN: usize = 16;
+
+/// version + big-endian timestamp + tag
+const TOKEN_LEN: usize = 1 + 8 + TAG_LEN;
+
+/// First byte of keyset tokens, which also carry a row id
+const KEYSET_VERSION: u8 = 2;
+
+/// version + big-endian timestamp + row id + tag
+const KEYSET_TOKEN_LEN: usize = 1 + 8 + 16 + TAG_LEN;
+
+/// Signing key, fresh each run. Tokens from before a restart are rejected and
+/// the client starts over from the newest page.
+static CURSOR_KEY: Lazy<[u8; 32]> = Lazy::new(rand::random);
+
+/// Issues and checks the opaque pagination tokens handed to clients. A token
+/// is bound to the conversation it was issued for (a channel, or both users
+/// of a DM), so it can't be replayed elsewhere or edited to another timestamp.
+pub struct CursorService;
+
+impl CursorService {
+    fn mac(scope: &[Uuid], timestamp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&*CURSOR_KEY).expect("HMAC accepts keys of any length");
+        mac.update(&[CURSOR_VERSION]);
+        mac.update(&timestamp.to_be_bytes());
+        for id in scope {
+            mac.update(id.as_bytes());
+        }
+        mac
+    }
+
+    fn keyset_mac(scope: &[Uuid], timestamp: i64, row_id: Uuid) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&*CURSOR_KEY).expect("HMAC accepts keys of any length");
+        mac.update(&[KEYSET_VERSION]);
+        mac.update(&timestamp.to_be_bytes());
+        mac.update(row_id.as_bytes());
+        for id in scope {
+            mac.update(id.as_bytes());
+        }
+        mac
+    }
+
+    /// Scope of a DM conversation, the same whichever side asks
+    pub fn dm_scope(user_a: Uuid, user_b: Uuid) -> [Uuid; 2] {
+        if user_a <= user_b { [user_a, user_b] } else { [user_b, user_a] }
+    }
+
+    /// Wrap a timestamp in a signed token for the client
+    pub fn issue(scope: &[Uuid], timestamp: i64) -> PaginationCursor {
+        let tag = Self::mac(scope, timestamp).finalize().into_bytes();
+
+        let mut bytes = Vec::with_capacity(TOKEN_LEN);
+        bytes.push(CURSOR_VERSION);
+        bytes.extend_from_slice(&timestamp.to_be_bytes());
+        bytes.extend_from_slice(&tag[..TAG_LEN]);
+        PaginationCursor::Token(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Turn a client's cursor back into the timestamp to page from; None starts at the newest page
+    pub fn resolve(scope: &[Uuid], cursor: PaginationCursor) -> Result<Option<i64>> {
+        match cursor {
+            PaginationCursor::Start => Ok(None),
+            PaginationCursor::Token(token) => Self::verify(scope, &token).map(Some),
+            PaginationCursor::Timestamp(_) => Err(ServerError::BadRequest(
+                "Raw timestamp cursors are not accepted; pass back the cursor the server returned".to_string()
+            )),
+            PaginationCursor::Offset(_) => Err(ServerError::BadRequest(
+                "Offset pagination not supported for messages".to_string()
+            )),
+        }
+    }
+
+    /// Wrap a (timestamp, row id) position in a signed token, for lists where
+    /// several rows can share a timestamp
+    pub fn issue_keyset(scope: &[Uuid], timestamp: i64, row_id: Uuid) -> PaginationCursor {
+        let tag = Self::keyset_mac(scope, timestamp, row_id).finalize().into_bytes();
+
+        let mut bytes = Vec::with_capacity(KEYSET_TOKEN_LEN);
+        bytes.push(KEYSET_VERSION);
+        bytes.extend_from_slice(&timestamp.to_be_bytes());
+        bytes.extend_from_slice(row_id.as_bytes());
+        bytes.extend_from_slice(&tag[..TAG_LEN]);
+        PaginationCursor::Token(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Turn a cursor from `issue_keyset` back into the position to page from
+    pub fn resolve_keyset(scope: &[Uuid], cursor: PaginationCursor) -> Result<Option<(i64, Uuid)>> {
+        match cursor {
+            PaginationCursor::Start => Ok(None),
+            PaginationCursor::Token(token) => Self::verify_keyset(scope, &token).map(Some),
+            PaginationCursor::Timestamp(_) | PaginationCursor::Offset(_) => Err(ServerError::BadRequest(
+                "Pass back the cursor the server returned".to_string()
+            )),
+        }
+    }
+
+    fn verify_keyset(scope: &[Uuid], token: &str) -> Result<(i64, Uuid)> {
+        let invalid = || ServerError::BadRequest("Invalid pagination cursor".to_string());
+
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        if bytes.len() != KEYSET_TOKEN_LEN || bytes[0] != KEYSET_VERSION {
+            return Err(invalid());
+        }
+
+        let timestamp = i64::from_be_bytes(bytes[1..9].try_into().map_err(|_| invalid())?);
+        let row_id = Uuid::from_slice(&bytes[9..25]).map_err(|_| invalid())?;
+        Self::keyset_mac(scope, timestamp, row_id)
+            .verify_truncated_left(&bytes[25..])
+            .map_err(|_| invalid())?;
+        Ok((timestamp, row_id))
+    }
+
+    fn verify(scope: &[Uuid], token: &str) -> Result<i64> {
+        let invalid = || ServerError::BadRequest("Invalid pagination cursor".to_string());
+
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        if bytes.len() != TOKEN_LEN || bytes[0] != CURSOR_VERSION {
+            return Err(invalid());
+        }
+
+        let timestamp = i64::from_be_bytes(bytes[1..9].try_into().map_err(|_| invalid())?);
+        Self::mac(scope, timestamp)
+            .verify_truncated_left(&bytes[9..])
+            .map_err(|_| invalid())?;
+        Ok(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(cursor: PaginationCursor) -> String {
+        match cursor {
+            PaginationCursor::Token(token) => token,
+            _ => panic!("the server issues token cursors"),
+        }
+    }
+
+    #[test]
+    fn an_issued_cursor_round_trips() {
+        let channel = [Uuid::new_v4()];
+        let cursor = CursorService::issue(&channel, 1_700_000_000);
+        assert_eq!(CursorService::resolve(&channel, cursor).unwrap(), Some(1_700_000_000));
+        assert_eq!(CursorService::resolve(&channel, PaginationCursor::Start).unwrap(), None);
+
+        // Either side of a DM can page with the other's cursor
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let cursor = CursorService::issue(&CursorService::dm_scope(alice, bob), 42);
+        assert_eq!(CursorService::resolve(&CursorService::dm_scope(bob, alice), cursor).unwrap(), Some(42));
+    }
+
+    #[test]
+    fn a_tampered_cursor_is_rejected() {
+        let channel = [Uuid::new_v4()];
+        let mut bytes = URL_SAFE_NO_PAD.decode(token(CursorService::issue(&channel, 1_700_000_000))).unwrap();
+        // Move the timestamp back a second, keeping the old tag
+        bytes[1..9].copy_from_slice(&1_699_999_999i64.to_be_bytes());
+        let forged = PaginationCursor::Token(URL_SAFE_NO_PAD.encode(&bytes));
+        assert!(matches!(CursorService::resolve(&channel, forged), Err(ServerError::BadRequest(_))));
+
+        let garbage = PaginationCursor::Token("not a cursor".to_string());
+        assert!(matches!(CursorService::resolve(&channel, garbage), Err(ServerError::BadRequest(_))));
+        let raw = PaginationCursor::Timestamp(1_700_000_000);
+        assert!(matches!(CursorService::resolve(&channel, raw), Err(ServerError::BadRequest(_))));
+    }
+
+    #[test]
+    fn a_keyset_cursor_round_trips_and_rejects_tampering() {
+        let user = [Uuid::new_v4()];
+        let row_id = Uuid::new_v4();
+        let cursor = || CursorService::issue_keyset(&user, 1_700_000_000, row_id);
+        assert_eq!(CursorService::resolve_keyset(&user, cursor()).unwrap(), Some((1_700_000_000, row_id)));
+        // Neither kind of token passes for the other
+        assert!(CursorService::resolve(&user, cursor()).is_err());
+        assert!(CursorService::resolve_keyset(&user, CursorService::issue(&user, 1_700_000_000)).is_err());
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(token(cursor())).unwrap();
+        bytes[9..25].copy_from_slice(Uuid::new_v4().as_bytes());
+        let forged = PaginationCursor::Token(URL_SAFE_NO_PAD.encode(&bytes));
+        assert!(matches!(CursorService::resolve_keyset(&user, forged), Err(ServerError::BadRequest(_))));
+    }
+
+    #[test]
+    fn a_cursor_is_only_valid_in_its_own_scope() {
+        let (general, random) = ([Uuid::new_v4()], [Uuid::new_v4()]);
+        let cursor = || CursorService::issue(&general, 1_700_000_000);
+        assert!(matches!(CursorService::resolve(&random, cursor()), Err(ServerError::BadRequest(_))));
+
+        let dm = CursorService::dm_scope(Uuid::new_v4(), Uuid::new_v4());
+        assert!(matches!(CursorService::resolve(&dm, cursor()), Err(ServerError::BadRequest(_))));
+    }
+}
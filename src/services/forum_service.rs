@@ -0,0 +1,368 @@
+use crate::db::forums;
+use crate::errors::{Result, ServerError};
+use crate::services::{audit_service, AuditService, SettingsService};
+use nexus_tui_common::{TrendingThread, User, UserRole};
+use once_cell::sync::Lazy;
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// A user's repeat views of a thread within this window count once
+const THREAD_VIEW_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Most (user, thread) views remembered per generation of `RecentViews`
+const MAX_TRACKED_THREAD_VIEWS: usize = 100_000;
+
+/// Each post in the trending window weighs as much as this many views
+const TRENDING_POST_WEIGHT: i64 = 10;
+
+/// Bounds on the trending window and list length
+const MAX_TRENDING_DAYS: u32 = 30;
+const MAX_TRENDING_THREADS: usize = 50;
+
+/// Largest post edit diff sent to a moderator
+const MAX_POST_DIFF_BYTES: usize = 10 * 1024;
+
+/// Last counted view per (user, thread)
+static THREAD_VIEWS: Lazy<Mutex<RecentViews>> =
+    Lazy::new(|| Mutex::new(RecentViews::new(MAX_TRACKED_THREAD_VIEWS, Instant::now())));
+
+/// Recently counted views in two generations. New views go into the current
+/// one; once a window has passed, or it holds `capacity` views, it becomes
+/// the previous one and the old previous generation is dropped whole. Expiry
+/// never scans the map, and at most two generations are kept.
+struct RecentViews {
+    current: HashMap<(Uuid, Uuid), Instant>,
+    previous: HashMap<(Uuid, Uuid), Instant>,
+    rotated_at: Instant,
+    capacity: usize,
+}
+
+impl RecentViews {
+    fn new(capacity: usize, now: Instant) -> Self {
+        Self { current: HashMap::new(), previous: HashMap::new(), rotated_at: now, capacity }
+    }
+
+    /// Remember a view at `now`, returning false if the same view was counted
+    /// within the window. A dropped generation is handed back so the caller
+    /// can free it after releasing the lock.
+    fn claim(&mut self, key: (Uuid, Uuid), now: Instant) -> (bool, Option<HashMap<(Uuid, Uuid), Instant>>) {
+        if let Some(viewed_at) = self.current.get(&key).or_else(|| self.previous.get(&key)) {
+            if now.duration_since(*viewed_at) < THREAD_VIEW_WINDOW {
+                return (false, None);
+            }
+        }
+
+        let mut dropped = None;
+        // After a full window everything in `previous` is older than the window.
+        // Rotating early for capacity may forget a few live views instead.
+        if now.duration_since(self.rotated_at) >= THREAD_VIEW_WINDOW || self.current.len() >= self.capacity {
+            dropped = Some(std::mem::replace(&mut self.previous, std::mem::take(&mut self.current)));
+            self.rotated_at = now;
+        }
+        self.current.insert(key, now);
+        (true, dropped)
+    }
+}
+
+/// Forum actions that need an authorization check
+#[derive(Debug, Clone, Copy)]
+pub enum ForumAction {
+    /// Create, delete or configure forums
+    ManageForums,
+    /// Start a thread in a forum
+    CreateThread { forum_id: Uuid },
+    /// Post or reply in a thread
+    CreatePost { thread_id: Uuid },
+}
+
+pub struct ForumService;
+
+impl ForumService {
+    fn role_rank(role: &UserRole) -> u8 {
+        match role {
+            UserRole::User | UserRole::Bot => 0,
+            UserRole::Moderator => 1,
+            UserRole::Admin => 2,
+        }
+    }
+
+    /// Check that a user may perform a forum action
+    pub async fn authorize(user: &User, action: ForumAction) -> Result<()> {
+        let forum_id = match action {
+            ForumAction::ManageForums => {
+                if user.role == UserRole::Admin {
+                    return Ok(());
+                }
+                return Err(ServerError::Forbidden("Only admins can manage forums".to_string()));
+            }
+            ForumAction::CreateThread { forum_id } => forum_id,
+            ForumAction::CreatePost { thread_id } => forums::db_get_thread_forum_id(thread_id).await
+                .map_err(|e| ServerError::Database(e))?,
+        };
+
+        let min_role = forums::db_get_forum_post_role(forum_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if Self::role_rank(&user.role) < Self::role_rank(&min_role) {
+            return Err(ServerError::Forbidden(format!(
+                "Posting in this forum requires the {:?} role", min_role
+            )));
+        }
+        Ok(())
+    }
+
+    /// Restrict posting in a forum to users with at least the given role (Admin only)
+    pub async fn set_posting_role(admin: &User, forum_id: Uuid, min_role: UserRole) -> Result<()> {
+        Self::authorize(admin, ForumAction::ManageForums).await?;
+
+        let role_str = match min_role {
+            UserRole::Admin => "Admin",
+            UserRole::Moderator => "Moderator",
+            UserRole::Bot => "Bot",
+            UserRole::User => "User",
+        };
+        forums::db_set_forum_post_role(forum_id, role_str).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        AuditService::record(
+            admin, audit_service::SET_FORUM_POSTING_ROLE, Some(forum_id.to_string()), Some(role_str.to_string())
+        ).await;
+        info!("Forum {} posting restricted to {} by {}", forum_id, role_str, admin.username);
+        Ok(())
+    }
+
+    /// Line-based unified diff between two revisions of a post (moderators only),
+    /// cut off at MAX_POST_DIFF_BYTES with a marker saying how much was left out
+    pub async fn post_edit_diff(user: &User, post_id: Uuid, from_revision: u32, to_revision: u32) -> Result<String> {
+        if Self::role_rank(&user.role) < Self::role_rank(&UserRole::Moderator) {
+            return Err(ServerError::Forbidden("Only moderators can view post edit diffs".to_string()));
+        }
+
+        let revision = |revision: u32| async move {
+            forums::db_get_post_revision(post_id, revision).await
+                .map_err(|e| ServerError::Database(e))?
+                .ok_or_else(|| ServerError::NotFound(format!("Post has no revision {}", revision)))
+        };
+        let old = revision(from_revision).await?;
+        let new = revision(to_revision).await?;
+
+        let diff = TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(&format!("revision {}", from_revision), &format!("revision {}", to_revision))
+            .to_string();
+        Ok(truncate_diff(diff))
+    }
+
+    /// Count a user's view of a thread, at most once per hour. The counter is
+    /// bumped in the background so it never holds up the caller.
+    pub fn record_thread_view(user_id: Uuid, thread_id: Uuid) {
+        if !Self::claim_view(user_id, thread_id) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            // Views are writes too, so they're dropped rather than refused in maintenance mode
+            if SettingsService::maintenance_mode().await.unwrap_or(false) {
+                return;
+            }
+            if let Err(e) = forums::db_increment_thread_views(thread_id).await {
+                warn!("Failed to record view of thread {}: {}", thread_id, e);
+            }
+        });
+    }
+
+    /// Remember the view, returning false if the user already viewed the thread this hour
+    fn claim_view(user_id: Uuid, thread_id: Uuid) -> bool {
+        let (claimed, dropped) = THREAD_VIEWS.lock().unwrap().claim((user_id, thread_id), Instant::now());
+        drop(dropped);
+        claimed
+    }
+
+    /// Threads with the most posts and views over the last `days` days
+    pub async fn trending_threads(days: u32, limit: usize) -> Result<Vec<TrendingThread>> {
+        let days = days.clamp(1, MAX_TRENDING_DAYS);
+        let since = crate::util::now_secs() - i64::from(days) * 24 * 60 * 60;
+        forums::db_get_trending_threads(since, TRENDING_POST_WEIGHT, limit.clamp(1, MAX_TRENDING_THREADS)).await
+            .map_err(|e| ServerError::Database(e))
+    }
+}
+
+/// Cap a diff at MAX_POST_DIFF_BYTES, cutting at a line break where possible
+fn truncate_diff(diff: String) -> String {
+    if diff.len() <= MAX_POST_DIFF_BYTES {
+        return diff;
+    }
+
+    let mut cut = MAX_POST_DIFF_BYTES;
+    while !diff.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    if let Some(line_end) = diff[..cut].rfind('\n') {
+        cut = line_end + 1;
+    }
+    format!("{}... diff truncated ({} more bytes) ...\n", &diff[..cut], diff.len() - cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    /// A forum restricted to moderators, with one thread started by an admin
+    async fn moderator_forum() -> (Uuid, Uuid) {
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        forums::db_create_forum("Announcements", "").await.unwrap();
+        let forum_id = forums::db_get_forums().await.unwrap()[0].id;
+        ForumService::set_posting_role(&admin, forum_id, UserRole::Moderator).await.unwrap();
+        forums::db_create_thread(forum_id, "Welcome", admin.id, "first post").await.unwrap();
+        let thread_id = forums::db_get_forums().await.unwrap()[0].threads[0].id;
+        (forum_id, thread_id)
+    }
+
+    #[tokio::test]
+    async fn user_cannot_post_in_a_moderator_forum() {
+        let _db = TestDb::new().await;
+        let (forum_id, thread_id) = moderator_forum().await;
+        let user = test_support::create_user("alice").await;
+
+        let thread = ForumService::authorize(&user, ForumAction::CreateThread { forum_id }).await;
+        assert!(matches!(thread, Err(ServerError::Forbidden(_))));
+        let post = ForumService::authorize(&user, ForumAction::CreatePost { thread_id }).await;
+        assert!(matches!(post, Err(ServerError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn moderator_can_post_in_a_moderator_forum() {
+        let _db = TestDb::new().await;
+        let (forum_id, thread_id) = moderator_forum().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+
+        ForumService::authorize(&moderator, ForumAction::CreateThread { forum_id }).await.unwrap();
+        ForumService::authorize(&moderator, ForumAction::CreatePost { thread_id }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_admins_manage_forums() {
+        let _db = TestDb::new().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+
+        let result = ForumService::authorize(&moderator, ForumAction::ManageForums).await;
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+        ForumService::authorize(&admin, ForumAction::ManageForums).await.unwrap();
+    }
+
+    /// A post whose revisions are `revisions`: the last is the live content,
+    /// the others sit in post_edits
+    fn post_with_revisions(db: &TestDb, author: &User, revisions: &[&str]) -> Uuid {
+        let conn = rusqlite::Connection::open(db.path()).unwrap();
+        let post_id = Uuid::new_v4();
+        let (live, earlier) = revisions.split_last().unwrap();
+        conn.execute(
+            "INSERT INTO posts (id, thread_id, author_id, content, timestamp, depth) VALUES (?1, ?2, ?3, ?4, 0, 0)",
+            rusqlite::params![post_id.to_string(), Uuid::new_v4().to_string(), author.id.to_string(), live],
+        ).unwrap();
+        for (revision, content) in earlier.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO post_edits (post_id, revision, content, edited_by, edited_at) VALUES (?1, ?2, ?3, ?4, 0)",
+                rusqlite::params![post_id.to_string(), revision as i64, content, author.id.to_string()],
+            ).unwrap();
+        }
+        post_id
+    }
+
+    /// The changed lines of a diff, without its header
+    fn changes(diff: &str) -> Vec<&str> {
+        diff.lines()
+            .filter(|line| !line.starts_with("---") && !line.starts_with("+++"))
+            .filter(|line| line.starts_with('+') || line.starts_with('-'))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn post_diffs_show_insertions_and_deletions() {
+        let db = TestDb::new().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        let post_id = post_with_revisions(&db, &moderator, &["a\nb\n", "a\nb\nc\n", "a\nc\n", "a\nc\n"]);
+
+        let inserted = ForumService::post_edit_diff(&moderator, post_id, 0, 1).await.unwrap();
+        assert!(inserted.contains("--- revision 0") && inserted.contains("+++ revision 1"), "{}", inserted);
+        assert_eq!(changes(&inserted), vec!["+c"]);
+
+        let deleted = ForumService::post_edit_diff(&moderator, post_id, 1, 2).await.unwrap();
+        assert_eq!(changes(&deleted), vec!["-b"]);
+
+        let unchanged = ForumService::post_edit_diff(&moderator, post_id, 2, 3).await.unwrap();
+        assert!(changes(&unchanged).is_empty(), "{}", unchanged);
+        assert!(!unchanged.contains("@@"), "{}", unchanged);
+    }
+
+    #[tokio::test]
+    async fn post_diffs_need_a_moderator_and_existing_revisions() {
+        let db = TestDb::new().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        let user = test_support::create_user("alice").await;
+        let post_id = post_with_revisions(&db, &moderator, &["old\n", "new\n"]);
+
+        let refused = ForumService::post_edit_diff(&user, post_id, 0, 1).await;
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+        let missing = ForumService::post_edit_diff(&moderator, post_id, 0, 2).await;
+        assert!(matches!(missing, Err(ServerError::NotFound(_))));
+    }
+
+    #[test]
+    fn long_diffs_are_cut_at_a_line_break_with_a_marker() {
+        let diff = "+line\n".repeat(MAX_POST_DIFF_BYTES);
+        let cut = truncate_diff(diff.clone());
+        let (kept, marker) = cut.split_at(cut.find("... diff truncated").unwrap());
+        assert!(kept.len() <= MAX_POST_DIFF_BYTES);
+        assert!(kept.ends_with('\n'));
+        assert_eq!(marker, format!("... diff truncated ({} more bytes) ...\n", diff.len() - kept.len()));
+    }
+
+    #[test]
+    fn a_view_counts_once_per_window() {
+        let start = Instant::now();
+        let mut views = RecentViews::new(100, start);
+        let key = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(views.claim(key, start).0);
+        assert!(!views.claim(key, start + Duration::from_secs(60)).0);
+        // Still remembered after its generation became the previous one
+        let other = (Uuid::new_v4(), Uuid::new_v4());
+        let (_, dropped) = views.claim(other, start + THREAD_VIEW_WINDOW - Duration::from_secs(1));
+        assert!(dropped.is_none());
+        let (_, dropped) = views.claim((Uuid::new_v4(), Uuid::new_v4()), start + THREAD_VIEW_WINDOW);
+        assert!(dropped.is_some());
+        assert!(!views.claim(other, start + THREAD_VIEW_WINDOW + Duration::from_secs(1)).0);
+        assert!(views.claim(key, start + THREAD_VIEW_WINDOW + Duration::from_secs(1)).0);
+    }
+
+    #[test]
+    fn old_generations_are_dropped_whole() {
+        let start = Instant::now();
+        let mut views = RecentViews::new(100, start);
+        for _ in 0..10 {
+            views.claim((Uuid::new_v4(), Uuid::new_v4()), start);
+        }
+
+        views.claim((Uuid::new_v4(), Uuid::new_v4()), start + THREAD_VIEW_WINDOW);
+        let (_, dropped) = views.claim((Uuid::new_v4(), Uuid::new_v4()), start + THREAD_VIEW_WINDOW * 2);
+
+        assert_eq!(dropped.map(|generation| generation.len()), Some(10));
+        assert_eq!(views.previous.len() + views.current.len(), 2);
+    }
+
+    #[test]
+    fn a_full_generation_rotates_early() {
+        let start = Instant::now();
+        let mut views = RecentViews::new(3, start);
+        for _ in 0..10 {
+            assert!(views.claim((Uuid::new_v4(), Uuid::new_v4()), start).0);
+            assert!(views.current.len() <= 3 && views.previous.len() <= 3);
+        }
+    }
+}
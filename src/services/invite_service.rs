@@ -1,108 +1,158 @@
 use crate::db::invites::*;
-use crate::db::servers::{db_add_user_to_server, db_is_user_in_server};
+use crate::db::servers::{db_add_user_to_server, db_get_server_by_invite_code, db_is_user_banned, db_is_user_in_server};
 use crate::db::users::db_get_user_by_id;
 use crate::db::messages;
 use crate::errors::{Result, ServerError};
-use crate::services::BroadcastService;
+use crate::services::{BroadcastService, SystemMessageService};
 use crate::api::connection::PeerMap;
-use nexus_tui_common::{ServerInvite, ServerInviteStatus, ServerMessage, User, DirectMessage};
+use nexus_tui_common::{ServerInvite, ServerInviteStatus, ServerMessage, ServerPreview, User, DirectMessage};
 use tracing::{error, info};
 use uuid::Uuid;
 
+/// Pending invites older than this are expired by the maintenance job
+pub const INVITE_TTL_DAYS: i64 = 7;
+
+/// Human-readable remaining cooldown, rounded up to the minute
+fn format_cooldown(seconds: i64) -> String {
+    let minutes = (seconds + 59) / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h {}m", h, m),
+    }
+}
+
+/// Text of the DM announcing an invite. Once the invite is settled the
+/// instructions are replaced by the outcome.
+fn invite_dm_content(from_username: &str, server_name: &str, outcome: Option<&str>) -> String {
+    match outcome {
+        None => format!(
+            "🎮 SERVER INVITE: {} invited you to join '{}'!\n\nType /accept to accept or /decline to decline this invitation.",
+            from_username, server_name
+        ),
+        Some(outcome) => format!(
+            "🎮 SERVER INVITE: {} invited you to join '{}' ({})",
+            from_username, server_name, outcome
+        ),
+    }
+}
+
 pub struct InviteService;
 
 impl InviteService {
-    /// Send a server invite to another user
+    /// Send a server invite to another user. The invite row, its DM and the
+    /// ServerInviteReceived event are created together or not at all, so a
+    /// repeated invite never produces a second DM.
     pub async fn send_server_invite(
         from_user_id: Uuid,
         to_user_id: Uuid,
         server_id: Uuid,
         peer_map: &PeerMap,
     ) -> Result<Uuid> {
+        if from_user_id == to_user_id {
+            return Err(ServerError::BadRequest("You can't invite yourself".to_string()));
+        }
+
         // Check if the sender is a member of the server
         if !db_is_user_in_server(from_user_id, server_id).await? {
             return Err(ServerError::Authorization("You must be a member of this server to invite others".to_string()));
         }
 
+        if db_is_user_banned(from_user_id, server_id).await? {
+            return Err(ServerError::Forbidden("You are banned from this server and can't invite others".to_string()));
+        }
+
+        // A banned user could never accept the invite
+        if db_is_user_banned(to_user_id, server_id).await? {
+            return Err(ServerError::Forbidden("This user is banned from this server and can't be invited".to_string()));
+        }
+
         // Check if the target user is already in the server
         if db_is_user_in_server(to_user_id, server_id).await? {
             return Err(ServerError::BadRequest("User is already in this server".to_string()));
         }
 
-        // Check if there's already a pending invite
-        if db_check_existing_invite(from_user_id, to_user_id, server_id).await? {
-            return Err(ServerError::BadRequest("Invite already sent to this user".to_string()));
+        // Check if there's already a pending invite, from anyone
+        if db_check_existing_invite(to_user_id, server_id).await? {
+            return Err(ServerError::BadRequest("User already has a pending invite to this server".to_string()));
         }
 
-        // Create the invite
-        let invite_id = db_create_server_invite(from_user_id, to_user_id, server_id).await?;
-        
-        // Get the full invite details to send to the recipient
-        if let Some(invite) = db_get_invite_by_id(invite_id).await? {
-            // Get sender user info for the DM
-            match db_get_user_by_id(from_user_id).await {
-                Ok(from_user_profile) => {
-                    // Convert UserProfile to User
-                    let from_user = User {
-                        id: from_user_profile.id,
-                        username: from_user_profile.username.clone(),
-                        color: from_user_profile.color,
-                        role: from_user_profile.role,
-                        profile_pic: from_user_profile.profile_pic.clone(),
-                        cover_banner: from_user_profile.cover_banner.clone(),
-                        status: nexus_tui_common::UserStatus::Connected,
-                    };
-                    
-                    let timestamp = chrono::Utc::now().timestamp();
-                    
-                    // Create special DM content for server invite
-                    let invite_content = format!("🎮 SERVER INVITE: {} invited you to join '{}'!\n\nType /accept to accept or /decline to decline this invitation.", 
-                        from_user.username, 
-                        invite.server.name
-                    );
-                    
-                    // Store the invite message as a DM in the database
-                    let dm_id = messages::db_store_direct_message(
-                        from_user_id, to_user_id, &invite_content, timestamp
-                    ).await.map_err(|e| ServerError::Database(e))?;
-
-                    // Create DM object - no redundant author fields
-                    let dm = DirectMessage {
-                        id: dm_id,
-                        from: from_user_id,
-                        to: to_user_id,
-                        timestamp,
-                        content: invite_content,
-                    };
-
-                    // Send the DM to both users
-                    let dm_message = ServerMessage::DirectMessage(dm);
-                    let user_ids = vec![from_user_id, to_user_id];
-                    BroadcastService::broadcast_to_users(peer_map, &user_ids, &dm_message).await;
-
-                    // Also send the raw invite data for the client to handle specially
-                    let invite_message = ServerMessage::ServerInviteReceived(invite.clone());
-                    BroadcastService::send_to_user(peer_map, to_user_id, &invite_message).await;
-                    
-                    info!("Server invite sent as DM to user {}", to_user_id);
-                }
-                Err(e) => {
-                    error!("Failed to get user profile for invite DM: {:?}", e);
-                    return Err(ServerError::Database(e));
+        // A declined invite keeps the sender from re-inviting straight away
+        let cooldown_secs = crate::config::settings().invites.decline_cooldown_hours * 3600;
+        if cooldown_secs > 0 {
+            if let Some(declined_at) = db_last_declined_invite_at(from_user_id, to_user_id).await? {
+                let remaining = declined_at + cooldown_secs - crate::util::now_secs();
+                if remaining > 0 {
+                    return Err(ServerError::BadRequest(format!(
+                        "This user declined your last invite; you can invite them again in {}",
+                        format_cooldown(remaining)
+                    )));
                 }
             }
-            
-            // Also notify the sender about successful invite creation
-            let sender_message = ServerMessage::Notification(
-                "Server invite sent successfully!".to_string(), 
-                false
-            );
-            BroadcastService::send_to_user(peer_map, from_user_id, &sender_message).await;
         }
-        
+
+        let from_user_profile = db_get_user_by_id(from_user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        // The checks above are for friendly errors; this insert is the real guard against races
+        let invite_id = db_create_server_invite(from_user_id, to_user_id, server_id).await?
+            .ok_or_else(|| ServerError::BadRequest("User already has a pending invite to this server".to_string()))?;
+
+        let (invite, dm) = match Self::announce_invite(invite_id, &from_user_profile.username).await {
+            Ok(announced) => announced,
+            Err(e) => {
+                // Don't leave an invite behind that the recipient was never told about
+                if let Err(cleanup) = db_delete_server_invite(invite_id).await {
+                    error!("Failed to remove unannounced invite {}: {}", invite_id, cleanup);
+                }
+                return Err(e);
+            }
+        };
+
+        // Send the DM to both users
+        let dm_message = ServerMessage::DirectMessage(dm);
+        let user_ids = vec![from_user_id, to_user_id];
+        BroadcastService::broadcast_to_users(peer_map, &user_ids, &dm_message).await;
+
+        // Also send the raw invite data for the client to handle specially
+        let invite_message = ServerMessage::ServerInviteReceived(invite);
+        BroadcastService::send_to_user(peer_map, to_user_id, &invite_message).await;
+        info!("Server invite sent as DM to user {}", to_user_id);
+
+        // Also notify the sender about successful invite creation
+        let sender_message = ServerMessage::Notification(
+            "Server invite sent successfully!".to_string(), 
+            false
+        );
+        BroadcastService::send_to_user(peer_map, from_user_id, &sender_message).await;
+
         Ok(invite_id)
     }
 
+    /// Store the DM announcing a freshly created invite and link it to the invite
+    async fn announce_invite(invite_id: Uuid, from_username: &str) -> Result<(ServerInvite, DirectMessage)> {
+        let invite = db_get_invite_by_id(invite_id).await?
+            .ok_or_else(|| ServerError::NotFound("Invite not found".to_string()))?;
+
+        let timestamp = crate::util::now_secs();
+        let content = invite_dm_content(from_username, &invite.server.name, None);
+
+        let dm_id = messages::db_store_direct_message(
+            invite.from_user.id, invite.to_user_id, &content, timestamp
+        ).await.map_err(|e| ServerError::Database(e))?;
+        db_set_invite_dm(invite_id, dm_id).await?;
+
+        // Create DM object - no redundant author fields
+        let dm = DirectMessage {
+            id: dm_id,
+            from: invite.from_user.id,
+            to: invite.to_user_id,
+            timestamp,
+            content,
+        };
+        Ok((invite, dm))
+    }
+
     /// Respond to a server invite (accept or decline)
     pub async fn respond_to_invite(
         invite_id: Uuid,
@@ -124,6 +174,11 @@ impl InviteService {
             return Err(ServerError::BadRequest("Invite is no longer pending".to_string()));
         }
 
+        // Invites sent before a ban can't be used to get back in
+        if accept && db_is_user_banned(user_id, invite.server.id).await? {
+            return Err(ServerError::Forbidden("You are banned from this server".to_string()));
+        }
+
         let new_status = if accept {
             ServerInviteStatus::Accepted
         } else {
@@ -144,6 +199,10 @@ impl InviteService {
         let user = db_get_user_by_id(user_id).await
             .map_err(|e| ServerError::Database(e))?;
 
+        if accept {
+            SystemMessageService::member_joined(invite.server.id, user_id, &user.username, peer_map).await;
+        }
+
         // Notify the original sender about the response
         let response_message = ServerMessage::ServerInviteResponse {
             invite_id,
@@ -160,10 +219,11 @@ impl InviteService {
         };
         
         BroadcastService::send_to_user(peer_map, invite.from_user.id, &response_message).await;
+
+        let outcome = if accept { "accepted" } else { "declined" };
+        Self::settle_invite_dm(&invite, outcome, user_id, peer_map).await;
         
-        info!("Server invite {} by user {}", 
-              if accept { "accepted" } else { "declined" }, 
-              user_id);
+        info!("Server invite {} by user {}", outcome, user_id);
 
         // Return updated invite
         let mut updated_invite = invite;
@@ -188,6 +248,66 @@ impl InviteService {
         Ok(())
     }
 
+    /// Expire pending invites older than `INVITE_TTL_DAYS`, settling their DMs
+    pub async fn expire_stale_invites(peer_map: &PeerMap) -> Result<usize> {
+        let cutoff = crate::util::now_secs() - INVITE_TTL_DAYS * 86400;
+        let expired = db_expire_pending_invites(cutoff).await?;
+
+        for invite_id in &expired {
+            if let Some(invite) = db_get_invite_by_id(*invite_id).await? {
+                Self::settle_invite_dm(&invite, "expired", invite.from_user.id, peer_map).await;
+            }
+        }
+
+        if !expired.is_empty() {
+            info!("Expired {} stale server invites", expired.len());
+        }
+        Ok(expired.len())
+    }
+
+    /// Once an invite is settled, strip the /accept instructions from its DM
+    /// and post the outcome in the same conversation, so history never asks
+    /// anyone to answer an invite that's already closed. `sent_by` is the
+    /// participant the follow-up comes from. Failures are only logged: the
+    /// invite itself is already settled.
+    async fn settle_invite_dm(invite: &ServerInvite, outcome: &str, sent_by: Uuid, peer_map: &PeerMap) {
+        let participants = [invite.from_user.id, invite.to_user_id];
+
+        match db_get_invite_dm(invite.id).await {
+            Ok(Some(dm_id)) => {
+                let content = invite_dm_content(&invite.from_user.username, &invite.server.name, Some(outcome));
+                if let Err(e) = messages::db_update_direct_message_content(dm_id, &content).await {
+                    error!("Failed to update invite DM {}: {}", dm_id, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to look up DM for invite {}: {:?}", invite.id, e),
+        }
+
+        let to = if sent_by == invite.from_user.id { invite.to_user_id } else { invite.from_user.id };
+        let timestamp = crate::util::now_secs();
+        let content = format!("🎮 Invite to '{}' {}", invite.server.name, outcome);
+        match messages::db_store_direct_message(sent_by, to, &content, timestamp).await {
+            Ok(dm_id) => {
+                let dm = DirectMessage { id: dm_id, from: sent_by, to, timestamp, content };
+                BroadcastService::broadcast_to_users(peer_map, &participants, &ServerMessage::DirectMessage(dm)).await;
+            }
+            Err(e) => error!("Failed to store follow-up DM for invite {}: {}", invite.id, e),
+        }
+    }
+
+    /// Look up the server behind an invite code without joining it. Unknown
+    /// and malformed codes get the same error, so probing reveals nothing.
+    pub async fn preview_invite_code(code: &str) -> Result<ServerPreview> {
+        let code = code.trim();
+        let preview = if code.is_empty() {
+            None
+        } else {
+            db_get_server_by_invite_code(code).await.map_err(|e| ServerError::Database(e))?
+        };
+        preview.ok_or_else(|| ServerError::NotFound("Invalid invite code".to_string()))
+    }
+
     /// Get pending invites for a user
     pub async fn get_pending_invites(user_id: Uuid) -> Result<Vec<ServerInvite>> {
         db_get_pending_invites_for_user(user_id).await
@@ -197,4 +317,156 @@ impl InviteService {
     pub async fn get_invite_by_id(invite_id: Uuid) -> Result<Option<ServerInvite>> {
         db_get_invite_by_id(invite_id).await
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    /// `alice` owns the server "Test"; `bob` is not a member yet
+    async fn server_and_outsider() -> (User, User, Uuid) {
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        (alice, bob, server_id)
+    }
+
+    /// Contents of the DM conversation between the two users
+    async fn dm_history(a: Uuid, b: Uuid) -> Vec<String> {
+        let (dms, _) = messages::db_get_direct_messages(a, b, None, 50).await.unwrap();
+        dms.into_iter().map(|dm| dm.content).collect()
+    }
+
+    /// The invite DM no longer asks for an answer, says how it ended, and a
+    /// follow-up with the outcome sits next to it
+    fn assert_settled(history: &[String], outcome: &str) {
+        assert_eq!(history.len(), 2, "{:?}", history);
+        assert!(history.iter().all(|content| !content.contains("/accept")), "{:?}", history);
+        assert!(history.iter().any(|content| content.ends_with(&format!("'Test' ({})", outcome))), "{:?}", history);
+        assert!(history.contains(&format!("🎮 Invite to 'Test' {}", outcome)), "{:?}", history);
+    }
+
+    #[tokio::test]
+    async fn an_invite_code_previews_its_server() {
+        let db = TestDb::new().await;
+        let (_, _, server_id) = server_and_outsider().await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE servers SET invite_code = 'join-us' WHERE id = ?1", rusqlite::params![server_id.to_string()]
+        ).unwrap();
+
+        let preview = InviteService::preview_invite_code(" join-us ").await.unwrap();
+        assert_eq!(preview.id, server_id);
+        assert_eq!(preview.name, "Test");
+        assert_eq!(preview.member_count, 1);
+
+        for code in ["nope", "", "   "] {
+            assert!(matches!(InviteService::preview_invite_code(code).await, Err(ServerError::NotFound(_))), "{:?}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_second_invite_sends_no_second_dm() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+        let carol = test_support::create_user("carol").await;
+        test_support::join_server(server_id, carol.id).await;
+
+        InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        let again = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await;
+        let from_carol = InviteService::send_server_invite(carol.id, bob.id, server_id, &peer_map).await;
+
+        assert!(matches!(again, Err(ServerError::BadRequest(_))));
+        assert!(matches!(from_carol, Err(ServerError::BadRequest(_))));
+        assert_eq!(messages::db_get_direct_message_count(alice.id, bob.id).await.unwrap(), 1);
+        assert_eq!(messages::db_get_direct_message_count(carol.id, bob.id).await.unwrap(), 0);
+        assert_eq!(InviteService::get_pending_invites(bob.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn re_inviting_after_a_decline_waits_for_the_cooldown() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+        let invite_id = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        InviteService::respond_to_invite(invite_id, bob.id, false, &peer_map).await.unwrap();
+
+        let refused = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await;
+        assert!(matches!(refused, Err(ServerError::BadRequest(reason)) if reason.contains("declined")));
+
+        let cooldown_secs = crate::config::settings().invites.decline_cooldown_hours * 3600;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE server_invites SET responded_at = ?1",
+            rusqlite::params![crate::util::now_secs() - cooldown_secs - 1],
+        ).unwrap();
+        assert!(InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn banned_users_cannot_be_invited_or_use_an_old_invite() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+        let carol = test_support::create_user("carol").await;
+        let invite_id = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        crate::db::servers::db_ban_user(server_id, bob.id, alice.id, None, None).await.unwrap();
+        crate::db::servers::db_ban_user(server_id, carol.id, alice.id, None, None).await.unwrap();
+
+        let refused = InviteService::send_server_invite(alice.id, carol.id, server_id, &peer_map).await;
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+        assert_eq!(messages::db_get_direct_message_count(alice.id, carol.id).await.unwrap(), 0);
+
+        let accepted = InviteService::respond_to_invite(invite_id, bob.id, true, &peer_map).await;
+        assert!(matches!(accepted, Err(ServerError::Forbidden(_))));
+        assert!(!db_is_user_in_server(bob.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_accepted_invite_settles_its_dm() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+
+        let invite_id = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        assert!(dm_history(alice.id, bob.id).await[0].contains("/accept"));
+        InviteService::respond_to_invite(invite_id, bob.id, true, &peer_map).await.unwrap();
+
+        assert_settled(&dm_history(alice.id, bob.id).await, "accepted");
+        assert!(db_is_user_in_server(bob.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_declined_invite_settles_its_dm() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+
+        InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        InviteService::respond_to_invite_from_user(alice.id, bob.id, false, &peer_map).await.unwrap();
+
+        assert_settled(&dm_history(alice.id, bob.id).await, "declined");
+        assert!(!db_is_user_in_server(bob.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_expired_invite_settles_its_dm_and_can_no_longer_be_accepted() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, server_id) = server_and_outsider().await;
+        let invite_id = InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE server_invites SET timestamp = ?1",
+            rusqlite::params![crate::util::now_secs() - INVITE_TTL_DAYS * 86400 - 1],
+        ).unwrap();
+
+        assert_eq!(InviteService::expire_stale_invites(&peer_map).await.unwrap(), 1);
+
+        assert_settled(&dm_history(alice.id, bob.id).await, "expired");
+        let invite = InviteService::get_invite_by_id(invite_id).await.unwrap().unwrap();
+        assert!(invite.status == ServerInviteStatus::Expired);
+        assert!(matches!(
+            InviteService::respond_to_invite(invite_id, bob.id, true, &peer_map).await,
+            Err(ServerError::BadRequest(_))
+        ));
+    }
+}
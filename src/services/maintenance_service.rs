@@ -0,0 +1,420 @@
+use crate::api::connection::PeerMap;
+use crate::config::MessageConfig;
+use crate::db::{channels, notifications, pending_deliveries, quarantine, users};
+use crate::errors::{Result, ServerError};
+use crate::services::{broadcast_service, metrics_service, rate_limit_service, BroadcastService, InviteService, MetricsService, NotificationService, PollService, RateLimitService, StorageService};
+use nexus_tui_common::{ChannelMessage, ServerMessage};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Shortest interval an admin can ask for their dashboard digest
+pub const MIN_ADMIN_DIGEST_MINUTES: u32 = 5;
+/// Messages pruned (and archived) per round trip to the database
+const PRUNE_BATCH_SIZE: usize = 500;
+/// Length of the period a profile view digest covers
+const PROFILE_VIEW_DIGEST_DAYS: i64 = 7;
+/// How often polls past their closing time are closed
+const POLL_EXPIRY_INTERVAL_SECS: u64 = 30;
+/// Channel messages checked per integrity scan chunk
+const INTEGRITY_SCAN_CHUNK: usize = 5000;
+
+/// Where an admin's last dashboard digest left off, so the next one reports deltas
+struct AdminDigestCursor {
+    sent_at: i64,
+    messages_sent: u64,
+    handler_errors: u64,
+}
+
+impl AdminDigestCursor {
+    fn now() -> Self {
+        Self {
+            sent_at: crate::util::now_secs(),
+            messages_sent: MetricsService::counter(metrics_service::MESSAGES_SENT),
+            handler_errors: MetricsService::counter(metrics_service::HANDLER_ERRORS),
+        }
+    }
+}
+
+pub struct MaintenanceService;
+
+impl MaintenanceService {
+    /// Start the periodic maintenance jobs in the background
+    pub fn spawn(peer_map: PeerMap, rate_limiter: Arc<RateLimitService>) {
+        let interval_minutes = crate::config::settings().digest.interval_minutes.max(1);
+        let peer_map_admin = peer_map.clone();
+        let peer_map_polls = peer_map.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_minutes * 60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = Self::run_digest_rollup(&peer_map).await {
+                    error!("Notification digest rollup failed: {}", e);
+                }
+                if let Err(e) = Self::purge_expired_pending_deliveries().await {
+                    error!("Pending delivery cleanup failed: {}", e);
+                }
+                if let Err(e) = Self::purge_message_tombstones().await {
+                    error!("Deleted message cleanup failed: {}", e);
+                }
+                if let Err(e) = Self::prune_old_messages().await {
+                    error!("Message retention pruning failed: {}", e);
+                }
+                if let Err(e) = InviteService::expire_stale_invites(&peer_map).await {
+                    error!("Invite expiry failed: {}", e);
+                }
+                if let Err(e) = Self::send_profile_view_digests().await {
+                    error!("Profile view digest failed: {}", e);
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut cursors = HashMap::new();
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                // Keep the queued-messages gauge fresh even when nobody asks for connection stats
+                crate::api::connection::connection_stats(&peer_map_admin).await;
+                if let Err(e) = Self::run_admin_digests(&peer_map_admin, &mut cursors).await {
+                    error!("Admin digest failed: {}", e);
+                }
+            }
+        });
+
+        // Polls close on a short cadence so results go out close to the deadline
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(POLL_EXPIRY_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                if let Err(e) = PollService::close_expired_polls(&peer_map_polls).await {
+                    error!("Poll expiry failed: {}", e);
+                }
+            }
+        });
+
+        // Runs once, in the background, so a large table doesn't hold up startup
+        tokio::spawn(async {
+            if let Err(e) = Self::scan_message_integrity().await {
+                error!("Message integrity scan failed: {}", e);
+            }
+        });
+
+        let recompute_hours = crate::config::settings().quotas.recompute_interval_hours.max(1);
+        tokio::spawn(async move {
+            // The first tick fires immediately, so totals are seeded at startup
+            let mut interval = tokio::time::interval(Duration::from_secs(recompute_hours * 3600));
+            loop {
+                interval.tick().await;
+                if let Err(e) = StorageService::recompute_all().await {
+                    error!("Storage recompute failed: {}", e);
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(rate_limit_service::CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let removed = rate_limiter.cleanup_old_entries();
+                if removed > 0 {
+                    debug!("Swept {} ended rate limit windows", removed);
+                }
+            }
+        });
+    }
+
+    /// Hard-delete channel messages whose tombstone grace window has passed
+    pub async fn purge_message_tombstones() -> Result<usize> {
+        let grace_hours = crate::config::settings().messages.tombstone_grace_hours.max(0);
+        let cutoff = crate::util::now_secs() - grace_hours * 3600;
+        let purged = channels::db_purge_channel_message_tombstones(cutoff).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if purged > 0 {
+            info!("Purged {} deleted channel messages", purged);
+        }
+        Ok(purged)
+    }
+
+    /// Delete channel messages past the retention window, archiving them first
+    /// if configured. A batch is only deleted once its archive write succeeded.
+    pub async fn prune_old_messages() -> Result<usize> {
+        let config = crate::config::settings().messages.clone();
+        Self::prune_messages(&config).await
+    }
+
+    async fn prune_messages(config: &MessageConfig) -> Result<usize> {
+        if config.retention_days <= 0 {
+            return Ok(0);
+        }
+        let cutoff = crate::util::now_secs() - config.retention_days * 86400;
+
+        let mut pruned = 0;
+        loop {
+            let batch = channels::db_get_channel_messages_before(cutoff, PRUNE_BATCH_SIZE).await
+                .map_err(|e| ServerError::Database(e))?;
+            if batch.is_empty() {
+                break;
+            }
+            let full_batch = batch.len() == PRUNE_BATCH_SIZE;
+
+            if config.archive_pruned {
+                let archive_dir = PathBuf::from(&config.archive_dir);
+                let archived = batch.clone();
+                tokio::task::spawn_blocking(move || archive_channel_messages(&archive_dir, &archived))
+                    .await
+                    .map_err(|e| ServerError::Internal(e.to_string()))?
+                    .map_err(|e| ServerError::Internal(format!("Failed to archive messages: {}", e)))?;
+            }
+
+            let ids = batch.into_iter().map(|message| message.id).collect();
+            let deleted = channels::db_delete_channel_messages(ids).await
+                .map_err(|e| ServerError::Database(e))?;
+            pruned += deleted;
+
+            if !full_batch || deleted == 0 {
+                break;
+            }
+        }
+
+        if pruned > 0 {
+            info!("Pruned {} channel messages older than {} days", pruned, config.retention_days);
+        }
+        Ok(pruned)
+    }
+
+    /// Walk channel_messages in rowid chunks looking for messages whose author
+    /// or channel doesn't exist, and log what was found. Repairs them when
+    /// `[messages] repair_orphans` is on.
+    pub async fn scan_message_integrity() -> Result<(usize, usize)> {
+        let repair = crate::config::settings().messages.repair_orphans;
+        let mut after_rowid = 0;
+        let (mut scanned, mut orphaned_authors, mut orphaned_channels) = (0, 0, 0);
+        loop {
+            let chunk = channels::db_scan_orphaned_messages(after_rowid, INTEGRITY_SCAN_CHUNK, repair).await
+                .map_err(|e| ServerError::Database(e))?;
+            scanned += chunk.scanned;
+            orphaned_authors += chunk.orphaned_authors;
+            orphaned_channels += chunk.orphaned_channels;
+            match chunk.last_rowid {
+                Some(rowid) if chunk.scanned == INTEGRITY_SCAN_CHUNK => after_rowid = rowid,
+                _ => break,
+            }
+            // Let connection handling use the database between chunks
+            tokio::task::yield_now().await;
+        }
+
+        if orphaned_authors == 0 && orphaned_channels == 0 {
+            info!("Message integrity scan: {} channel messages checked, no orphans", scanned);
+        } else if repair {
+            warn!(
+                "Message integrity scan: reassigned {} messages with missing authors to the System user, moved {} messages in missing channels to orphaned_channel_messages",
+                orphaned_authors, orphaned_channels
+            );
+        } else {
+            warn!(
+                "Message integrity scan: {} messages have missing authors and {} are in missing channels; set [messages] repair_orphans = true to fix them",
+                orphaned_authors, orphaned_channels
+            );
+        }
+        Ok((orphaned_authors, orphaned_channels))
+    }
+
+    /// Tell opted-in users how many views their profile had in the week just ended
+    pub async fn send_profile_view_digests() -> Result<usize> {
+        let period_start = crate::util::now_secs() - PROFILE_VIEW_DIGEST_DAYS * 86400;
+        let digests = users::db_take_profile_view_digests(period_start).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        for (user_id, views) in &digests {
+            NotificationService::create_profile_view_digest_notification(*user_id, *views).await;
+        }
+        if !digests.is_empty() {
+            info!("Sent {} profile view digests", digests.len());
+        }
+        Ok(digests.len())
+    }
+
+    /// Drop undelivered messages older than the retention window
+    pub async fn purge_expired_pending_deliveries() -> Result<usize> {
+        let cutoff = crate::util::now_secs() - broadcast_service::PENDING_DELIVERY_TTL_DAYS * 86400;
+        let purged = pending_deliveries::db_purge_pending_deliveries(cutoff).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if purged > 0 {
+            info!("Purged {} expired pending deliveries", purged);
+        }
+        Ok(purged)
+    }
+
+    /// Push dashboard digests to online admins whose interval has elapsed
+    async fn run_admin_digests(peer_map: &PeerMap, cursors: &mut HashMap<Uuid, AdminDigestCursor>) -> Result<()> {
+        let subscribers = users::db_get_admin_digest_subscribers().await
+            .map_err(|e| ServerError::Database(e))?;
+        let online = BroadcastService::get_online_users(peer_map).await;
+
+        let online_subscribers: Vec<(Uuid, u32)> = subscribers
+            .into_iter()
+            .filter(|(admin_id, _)| online.contains(admin_id))
+            .collect();
+
+        // Forget admins who went offline or opted out; they start a fresh interval next time
+        let active: HashSet<Uuid> = online_subscribers.iter().map(|(admin_id, _)| *admin_id).collect();
+        cursors.retain(|admin_id, _| active.contains(admin_id));
+
+        let now = crate::util::now_secs();
+        for (admin_id, interval_minutes) in online_subscribers {
+            let interval_secs = interval_minutes.max(MIN_ADMIN_DIGEST_MINUTES) as i64 * 60;
+            let cursor = cursors.entry(admin_id).or_insert_with(AdminDigestCursor::now);
+            if now - cursor.sent_at < interval_secs {
+                continue;
+            }
+
+            let next = AdminDigestCursor::now();
+            let new_users = users::db_count_users_created_since(cursor.sent_at).await
+                .map_err(|e| ServerError::Database(e))?;
+            let reports_open = quarantine::db_count_quarantined_messages().await
+                .map_err(|e| ServerError::Database(e))?;
+
+            let digest = ServerMessage::AdminDigest {
+                new_users: new_users as u64,
+                messages_last_interval: next.messages_sent - cursor.messages_sent,
+                reports_open: reports_open as u64,
+                errors_last_interval: next.handler_errors - cursor.handler_errors,
+            };
+            BroadcastService::send_to_user(peer_map, admin_id, &digest).await;
+            *cursor = next;
+        }
+        Ok(())
+    }
+
+    /// Collapse old unread notifications of inactive users into digests
+    pub async fn run_digest_rollup(peer_map: &PeerMap) -> Result<usize> {
+        let digest = crate::config::settings().digest.clone();
+        if !digest.enabled {
+            return Ok(0);
+        }
+
+        let cutoff = crate::util::now_secs() - digest.inactive_days * 86400;
+        let online_user_ids = {
+            let peers = peer_map.lock().await;
+            peers.values().filter_map(|peer| peer.user_id).collect()
+        };
+
+        let created = notifications::db_rollup_notifications(cutoff, online_user_ids).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if created > 0 {
+            info!("Rolled up notifications into {} digest(s)", created);
+        }
+        Ok(created)
+    }
+}
+
+/// Append messages as JSON lines to one file per channel and UTC day
+fn archive_channel_messages(archive_dir: &Path, messages: &[ChannelMessage]) -> std::io::Result<()> {
+    let mut files: HashMap<PathBuf, Vec<&ChannelMessage>> = HashMap::new();
+    for message in messages {
+        let day = chrono::DateTime::from_timestamp(message.timestamp, 0)
+            .map(|time| time.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let path = archive_dir.join(message.channel_id.to_string()).join(format!("{}.jsonl", day));
+        files.entry(path).or_default().push(message);
+    }
+
+    for (path, messages) in files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        for message in messages {
+            let line = serde_json::to_string(message)?;
+            writeln!(file, "{}", line)?;
+        }
+        // Make sure the lines are on disk before the rows are deleted
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    #[tokio::test]
+    async fn pruned_messages_are_archived_before_they_are_deleted() {
+        let _db = TestDb::new().await;
+        let archive = tempfile::tempdir().unwrap();
+        let owner = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&owner, "Archive").await;
+        let channel_id = test_support::create_channel(server_id, "history").await;
+
+        // 2020-01-02, well past any retention window, and one from today
+        let old_timestamp = 1_577_923_200;
+        let old = channels::db_create_channel_message(channel_id, owner.id, old_timestamp, "ancient", None).await.unwrap();
+        let recent = channels::db_create_channel_message(channel_id, owner.id, crate::util::now_secs(), "fresh", None).await.unwrap();
+
+        let config = MessageConfig {
+            retention_days: 30,
+            archive_pruned: true,
+            archive_dir: archive.path().to_string_lossy().into_owned(),
+            ..MessageConfig::default()
+        };
+        assert_eq!(MaintenanceService::prune_messages(&config).await.unwrap(), 1);
+
+        let file = archive.path().join(channel_id.to_string()).join("2020-01-02.jsonl");
+        let lines: Vec<ChannelMessage> = fs::read_to_string(&file).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].id, old);
+        assert_eq!(lines[0].content, "ancient");
+
+        let left = channels::db_get_channel_messages_before(i64::MAX, 10).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, recent);
+    }
+
+    #[tokio::test]
+    async fn a_failed_archive_write_keeps_the_messages() {
+        let _db = TestDb::new().await;
+        let archive = tempfile::tempdir().unwrap();
+        let owner = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&owner, "Archive").await;
+        let channel_id = test_support::create_channel(server_id, "history").await;
+        channels::db_create_channel_message(channel_id, owner.id, 1_577_923_200, "ancient", None).await.unwrap();
+
+        // A file where the archive directory should be makes the write fail
+        let blocker = archive.path().join("not-a-dir");
+        fs::write(&blocker, "").unwrap();
+        let config = MessageConfig {
+            retention_days: 30,
+            archive_pruned: true,
+            archive_dir: blocker.to_string_lossy().into_owned(),
+            ..MessageConfig::default()
+        };
+        assert!(MaintenanceService::prune_messages(&config).await.is_err());
+        assert_eq!(channels::db_get_channel_messages_before(i64::MAX, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retention_zero_keeps_everything() {
+        let _db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&owner, "Archive").await;
+        let channel_id = test_support::create_channel(server_id, "history").await;
+        channels::db_create_channel_message(channel_id, owner.id, 1_577_923_200, "ancient", None).await.unwrap();
+
+        assert_eq!(MaintenanceService::prune_messages(&MessageConfig::default()).await.unwrap(), 0);
+        assert_eq!(channels::db_get_channel_messages_before(i64::MAX, 10).await.unwrap().len(), 1);
+    }
+}
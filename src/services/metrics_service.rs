@@ -0,0 +1,217 @@
+use nexus_tui_common::{ChannelThroughput, LatencyStats};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Upper bounds (in microseconds) of the latency buckets. The last bucket is unbounded.
+const BUCKET_BOUNDS_US: [u64; 16] = [
+    250, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000,
+    100_000, 200_000, 500_000, 1_000_000, 2_000_000, 5_000_000, 10_000_000, u64::MAX,
+];
+
+/// Fixed-bucket latency histogram. Percentiles are reported as the upper
+/// bound of the bucket they fall in, which is plenty for spotting slow paths.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKET_BOUNDS_US.len()],
+    count: u64,
+    max_us: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, micros: u64) {
+        let index = BUCKET_BOUNDS_US.iter().position(|&bound| micros <= bound).unwrap_or(BUCKET_BOUNDS_US.len() - 1);
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.max_us = self.max_us.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Estimated latency in microseconds at the given percentile (0-100)
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let target = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, &bucket_count) in self.buckets.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= target {
+                // Never report more than the slowest sample we actually saw
+                return BUCKET_BOUNDS_US[index].min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+}
+
+/// Per-channel throughput is counted over windows of this length, then reset
+const CHANNEL_THROUGHPUT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Busiest channels reported in the admin stats
+pub const BUSIEST_CHANNELS_REPORTED: usize = 10;
+
+/// Messages and bytes sent per channel since `window_start`
+struct ChannelCounters {
+    window_start: Instant,
+    channels: HashMap<Uuid, (u64, u64)>,
+}
+
+impl ChannelCounters {
+    /// Start a fresh window once the current one has run its course
+    fn roll(&mut self) {
+        if self.window_start.elapsed() >= CHANNEL_THROUGHPUT_WINDOW {
+            self.window_start = Instant::now();
+            self.channels.clear();
+        }
+    }
+}
+
+static HISTOGRAMS: Lazy<Mutex<HashMap<&'static str, LatencyHistogram>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static COUNTERS: Lazy<Mutex<HashMap<&'static str, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static GAUGES: Lazy<Mutex<HashMap<&'static str, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static CHANNEL_COUNTERS: Lazy<Mutex<ChannelCounters>> = Lazy::new(|| Mutex::new(ChannelCounters {
+    window_start: Instant::now(),
+    channels: HashMap::new(),
+}));
+
+/// Counter names shared between the places that bump them and the readers
+pub const MESSAGES_SENT: &str = "messages_sent";
+pub const HANDLER_ERRORS: &str = "handler_errors";
+pub const MALFORMED_FRAMES: &str = "malformed_frames";
+pub const SEND_BUFFER_OVERFLOWS: &str = "send_buffer_overflows";
+pub const SERIALIZATION_FAILURES: &str = "serialization_failures";
+/// Gauge names
+pub const QUEUED_MESSAGES: &str = "queued_messages";
+
+pub struct MetricsService;
+
+impl MetricsService {
+    /// Record a single latency sample for an action
+    pub fn record(action: &'static str, micros: u64) {
+        let mut histograms = HISTOGRAMS.lock().unwrap();
+        histograms.entry(action).or_default().record(micros);
+    }
+
+    /// Bump a monotonically increasing counter
+    pub fn increment(counter: &'static str) {
+        let mut counters = COUNTERS.lock().unwrap();
+        *counters.entry(counter).or_default() += 1;
+    }
+
+    /// Current value of a counter since startup
+    pub fn counter(counter: &'static str) -> u64 {
+        COUNTERS.lock().unwrap().get(counter).copied().unwrap_or(0)
+    }
+
+    /// Set a gauge to its latest sampled value
+    pub fn set_gauge(gauge: &'static str, value: u64) {
+        GAUGES.lock().unwrap().insert(gauge, value);
+    }
+
+    /// Last sampled value of a gauge
+    pub fn gauge(gauge: &'static str) -> u64 {
+        GAUGES.lock().unwrap().get(gauge).copied().unwrap_or(0)
+    }
+
+    /// Count a message delivered to a channel
+    pub fn record_channel_message(channel_id: Uuid, bytes: usize) {
+        let mut counters = CHANNEL_COUNTERS.lock().unwrap();
+        counters.roll();
+        let (messages, total_bytes) = counters.channels.entry(channel_id).or_default();
+        *messages += 1;
+        *total_bytes += bytes as u64;
+    }
+
+    /// The channels with the most messages in the current window, and how many
+    /// seconds that window has been open
+    pub fn busiest_channels(limit: usize) -> (Vec<ChannelThroughput>, u64) {
+        let mut counters = CHANNEL_COUNTERS.lock().unwrap();
+        counters.roll();
+        let mut busiest: Vec<ChannelThroughput> = counters
+            .channels
+            .iter()
+            .map(|(channel_id, (messages, bytes))| ChannelThroughput {
+                channel_id: *channel_id,
+                messages: *messages,
+                bytes: *bytes,
+            })
+            .collect();
+        busiest.sort_by(|a, b| b.messages.cmp(&a.messages).then(b.bytes.cmp(&a.bytes)));
+        busiest.truncate(limit);
+        (busiest, counters.window_start.elapsed().as_secs())
+    }
+
+    /// Run a future and record how long it took under the given action name
+    pub async fn time<F: Future>(action: &'static str, fut: F) -> F::Output {
+        let start = Instant::now();
+        let output = fut.await;
+        Self::record(action, start.elapsed().as_micros() as u64);
+        output
+    }
+
+    /// p50/p95/p99 for every action recorded so far
+    pub fn latency_stats() -> Vec<LatencyStats> {
+        let histograms = HISTOGRAMS.lock().unwrap();
+        let mut stats: Vec<LatencyStats> = histograms
+            .iter()
+            .map(|(action, histogram)| LatencyStats {
+                action: action.to_string(),
+                count: histogram.count(),
+                p50_ms: histogram.percentile(50.0) as f64 / 1000.0,
+                p95_ms: histogram.percentile(95.0) as f64 / 1000.0,
+                p99_ms: histogram.percentile(99.0) as f64 / 1000.0,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.action.cmp(&b.action));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(samples: &[(u64, usize)]) -> LatencyHistogram {
+        let mut histogram = LatencyHistogram::default();
+        for &(micros, times) in samples {
+            for _ in 0..times {
+                histogram.record(micros);
+            }
+        }
+        histogram
+    }
+
+    #[test]
+    fn empty_histogram_reports_zero() {
+        assert_eq!(LatencyHistogram::default().percentile(99.0), 0);
+    }
+
+    #[test]
+    fn percentiles_report_the_bucket_bound_capped_at_the_slowest_sample() {
+        // 90 fast samples, 5 in the 20-50 ms bucket, 5 in the 50-100 ms bucket
+        let histogram = histogram(&[(100, 90), (30_000, 5), (60_000, 5)]);
+        assert_eq!(histogram.count(), 100);
+
+        assert_eq!(histogram.percentile(0.0), 250);
+        assert_eq!(histogram.percentile(50.0), 250);
+        assert_eq!(histogram.percentile(90.0), 250);
+        assert_eq!(histogram.percentile(95.0), 50_000);
+        // The 100 ms bucket bound is above anything recorded
+        assert_eq!(histogram.percentile(99.0), 60_000);
+        assert_eq!(histogram.percentile(100.0), 60_000);
+    }
+
+    #[test]
+    fn samples_past_the_last_bound_report_the_slowest_sample() {
+        let histogram = histogram(&[(25_000_000, 1)]);
+        assert_eq!(histogram.percentile(50.0), 25_000_000);
+    }
+}
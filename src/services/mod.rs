@@ -3,9 +3,37 @@ pub mod chat_service;
 pub mod notification_service;
 pub mod broadcast_service;
 pub mod invite_service;
+pub mod content_filter_service;
+pub mod moderation_service;
+pub mod server_service;
+pub mod metrics_service;
+pub mod maintenance_service;
+pub mod system_message_service;
+pub mod forum_service;
+pub mod rate_limit_service;
+pub mod storage_service;
+pub mod audit_service;
+pub mod settings_service;
+pub mod cursor_service;
+pub mod action_token_service;
+pub mod poll_service;
 
 pub use user_service::UserService;
 pub use chat_service::ChatService;
 pub use notification_service::NotificationService;
 pub use broadcast_service::BroadcastService;
-pub use invite_service::InviteService;
\ No newline at end of file
+pub use invite_service::InviteService;
+pub use content_filter_service::ContentFilterService;
+pub use moderation_service::ModerationService;
+pub use server_service::ServerService;
+pub use metrics_service::MetricsService;
+pub use maintenance_service::MaintenanceService;
+pub use system_message_service::SystemMessageService;
+pub use forum_service::ForumService;
+pub use rate_limit_service::RateLimitService;
+pub use storage_service::StorageService;
+pub use audit_service::AuditService;
+pub use settings_service::SettingsService;
+pub use cursor_service::CursorService;
+pub use action_token_service::ActionTokenService;
+pub use poll_service::PollService;
\ No newline at end of file
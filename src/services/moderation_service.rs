@@ -0,0 +1,794 @@
+use crate::db::{channels, quarantine, servers, users};
+use crate::errors::{Result, ServerError};
+use crate::services::{audit_service, AuditService, BroadcastService, ChatService, NotificationService, RateLimitService};
+use crate::api::connection::{self, PeerMap};
+use nexus_tui_common::{ChannelMessage, FlaggedMessage, ServerMessage, User, UserRole, UserStatus};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Longest reason stored with a ban
+const MAX_BAN_REASON_CHARS: usize = 500;
+
+pub struct ModerationService;
+
+impl ModerationService {
+    /// Check whether an account is still inside the new-account probation window
+    pub async fn is_on_probation(user_id: Uuid) -> Result<bool> {
+        let created_at = users::db_get_user_created_at(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        // Accounts that predate created_at tracking are established
+        let Some(created_at) = created_at else {
+            return Ok(false);
+        };
+
+        let probation_secs = crate::config::settings().moderation.probation_hours * 3600;
+        Ok(crate::util::now_secs() - created_at < probation_secs)
+    }
+
+    /// Whether an account is still in slow start: younger than
+    /// `slow_start_hours` and short of the graduation message count.
+    /// Staff and bots are never in slow start.
+    pub async fn is_in_slow_start(user: &User) -> Result<bool> {
+        if user.role != UserRole::User {
+            return Ok(false);
+        }
+        let config = &crate::config::settings().moderation;
+        if config.slow_start_hours <= 0 {
+            return Ok(false);
+        }
+
+        let created_at = users::db_get_user_created_at(user.id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let Some(created_at) = created_at else {
+            return Ok(false);
+        };
+        if crate::util::now_secs() - created_at >= config.slow_start_hours * 3600 {
+            return Ok(false);
+        }
+
+        let sent = channels::db_count_user_channel_messages(user.id).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok((sent as i64) < config.slow_start_graduation_messages)
+    }
+
+    /// Apply slow start to a message about to be sent: the tighter rate limit,
+    /// and for DMs (`dm_to`) a recipient the sender shares a server with
+    pub async fn enforce_slow_start(user: &User, dm_to: Option<Uuid>, rate_limiter: &RateLimitService) -> Result<()> {
+        if !Self::is_in_slow_start(user).await? {
+            return Ok(());
+        }
+
+        if let Some(to_user_id) = dm_to {
+            let shares_server = servers::db_users_share_server(user.id, to_user_id).await
+                .map_err(|e| ServerError::Database(e))?;
+            if !shares_server {
+                return Err(ServerError::Forbidden(
+                    "New accounts can only message people they share a server with. \
+                     This lifts once your account is a few days old or has been active in channels".to_string()
+                ));
+            }
+        }
+
+        if let Err(retry_after) = rate_limiter.check_new_account_message_rate_limit(user.id) {
+            return Err(ServerError::Forbidden(format!(
+                "New accounts are limited to {} messages per minute; try again in {}s",
+                crate::config::settings().rate_limits.new_account_messages_per_minute, retry_after
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether a user may ban from a server: its owner and mods, or an Admin
+    async fn can_ban(user: &User, server_id: Uuid) -> Result<bool> {
+        if user.role == UserRole::Admin {
+            return Ok(true);
+        }
+        servers::db_is_user_server_mod(user.id, server_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Ban a user from a server for `duration_secs` (None is permanent). A
+    /// member is removed from the server and its channels, and their sessions
+    /// are closed after being told why; a non-member is only kept from
+    /// joining. Server mods can't ban each other; only the
+    /// owner or an Admin can ban a mod, and nobody can ban the owner or an Admin.
+    pub async fn ban_user(
+        actor: &User,
+        server_id: Uuid,
+        user_id: Uuid,
+        reason: String,
+        duration_secs: Option<u64>,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        if !Self::can_ban(actor, server_id).await? {
+            return Err(ServerError::Forbidden("Only the server's owner and moderators can ban".to_string()));
+        }
+        if user_id == actor.id {
+            return Err(ServerError::BadRequest("You can't ban yourself".to_string()));
+        }
+
+        let owner = servers::db_get_server_owner(server_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if user_id == owner {
+            return Err(ServerError::Forbidden("The server owner can't be banned".to_string()));
+        }
+        let target = users::db_get_user_by_id(user_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if target.role == UserRole::Admin {
+            return Err(ServerError::Forbidden("Admins can't be banned".to_string()));
+        }
+        let target_is_mod = servers::db_is_user_server_mod(user_id, server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if target_is_mod && actor.id != owner && actor.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only the server owner can ban a moderator".to_string()));
+        }
+
+        let reason = reason.trim();
+        if reason.chars().count() > MAX_BAN_REASON_CHARS {
+            return Err(ServerError::Validation(format!(
+                "Ban reason is limited to {} characters", MAX_BAN_REASON_CHARS
+            )));
+        }
+        let reason = (!reason.is_empty()).then(|| reason.to_string());
+        let expires_at = duration_secs
+            .map(|secs| crate::util::now_secs().saturating_add(i64::try_from(secs).unwrap_or(i64::MAX)));
+
+        // Anyone can own a server, so a ban on a non-member only keeps them
+        // out; it never reaches their sessions
+        let was_member = servers::db_is_user_in_server(user_id, server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        servers::db_ban_user(server_id, user_id, actor.id, reason.clone(), expires_at).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let mut closed = 0;
+        if was_member {
+            let notice = ServerMessage::BannedFromServer { server_id, reason: reason.clone(), expires_at };
+            closed = connection::close_user_sessions(peer_map, user_id, notice, "banned from server").await;
+
+            let members = servers::db_get_server_member_ids(server_id).await
+                .map_err(|e| ServerError::Database(e))?;
+            BroadcastService::broadcast_to_users(peer_map, &members, &ServerMessage::ServerMemberRemoved { server_id, user_id }).await;
+        }
+
+        let details = format!(
+            "server={} reason={} expires_at={}",
+            server_id,
+            reason.as_deref().unwrap_or("-"),
+            expires_at.map_or("never".to_string(), |at| at.to_string())
+        );
+        AuditService::record(actor, audit_service::USER_BANNED, Some(user_id.to_string()), Some(details)).await;
+        let summary = match (&reason, expires_at) {
+            (Some(reason), Some(_)) => format!("{} temporarily banned {}: {}", actor.username, target.username, reason),
+            (Some(reason), None) => format!("{} banned {}: {}", actor.username, target.username, reason),
+            (None, Some(_)) => format!("{} temporarily banned {}", actor.username, target.username),
+            (None, None) => format!("{} banned {}", actor.username, target.username),
+        };
+        AuditService::mirror_to_server(server_id, actor, audit_service::USER_BANNED, summary, peer_map).await;
+
+        info!("{} banned {} from server {} ({} sessions closed)", actor.username, target.username, server_id, closed);
+        Ok(())
+    }
+
+    /// Lift a ban. Membership isn't restored; the user can be invited again.
+    pub async fn unban_user(actor: &User, server_id: Uuid, user_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        if !Self::can_ban(actor, server_id).await? {
+            return Err(ServerError::Forbidden("Only the server's owner and moderators can unban".to_string()));
+        }
+        if !servers::db_unban_user(server_id, user_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound("User is not banned from this server".to_string()));
+        }
+
+        AuditService::record(actor, audit_service::USER_UNBANNED, Some(user_id.to_string()), Some(format!("server={}", server_id))).await;
+        let summary = format!("{} unbanned {}", actor.username, AuditService::display_name(user_id).await);
+        AuditService::mirror_to_server(server_id, actor, audit_service::USER_UNBANNED, summary, peer_map).await;
+
+        info!("{} unbanned {} from server {}", actor.username, user_id, server_id);
+        Ok(())
+    }
+
+    /// Remove a member from a server and its channels without banning them;
+    /// they can rejoin with a new invite. The same rank rules as bans apply.
+    pub async fn kick_user(actor: &User, server_id: Uuid, user_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        if !Self::can_ban(actor, server_id).await? {
+            return Err(ServerError::Forbidden("Only the server's owner and moderators can kick".to_string()));
+        }
+        if user_id == actor.id {
+            return Err(ServerError::BadRequest("You can't kick yourself".to_string()));
+        }
+
+        let owner = servers::db_get_server_owner(server_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if user_id == owner {
+            return Err(ServerError::Forbidden("The server owner can't be kicked".to_string()));
+        }
+        let target = users::db_get_user_by_id(user_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if target.role == UserRole::Admin {
+            return Err(ServerError::Forbidden("Admins can't be kicked".to_string()));
+        }
+        let target_is_mod = servers::db_is_user_server_mod(user_id, server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if target_is_mod && actor.id != owner && actor.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only the server owner can kick a moderator".to_string()));
+        }
+
+        if !servers::db_remove_user_from_server(server_id, user_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound("User is not a member of this server".to_string()));
+        }
+
+        // Every live session of the kicked user drops the server from its
+        // cached list on the same delta the remaining members get. Offline
+        // clients fetch a fresh list at login, so nothing is queued for them.
+        let removed = ServerMessage::ServerMemberRemoved { server_id, user_id };
+        let mut recipients = servers::db_get_server_member_ids(server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        recipients.push(user_id);
+        BroadcastService::broadcast_to_users(peer_map, &recipients, &removed).await;
+        let server_name = servers::db_get_server_detail(server_id).await
+            .map(|server| server.name)
+            .unwrap_or_else(|_| "a server".to_string());
+        NotificationService::create_kick_notification(user_id, server_id, &server_name, peer_map).await;
+
+        AuditService::record(actor, audit_service::USER_KICKED, Some(user_id.to_string()), Some(format!("server={}", server_id))).await;
+        let summary = format!("{} kicked {}", actor.username, target.username);
+        AuditService::mirror_to_server(server_id, actor, audit_service::USER_KICKED, summary, peer_map).await;
+
+        info!("{} kicked {} from server {}", actor.username, target.username, server_id);
+        Ok(())
+    }
+
+    /// Hold a flagged message back from the channel until a moderator reviews it
+    pub async fn quarantine_message(
+        channel_id: Uuid,
+        user: &User,
+        content: &str,
+        timestamp: i64,
+        reason: &str,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        let quarantine_id = quarantine::db_quarantine_message(
+            channel_id, user.id, content, timestamp, reason
+        ).await.map_err(|e| ServerError::Database(e))?;
+
+        // Put it in front of everyone who can review it
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        let moderator_ids = users::db_get_moderator_ids(server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        for moderator_id in moderator_ids {
+            NotificationService::create_quarantine_notification(
+                moderator_id, quarantine_id, &user.username, peer_map
+            ).await;
+        }
+
+        let message = ServerMessage::Notification(
+            "Your message is pending moderator review".to_string(),
+            false,
+        );
+        BroadcastService::send_to_user(peer_map, user.id, &message).await;
+
+        info!("Quarantined first message from {} in channel {}: {}", user.username, channel_id, reason);
+        Ok(())
+    }
+
+    /// Put a delivered message the content filter flagged in front of
+    /// moderators. The message is already out, so a failure here is only logged.
+    pub async fn flag_message(
+        user: &User,
+        message_id: Uuid,
+        channel_id: Option<Uuid>,
+        dm_to: Option<Uuid>,
+        content: &str,
+        reason: &str,
+        timestamp: i64,
+    ) {
+        match quarantine::db_flag_message(message_id, channel_id, dm_to, user.id, content, reason, timestamp).await {
+            Ok(_) => info!("Flagged message {} from {}: {}", message_id, user.username, reason),
+            Err(e) => error!("Failed to record flagged message {}: {}", message_id, e),
+        }
+    }
+
+    /// List the flagged messages a moderator is allowed to review
+    pub async fn get_flagged_messages(moderator: &User) -> Result<Vec<FlaggedMessage>> {
+        // Global staff see everything including DMs, server mods only their own servers
+        let server_mod_id = match moderator.role {
+            UserRole::Admin | UserRole::Moderator => None,
+            _ => Some(moderator.id),
+        };
+
+        quarantine::db_get_flagged_messages(server_mod_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Clear a flagged message from the review queue. The message itself stays;
+    /// deleting it is a separate action.
+    pub async fn dismiss_flagged_message(moderator: &User, flag_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        let flagged = quarantine::db_get_flagged_message(flag_id).await
+            .map_err(|e| ServerError::Database(e))?
+            .ok_or_else(|| ServerError::NotFound("Flagged message not found".to_string()))?;
+
+        let server_id = match flagged.channel_id {
+            Some(channel_id) => {
+                let server_id = channels::db_get_channel_server_id(channel_id).await
+                    .map_err(|e| ServerError::NotFound(e))?;
+                let moderator_ids = users::db_get_moderator_ids(server_id).await
+                    .map_err(|e| ServerError::Database(e))?;
+                if !moderator_ids.contains(&moderator.id) {
+                    return Err(ServerError::Forbidden("Not a moderator of this server".to_string()));
+                }
+                Some(server_id)
+            }
+            None => {
+                if !matches!(moderator.role, UserRole::Admin | UserRole::Moderator) {
+                    return Err(ServerError::Forbidden("Only global moderators can review direct messages".to_string()));
+                }
+                None
+            }
+        };
+
+        quarantine::db_delete_flagged_message(flag_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+
+        AuditService::record(
+            moderator, audit_service::DISMISS_FLAGGED, Some(flagged.message_id.to_string()), Some(flagged.reason.clone())
+        ).await;
+        if let Some(server_id) = server_id {
+            let summary = format!(
+                "{} cleared a flagged message by {}",
+                moderator.username,
+                AuditService::display_name(flagged.sent_by).await
+            );
+            AuditService::mirror_to_server(server_id, moderator, audit_service::DISMISS_FLAGGED, summary, peer_map).await;
+        }
+        info!("Flagged message {} dismissed by {}", flag_id, moderator.username);
+        Ok(())
+    }
+
+    /// List the quarantined messages a moderator is allowed to review
+    pub async fn get_quarantined_messages(moderator: &User) -> Result<Vec<(ChannelMessage, String)>> {
+        // Global staff see everything, server mods only their own servers
+        let server_mod_id = match moderator.role {
+            UserRole::Admin | UserRole::Moderator => None,
+            _ => Some(moderator.id),
+        };
+
+        quarantine::db_get_quarantined_messages(server_mod_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Deliver an approved quarantined message as its author
+    async fn release_quarantined_message(message: &ChannelMessage, peer_map: &PeerMap) -> Result<()> {
+        let profile = users::db_get_user_by_id(message.sent_by).await
+            .map_err(|e| ServerError::Database(e))?;
+        let author = User {
+            id: profile.id,
+            username: profile.username,
+            color: profile.color,
+            role: profile.role,
+            profile_pic: profile.profile_pic,
+            cover_banner: profile.cover_banner,
+            status: UserStatus::Connected,
+        };
+
+        // Stored under the quarantine id, so a retried release can't post it twice
+        ChatService::deliver_channel_message(
+            message.channel_id, &author, &message.content, None, message.timestamp, Some(message.id), peer_map
+        ).await?;
+        Ok(())
+    }
+
+    /// Approve or reject a quarantined message
+    pub async fn review_quarantined_message(
+        moderator: &User,
+        quarantine_id: Uuid,
+        approve: bool,
+        warning: Option<String>,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        let (message, reason) = quarantine::db_get_quarantined_message(quarantine_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+
+        let server_id = channels::db_get_channel_server_id(message.channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        let moderator_ids = users::db_get_moderator_ids(server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        if !moderator_ids.contains(&moderator.id) {
+            return Err(ServerError::Forbidden("Not a moderator of this server".to_string()));
+        }
+
+        // Removing the entry first means a concurrent review can't release it twice;
+        // if the release then fails the entry goes back for another try
+        quarantine::db_delete_quarantined_message(quarantine_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+
+        if approve {
+            if let Err(e) = Self::release_quarantined_message(&message, peer_map).await {
+                if let Err(restore_error) = quarantine::db_restore_quarantined_message(&message, &reason).await {
+                    warn!("Cannot put quarantined message {} back after a failed release: {}", quarantine_id, restore_error);
+                }
+                return Err(e);
+            }
+        } else if let Some(warning) = warning {
+            NotificationService::create_warning_notification(
+                message.sent_by, quarantine_id, &warning, peer_map
+            ).await;
+        }
+
+        AuditService::record(
+            moderator,
+            audit_service::REVIEW_QUARANTINE,
+            Some(quarantine_id.to_string()),
+            Some(if approve { "approved" } else { "rejected" }.to_string()),
+        ).await;
+        let summary = format!(
+            "{} {} a held message by {}",
+            moderator.username,
+            if approve { "approved" } else { "rejected" },
+            AuditService::display_name(message.sent_by).await
+        );
+        AuditService::mirror_to_server(server_id, moderator, audit_service::REVIEW_QUARANTINE, summary, peer_map).await;
+        info!(
+            "Quarantined message {} {} by {}",
+            quarantine_id,
+            if approve { "approved" } else { "rejected" },
+            moderator.username
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakePeer, TestDb};
+
+    /// A server owned by `alice` with a new account, `bob`, in its one channel
+    async fn channel_with_newcomer() -> (User, User, Uuid) {
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        (alice, bob, channel_id)
+    }
+
+    #[tokio::test]
+    async fn flagged_first_message_is_held_and_delivered_once_approved() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let filter = test_support::content_filter_with(&[], &["cheap pills"]);
+
+        ChatService::send_channel_message(
+            channel_id, &bob, "cheap pills here", None, &filter, &peer_map
+        ).await.unwrap();
+        assert_eq!(db.count_rows("channel_messages"), 0);
+        let held = ModerationService::get_quarantined_messages(&alice).await.unwrap();
+        assert_eq!(held.len(), 1);
+        let quarantine_id = held[0].0.id;
+        alice_peer.drain();
+
+        ModerationService::review_quarantined_message(&alice, quarantine_id, true, None, &peer_map).await.unwrap();
+
+        assert_eq!(quarantine::db_count_quarantined_messages().await.unwrap(), 0);
+        assert_eq!(channels::db_get_channel_message_count(channel_id).await.unwrap(), 1);
+        assert!(alice_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::NewChannelMessage(msg) if msg.sent_by == bob.id && msg.content == "cheap pills here"
+        )));
+    }
+
+    #[tokio::test]
+    async fn rejected_message_is_dropped_without_delivery() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let filter = test_support::content_filter_with(&[], &["cheap pills"]);
+
+        ChatService::send_channel_message(
+            channel_id, &bob, "cheap pills here", None, &filter, &peer_map
+        ).await.unwrap();
+        let quarantine_id = ModerationService::get_quarantined_messages(&alice).await.unwrap()[0].0.id;
+
+        ModerationService::review_quarantined_message(&alice, quarantine_id, false, None, &peer_map).await.unwrap();
+
+        assert_eq!(quarantine::db_count_quarantined_messages().await.unwrap(), 0);
+        assert_eq!(db.count_rows("channel_messages"), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_release_keeps_the_message_in_quarantine() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _bob, channel_id) = channel_with_newcomer().await;
+        // An author that no longer exists can't be delivered as
+        let quarantine_id = quarantine::db_quarantine_message(
+            channel_id, Uuid::new_v4(), "orphaned", crate::util::now_secs(), "test"
+        ).await.unwrap();
+
+        let result = ModerationService::review_quarantined_message(&alice, quarantine_id, true, None, &peer_map).await;
+
+        assert!(result.is_err());
+        assert_eq!(db.count_rows("channel_messages"), 0);
+        let (message, reason) = quarantine::db_get_quarantined_message(quarantine_id).await.unwrap();
+        assert_eq!(message.content, "orphaned");
+        assert_eq!(reason, "test");
+    }
+
+    #[tokio::test]
+    async fn a_held_message_is_released_at_most_once() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let filter = test_support::content_filter_with(&[], &["cheap pills"]);
+        ChatService::send_channel_message(
+            channel_id, &bob, "cheap pills here", None, &filter, &peer_map
+        ).await.unwrap();
+        let (held, reason) = ModerationService::get_quarantined_messages(&alice).await.unwrap().remove(0);
+
+        ModerationService::review_quarantined_message(&alice, held.id, true, None, &peer_map).await.unwrap();
+        // As if a release had stored the message, failed afterwards and put the entry back
+        quarantine::db_restore_quarantined_message(&held, &reason).await.unwrap();
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        ModerationService::review_quarantined_message(&alice, held.id, true, None, &peer_map).await.unwrap();
+
+        assert_eq!(db.count_rows("channel_messages"), 1);
+        assert_eq!(channels::db_get_channel_message(held.id).await.unwrap().0.content, "cheap pills here");
+        assert_eq!(quarantine::db_count_quarantined_messages().await.unwrap(), 0);
+        assert!(!alice_peer.drain().iter().any(|message| matches!(message, ServerMessage::NewChannelMessage(_))));
+    }
+
+    fn default_limiter() -> RateLimitService {
+        RateLimitService::new(&crate::config::RateLimitConfig::default())
+    }
+
+    #[tokio::test]
+    async fn a_fresh_account_gets_the_slow_start_limits() {
+        let _db = TestDb::new().await;
+        let (alice, bob, _) = channel_with_newcomer().await;
+        let stranger = test_support::create_user("stranger").await;
+        let limiter = default_limiter();
+        let per_minute = crate::config::settings().rate_limits.new_account_messages_per_minute;
+
+        let to_stranger = ModerationService::enforce_slow_start(&bob, Some(stranger.id), &limiter).await;
+        assert!(matches!(to_stranger, Err(ServerError::Forbidden(reason)) if reason.contains("share a server")));
+        ModerationService::enforce_slow_start(&bob, Some(alice.id), &limiter).await.unwrap();
+        for _ in 1..per_minute {
+            ModerationService::enforce_slow_start(&bob, None, &limiter).await.unwrap();
+        }
+        let over = ModerationService::enforce_slow_start(&bob, None, &limiter).await;
+        assert!(matches!(over, Err(ServerError::Forbidden(reason)) if reason.contains("New accounts are limited")));
+    }
+
+    #[tokio::test]
+    async fn an_aged_account_is_not_held_back() {
+        let db = TestDb::new().await;
+        let (_, bob, _) = channel_with_newcomer().await;
+        let stranger = test_support::create_user("stranger").await;
+        let limiter = default_limiter();
+        let slow_start_secs = crate::config::settings().moderation.slow_start_hours * 3600;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE users SET created_at = ?1 WHERE id = ?2",
+            rusqlite::params![crate::util::now_secs() - slow_start_secs - 60, bob.id.to_string()],
+        ).unwrap();
+
+        assert!(!ModerationService::is_in_slow_start(&bob).await.unwrap());
+        ModerationService::enforce_slow_start(&bob, Some(stranger.id), &limiter).await.unwrap();
+        for _ in 0..crate::config::settings().rate_limits.new_account_messages_per_minute * 2 {
+            ModerationService::enforce_slow_start(&bob, None, &limiter).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn staff_are_never_in_slow_start() {
+        let _db = TestDb::new().await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        assert!(!ModerationService::is_in_slow_start(&moderator).await.unwrap());
+    }
+
+    /// Whether the peer's disconnect has been handled
+    async fn disconnected(peer_map: &PeerMap, peer: &FakePeer) -> bool {
+        peer_map.lock().await[&peer.peer_id].disconnected.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn a_ban_removes_the_member_and_closes_their_sessions() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ModerationService::ban_user(&alice, server_id, bob.id, "spam".to_string(), None, &peer_map).await.unwrap();
+
+        assert!(bob_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::BannedFromServer { server_id: id, reason, expires_at: None }
+                if *id == server_id && reason.as_deref() == Some("spam")
+        )));
+        assert!(disconnected(&peer_map, &bob_peer).await);
+        assert!(alice_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::ServerMemberRemoved { user_id, .. } if *user_id == bob.id
+        )));
+        assert!(!servers::db_is_user_in_server(bob.id, server_id).await.unwrap());
+        assert!(servers::db_is_user_banned(bob.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_server_staff_can_ban() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+
+        let refused = ModerationService::ban_user(&bob, server_id, alice.id, String::new(), None, &peer_map).await;
+
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+        assert!(!servers::db_is_user_banned(alice.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_banned_user_logs_in_without_the_server() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        ModerationService::ban_user(&alice, server_id, bob.id, String::new(), Some(3600), &peer_map).await.unwrap();
+
+        let user = crate::services::UserService::login("bob", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+
+        assert_eq!(user.id, bob.id);
+        let servers = servers::db_get_user_servers(bob.id).await.unwrap();
+        assert!(servers.iter().all(|server| server.id != server_id));
+        assert!(matches!(
+            ChatService::ensure_can_read_channel(bob.id, channel_id).await,
+            Err(ServerError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn an_unban_allows_a_new_invite_but_restores_nothing() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        ModerationService::ban_user(&alice, server_id, bob.id, String::new(), None, &peer_map).await.unwrap();
+
+        ModerationService::unban_user(&alice, server_id, bob.id, &peer_map).await.unwrap();
+
+        assert!(!servers::db_is_user_banned(bob.id, server_id).await.unwrap());
+        assert!(!servers::db_is_user_in_server(bob.id, server_id).await.unwrap());
+        crate::services::InviteService::send_server_invite(alice.id, bob.id, server_id, &peer_map).await.unwrap();
+        assert!(matches!(
+            ModerationService::unban_user(&alice, server_id, bob.id, &peer_map).await,
+            Err(ServerError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn banning_a_non_member_leaves_their_sessions_alone() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let mallory = test_support::create_user("mallory").await;
+        let carol = test_support::create_user("carol").await;
+        let server_id = test_support::create_server(&mallory, "Mine").await;
+        let mut carol_peer = FakePeer::connect(&peer_map, Some(carol.id)).await;
+
+        ModerationService::ban_user(&mallory, server_id, carol.id, String::new(), None, &peer_map).await.unwrap();
+
+        assert!(carol_peer.drain().is_empty());
+        assert!(!disconnected(&peer_map, &carol_peer).await);
+        // The ban still keeps them out of that server
+        assert!(servers::db_is_user_banned(carol.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_kick_reaches_every_live_session_of_the_kicked_user() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+        let mut bob_laptop = FakePeer::connect(&peer_map, Some(bob.id)).await;
+        let mut bob_phone = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        ModerationService::kick_user(&alice, server_id, bob.id, &peer_map).await.unwrap();
+
+        for peer in [&mut alice_peer, &mut bob_laptop, &mut bob_phone] {
+            let received = peer.drain();
+            assert!(received.iter().any(|message| matches!(
+                message,
+                ServerMessage::ServerMemberRemoved { server_id: id, user_id } if *id == server_id && *user_id == bob.id
+            )));
+            assert!(!received.iter().any(|message| matches!(message, ServerMessage::Servers(_))));
+        }
+        assert!(!disconnected(&peer_map, &bob_laptop).await);
+        assert!(!servers::db_is_user_in_server(bob.id, server_id).await.unwrap());
+        assert_eq!(db.count_rows("pending_deliveries"), 0);
+    }
+
+    #[tokio::test]
+    async fn an_offline_kicked_user_only_gets_a_notification() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+
+        ModerationService::kick_user(&alice, server_id, bob.id, &peer_map).await.unwrap();
+
+        assert_eq!(db.count_rows("pending_deliveries"), 0);
+        let (notifications, _) = crate::db::notifications::db_get_notifications(bob.id, None, 10).await.unwrap();
+        assert_eq!(notifications.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn kicks_are_limited_to_staff_and_members() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let server_id = channels::db_get_channel_server_id(channel_id).await.unwrap();
+        let carol = test_support::create_user("carol").await;
+
+        assert!(matches!(
+            ModerationService::kick_user(&bob, server_id, alice.id, &peer_map).await,
+            Err(ServerError::Forbidden(_))
+        ));
+        assert!(matches!(
+            ModerationService::kick_user(&alice, server_id, carol.id, &peer_map).await,
+            Err(ServerError::NotFound(_))
+        ));
+        assert!(matches!(
+            ModerationService::kick_user(&alice, server_id, alice.id, &peer_map).await,
+            Err(ServerError::BadRequest(_))
+        ));
+        assert!(servers::db_is_user_in_server(bob.id, server_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_flagged_channel_message_is_queued_until_deleted() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_newcomer().await;
+        let filter = test_support::content_filter_with(&[], &["cheap pills"]);
+        ChatService::send_channel_message(channel_id, &bob, "hello", None, &filter, &peer_map).await.unwrap();
+
+        ChatService::send_channel_message(channel_id, &bob, "cheap pills here", None, &filter, &peer_map).await.unwrap();
+
+        assert_eq!(db.count_rows("flagged_messages"), 1);
+        let flagged = ModerationService::get_flagged_messages(&alice).await.unwrap();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].channel_id, Some(channel_id));
+        assert_eq!(flagged[0].sent_by, bob.id);
+
+        ChatService::delete_channel_message(&bob, flagged[0].message_id, &peer_map).await.unwrap();
+        assert_eq!(db.count_rows("flagged_messages"), 0);
+    }
+
+    #[tokio::test]
+    async fn a_server_moderator_cannot_dismiss_a_flagged_direct_message() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, _) = channel_with_newcomer().await;
+        let carol = test_support::create_user("carol").await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        ModerationService::flag_message(
+            &bob, Uuid::new_v4(), None, Some(carol.id), "cheap pills", "flagged word", crate::util::now_secs()
+        ).await;
+
+        // Alice only moderates one server; DMs are left to global staff
+        assert!(ModerationService::get_flagged_messages(&alice).await.unwrap().is_empty());
+        let flag_id = ModerationService::get_flagged_messages(&moderator).await.unwrap()[0].id;
+        assert!(matches!(
+            ModerationService::dismiss_flagged_message(&alice, flag_id, &peer_map).await,
+            Err(ServerError::Forbidden(_))
+        ));
+        assert_eq!(db.count_rows("flagged_messages"), 1);
+
+        ModerationService::dismiss_flagged_message(&moderator, flag_id, &peer_map).await.unwrap();
+        assert_eq!(db.count_rows("flagged_messages"), 0);
+    }
+}
@@ -6,6 +6,9 @@ use nexus_tui_common::{Notification, ServerMessage};
 use tracing::{error, info};
 use uuid::Uuid;
 
+/// Most notifications one MarkNotificationsRead request may touch
+const MAX_MARK_READ_BATCH: usize = 500;
+
 pub struct NotificationService;
 
 impl NotificationService {
@@ -59,6 +62,53 @@ impl NotificationService {
         info!("Mention notification created for user {}", user_id);
     }
 
+    /// Notify a channel follower of a new message
+    pub async fn create_channel_message_notification(
+        user_id: Uuid,
+        channel_id: Uuid,
+        from_username: &str,
+        peer_map: &PeerMap,
+    ) {
+        let extra = format!("New message from: {}", from_username);
+
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "ChannelMessage",
+            channel_id,
+            Some(extra),
+        ).await {
+            error!("Failed to create channel message notification: {}", e);
+            return;
+        }
+
+        // Push notification if user is online
+        Self::push_notifications_if_online(peer_map, user_id).await;
+    }
+
+    /// Offer a new channel to a server member who doesn't auto-join new channels.
+    /// The client answers with JoinChannel for the related channel.
+    pub async fn create_new_channel_notification(
+        user_id: Uuid,
+        channel_id: Uuid,
+        channel_name: &str,
+        peer_map: &PeerMap,
+    ) {
+        let extra = format!("New channel #{} was created — join?", channel_name);
+
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "NewChannel",
+            channel_id,
+            Some(extra),
+        ).await {
+            error!("Failed to create new channel notification: {}", e);
+            return;
+        }
+
+        // Push notification if user is online
+        Self::push_notifications_if_online(peer_map, user_id).await;
+    }
+
     /// Create a thread reply notification
     pub async fn create_thread_reply_notification(
         user_id: Uuid,
@@ -100,12 +150,120 @@ impl NotificationService {
         info!("Thread reply notification created for user {}", user_id);
     }
 
+    /// Notify a moderator that a message is waiting in quarantine
+    pub async fn create_quarantine_notification(
+        user_id: Uuid,
+        quarantine_id: Uuid,
+        from_username: &str,
+        peer_map: &PeerMap,
+    ) {
+        let extra = format!("Held message from: {}", from_username);
+
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "Quarantine",
+            quarantine_id,
+            Some(extra),
+        ).await {
+            error!("Failed to create quarantine notification: {}", e);
+            return;
+        }
+
+        Self::push_notifications_if_online(peer_map, user_id).await;
+
+        info!("Quarantine notification created for moderator {}", user_id);
+    }
+
+    /// Create a moderator warning notification
+    pub async fn create_warning_notification(
+        user_id: Uuid,
+        related_id: Uuid,
+        warning: &str,
+        peer_map: &PeerMap,
+    ) {
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "Warning",
+            related_id,
+            Some(warning.to_string()),
+        ).await {
+            error!("Failed to create warning notification: {}", e);
+            return;
+        }
+
+        Self::push_notifications_if_online(peer_map, user_id).await;
+
+        info!("Warning notification created for user {}", user_id);
+    }
+
+    /// Tell a user they were removed from a server
+    pub async fn create_kick_notification(
+        user_id: Uuid,
+        server_id: Uuid,
+        server_name: &str,
+        peer_map: &PeerMap,
+    ) {
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "Kicked",
+            server_id,
+            Some(format!("You were removed from {}", server_name)),
+        ).await {
+            error!("Failed to create kick notification: {}", e);
+            return;
+        }
+
+        Self::push_notifications_if_online(peer_map, user_id).await;
+
+        info!("Kick notification created for user {}", user_id);
+    }
+
+    /// Tell a user they've gone over their storage soft quota
+    pub async fn create_storage_quota_notification(
+        user_id: Uuid,
+        warning: &str,
+        peer_map: &PeerMap,
+    ) {
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "StorageQuota",
+            user_id,
+            Some(warning.to_string()),
+        ).await {
+            error!("Failed to create storage quota notification: {}", e);
+            return;
+        }
+
+        Self::push_notifications_if_online(peer_map, user_id).await;
+
+        info!("Storage quota notification created for user {}", user_id);
+    }
+
+    /// Weekly count of profile views. Low priority: stored for the user's next
+    /// notification fetch rather than pushed.
+    pub async fn create_profile_view_digest_notification(user_id: Uuid, views: i64) {
+        let summary = if views == 1 {
+            "1 view of your profile this week".to_string()
+        } else {
+            format!("{} views of your profile this week", views)
+        };
+        if let Err(e) = notifications::db_insert_notification(
+            user_id,
+            "ProfileViews",
+            user_id,
+            Some(summary),
+        ).await {
+            error!("Failed to create profile view digest for user {}: {}", user_id, e);
+        }
+    }
+
     /// Get user notifications with pagination
     pub async fn get_notifications(
         user_id: Uuid,
         before: Option<i64>,
     ) -> Result<(Vec<Notification>, bool)> {
-        notifications::db_get_notifications(user_id, before).await
+        let limit = crate::config::settings().pagination.notifications.resolve(None);
+        notifications::db_get_notifications(user_id, before, limit).await
             .map_err(|e| ServerError::Database(e))
     }
 
@@ -118,11 +276,25 @@ impl NotificationService {
         Ok(())
     }
 
+    /// Mark a batch of the user's own notifications as read
+    pub async fn mark_notifications_read(user_id: Uuid, ids: &[Uuid]) -> Result<usize> {
+        if ids.len() > MAX_MARK_READ_BATCH {
+            return Err(ServerError::Validation(format!(
+                "Can mark at most {} notifications read at once", MAX_MARK_READ_BATCH
+            )));
+        }
+        let updated = notifications::db_mark_notifications_read(ids, user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("{} notifications marked as read for {}", updated, user_id);
+        Ok(updated)
+    }
+
     /// Push notifications to user if they're online
     async fn push_notifications_if_online(peer_map: &PeerMap, user_id: Uuid) {
         if BroadcastService::is_user_online(peer_map, user_id).await {
             if let Ok((notifications, history_complete)) = 
-                notifications::db_get_notifications(user_id, None).await 
+                notifications::db_get_notifications(user_id, None, crate::config::settings().pagination.notifications.resolve(None)).await 
             {
                 let message = ServerMessage::Notifications { 
                     notifications, 
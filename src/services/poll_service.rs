@@ -0,0 +1,473 @@
+use crate::api::connection::PeerMap;
+use crate::db::polls::{self, PollVote};
+use crate::db::{channels, users};
+use crate::errors::{Result, ServerError};
+use crate::services::content_filter_service::{ContentFilterService, FilterResult};
+use crate::services::system_message_service::SystemEvent;
+use crate::services::{audit_service, AuditService, BroadcastService, ModerationService, SystemMessageService};
+use nexus_tui_common::{Poll, PollResults, ServerMessage, User};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Fewest and most options a poll may offer
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 10;
+/// Longest question and option, in characters
+const MAX_POLL_QUESTION_CHARS: usize = 300;
+const MAX_POLL_OPTION_CHARS: usize = 100;
+/// Longest a poll may stay open
+const MAX_POLL_DURATION_SECS: u64 = 30 * 24 * 3600;
+/// Result broadcasts for one poll are sent at most this often while votes come in
+const RESULTS_BROADCAST_INTERVAL: Duration = Duration::from_secs(5);
+
+struct ResultsThrottle {
+    last_sent: Instant,
+    /// A delayed broadcast is already scheduled and will carry the latest tallies
+    pending: bool,
+}
+
+static RESULTS_THROTTLE: Lazy<Mutex<HashMap<Uuid, ResultsThrottle>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// What a new vote means for the poll's result broadcasts
+#[derive(Debug, PartialEq, Eq)]
+enum ResultsBroadcast {
+    Now,
+    After(Duration),
+    /// A broadcast is already scheduled and will pick up this vote
+    AlreadyScheduled,
+}
+
+/// Decide when the results of `poll_id` go out after a vote at `now`. Entries
+/// whose last broadcast is older than the interval and that have nothing
+/// pending are dropped, so the map only holds polls with recent votes.
+fn schedule_results(throttle: &mut HashMap<Uuid, ResultsThrottle>, poll_id: Uuid, now: Instant) -> ResultsBroadcast {
+    throttle.retain(|_, entry| entry.pending || now.duration_since(entry.last_sent) < RESULTS_BROADCAST_INTERVAL);
+    match throttle.get_mut(&poll_id) {
+        Some(entry) if entry.pending => ResultsBroadcast::AlreadyScheduled,
+        Some(entry) => {
+            entry.pending = true;
+            ResultsBroadcast::After(RESULTS_BROADCAST_INTERVAL - now.duration_since(entry.last_sent))
+        }
+        None => {
+            throttle.insert(poll_id, ResultsThrottle { last_sent: now, pending: false });
+            ResultsBroadcast::Now
+        }
+    }
+}
+
+pub struct PollService;
+
+impl PollService {
+    /// Start a poll in a channel the author can write to. It is announced
+    /// with a system message carrying the poll id and a PollCreated broadcast.
+    pub async fn create_poll(
+        author: &User,
+        channel_id: Uuid,
+        question: &str,
+        options: Vec<String>,
+        multi_choice: bool,
+        duration_secs: Option<u64>,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<Poll> {
+        if !channels::db_can_user_write_channel(author.id, channel_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("You don't have permission to write in this channel".to_string()));
+        }
+
+        let question = question.trim();
+        if question.is_empty() || question.chars().count() > MAX_POLL_QUESTION_CHARS {
+            return Err(ServerError::Validation(format!(
+                "Poll question must be 1 to {} characters", MAX_POLL_QUESTION_CHARS
+            )));
+        }
+        let options: Vec<String> = options.iter().map(|option| option.trim().to_string()).collect();
+        if options.len() < MIN_POLL_OPTIONS || options.len() > MAX_POLL_OPTIONS {
+            return Err(ServerError::Validation(format!(
+                "A poll needs {} to {} options", MIN_POLL_OPTIONS, MAX_POLL_OPTIONS
+            )));
+        }
+        if options.iter().any(|option| option.is_empty() || option.chars().count() > MAX_POLL_OPTION_CHARS) {
+            return Err(ServerError::Validation(format!(
+                "Poll options must be 1 to {} characters", MAX_POLL_OPTION_CHARS
+            )));
+        }
+        let mut flagged = None;
+        for text in std::iter::once(question).chain(options.iter().map(String::as_str)) {
+            match content_filter.filter_message(text) {
+                FilterResult::Allowed => {}
+                FilterResult::Blocked(reason) => return Err(ServerError::Validation(reason)),
+                FilterResult::Flagged(reason) => {
+                    flagged.get_or_insert(reason);
+                }
+            }
+        }
+        let closes_at = match duration_secs {
+            Some(0) => return Err(ServerError::Validation("Poll duration must be positive".to_string())),
+            Some(secs) if secs > MAX_POLL_DURATION_SECS => {
+                return Err(ServerError::Validation("Polls can stay open for at most 30 days".to_string()));
+            }
+            Some(secs) => Some(crate::util::now_secs() + secs as i64),
+            None => None,
+        };
+
+        // What a moderator reviewing a flagged poll gets to read
+        let flagged = flagged.map(|reason| (reason, format!("{}\n{}", question, options.join("\n"))));
+        let poll = polls::db_create_poll(channel_id, author.id, question, options, multi_choice, closes_at).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if let Some((reason, text)) = flagged {
+            ModerationService::flag_message(author, poll.id, Some(channel_id), None, &text, &reason, poll.created_at).await;
+            AuditService::record(
+                author, audit_service::MESSAGE_MODERATED, Some(channel_id.to_string()), Some(reason)
+            ).await;
+        }
+
+        let event = SystemEvent::PollCreated {
+            poll_id: poll.id,
+            author_id: author.id,
+            username: author.username.clone(),
+            question: poll.question.clone(),
+        };
+        SystemMessageService::post(channel_id, &event, peer_map).await?;
+
+        let member_ids = Self::channel_member_ids(channel_id).await?;
+        BroadcastService::broadcast_to_channel_users(peer_map, &member_ids, &ServerMessage::PollCreated(poll.clone())).await;
+
+        info!("{} started poll {} in channel {}", author.username, poll.id, channel_id);
+        Ok(poll)
+    }
+
+    /// Cast or change a vote (channel members only, until the poll closes).
+    /// An empty selection retracts the user's vote.
+    pub async fn vote(user: &User, poll_id: Uuid, option_indices: Vec<usize>, peer_map: &PeerMap) -> Result<()> {
+        let poll = Self::get_poll(poll_id).await?;
+
+        let mut option_indices = option_indices;
+        option_indices.sort_unstable();
+        option_indices.dedup();
+        if option_indices.iter().any(|index| *index >= poll.options.len()) {
+            return Err(ServerError::Validation("No such poll option".to_string()));
+        }
+        if !poll.multi_choice && option_indices.len() > 1 {
+            return Err(ServerError::Validation("This poll allows a single choice".to_string()));
+        }
+
+        match polls::db_vote_poll(poll_id, user.id, option_indices).await.map_err(|e| ServerError::Database(e))? {
+            PollVote::Recorded => {}
+            PollVote::NotFound => return Err(ServerError::NotFound("Poll not found".to_string())),
+            PollVote::Closed => return Err(ServerError::Validation("This poll is closed".to_string())),
+            PollVote::NotMember => {
+                return Err(ServerError::Forbidden("Only members of the channel can vote".to_string()));
+            }
+        }
+
+        Self::queue_results_broadcast(poll_id, peer_map).await;
+        Ok(())
+    }
+
+    /// Close a poll early (its author, or a moderator of the channel's server)
+    pub async fn close_poll(user: &User, poll_id: Uuid, peer_map: &PeerMap) -> Result<()> {
+        let poll = Self::get_poll(poll_id).await?;
+        if poll.author_id != user.id {
+            let server_id = channels::db_get_channel_server_id(poll.channel_id).await
+                .map_err(|e| ServerError::NotFound(e))?;
+            let moderator_ids = users::db_get_moderator_ids(server_id).await
+                .map_err(|e| ServerError::Database(e))?;
+            if !moderator_ids.contains(&user.id) {
+                return Err(ServerError::Forbidden("Only the poll's author or a moderator can close it".to_string()));
+            }
+        }
+        if !polls::db_close_poll(poll_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Validation("This poll is already closed".to_string()));
+        }
+
+        Self::broadcast_final_results(poll_id, peer_map).await;
+        info!("Poll {} closed by {}", poll_id, user.username);
+        Ok(())
+    }
+
+    /// Current tallies (channel members only)
+    pub async fn get_results(user: &User, poll_id: Uuid) -> Result<PollResults> {
+        let results = polls::db_get_poll_results(poll_id).await
+            .map_err(|e| ServerError::Database(e))?
+            .ok_or_else(|| ServerError::NotFound("Poll not found".to_string()))?;
+        if !Self::channel_member_ids(results.channel_id).await?.contains(&user.id) {
+            return Err(ServerError::Forbidden("Only members of the channel can see this poll".to_string()));
+        }
+        Ok(results)
+    }
+
+    /// Close polls whose time is up; run by the maintenance scheduler
+    pub async fn close_expired_polls(peer_map: &PeerMap) -> Result<usize> {
+        let expired = polls::db_get_expired_poll_ids(crate::util::now_secs()).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let mut closed = 0;
+        for poll_id in expired {
+            if polls::db_close_poll(poll_id).await.map_err(|e| ServerError::Database(e))? {
+                Self::broadcast_final_results(poll_id, peer_map).await;
+                closed += 1;
+            }
+        }
+        if closed > 0 {
+            info!("Closed {} expired polls", closed);
+        }
+        Ok(closed)
+    }
+
+    async fn get_poll(poll_id: Uuid) -> Result<Poll> {
+        polls::db_get_poll(poll_id).await
+            .map_err(|e| ServerError::Database(e))?
+            .ok_or_else(|| ServerError::NotFound("Poll not found".to_string()))
+    }
+
+    async fn channel_member_ids(channel_id: Uuid) -> Result<Vec<Uuid>> {
+        let members = channels::db_get_channel_user_list(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(members.iter().map(|u| u.id).collect())
+    }
+
+    /// Broadcast the tallies now if the poll hasn't had a broadcast in the last
+    /// interval, otherwise once the interval is up. Votes arriving in between
+    /// ride along with the scheduled broadcast.
+    async fn queue_results_broadcast(poll_id: Uuid, peer_map: &PeerMap) {
+        let when = schedule_results(&mut RESULTS_THROTTLE.lock().unwrap(), poll_id, Instant::now());
+
+        match when {
+            ResultsBroadcast::AlreadyScheduled => {}
+            ResultsBroadcast::Now => Self::broadcast_results(poll_id, peer_map).await,
+            ResultsBroadcast::After(delay) => {
+                let peer_map = peer_map.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    {
+                        let mut throttle = RESULTS_THROTTLE.lock().unwrap();
+                        match throttle.get_mut(&poll_id) {
+                            Some(entry) => {
+                                entry.pending = false;
+                                entry.last_sent = Instant::now();
+                            }
+                            // Closed meanwhile; the final results already went out
+                            None => return,
+                        }
+                    }
+                    Self::broadcast_results(poll_id, &peer_map).await;
+                });
+            }
+        }
+    }
+
+    /// Send the closing tallies right away and stop throttling the poll
+    async fn broadcast_final_results(poll_id: Uuid, peer_map: &PeerMap) {
+        RESULTS_THROTTLE.lock().unwrap().remove(&poll_id);
+        Self::broadcast_results(poll_id, peer_map).await;
+    }
+
+    async fn broadcast_results(poll_id: Uuid, peer_map: &PeerMap) {
+        let results = match polls::db_get_poll_results(poll_id).await {
+            Ok(Some(results)) => results,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to load results of poll {}: {}", poll_id, e);
+                return;
+            }
+        };
+        match Self::channel_member_ids(results.channel_id).await {
+            Ok(member_ids) => {
+                BroadcastService::broadcast_to_channel_users(peer_map, &member_ids, &ServerMessage::PollResults(results)).await;
+            }
+            Err(e) => error!("Failed to broadcast results of poll {}: {}", poll_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, FakePeer, TestDb};
+
+    /// A channel owned by `alice` with `bob` as the other member
+    async fn channel_with_bob() -> (User, User, Uuid) {
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        (alice, bob, channel_id)
+    }
+
+    async fn start_poll(author: &User, channel_id: Uuid, multi_choice: bool, peer_map: &PeerMap) -> Poll {
+        let options = vec!["tea".to_string(), "coffee".to_string(), "water".to_string()];
+        PollService::create_poll(
+            author, channel_id, "Drinks?", options, multi_choice, Some(3600), &test_support::content_filter(), peer_map
+        ).await.unwrap()
+    }
+
+    fn result_broadcasts(peer: &mut FakePeer) -> usize {
+        peer.drain().iter().filter(|message| matches!(message, ServerMessage::PollResults(_))).count()
+    }
+
+    #[tokio::test]
+    async fn only_channel_members_can_vote() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = channel_with_bob().await;
+        let outsider = test_support::create_user("outsider").await;
+        let poll = start_poll(&alice, channel_id, false, &peer_map).await;
+
+        let result = PollService::vote(&outsider, poll.id, vec![0], &peer_map).await;
+
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+        assert_eq!(PollService::get_results(&alice, poll.id).await.unwrap().voters, 0);
+    }
+
+    #[tokio::test]
+    async fn a_vote_can_be_changed_and_retracted() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_bob().await;
+        let poll = start_poll(&alice, channel_id, true, &peer_map).await;
+
+        PollService::vote(&bob, poll.id, vec![0, 2], &peer_map).await.unwrap();
+        assert_eq!(PollService::get_results(&bob, poll.id).await.unwrap().counts, vec![1, 0, 1]);
+
+        PollService::vote(&bob, poll.id, vec![1], &peer_map).await.unwrap();
+        let results = PollService::get_results(&bob, poll.id).await.unwrap();
+        assert_eq!(results.counts, vec![0, 1, 0]);
+        assert_eq!(results.voters, 1);
+
+        PollService::vote(&bob, poll.id, vec![], &peer_map).await.unwrap();
+        let results = PollService::get_results(&bob, poll.id).await.unwrap();
+        assert_eq!(results.counts, vec![0, 0, 0]);
+        assert_eq!(results.voters, 0);
+    }
+
+    #[tokio::test]
+    async fn a_single_choice_poll_takes_one_option() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_bob().await;
+        let poll = start_poll(&alice, channel_id, false, &peer_map).await;
+
+        let result = PollService::vote(&bob, poll.id, vec![0, 1], &peer_map).await;
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+        // Repeating the same option is still one choice
+        PollService::vote(&bob, poll.id, vec![1, 1], &peer_map).await.unwrap();
+        assert_eq!(PollService::get_results(&bob, poll.id).await.unwrap().counts, vec![0, 1, 0]);
+        let result = PollService::vote(&bob, poll.id, vec![3], &peer_map).await;
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn a_closed_poll_refuses_votes() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_bob().await;
+        let poll = start_poll(&alice, channel_id, false, &peer_map).await;
+        PollService::vote(&bob, poll.id, vec![0], &peer_map).await.unwrap();
+
+        // Only the author or a moderator may close it
+        assert!(matches!(PollService::close_poll(&bob, poll.id, &peer_map).await, Err(ServerError::Forbidden(_))));
+        PollService::close_poll(&alice, poll.id, &peer_map).await.unwrap();
+
+        let result = PollService::vote(&bob, poll.id, vec![1], &peer_map).await;
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+        let results = PollService::get_results(&bob, poll.id).await.unwrap();
+        assert!(results.closed);
+        assert_eq!(results.counts, vec![1, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn the_scheduler_closes_expired_polls_once() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_bob().await;
+        let poll = start_poll(&alice, channel_id, false, &peer_map).await;
+        let open = start_poll(&alice, channel_id, false, &peer_map).await;
+        rusqlite::Connection::open(db.path()).unwrap().execute(
+            "UPDATE polls SET closes_at = ?1 WHERE id = ?2",
+            rusqlite::params![crate::util::now_secs() - 1, poll.id.to_string()],
+        ).unwrap();
+        let mut bob_peer = FakePeer::connect(&peer_map, Some(bob.id)).await;
+
+        assert_eq!(PollService::close_expired_polls(&peer_map).await.unwrap(), 1);
+
+        assert!(bob_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::PollResults(results) if results.poll_id == poll.id && results.closed
+        )));
+        assert!(PollService::get_results(&bob, poll.id).await.unwrap().closed);
+        assert!(!PollService::get_results(&bob, open.id).await.unwrap().closed);
+        assert_eq!(PollService::close_expired_polls(&peer_map).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn results_go_out_at_most_once_per_interval() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, bob, channel_id) = channel_with_bob().await;
+        let poll = start_poll(&alice, channel_id, false, &peer_map).await;
+        let mut alice_peer = FakePeer::connect(&peer_map, Some(alice.id)).await;
+
+        PollService::vote(&bob, poll.id, vec![0], &peer_map).await.unwrap();
+        PollService::vote(&bob, poll.id, vec![1], &peer_map).await.unwrap();
+        PollService::vote(&alice, poll.id, vec![2], &peer_map).await.unwrap();
+
+        // The first vote goes out at once, the others wait for the interval
+        assert_eq!(result_broadcasts(&mut alice_peer), 1);
+        assert!(RESULTS_THROTTLE.lock().unwrap().get(&poll.id).unwrap().pending);
+    }
+
+    #[test]
+    fn the_throttle_delays_then_batches_then_forgets() {
+        let mut throttle = HashMap::new();
+        let (poll, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+
+        assert_eq!(schedule_results(&mut throttle, poll, start), ResultsBroadcast::Now);
+        assert_eq!(
+            schedule_results(&mut throttle, poll, start + Duration::from_secs(2)),
+            ResultsBroadcast::After(Duration::from_secs(3))
+        );
+        assert_eq!(
+            schedule_results(&mut throttle, poll, start + Duration::from_secs(3)),
+            ResultsBroadcast::AlreadyScheduled
+        );
+
+        // The delayed broadcast went out at the five second mark
+        let entry = throttle.get_mut(&poll).unwrap();
+        entry.pending = false;
+        entry.last_sent = start + RESULTS_BROADCAST_INTERVAL;
+
+        // A vote on another poll long after prunes the idle entry
+        let later = start + RESULTS_BROADCAST_INTERVAL * 3;
+        assert_eq!(schedule_results(&mut throttle, other, later), ResultsBroadcast::Now);
+        assert!(!throttle.contains_key(&poll));
+        assert_eq!(throttle.len(), 1);
+        assert_eq!(schedule_results(&mut throttle, poll, later), ResultsBroadcast::Now);
+    }
+
+    #[tokio::test]
+    async fn a_flagged_poll_is_recorded_for_review() {
+        let db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let (alice, _, channel_id) = channel_with_bob().await;
+        let filter = test_support::content_filter_with(&[], &["cheap"]);
+        let options = vec!["yes".to_string(), "cheap pills".to_string()];
+
+        let poll = PollService::create_poll(
+            &alice, channel_id, "Buy now?", options, false, None, &filter, &peer_map
+        ).await.unwrap();
+
+        assert_eq!(db.count_rows("flagged_messages"), 1);
+        let (message_id, content): (String, String) = rusqlite::Connection::open(db.path()).unwrap().query_row(
+            "SELECT message_id, content FROM flagged_messages", [], |row| Ok((row.get(0)?, row.get(1)?))
+        ).unwrap();
+        assert_eq!(message_id, poll.id.to_string());
+        assert_eq!(content, "Buy now?\nyes\ncheap pills");
+    }
+}
@@ -0,0 +1,365 @@
+use crate::config::RateLimitConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Length of the fixed window the per-minute limits are counted over
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How far in the future a saved window may start before it's treated as
+/// bogus; small wall-clock corrections between shutdown and startup are fine
+const CLOCK_DRIFT_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// How often ended windows are swept out of every limiter
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Requests counted in the current window for one user or address
+#[derive(Debug, Clone)]
+pub struct RateWindow {
+    window_start: Instant,
+    count: u32,
+}
+
+/// Snapshot of rate limiter activity since startup
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitStats {
+    pub uploads_allowed: u64,
+    pub uploads_throttled: u64,
+    /// Users with an upload window currently open
+    pub upload_users_tracked: usize,
+}
+
+pub struct RateLimitService {
+    uploads_per_minute: u32,
+    server_info_per_minute: u32,
+    messages_per_minute: u32,
+    new_account_messages_per_minute: u32,
+    file_upload_limits: Mutex<HashMap<Uuid, RateWindow>>,
+    server_info_limits: Mutex<HashMap<IpAddr, RateWindow>>,
+    message_limits: Mutex<HashMap<Uuid, RateWindow>>,
+    new_account_message_limits: Mutex<HashMap<Uuid, RateWindow>>,
+    stats: Mutex<RateLimitStats>,
+}
+
+impl RateLimitService {
+    /// Build the limiter from the rate limit config
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            uploads_per_minute: config.uploads_per_minute,
+            server_info_per_minute: config.server_info_per_minute,
+            messages_per_minute: config.messages_per_minute,
+            new_account_messages_per_minute: config.new_account_messages_per_minute,
+            file_upload_limits: Mutex::new(HashMap::new()),
+            server_info_limits: Mutex::new(HashMap::new()),
+            message_limits: Mutex::new(HashMap::new()),
+            new_account_message_limits: Mutex::new(HashMap::new()),
+            stats: Mutex::new(RateLimitStats::default()),
+        }
+    }
+
+    /// Record an upload attempt, returning the seconds until the window resets if the user is over the limit
+    pub fn check_file_upload_rate_limit(&self, user_id: Uuid) -> Result<(), u64> {
+        self.check_file_uploads_rate_limit(user_id, 1)
+    }
+
+    /// Record `uploads` uploads made together: they are counted only if they
+    /// all fit in the user's window, so a refused batch costs nothing
+    pub fn check_file_uploads_rate_limit(&self, user_id: Uuid, uploads: u32) -> Result<(), u64> {
+        let result = check_window_for(&self.file_upload_limits, user_id, self.uploads_per_minute, uploads);
+
+        let mut stats = self.stats.lock().unwrap();
+        match result {
+            Ok(()) => stats.uploads_allowed += uploads as u64,
+            Err(_) => stats.uploads_throttled += uploads as u64,
+        }
+        result
+    }
+
+    /// Record a pre-auth GetServerInfo from an address, returning the seconds until the window resets if it is over the limit
+    pub fn check_server_info_rate_limit(&self, ip: IpAddr) -> Result<(), u64> {
+        check_window(&self.server_info_limits, ip, self.server_info_per_minute)
+    }
+
+    /// Record a channel or direct message from a user, returning the seconds until the window resets if they are over the limit
+    pub fn check_message_rate_limit(&self, user_id: Uuid) -> Result<(), u64> {
+        check_window(&self.message_limits, user_id, self.messages_per_minute)
+    }
+
+    /// Record a message from an account in slow start, returning the seconds until the window resets if it is over the limit
+    pub fn check_new_account_message_rate_limit(&self, user_id: Uuid) -> Result<(), u64> {
+        check_window(&self.new_account_message_limits, user_id, self.new_account_messages_per_minute)
+    }
+
+    /// Write the still-open windows to `path` so a restart doesn't reset them.
+    /// Instants only mean something inside this process, so windows are saved
+    /// with wall-clock start times.
+    pub fn save_state(&self, path: &Path) -> std::io::Result<usize> {
+        let state = SavedState {
+            uploads: save_windows(&self.file_upload_limits),
+            server_info: save_windows(&self.server_info_limits),
+            messages: save_windows(&self.message_limits),
+            new_account_messages: save_windows(&self.new_account_message_limits),
+        };
+        let saved = state.uploads.len() + state.server_info.len() + state.messages.len() + state.new_account_messages.len();
+
+        let json = serde_json::to_vec(&state).map_err(std::io::Error::other)?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(saved)
+    }
+
+    /// Restore windows saved by `save_state`, dropping ones that have ended
+    /// in the meantime. A missing or unreadable file just means starting fresh.
+    pub fn load_state(&self, path: &Path) {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                info!("No rate limit state loaded from {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let state: SavedState = match serde_json::from_slice(&contents) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Ignoring corrupt rate limit state in {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        let restored = restore_windows(&self.file_upload_limits, state.uploads)
+            + restore_windows(&self.server_info_limits, state.server_info)
+            + restore_windows(&self.message_limits, state.messages)
+            + restore_windows(&self.new_account_message_limits, state.new_account_messages);
+        info!("Restored {} rate limit windows from {}", restored, path.display());
+    }
+
+    /// Drop windows that have ended. A window is otherwise only swept when
+    /// its own limiter is next hit, so a quiet limiter would keep every user
+    /// who ever touched it. Returns the number of windows removed.
+    pub fn cleanup_old_entries(&self) -> usize {
+        prune_windows(&self.file_upload_limits)
+            + prune_windows(&self.server_info_limits)
+            + prune_windows(&self.message_limits)
+            + prune_windows(&self.new_account_message_limits)
+    }
+
+    /// Current rate limiter counters
+    pub fn stats(&self) -> RateLimitStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.upload_users_tracked = self.file_upload_limits.lock().unwrap().len();
+        stats
+    }
+}
+
+/// Count one request for `key` in its fixed window, dropping windows that have ended
+fn check_window<K: Eq + Hash>(limits: &Mutex<HashMap<K, RateWindow>>, key: K, max_per_window: u32) -> Result<(), u64> {
+    check_window_for(limits, key, max_per_window, 1)
+}
+
+/// Count `requests` requests for `key` at once, or none of them if they don't all fit
+fn check_window_for<K: Eq + Hash>(limits: &Mutex<HashMap<K, RateWindow>>, key: K, max_per_window: u32, requests: u32) -> Result<(), u64> {
+    let now = Instant::now();
+    let mut limits = limits.lock().unwrap();
+    limits.retain(|_, limit| now.duration_since(limit.window_start) < RATE_LIMIT_WINDOW);
+
+    let limit = limits.entry(key).or_insert(RateWindow {
+        window_start: now,
+        count: 0,
+    });
+
+    if limit.count.saturating_add(requests) > max_per_window {
+        let elapsed = now.duration_since(limit.window_start);
+        return Err(RATE_LIMIT_WINDOW.saturating_sub(elapsed).as_secs().max(1));
+    }
+
+    limit.count += requests;
+    Ok(())
+}
+
+fn prune_windows<K: Eq + Hash>(limits: &Mutex<HashMap<K, RateWindow>>) -> usize {
+    let now = Instant::now();
+    let mut limits = limits.lock().unwrap();
+    let before = limits.len();
+    limits.retain(|_, limit| now.duration_since(limit.window_start) < RATE_LIMIT_WINDOW);
+    before - limits.len()
+}
+
+/// Where the rate limit state lives: `state_file`, relative to the database's directory
+pub fn state_file_path(config: &RateLimitConfig) -> PathBuf {
+    let state_file = PathBuf::from(&config.state_file);
+    if state_file.is_absolute() {
+        return state_file;
+    }
+    let db_path = PathBuf::from(crate::db::db_config::get_db_path());
+    db_path.parent().unwrap_or(Path::new("")).join(state_file)
+}
+
+/// Rate limit windows as written to disk on shutdown
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedState {
+    uploads: Vec<SavedWindow>,
+    server_info: Vec<SavedWindow>,
+    /// Missing from state files written before the general message limit existed
+    #[serde(default)]
+    messages: Vec<SavedWindow>,
+    /// Missing from state files written before slow start existed
+    #[serde(default)]
+    new_account_messages: Vec<SavedWindow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedWindow {
+    key: String,
+    /// Unix time the window opened, in seconds
+    window_start: f64,
+    count: u32,
+}
+
+fn save_windows<K: Eq + Hash + ToString>(limits: &Mutex<HashMap<K, RateWindow>>) -> Vec<SavedWindow> {
+    let now = Instant::now();
+    let wall_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    limits.lock().unwrap()
+        .iter()
+        .filter(|(_, limit)| now.duration_since(limit.window_start) < RATE_LIMIT_WINDOW)
+        .map(|(key, limit)| SavedWindow {
+            key: key.to_string(),
+            window_start: wall_now - now.duration_since(limit.window_start).as_secs_f64(),
+            count: limit.count,
+        })
+        .collect()
+}
+
+fn restore_windows<K: Eq + Hash + FromStr>(limits: &Mutex<HashMap<K, RateWindow>>, saved: Vec<SavedWindow>) -> usize {
+    let now = Instant::now();
+    let wall_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    let mut limits = limits.lock().unwrap();
+
+    let mut restored = 0;
+    for window in saved {
+        let Ok(key) = window.key.parse::<K>() else { continue };
+        let age = wall_now - window.window_start;
+        if age < -CLOCK_DRIFT_TOLERANCE.as_secs_f64() || !age.is_finite() {
+            continue;
+        }
+        let age = Duration::from_secs_f64(age.max(0.0));
+        if age >= RATE_LIMIT_WINDOW {
+            continue;
+        }
+        let Some(window_start) = now.checked_sub(age) else { continue };
+
+        limits.insert(key, RateWindow { window_start, count: window.count });
+        restored += 1;
+    }
+    restored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimitService {
+        RateLimitService::new(&RateLimitConfig {
+            uploads_per_minute: 2,
+            messages_per_minute: 3,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    /// Move a user's message window back so it started `age` ago
+    fn backdate_message_window(limiter: &RateLimitService, user_id: Uuid, age: Duration) {
+        let mut limits = limiter.message_limits.lock().unwrap();
+        let window = limits.get_mut(&user_id).expect("user has a message window");
+        window.window_start = Instant::now() - age;
+    }
+
+    #[test]
+    fn the_message_over_the_limit_is_rejected() {
+        let limiter = limiter();
+        let alice = Uuid::new_v4();
+        for _ in 0..3 {
+            assert!(limiter.check_message_rate_limit(alice).is_ok());
+        }
+        let retry_after = limiter.check_message_rate_limit(alice).unwrap_err();
+        assert!((1..=60).contains(&retry_after));
+
+        // Other users have windows of their own
+        assert!(limiter.check_message_rate_limit(Uuid::new_v4()).is_ok());
+    }
+
+    #[test]
+    fn a_new_window_resets_the_count() {
+        let limiter = limiter();
+        let alice = Uuid::new_v4();
+        for _ in 0..3 {
+            limiter.check_message_rate_limit(alice).unwrap();
+        }
+        backdate_message_window(&limiter, alice, RATE_LIMIT_WINDOW);
+        assert!(limiter.check_message_rate_limit(alice).is_ok());
+    }
+
+    #[test]
+    fn uploads_are_throttled_until_the_window_ends() {
+        let limiter = limiter();
+        let alice = Uuid::new_v4();
+        assert!(limiter.check_file_upload_rate_limit(alice).is_ok());
+        assert!(limiter.check_file_upload_rate_limit(alice).is_ok());
+        assert!(limiter.check_file_upload_rate_limit(alice).is_err());
+
+        limiter.file_upload_limits.lock().unwrap().get_mut(&alice).unwrap().window_start =
+            Instant::now() - RATE_LIMIT_WINDOW;
+        assert!(limiter.check_file_upload_rate_limit(alice).is_ok());
+    }
+
+    #[test]
+    fn a_batch_of_uploads_is_charged_whole_or_not_at_all() {
+        let limiter = limiter();
+        let alice = Uuid::new_v4();
+        limiter.check_file_upload_rate_limit(alice).unwrap();
+
+        // Two more don't fit in a limit of two, and the refusal costs nothing
+        assert!(limiter.check_file_uploads_rate_limit(alice, 2).is_err());
+        assert!(limiter.check_file_uploads_rate_limit(alice, 1).is_ok());
+        assert!(limiter.check_file_upload_rate_limit(alice).is_err());
+
+        let bob = Uuid::new_v4();
+        assert!(limiter.check_file_uploads_rate_limit(bob, 2).is_ok());
+        assert!(limiter.check_file_upload_rate_limit(bob).is_err());
+    }
+
+    #[test]
+    fn stats_count_uploads_and_tracked_users() {
+        let limiter = limiter();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..3 {
+            let _ = limiter.check_file_upload_rate_limit(alice);
+        }
+        limiter.check_file_upload_rate_limit(bob).unwrap();
+
+        let stats = limiter.stats();
+        assert_eq!(stats.uploads_allowed, 3);
+        assert_eq!(stats.uploads_throttled, 1);
+        assert_eq!(stats.upload_users_tracked, 2);
+    }
+
+    #[test]
+    fn cleanup_prunes_only_ended_windows() {
+        let limiter = limiter();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        limiter.check_message_rate_limit(alice).unwrap();
+        limiter.check_message_rate_limit(bob).unwrap();
+        backdate_message_window(&limiter, alice, RATE_LIMIT_WINDOW + Duration::from_secs(1));
+
+        assert_eq!(limiter.cleanup_old_entries(), 1);
+        let limits = limiter.message_limits.lock().unwrap();
+        assert!(!limits.contains_key(&alice));
+        assert!(limits.contains_key(&bob));
+    }
+}
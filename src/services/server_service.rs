@@ -0,0 +1,641 @@
+use crate::db::{channels, server_roles, servers};
+use crate::errors::{Result, ServerError};
+use crate::api::connection::PeerMap;
+use crate::services::{audit_service, AuditService, BroadcastService, ChatService, NotificationService, SystemMessageService};
+use crate::services::content_filter_service::{ContentFilterService, FilterResult};
+use crate::services::system_message_service::SystemEvent;
+use nexus_tui_common::{ChannelInfo, ServerEmoji, ServerMessage, User};
+use tracing::info;
+use uuid::Uuid;
+
+/// Image formats accepted for custom emoji, as data URI prefixes
+const EMOJI_IMAGE_PREFIXES: [&str; 3] = ["data:image/png;base64,", "data:image/gif;base64,", "data:image/webp;base64,"];
+
+pub struct ServerService;
+
+impl ServerService {
+    /// Validate a server name and description against the configured limits
+    fn validate_server_fields(
+        name: &str,
+        description: &str,
+        content_filter: &ContentFilterService,
+    ) -> Result<()> {
+        let limits = crate::config::settings().servers.clone();
+
+        if name.trim().is_empty() {
+            return Err(ServerError::Validation("Server name cannot be empty".to_string()));
+        }
+        if name.chars().count() > limits.max_name_length {
+            return Err(ServerError::Validation(format!(
+                "Server name must be at most {} characters", limits.max_name_length
+            )));
+        }
+        if description.chars().count() > limits.max_description_length {
+            return Err(ServerError::Validation(format!(
+                "Server description must be at most {} characters", limits.max_description_length
+            )));
+        }
+
+        match content_filter.filter_message(name) {
+            FilterResult::Allowed => Ok(()),
+            FilterResult::Flagged(reason) | FilterResult::Blocked(reason) => {
+                Err(ServerError::Validation(format!("Server name rejected: {}", reason)))
+            }
+        }
+    }
+
+    /// Create a new server owned by the given user
+    pub async fn create_server(
+        owner_id: Uuid,
+        name: &str,
+        description: &str,
+        public: bool,
+        content_filter: &ContentFilterService,
+    ) -> Result<Uuid> {
+        let name = name.trim();
+        let description = description.trim();
+        Self::validate_server_fields(name, description, content_filter)?;
+
+        let server_id = servers::db_create_server(name, description, public, owner_id, None, None).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Server '{}' created by {}", name, owner_id);
+        Ok(server_id)
+    }
+
+    /// Update a server's name and description (owner or server mods only)
+    pub async fn update_server(
+        user_id: Uuid,
+        server_id: Uuid,
+        name: &str,
+        description: &str,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can edit this server".to_string()));
+        }
+
+        let name = name.trim();
+        let description = description.trim();
+        Self::validate_server_fields(name, description, content_filter)?;
+
+        let old_name = servers::db_update_server(server_id, name, description).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if old_name != name {
+            let event = SystemEvent::ServerRenamed { old_name, new_name: name.to_string() };
+            SystemMessageService::emit(server_id, event, peer_map).await;
+        }
+
+        info!("Server {} updated by {}", server_id, user_id);
+        Ok(())
+    }
+
+    /// Create a channel in a server (owner or server mods only). Members who
+    /// turned off auto-join get a notification offering the channel instead.
+    pub async fn create_channel(
+        user_id: Uuid,
+        server_id: Uuid,
+        name: &str,
+        description: &str,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<Uuid> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can create channels".to_string()));
+        }
+
+        let name = name.trim();
+        let limits = crate::config::settings().servers.clone();
+        if name.is_empty() {
+            return Err(ServerError::Validation("Channel name cannot be empty".to_string()));
+        }
+        if name.chars().count() > limits.max_name_length {
+            return Err(ServerError::Validation(format!(
+                "Channel name must be at most {} characters", limits.max_name_length
+            )));
+        }
+        if let FilterResult::Flagged(reason) | FilterResult::Blocked(reason) = content_filter.filter_message(name) {
+            return Err(ServerError::Validation(format!("Channel name rejected: {}", reason)));
+        }
+        let description = Self::sanitize_channel_text(description, limits.max_description_length, "Channel description", content_filter)?;
+
+        let (channel_id, opted_out) = channels::db_create_channel(server_id, name, &description).await
+            .map_err(|e| ServerError::Database(e))?;
+        for member_id in opted_out {
+            NotificationService::create_new_channel_notification(member_id, channel_id, name, peer_map).await;
+        }
+
+        info!("Channel '{}' created in server {} by {}", name, server_id, user_id);
+        Ok(channel_id)
+    }
+
+    /// Strip control characters (newlines are kept), trim, and check the
+    /// length and content filter of a channel description or topic
+    fn sanitize_channel_text(text: &str, max_length: usize, field: &str, content_filter: &ContentFilterService) -> Result<String> {
+        let cleaned: String = text.chars().filter(|c| !c.is_control() || *c == '\n').collect();
+        let cleaned = cleaned.trim();
+        if cleaned.chars().count() > max_length {
+            return Err(ServerError::Validation(format!("{} must be at most {} characters", field, max_length)));
+        }
+        if cleaned.is_empty() {
+            return Ok(String::new());
+        }
+        match content_filter.filter_message(cleaned) {
+            FilterResult::Allowed => Ok(cleaned.to_string()),
+            FilterResult::Flagged(reason) | FilterResult::Blocked(reason) => {
+                Err(ServerError::Validation(format!("{} rejected: {}", field, reason)))
+            }
+        }
+    }
+
+    /// Change a channel's description and/or topic (owner or server mods only).
+    /// An empty topic clears it. Members are sent the updated header.
+    pub async fn update_channel(
+        user: &User,
+        channel_id: Uuid,
+        description: Option<String>,
+        topic: Option<String>,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<ChannelInfo> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if !servers::db_is_user_server_mod(user.id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can edit channels".to_string()));
+        }
+        if description.is_none() && topic.is_none() {
+            return Err(ServerError::Validation("Nothing to update".to_string()));
+        }
+
+        let limits = crate::config::settings().servers.clone();
+        let description = description
+            .map(|d| Self::sanitize_channel_text(&d, limits.max_description_length, "Channel description", content_filter))
+            .transpose()?;
+        let topic = topic
+            .map(|t| Self::sanitize_channel_text(&t, limits.max_channel_topic_length, "Channel topic", content_filter))
+            .transpose()?
+            .map(|t| Some(t).filter(|t| !t.is_empty()));
+
+        channels::db_update_channel_details(channel_id, description, topic, user.id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let info = channels::db_get_channel_info(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let members = channels::db_get_channel_user_list_lightweight(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let member_ids: Vec<Uuid> = members.iter().map(|m| m.id).collect();
+        BroadcastService::broadcast_to_channel_users(peer_map, &member_ids, &ServerMessage::ChannelUpdated(info.clone())).await;
+
+        info!("Channel {} updated by {}", channel_id, user.username);
+        Ok(info)
+    }
+
+    /// Header details of a channel the user can read
+    pub async fn channel_info(user_id: Uuid, channel_id: Uuid) -> Result<ChannelInfo> {
+        ChatService::ensure_can_read_channel(user_id, channel_id).await?;
+        channels::db_get_channel_info(channel_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Join a channel of a server the user belongs to, e.g. one offered by a
+    /// new channel notification
+    pub async fn join_channel(user_id: Uuid, channel_id: Uuid) -> Result<()> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if !servers::db_is_user_in_server(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("You are not a member of this server".to_string()));
+        }
+        if !channels::db_can_user_read_channel(user_id, channel_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Not authorized to read this channel".to_string()));
+        }
+
+        channels::db_add_user_to_channel(channel_id, user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("User {} joined channel {}", user_id, channel_id);
+        Ok(())
+    }
+
+    /// Ensure the user is the owner or a moderator of the server
+    async fn require_server_mod(user_id: Uuid, server_id: Uuid) -> Result<()> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can manage roles".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Create a permission role in a server (owner or server mods only)
+    pub async fn create_server_role(
+        user_id: Uuid,
+        server_id: Uuid,
+        name: &str,
+        can_read: bool,
+        can_write: bool,
+    ) -> Result<Uuid> {
+        Self::require_server_mod(user_id, server_id).await?;
+
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ServerError::Validation("Role name cannot be empty".to_string()));
+        }
+
+        let role_id = server_roles::db_create_server_role(server_id, name, can_read, can_write).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Role '{}' created in server {} by {}", name, server_id, user_id);
+        Ok(role_id)
+    }
+
+    /// Give a server member a role (owner or server mods only)
+    pub async fn assign_server_role(
+        moderator: &User,
+        role_id: Uuid,
+        target_user_id: Uuid,
+        peer_map: &PeerMap,
+    ) -> Result<()> {
+        let server_id = server_roles::db_get_role_server_id(role_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        Self::require_server_mod(moderator.id, server_id).await?;
+
+        if !servers::db_is_user_in_server(target_user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::BadRequest("User is not a member of this server".to_string()));
+        }
+
+        server_roles::db_assign_server_role(role_id, target_user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        AuditService::record(
+            moderator, audit_service::ASSIGN_SERVER_ROLE, Some(target_user_id.to_string()), Some(role_id.to_string())
+        ).await;
+        let summary = format!(
+            "{} gave {} a new server role", moderator.username, AuditService::display_name(target_user_id).await
+        );
+        AuditService::mirror_to_server(server_id, moderator, audit_service::ASSIGN_SERVER_ROLE, summary, peer_map).await;
+
+        info!("Role {} assigned to {} by {}", role_id, target_user_id, moderator.username);
+        Ok(())
+    }
+
+    /// Override a role's permissions on one channel (owner or server mods only)
+    pub async fn set_role_channel_permission(
+        user_id: Uuid,
+        role_id: Uuid,
+        channel_id: Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> Result<()> {
+        let server_id = server_roles::db_get_role_server_id(role_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        Self::require_server_mod(user_id, server_id).await?;
+
+        let channel_server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if channel_server_id != server_id {
+            return Err(ServerError::BadRequest("Channel does not belong to the role's server".to_string()));
+        }
+
+        server_roles::db_set_role_channel_permission(role_id, channel_id, can_read, can_write).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Role {} permissions on channel {} set by {}", role_id, channel_id, user_id);
+        Ok(())
+    }
+
+    /// Set which links may be posted in a channel (owner or server mods only)
+    pub async fn set_channel_link_policy(
+        user_id: Uuid,
+        channel_id: Uuid,
+        links_allowed: bool,
+        allowed_domains: Option<Vec<String>>,
+    ) -> Result<()> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can change link policy".to_string()));
+        }
+
+        let allowed_domains = allowed_domains.map(|domains| {
+            domains.into_iter()
+                .map(|domain| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect::<Vec<_>>()
+        });
+
+        channels::db_set_channel_link_policy(channel_id, links_allowed, allowed_domains).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Link policy for channel {} updated by {}", channel_id, user_id);
+        Ok(())
+    }
+
+    /// Choose whether a channel's followers are notified of every message (owner or server mods only)
+    pub async fn set_channel_notify_policy(user_id: Uuid, channel_id: Uuid, followers_only: bool) -> Result<()> {
+        let server_id = channels::db_get_channel_server_id(channel_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can change notification policy".to_string()));
+        }
+
+        channels::db_set_channel_notify_followers(channel_id, followers_only).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Notify policy for channel {} set by {} (followers: {})", channel_id, user_id, followers_only);
+        Ok(())
+    }
+
+    /// Choose the channel that mirrors the server's moderation events, or clear it (owner only)
+    pub async fn set_audit_channel(user_id: Uuid, server_id: Uuid, channel_id: Option<Uuid>) -> Result<()> {
+        let owner = servers::db_get_server_owner(server_id).await
+            .map_err(|e| ServerError::NotFound(e))?;
+        if owner != user_id {
+            return Err(ServerError::Forbidden("Only the server owner can set the audit channel".to_string()));
+        }
+
+        if let Some(channel_id) = channel_id {
+            let channel_server_id = channels::db_get_channel_server_id(channel_id).await
+                .map_err(|e| ServerError::NotFound(e))?;
+            if channel_server_id != server_id {
+                return Err(ServerError::BadRequest("Channel does not belong to this server".to_string()));
+            }
+        }
+
+        servers::db_set_server_audit_channel(server_id, channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Audit channel of server {} set to {:?} by {}", server_id, channel_id, user_id);
+        Ok(())
+    }
+
+    /// Turn system join/rename messages on or off for a server (owner or server mods only)
+    pub async fn set_system_messages(user_id: Uuid, server_id: Uuid, enabled: bool) -> Result<()> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can change this setting".to_string()));
+        }
+
+        servers::db_set_server_system_messages(server_id, enabled).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("System messages {} for server {} by {}", if enabled { "enabled" } else { "disabled" }, server_id, user_id);
+        Ok(())
+    }
+
+    /// Clean up a welcome template before it's stored: control characters are
+    /// dropped (line breaks kept), and the text must pass the length limit and
+    /// the content filter. A blank template clears the setting.
+    fn sanitize_welcome_template(template: Option<String>, content_filter: &ContentFilterService) -> Result<Option<String>> {
+        let Some(template) = template else {
+            return Ok(None);
+        };
+        let cleaned: String = template.chars().filter(|c| !c.is_control() || *c == '\n').collect();
+        let cleaned = cleaned.trim();
+        if cleaned.is_empty() {
+            return Ok(None);
+        }
+
+        let max_length = crate::config::settings().servers.max_welcome_length;
+        if cleaned.chars().count() > max_length {
+            return Err(ServerError::Validation(format!(
+                "Welcome message must be at most {} characters", max_length
+            )));
+        }
+        match content_filter.filter_message(cleaned) {
+            FilterResult::Allowed => Ok(Some(cleaned.to_string())),
+            FilterResult::Flagged(reason) | FilterResult::Blocked(reason) => {
+                Err(ServerError::Validation(format!("Welcome message rejected: {}", reason)))
+            }
+        }
+    }
+
+    /// Change the welcome channel and templates greeting new members (owner or
+    /// server mods only). Fields left out (`None`) keep their value; a blank
+    /// template or the nil channel id clears the setting.
+    pub async fn update_settings(
+        user_id: Uuid,
+        server_id: Uuid,
+        welcome_channel_id: Option<Uuid>,
+        welcome_template: Option<String>,
+        welcome_dm_template: Option<String>,
+        content_filter: &ContentFilterService,
+    ) -> Result<()> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can change this setting".to_string()));
+        }
+
+        // The nil id is how a client asks for no welcome channel
+        let channel_id = welcome_channel_id.map(|channel_id| Some(channel_id).filter(|id| !id.is_nil()));
+        if let Some(Some(channel_id)) = channel_id {
+            let channel_server_id = channels::db_get_channel_server_id(channel_id).await
+                .map_err(|e| ServerError::NotFound(e))?;
+            if channel_server_id != server_id {
+                return Err(ServerError::BadRequest("Channel does not belong to this server".to_string()));
+            }
+        }
+
+        let update = servers::WelcomeUpdate {
+            channel_id,
+            template: welcome_template
+                .map(|template| Self::sanitize_welcome_template(Some(template), content_filter))
+                .transpose()?,
+            dm_template: welcome_dm_template
+                .map(|template| Self::sanitize_welcome_template(Some(template), content_filter))
+                .transpose()?,
+        };
+        servers::db_update_server_welcome(server_id, update).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        info!("Welcome settings of server {} updated by {}", server_id, user_id);
+        Ok(())
+    }
+
+    /// Register a custom emoji (owner or server mods only)
+    pub async fn add_emoji(user_id: Uuid, server_id: Uuid, name: &str, image_ref: &str) -> Result<String> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can manage emoji".to_string()));
+        }
+
+        let name = crate::util::parse_emoji_name(name).ok_or_else(|| ServerError::Validation(
+            "Emoji names must be 2-32 letters, digits or underscores, optionally written as :name:".to_string()
+        ))?;
+
+        let limits = crate::config::settings().servers.clone();
+        if !EMOJI_IMAGE_PREFIXES.iter().any(|prefix| image_ref.starts_with(prefix)) {
+            return Err(ServerError::Validation("Emoji must be a PNG, GIF or WebP image".to_string()));
+        }
+        if image_ref.len() > limits.max_emoji_bytes {
+            return Err(ServerError::Validation(format!(
+                "Emoji image must be at most {} bytes", limits.max_emoji_bytes
+            )));
+        }
+
+        servers::db_add_server_emoji(server_id, &name, image_ref, user_id, limits.max_emojis).await
+            .map_err(|e| ServerError::Validation(e))?;
+
+        info!("Emoji :{}: added to server {} by {}", name, server_id, user_id);
+        Ok(name)
+    }
+
+    /// Remove a custom emoji (owner or server mods only)
+    pub async fn delete_emoji(user_id: Uuid, server_id: Uuid, name: &str) -> Result<()> {
+        if !servers::db_is_user_server_mod(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("Only the server owner or moderators can manage emoji".to_string()));
+        }
+
+        let name = crate::util::parse_emoji_name(name)
+            .ok_or_else(|| ServerError::Validation(format!("Not an emoji name: {}", name.trim())))?;
+        if !servers::db_delete_server_emoji(server_id, &name).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound(format!("No emoji named :{}:", name)));
+        }
+
+        info!("Emoji :{}: removed from server {} by {}", name, server_id, user_id);
+        Ok(())
+    }
+
+    /// List a server's custom emoji (server members only)
+    pub async fn list_emojis(user_id: Uuid, server_id: Uuid) -> Result<Vec<ServerEmoji>> {
+        if !servers::db_is_user_in_server(user_id, server_id).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::Forbidden("You are not a member of this server".to_string()));
+        }
+
+        servers::db_get_server_emojis(server_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    #[tokio::test]
+    async fn over_length_server_name_is_rejected() {
+        let db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+        let name = "x".repeat(crate::config::settings().servers.max_name_length + 1);
+
+        let result = ServerService::create_server(owner.id, &name, "", true, &test_support::content_filter()).await;
+
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+        assert_eq!(db.count_rows("servers"), 0);
+    }
+
+    #[tokio::test]
+    async fn empty_server_name_is_rejected() {
+        let db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+
+        for name in ["", "   "] {
+            let result = ServerService::create_server(owner.id, name, "", true, &test_support::content_filter()).await;
+            assert!(matches!(result, Err(ServerError::Validation(_))), "{:?} was accepted", name);
+        }
+        assert_eq!(db.count_rows("servers"), 0);
+    }
+
+    #[tokio::test]
+    async fn rename_to_an_invalid_name_leaves_the_server_unchanged() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&owner, "Original").await;
+        let filter = test_support::content_filter();
+        let too_long = "x".repeat(crate::config::settings().servers.max_name_length + 1);
+
+        for name in ["", too_long.as_str()] {
+            let result = ServerService::update_server(owner.id, server_id, name, "", &filter, &peer_map).await;
+            assert!(matches!(result, Err(ServerError::Validation(_))));
+        }
+        let servers = servers::db_get_user_servers(owner.id).await.unwrap();
+        assert_eq!(servers[0].name, "Original");
+    }
+
+    #[tokio::test]
+    async fn name_at_the_limit_is_accepted() {
+        let db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+        let name = "x".repeat(crate::config::settings().servers.max_name_length);
+
+        ServerService::create_server(owner.id, &name, "", true, &test_support::content_filter()).await.unwrap();
+
+        assert_eq!(db.count_rows("servers"), 1);
+    }
+
+    const BLOB: &str = "data:image/png;base64,iVBORw0KGgo=";
+
+    #[tokio::test]
+    async fn custom_emoji_are_managed_by_moderators_only() {
+        let _db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+        let member = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        test_support::join_server(server_id, member.id).await;
+
+        let result = ServerService::add_emoji(member.id, server_id, "partyblob", BLOB).await;
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+        let result = ServerService::add_emoji(owner.id, server_id, "partyblob", "data:image/svg+xml;base64,PHN2Zz4=").await;
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+        let result = ServerService::add_emoji(owner.id, server_id, "::partyblob", BLOB).await;
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+
+        assert_eq!(ServerService::add_emoji(owner.id, server_id, ":PartyBlob:", BLOB).await.unwrap(), "partyblob");
+        let emojis = ServerService::list_emojis(member.id, server_id).await.unwrap();
+        assert_eq!(emojis.len(), 1);
+        assert_eq!(emojis[0].name, "partyblob");
+    }
+
+    #[tokio::test]
+    async fn custom_emoji_are_resolved_in_sent_and_fetched_messages() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("alice").await;
+        let member = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        test_support::join_server(server_id, member.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        ServerService::add_emoji(owner.id, server_id, "partyblob", BLOB).await.unwrap();
+        let mut member_peer = test_support::FakePeer::connect(&peer_map, Some(member.id)).await;
+
+        ChatService::send_channel_message(
+            channel_id, &owner, "ship it :partyblob: :unknown:", None, &test_support::content_filter(), &peer_map
+        ).await.unwrap();
+
+        let live = member_peer.drain().into_iter().find_map(|message| match message {
+            ServerMessage::NewChannelMessage(message) => Some(message),
+            _ => None,
+        }).expect("member gets the message");
+        assert_eq!(live.emojis.len(), 1);
+        assert!(live.emojis.iter().any(|(name, image)| name == "partyblob" && image == BLOB));
+
+        let (history, _) = ChatService::get_channel_messages(member.id, channel_id, None, 50).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].emojis.iter().any(|(name, image)| name == "partyblob" && image == BLOB));
+    }
+
+    #[tokio::test]
+    async fn settings_left_out_of_an_update_keep_their_value() {
+        let _db = TestDb::new().await;
+        let owner = test_support::create_user("alice").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "welcome").await;
+        let filter = test_support::content_filter();
+
+        ServerService::update_settings(
+            owner.id, server_id, Some(channel_id), Some("Hi {username}".to_string()), Some("Welcome aboard".to_string()), &filter
+        ).await.unwrap();
+        // Only the DM template is sent
+        ServerService::update_settings(owner.id, server_id, None, None, Some("Glad you came".to_string()), &filter).await.unwrap();
+
+        let welcome = servers::db_get_server_welcome(server_id).await.unwrap();
+        assert_eq!(welcome.channel_id, Some(channel_id));
+        assert_eq!(welcome.template.as_deref(), Some("Hi {username}"));
+        assert_eq!(welcome.dm_template.as_deref(), Some("Glad you came"));
+
+        // Clearing is explicit: a blank template and the nil channel id
+        ServerService::update_settings(owner.id, server_id, Some(Uuid::nil()), Some("  ".to_string()), None, &filter).await.unwrap();
+
+        let welcome = servers::db_get_server_welcome(server_id).await.unwrap();
+        assert_eq!(welcome.channel_id, None);
+        assert_eq!(welcome.template, None);
+        assert_eq!(welcome.dm_template.as_deref(), Some("Glad you came"));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::db::settings;
+use crate::errors::{Result, ServerError};
+use crate::services::{audit_service, AuditService};
+use nexus_tui_common::{User, UserRole};
+use tracing::info;
+
+// Runtime setting keys
+pub const MOTD: &str = "motd";
+pub const REGISTRATION_OPEN: &str = "registration_open";
+/// Only changed through `set_maintenance_mode`, which also announces it
+pub const MAINTENANCE_MODE: &str = "maintenance_mode";
+
+/// Longest message of the day accepted
+const MAX_MOTD_LENGTH: usize = 500;
+
+/// Settings admins can change while the server runs, stored in the database
+pub struct SettingsService;
+
+impl SettingsService {
+    /// Check a value is acceptable for a known setting
+    fn validate(key: &str, value: &str) -> Result<()> {
+        match key {
+            MOTD if value.chars().count() > MAX_MOTD_LENGTH => Err(ServerError::Validation(format!(
+                "MOTD must be at most {} characters", MAX_MOTD_LENGTH
+            ))),
+            MOTD => Ok(()),
+            REGISTRATION_OPEN if value == "true" || value == "false" => Ok(()),
+            REGISTRATION_OPEN => Err(ServerError::Validation(
+                "registration_open must be true or false".to_string()
+            )),
+            MAINTENANCE_MODE => Err(ServerError::Validation(
+                "Use SetMaintenanceMode to change maintenance_mode".to_string()
+            )),
+            other => Err(ServerError::Validation(format!("Unknown setting '{}'", other))),
+        }
+    }
+
+    /// Change a runtime setting (Admin only), recording the change in the audit log
+    pub async fn set(admin: &User, key: &str, value: &str) -> Result<()> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can change server settings".to_string()));
+        }
+        Self::validate(key, value)?;
+        Self::store(admin, key, value).await
+    }
+
+    /// Switch read-only maintenance mode on or off (Admin only)
+    pub async fn set_maintenance_mode(admin: &User, enabled: bool) -> Result<()> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can change maintenance mode".to_string()));
+        }
+        Self::store(admin, MAINTENANCE_MODE, if enabled { "true" } else { "false" }).await
+    }
+
+    /// Persist an already validated setting and audit the change
+    async fn store(admin: &User, key: &str, value: &str) -> Result<()> {
+        let old = settings::db_set_setting(key, value, admin.id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let details = serde_json::json!({ "key": key, "old": old, "new": value }).to_string();
+        AuditService::record(
+            admin, audit_service::CONFIGURATION_CHANGED, Some(key.to_string()), Some(details)
+        ).await;
+
+        info!("Setting {} changed by {}", key, admin.username);
+        Ok(())
+    }
+
+    /// The message of the day, if one is set
+    pub async fn motd() -> Result<Option<String>> {
+        let motd = settings::db_get_setting(MOTD).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(motd.filter(|motd| !motd.trim().is_empty()))
+    }
+
+    /// Whether new accounts may register; open unless an admin closed it
+    pub async fn registration_open() -> Result<bool> {
+        let value = settings::db_get_setting(REGISTRATION_OPEN).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(value.as_deref() != Some("false"))
+    }
+
+    /// Whether the server is in read-only maintenance mode
+    pub async fn maintenance_mode() -> Result<bool> {
+        let value = settings::db_get_setting(MAINTENANCE_MODE).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(value.as_deref() == Some("true"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::{self, AuditFilter};
+    use crate::test_support::{self, TestDb};
+
+    /// Details of every configuration change recorded so far, as JSON
+    async fn configuration_changes() -> Vec<serde_json::Value> {
+        let filter = AuditFilter { action: Some(audit_service::CONFIGURATION_CHANGED.to_string()), ..AuditFilter::default() };
+        let (entries, _) = audit::db_fetch_audit_entries(filter, 50, 0).await.unwrap();
+        entries.iter()
+            .map(|entry| serde_json::from_str(entry.details.as_deref().unwrap()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn a_setting_change_is_audited_with_old_and_new_values() {
+        let _db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+
+        SettingsService::set(&admin, MOTD, "hello").await.unwrap();
+        SettingsService::set(&admin, MOTD, "welcome back").await.unwrap();
+
+        let changes = configuration_changes().await;
+        assert_eq!(changes.len(), 2);
+        assert!(changes.contains(&serde_json::json!({ "key": "motd", "old": null, "new": "hello" })));
+        assert!(changes.contains(&serde_json::json!({ "key": "motd", "old": "hello", "new": "welcome back" })));
+        assert_eq!(SettingsService::motd().await.unwrap().as_deref(), Some("welcome back"));
+    }
+
+    #[tokio::test]
+    async fn refused_changes_are_not_audited() {
+        let _db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let user = test_support::create_user("user").await;
+
+        assert!(matches!(SettingsService::set(&user, MOTD, "hi").await, Err(ServerError::Forbidden(_))));
+        assert!(matches!(SettingsService::set(&admin, REGISTRATION_OPEN, "maybe").await, Err(ServerError::Validation(_))));
+        assert!(matches!(SettingsService::set(&admin, MAINTENANCE_MODE, "true").await, Err(ServerError::Validation(_))));
+
+        assert!(configuration_changes().await.is_empty());
+        assert!(!SettingsService::maintenance_mode().await.unwrap());
+    }
+}
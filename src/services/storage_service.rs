@@ -0,0 +1,196 @@
+use crate::api::connection::PeerMap;
+use crate::db::storage::{self, UserStorage};
+use crate::errors::{Result, ServerError};
+use crate::services::NotificationService;
+use nexus_tui_common::{StorageUsage, User};
+use tracing::{error, info};
+use uuid::Uuid;
+
+pub struct StorageService;
+
+impl StorageService {
+    /// Account for a newly stored message
+    pub async fn record_message(user_id: Uuid, content: &str, peer_map: &PeerMap) {
+        if let Err(e) = storage::db_add_message_bytes(user_id, content.len() as i64).await {
+            error!("Failed to record message storage for {}: {}", user_id, e);
+            return;
+        }
+        Self::check_soft_quota(user_id, peer_map).await;
+    }
+
+    /// Account for an edit that changed the size of a stored message
+    pub async fn record_message_edit(user_id: Uuid, previous_len: usize, new_len: usize, peer_map: &PeerMap) {
+        let delta = new_len as i64 - previous_len as i64;
+        if delta == 0 {
+            return;
+        }
+        if let Err(e) = storage::db_add_message_bytes(user_id, delta).await {
+            error!("Failed to record message storage for {}: {}", user_id, e);
+            return;
+        }
+        if delta > 0 {
+            Self::check_soft_quota(user_id, peer_map).await;
+        }
+    }
+
+    /// Recount a user's images after their profile changed
+    pub async fn record_media_change(user_id: Uuid, peer_map: &PeerMap) {
+        if let Err(e) = storage::db_refresh_media_bytes(user_id).await {
+            error!("Failed to record media storage for {}: {}", user_id, e);
+            return;
+        }
+        Self::check_soft_quota(user_id, peer_map).await;
+    }
+
+    /// Refuse a profile image upload that would take the user past the hard
+    /// quota. Each image given (by its size) replaces the current one; an
+    /// image not given is kept and still counts.
+    pub async fn check_upload_allowed(user_id: Uuid, profile_pic_bytes: Option<usize>, cover_banner_bytes: Option<usize>) -> Result<()> {
+        let hard_quota = crate::config::settings().quotas.hard_quota_bytes;
+        Self::check_upload_within(user_id, profile_pic_bytes, cover_banner_bytes, hard_quota).await
+    }
+
+    async fn check_upload_within(user_id: Uuid, profile_pic_bytes: Option<usize>, cover_banner_bytes: Option<usize>, hard_quota: u64) -> Result<()> {
+        // Only new image bytes are held to the quota; a profile edit without
+        // them goes through even for a user already over it
+        let upload_bytes = profile_pic_bytes.unwrap_or(0) as u64 + cover_banner_bytes.unwrap_or(0) as u64;
+        if hard_quota == 0 || upload_bytes == 0 {
+            return Ok(());
+        }
+
+        let (message_bytes, _, _) = storage::db_get_user_storage(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let (current_pic, current_banner) = storage::db_get_profile_image_bytes(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let kept_bytes = message_bytes.max(0) as u64
+            + if profile_pic_bytes.is_some() { 0 } else { current_pic.max(0) as u64 }
+            + if cover_banner_bytes.is_some() { 0 } else { current_banner.max(0) as u64 };
+        if kept_bytes + upload_bytes > hard_quota {
+            return Err(ServerError::Forbidden(format!(
+                "Upload of {} bytes exceeds your storage quota; {} of {} bytes remaining",
+                upload_bytes,
+                hard_quota.saturating_sub(kept_bytes),
+                hard_quota
+            )));
+        }
+        Ok(())
+    }
+
+    /// A user's own storage usage
+    pub async fn usage(user: &User) -> Result<StorageUsage> {
+        let (message_bytes, media_bytes, _) = storage::db_get_user_storage(user.id).await
+            .map_err(|e| ServerError::Database(e))?;
+        Ok(StorageUsage {
+            user_id: user.id,
+            username: user.username.clone(),
+            message_bytes: message_bytes.max(0) as u64,
+            media_bytes: media_bytes.max(0) as u64,
+        })
+    }
+
+    /// Warn a user the first time they cross the soft quota
+    async fn check_soft_quota(user_id: Uuid, peer_map: &PeerMap) {
+        let soft_quota = crate::config::settings().quotas.soft_quota_bytes;
+        if soft_quota == 0 {
+            return;
+        }
+
+        let (message_bytes, media_bytes, warned) = match storage::db_get_user_storage(user_id).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("Failed to read storage for {}: {}", user_id, e);
+                return;
+            }
+        };
+
+        let over = (message_bytes + media_bytes).max(0) as u64 > soft_quota;
+        if over == warned {
+            return;
+        }
+        let _ = storage::db_set_soft_quota_warned(user_id, over).await;
+
+        if over {
+            let warning = format!(
+                "You are using {} bytes of storage, over the {} byte soft quota",
+                message_bytes + media_bytes,
+                soft_quota
+            );
+            NotificationService::create_storage_quota_notification(user_id, &warning, peer_map).await;
+        }
+    }
+
+    /// Users using the most storage, largest first
+    pub async fn top_consumers(limit: usize) -> Result<Vec<UserStorage>> {
+        storage::db_get_top_storage_users(limit).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Recompute every user's usage from the stored data
+    pub async fn recompute_all() -> Result<usize> {
+        let updated = storage::db_recompute_user_storage().await
+            .map_err(|e| ServerError::Database(e))?;
+        info!("Recomputed storage usage for {} users", updated);
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+
+    #[tokio::test]
+    async fn an_upload_within_the_quota_is_allowed() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        storage::db_add_message_bytes(alice.id, 600).await.unwrap();
+
+        StorageService::check_upload_within(alice.id, Some(300), None, 1000).await.unwrap();
+        StorageService::check_upload_within(alice.id, Some(400), None, 1000).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_upload_past_the_quota_is_refused_with_the_remaining_budget() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        storage::db_add_message_bytes(alice.id, 600).await.unwrap();
+
+        let result = StorageService::check_upload_within(alice.id, Some(300), Some(101), 1000).await;
+        assert!(matches!(
+            result,
+            Err(ServerError::Forbidden(reason)) if reason.contains("401 bytes") && reason.contains("400 of 1000 bytes remaining")
+        ));
+        // 0 turns the quota off
+        StorageService::check_upload_within(alice.id, Some(5000), None, 0).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_profile_edit_without_images_is_allowed_over_the_quota() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        storage::db_add_message_bytes(alice.id, 2000).await.unwrap();
+
+        StorageService::check_upload_within(alice.id, None, None, 1000).await.unwrap();
+        // Clearing an image uploads nothing either
+        StorageService::check_upload_within(alice.id, Some(0), None, 1000).await.unwrap();
+        assert!(matches!(
+            StorageService::check_upload_within(alice.id, Some(1), None, 1000).await,
+            Err(ServerError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn usage_reports_what_was_recorded() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+
+        StorageService::record_message(alice.id, "hello", &peer_map).await;
+        StorageService::record_message(alice.id, "world!", &peer_map).await;
+
+        let usage = StorageService::usage(&alice).await.unwrap();
+        assert_eq!(usage.message_bytes, 11);
+        assert_eq!(usage.media_bytes, 0);
+    }
+}
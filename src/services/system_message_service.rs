@@ -0,0 +1,240 @@
+use crate::api::connection::PeerMap;
+use crate::db::{channels, messages, servers, users};
+use crate::errors::{Result, ServerError};
+use crate::services::BroadcastService;
+use crate::util::escape_markdown;
+use nexus_tui_common::{ChannelMessage, DirectMessage, ServerMessage};
+use serde::Serialize;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// A structured server event shown inline in a channel
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SystemEvent {
+    MemberJoined { user_id: Uuid, username: String },
+    ServerRenamed { old_name: String, new_name: String },
+    /// A moderation action mirrored into the server's audit channel
+    ModerationAction { action: String, actor_id: Uuid, summary: String },
+    /// The server's welcome message for a new member, already rendered
+    MemberWelcomed { user_id: Uuid, message: String },
+    /// A poll was started; clients render it from the poll id
+    PollCreated { poll_id: Uuid, author_id: Uuid, username: String, question: String },
+}
+
+impl SystemEvent {
+    /// Human-readable text stored as the message content
+    fn text(&self) -> String {
+        match self {
+            SystemEvent::MemberJoined { username, .. } => format!("{} joined the server", username),
+            SystemEvent::ServerRenamed { new_name, .. } => format!("The server was renamed to {}", new_name),
+            SystemEvent::ModerationAction { summary, .. } => summary.clone(),
+            SystemEvent::MemberWelcomed { message, .. } => message.clone(),
+            SystemEvent::PollCreated { username, question, .. } => format!("{} started a poll: {}", username, question),
+        }
+    }
+}
+
+/// Fill in a welcome template. The username is escaped, and substituted in a
+/// single pass so a name containing "{username}" isn't expanded again.
+fn render_welcome(template: &str, username: &str) -> String {
+    template.replace("{username}", &escape_markdown(username))
+}
+
+pub struct SystemMessageService;
+
+impl SystemMessageService {
+    /// Post an event to the server's system channel. Failures are logged rather
+    /// than returned so they never break the action that triggered the event.
+    pub async fn emit(server_id: Uuid, event: SystemEvent, peer_map: &PeerMap) {
+        if let Err(e) = Self::try_emit(server_id, &event, peer_map).await {
+            error!("Failed to emit system message {:?} in server {}: {}", event, server_id, e);
+        }
+    }
+
+    /// Announce a new member and send the server's welcome message, if any.
+    /// Every path that adds a user to a server goes through here, so each join
+    /// greets the member exactly once.
+    pub async fn member_joined(server_id: Uuid, user_id: Uuid, username: &str, peer_map: &PeerMap) {
+        let event = SystemEvent::MemberJoined { user_id, username: username.to_string() };
+        Self::emit(server_id, event, peer_map).await;
+
+        if let Err(e) = Self::welcome(server_id, user_id, username, peer_map).await {
+            error!("Failed to welcome {} to server {}: {}", user_id, server_id, e);
+        }
+    }
+
+    /// Post the welcome message to the welcome channel and/or DM it to the new member
+    async fn welcome(server_id: Uuid, user_id: Uuid, username: &str, peer_map: &PeerMap) -> Result<()> {
+        let welcome = servers::db_get_server_welcome(server_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if let (Some(channel_id), Some(template)) = (welcome.channel_id, &welcome.template) {
+            let event = SystemEvent::MemberWelcomed { user_id, message: render_welcome(template, username) };
+            Self::post(channel_id, &event, peer_map).await?;
+        }
+
+        if let Some(template) = &welcome.dm_template {
+            let timestamp = crate::util::now_secs();
+            let content = render_welcome(template, username);
+            let dm_id = messages::db_store_direct_message(users::SYSTEM_USER_ID, user_id, &content, timestamp).await
+                .map_err(|e| ServerError::Database(e))?;
+
+            let dm = DirectMessage {
+                id: dm_id,
+                from: users::SYSTEM_USER_ID,
+                to: user_id,
+                timestamp,
+                content,
+            };
+            BroadcastService::send_to_user(peer_map, user_id, &ServerMessage::DirectMessage(dm)).await;
+        }
+        Ok(())
+    }
+
+    async fn try_emit(server_id: Uuid, event: &SystemEvent, peer_map: &PeerMap) -> Result<()> {
+        let Some(channel_id) = servers::db_get_server_system_channel(server_id).await
+            .map_err(|e| ServerError::Database(e))? else {
+            return Ok(());
+        };
+        Self::post(channel_id, event, peer_map).await
+    }
+
+    /// Post an event to a specific channel as the System account
+    pub async fn post(channel_id: Uuid, event: &SystemEvent, peer_map: &PeerMap) -> Result<()> {
+        let timestamp = crate::util::now_secs();
+        let content = event.text();
+        let system_event = serde_json::to_string(event)
+            .map_err(|e| ServerError::Internal(e.to_string()))?;
+
+        let message_id = channels::db_create_system_channel_message(
+            channel_id, timestamp, &content, &system_event
+        ).await.map_err(|e| ServerError::Database(e))?;
+
+        let channel_msg = ChannelMessage {
+            id: message_id,
+            channel_id,
+            sent_by: users::SYSTEM_USER_ID,
+            timestamp,
+            content,
+            system_event: Some(system_event),
+            origin: None,
+            channel_refs: Vec::new(),
+            emojis: Vec::new(),
+            reactions: Vec::new(),
+        };
+
+        let channel_users = channels::db_get_channel_user_list(channel_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let user_ids: Vec<Uuid> = channel_users.iter().map(|u| u.id).collect();
+
+        let message = ServerMessage::NewChannelMessage(channel_msg);
+        BroadcastService::broadcast_to_channel_users(peer_map, &user_ids, &message).await;
+
+        info!("System message in channel {}: {:?}", channel_id, event);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{ChatService, InviteService, UserService};
+    use crate::test_support::{self, FakePeer, TestDb};
+
+    /// System messages in a channel's history, oldest first
+    async fn system_messages(viewer_id: Uuid, channel_id: Uuid) -> Vec<ChannelMessage> {
+        let (messages, _) = ChatService::get_channel_messages(viewer_id, channel_id, None, 50).await.unwrap();
+        messages.into_iter().filter(|message| message.sent_by == users::SYSTEM_USER_ID).collect()
+    }
+
+    #[tokio::test]
+    async fn joining_a_server_posts_a_system_message() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let mut owner_peer = FakePeer::connect(&peer_map, Some(owner.id)).await;
+        let bob = test_support::create_user("bob").await;
+
+        UserService::add_user_to_default_server(bob.id, &bob.username, &peer_map).await.unwrap();
+
+        let posted = system_messages(owner.id, channel_id).await;
+        assert_eq!(posted.len(), 1);
+        assert_eq!(posted[0].content, "bob joined the server");
+        let event: serde_json::Value = serde_json::from_str(posted[0].system_event.as_deref().unwrap()).unwrap();
+        assert_eq!(event["type"], "member_joined");
+        assert_eq!(event["user_id"], bob.id.to_string());
+        assert!(owner_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::NewChannelMessage(msg) if msg.id == posted[0].id
+        )));
+    }
+
+    #[tokio::test]
+    async fn no_join_message_when_system_messages_are_off() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        servers::db_set_server_system_messages(server_id, false).await.unwrap();
+        let bob = test_support::create_user("bob").await;
+
+        UserService::add_user_to_default_server(bob.id, &bob.username, &peer_map).await.unwrap();
+
+        assert!(system_messages(owner.id, channel_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn welcome_template_is_posted_with_the_username_escaped() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        servers::db_set_server_system_messages(server_id, false).await.unwrap();
+        servers::db_set_server_welcome(server_id, servers::WelcomeSettings {
+            channel_id: Some(channel_id),
+            template: Some("Welcome, {username}!".to_string()),
+            dm_template: None,
+        }).await.unwrap();
+        let bob = test_support::create_user("bob_b").await;
+
+        SystemMessageService::member_joined(server_id, bob.id, &bob.username, &peer_map).await;
+
+        let posted = system_messages(owner.id, channel_id).await;
+        assert_eq!(posted.len(), 1);
+        assert_eq!(posted[0].content, "Welcome, bob\\_b!");
+    }
+
+    #[tokio::test]
+    async fn each_join_path_greets_the_member_once() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let owner = test_support::create_user("owner").await;
+        let server_id = test_support::create_server(&owner, "Test").await;
+        let channel_id = test_support::create_channel(server_id, "welcome").await;
+        servers::db_set_server_system_messages(server_id, false).await.unwrap();
+        servers::db_set_server_welcome(server_id, servers::WelcomeSettings {
+            channel_id: Some(channel_id),
+            template: Some("Welcome, {username}!".to_string()),
+            dm_template: Some("Glad you're here, {username}".to_string()),
+        }).await.unwrap();
+        let bob = test_support::create_user("bob").await;
+        let carol = test_support::create_user("carol").await;
+
+        // Registration's default join, then an accepted invite
+        UserService::add_user_to_default_server(bob.id, &bob.username, &peer_map).await.unwrap();
+        InviteService::send_server_invite(owner.id, carol.id, server_id, &peer_map).await.unwrap();
+        InviteService::respond_to_invite_from_user(owner.id, carol.id, true, &peer_map).await.unwrap();
+
+        let mut posted: Vec<String> = system_messages(owner.id, channel_id).await.into_iter().map(|message| message.content).collect();
+        posted.sort();
+        assert_eq!(posted, vec!["Welcome, bob!".to_string(), "Welcome, carol!".to_string()]);
+        for member in [&bob, &carol] {
+            assert_eq!(messages::db_get_direct_message_count(users::SYSTEM_USER_ID, member.id).await.unwrap(), 1);
+        }
+    }
+}
@@ -1,12 +1,32 @@
-use crate::db::users;
+use crate::db::{messages, servers, users};
 use crate::errors::{Result, ServerError};
-use crate::services::BroadcastService;
-use crate::api::connection::PeerMap;
+use crate::services::{audit_service, AuditService, BroadcastService, SettingsService, SystemMessageService};
+use crate::services::content_filter_service::{ContentFilterService, FilterResult};
+use crate::api::connection::{self, PeerMap};
 use crate::auth::validate_password;
-use nexus_tui_common::{User, UserProfile, UserStatus};
+use nexus_tui_common::{ProfileVisibility, ServerMembership, ServerMessage, User, UserInfo, UserProfile, UserRole, UserStatus};
+use once_cell::sync::Lazy;
+use rand::distr::{Alphanumeric, SampleString};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use tracing::{error, info};
 use uuid::Uuid;
 
+/// Longest display name, in characters
+const MAX_DISPLAY_NAME_CHARS: usize = 32;
+/// Length of a generated bot token
+const BOT_TOKEN_LENGTH: usize = 48;
+/// Repeat views of the same profile by the same viewer within this window count once
+const PROFILE_VIEW_DEDUPE_SECS: i64 = 24 * 3600;
+
+/// Bumped on every credential rotation. A login notes it before checking the
+/// password, so a rotation that lands while the login is in flight is caught.
+static CREDENTIAL_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Epoch of each user's most recent credential rotation
+static ROTATION_EPOCHS: Lazy<Mutex<HashMap<Uuid, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 pub struct UserService;
 
 impl UserService {
@@ -23,13 +43,18 @@ impl UserService {
         // Check if this is the first user (make them admin)
         let is_first_user = users::db_count_users().await? == 0;
         let role = if is_first_user { "Admin" } else { "User" };
+
+        // The first account can always register, so a server can't lock out its own admin
+        if !is_first_user && !SettingsService::registration_open().await? {
+            return Err(ServerError::Forbidden("Registration is closed".to_string()));
+        }
         
         // Register user in database
         let profile = users::db_register_user(username, password, "Green", role).await
             .map_err(|e| ServerError::Database(e))?;
 
         // Add user to default server and channels
-        if let Err(e) = Self::add_user_to_default_server(profile.id).await {
+        if let Err(e) = Self::add_user_to_default_server(profile.id, &profile.username, peer_map).await {
             error!("Failed to add new user to default server: {}", e);
         }
 
@@ -70,6 +95,8 @@ impl UserService {
             status: UserStatus::Connected,
         };
 
+        let _ = users::db_touch_user_last_seen(user.id).await;
+
         // Broadcast user joined
         BroadcastService::broadcast_user_status_change(peer_map, &user, true).await;
         
@@ -77,8 +104,75 @@ impl UserService {
         Ok(user)
     }
 
+    /// Current credential epoch; take it before `login` and check it with
+    /// `credentials_rotated_since` once the session is registered
+    pub fn credential_epoch() -> u64 {
+        CREDENTIAL_EPOCH.load(Ordering::SeqCst)
+    }
+
+    /// Whether the user's credentials were rotated after `epoch` was taken
+    pub fn credentials_rotated_since(user_id: Uuid, epoch: u64) -> bool {
+        ROTATION_EPOCHS.lock().unwrap().get(&user_id).is_some_and(|rotated| *rotated > epoch)
+    }
+
+    /// Give a bot account a fresh token (Admin only). The old token stops
+    /// working as soon as the new one is stored and every session logged in
+    /// with it is revoked. The token is returned here and never again.
+    pub async fn rotate_bot_token(admin: &User, bot_user_id: Uuid, peer_map: &PeerMap) -> Result<String> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can rotate bot tokens".to_string()));
+        }
+
+        let token = Alphanumeric.sample_string(&mut rand::rng(), BOT_TOKEN_LENGTH);
+        if !users::db_rotate_bot_password(bot_user_id, &token).await.map_err(|e| ServerError::Database(e))? {
+            return Err(ServerError::NotFound("No bot account with that id".to_string()));
+        }
+
+        // Logins that started before this point are rejected once they register
+        let epoch = CREDENTIAL_EPOCH.fetch_add(1, Ordering::SeqCst) + 1;
+        ROTATION_EPOCHS.lock().unwrap().insert(bot_user_id, epoch);
+        let revoked = connection::revoke_user_sessions(peer_map, bot_user_id, "Bot token rotated").await;
+
+        AuditService::record(
+            admin, audit_service::ROTATE_BOT_TOKEN, Some(bot_user_id.to_string()),
+            Some(format!("{} session(s) revoked", revoked))
+        ).await;
+        info!("Bot token of {} rotated by {} ({} sessions revoked)", bot_user_id, admin.username, revoked);
+        Ok(token)
+    }
+
+    /// What a user sees, for support staff debugging their client (Admin only):
+    /// their server and channel memberships and who they have DMs with. No
+    /// message content is included. Every lookup is audited.
+    pub async fn user_view(admin: &User, user_id: Uuid) -> Result<(Vec<ServerMembership>, Vec<UserInfo>)> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can view another user's client state".to_string()));
+        }
+
+        let memberships = servers::db_get_user_memberships(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        let dm_partners = messages::db_get_dm_user_list_lightweight(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        AuditService::record(admin, audit_service::VIEW_USER_AS, Some(user_id.to_string()), None).await;
+        info!("{} viewed the client state of user {}", admin.username, user_id);
+        Ok((memberships, dm_partners))
+    }
+
+    /// Every server a user belongs to, with the channels they're in. Users can
+    /// list their own; admins can list anyone's.
+    pub async fn memberships(requester: &User, user_id: Uuid) -> Result<Vec<ServerMembership>> {
+        if requester.id != user_id && requester.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("You can only list your own memberships".to_string()));
+        }
+        servers::db_get_user_memberships(user_id).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
     /// Logout user
     pub async fn logout(user: &User, peer_map: &PeerMap) {
+        let _ = users::db_touch_user_last_seen(user.id).await;
+
         // Broadcast user left
         BroadcastService::broadcast_user_status_change(peer_map, user, false).await;
         
@@ -156,6 +250,61 @@ impl UserService {
         Ok(updated_user)
     }
 
+    /// Set or clear the name shown in place of the user's login name. The
+    /// username stays the unique login; display names go through the content
+    /// filter and can't impersonate another account's username.
+    pub async fn update_display_name(
+        user: &User,
+        display_name: Option<String>,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<Option<String>> {
+        if !crate::config::settings().users.display_names {
+            return Err(ServerError::Forbidden("Display names are disabled on this server".to_string()));
+        }
+        Self::set_display_name(user, display_name, content_filter, peer_map).await
+    }
+
+    async fn set_display_name(
+        user: &User,
+        display_name: Option<String>,
+        content_filter: &ContentFilterService,
+        peer_map: &PeerMap,
+    ) -> Result<Option<String>> {
+        let display_name = display_name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty() && *name != user.username);
+        if let Some(name) = &display_name {
+            if name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+                return Err(ServerError::Validation(format!(
+                    "Display name is limited to {} characters", MAX_DISPLAY_NAME_CHARS
+                )));
+            }
+            if name.chars().any(char::is_control) {
+                return Err(ServerError::Validation("Display name can't contain control characters".to_string()));
+            }
+            match content_filter.filter_message(name) {
+                FilterResult::Allowed => {}
+                FilterResult::Blocked(reason) | FilterResult::Flagged(reason) => {
+                    return Err(ServerError::Validation(format!("Display name not allowed: {}", reason)));
+                }
+            }
+        }
+
+        users::db_update_display_name(user.id, display_name.clone()).await
+            .map_err(|e| ServerError::Validation(e))?;
+
+        // Sessions sharing a channel re-render the user's messages and member entries
+        let mut recipients = crate::db::channels::db_get_users_sharing_channels_with(user.id).await
+            .map_err(|e| ServerError::Database(e))?;
+        recipients.push(user.id);
+        let changed = ServerMessage::DisplayNameChanged { user_id: user.id, display_name: display_name.clone() };
+        BroadcastService::broadcast_to_users(peer_map, &recipients, &changed).await;
+
+        info!("Display name of {} set to {:?}", user.username, display_name);
+        Ok(display_name)
+    }
+
     /// Update user password
     pub async fn update_password(user_id: Uuid, new_password: &str) -> Result<()> {
         // Validate password
@@ -169,12 +318,141 @@ impl UserService {
         Ok(())
     }
 
+    /// Change a user's role (Admin only). Their live sessions pick up the new
+    /// role as soon as the RoleChanged notice is dequeued, without re-login.
+    pub async fn set_user_role(admin: &User, user_id: Uuid, role: UserRole, peer_map: &PeerMap) -> Result<()> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can change roles".to_string()));
+        }
+
+        let role_str = match role {
+            UserRole::Admin => "Admin",
+            UserRole::Moderator => "Moderator",
+            UserRole::Bot => "Bot",
+            UserRole::User => "User",
+        };
+        users::db_update_user_role(user_id, role_str).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        AuditService::record(
+            admin, audit_service::SET_USER_ROLE, Some(user_id.to_string()), Some(role_str.to_string())
+        ).await;
+        BroadcastService::send_to_user(peer_map, user_id, &ServerMessage::RoleChanged { new_role: role }).await;
+        info!("Role of {} set to {} by {}", user_id, role_str, admin.username);
+        Ok(())
+    }
+
+    /// Rename a user (Admin only)
+    pub async fn rename_user(admin: &User, user_id: Uuid, new_username: &str) -> Result<()> {
+        if admin.role != UserRole::Admin {
+            return Err(ServerError::Forbidden("Only admins can rename users".to_string()));
+        }
+
+        let new_username = new_username.trim();
+        if new_username.is_empty() {
+            return Err(ServerError::Validation("Username cannot be empty".to_string()));
+        }
+
+        users::db_update_username(user_id, new_username).await
+            .map_err(|e| ServerError::Validation(e))?;
+
+        AuditService::record(
+            admin, audit_service::RENAME_USER, Some(user_id.to_string()), Some(new_username.to_string())
+        ).await;
+        info!("User {} renamed to {} by {}", user_id, new_username, admin.username);
+        Ok(())
+    }
+
     /// Get user profile
-    pub async fn get_profile(user_id: Uuid) -> Result<UserProfile> {
-        users::db_get_user_profile(user_id).await
+    pub async fn get_profile(requester: Option<&User>, user_id: Uuid) -> Result<UserProfile> {
+        let mut profile = users::db_get_user_profile(user_id).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        if !Self::can_see_full_profile(requester, user_id).await? {
+            // Only what already shows up next to the user's messages
+            profile.bio = None;
+            profile.url1 = None;
+            profile.url2 = None;
+            profile.url3 = None;
+            profile.location = None;
+            profile.cover_banner = None;
+        }
+        Ok(profile)
+    }
+
+    /// Check a target's profile visibility against the requester's relationship to them
+    async fn can_see_full_profile(requester: Option<&User>, target_id: Uuid) -> Result<bool> {
+        if let Some(requester) = requester {
+            if requester.id == target_id || requester.role == UserRole::Admin {
+                return Ok(true);
+            }
+        }
+
+        let visibility = users::db_get_profile_visibility(target_id).await
+            .map_err(|e| ServerError::Database(e))?;
+        match (visibility, requester) {
+            (ProfileVisibility::Public, _) => Ok(true),
+            (ProfileVisibility::ServerMembersOnly, Some(requester)) => {
+                servers::db_users_share_server(requester.id, target_id).await
+                    .map_err(|e| ServerError::Database(e))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Set who may see the user's full profile
+    pub async fn set_profile_visibility(user_id: Uuid, visibility: ProfileVisibility) -> Result<()> {
+        users::db_set_profile_visibility(user_id, visibility).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Choose whether new channels in the user's servers subscribe them automatically
+    pub async fn set_auto_join_new_channels(user_id: Uuid, enabled: bool) -> Result<()> {
+        users::db_set_auto_join_new_channels(user_id, enabled).await
+            .map_err(|e| ServerError::Database(e))
+    }
+
+    /// Opt in or out of the weekly profile view digest. Views are only recorded
+    /// between users who both opted in.
+    pub async fn set_profile_view_notices(user_id: Uuid, enabled: bool) -> Result<()> {
+        users::db_set_profile_view_notices(user_id, enabled).await
             .map_err(|e| ServerError::Database(e))
     }
 
+    /// Note that `viewer` opened a profile, for the owner's profile view digest.
+    /// Admin views and views of one's own profile are never recorded. Failures
+    /// are logged; they never get in the way of showing the profile.
+    pub async fn record_profile_view(viewer: &User, viewed_id: Uuid) {
+        if viewer.id == viewed_id || viewer.role == UserRole::Admin {
+            return;
+        }
+        let dedupe_since = crate::util::now_secs() - PROFILE_VIEW_DEDUPE_SECS;
+        if let Err(e) = users::db_record_profile_view(viewer.id, viewed_id, dedupe_since).await {
+            error!("Failed to record profile view of {} by {}: {}", viewed_id, viewer.id, e);
+        }
+    }
+
+    /// Get users changed since the client's last sync, with current online status
+    pub async fn get_user_updates(
+        since: i64,
+        user_ids: Option<Vec<Uuid>>,
+        peer_map: &PeerMap,
+    ) -> Result<Vec<UserInfo>> {
+        let mut updated = users::db_get_users_updated_since(since, user_ids).await
+            .map_err(|e| ServerError::Database(e))?;
+
+        let online_users = BroadcastService::get_online_users(peer_map).await;
+        for user in &mut updated {
+            user.status = if online_users.contains(&user.id) {
+                UserStatus::Connected
+            } else {
+                UserStatus::Offline
+            };
+        }
+
+        Ok(updated)
+    }
+
     /// Get list of online users with updated status
     pub async fn get_user_list(peer_map: &PeerMap) -> Result<Vec<User>> {
         let online_users = BroadcastService::get_online_users(peer_map).await;
@@ -197,8 +475,8 @@ impl UserService {
         Ok(users)
     }
 
-    /// Add user to default server (for new registrations)
-    async fn add_user_to_default_server(user_id: Uuid) -> Result<()> {
+    /// Add user to default server (for new registrations and bulk imports)
+    pub async fn add_user_to_default_server(user_id: Uuid, username: &str, peer_map: &PeerMap) -> Result<()> {
         // Get the default server (first server in the system)
         if let Ok(servers) = crate::db::servers::db_get_servers().await {
             if let Some(server) = servers.first() {
@@ -214,8 +492,318 @@ impl UserService {
                     crate::db::channels::db_add_user_to_channel(channel_id, user_id).await
                         .map_err(|e| ServerError::Database(e))?;
                 }
+
+                SystemMessageService::member_joined(server.id, user_id, username, peer_map).await;
             }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::channels;
+    use crate::test_support::{self, TestDb};
+
+    #[tokio::test]
+    async fn first_registered_user_is_admin_and_later_ones_are_not() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+
+        let first = UserService::register("founder", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+        let second = UserService::register("member", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+
+        assert_eq!(first.role, UserRole::Admin);
+        assert_eq!(second.role, UserRole::User);
+    }
+
+    #[tokio::test]
+    async fn registered_users_join_the_default_server_and_all_its_channels() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let founder = UserService::register("founder", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+        // The default server is created for the first admin at startup
+        servers::ensure_default_server_exists().await.unwrap();
+        let server_id = servers::get_default_server_id().await.unwrap().expect("default server");
+
+        let member = UserService::register("member", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+
+        let channel_ids = channels::db_get_server_channels(server_id).await.unwrap();
+        assert!(!channel_ids.is_empty());
+        assert!(servers::db_is_user_in_server(member.id, server_id).await.unwrap());
+        for channel_id in channel_ids {
+            let channel_users = channels::db_get_channel_user_list(channel_id).await.unwrap();
+            for user in [&founder, &member] {
+                assert!(channel_users.iter().any(|u| u.id == user.id), "{} missing from a default channel", user.username);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_rotated_bot_token_replaces_the_old_one_at_once() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let bot = test_support::create_user_with_role("helper", "Bot").await;
+        let mut session = test_support::FakePeer::connect(&peer_map, Some(bot.id)).await;
+
+        let token = UserService::rotate_bot_token(&admin, bot.id, &peer_map).await.unwrap();
+
+        assert!(session.drain().iter().any(|message| matches!(message, ServerMessage::SessionRevoked { .. })));
+        assert!(test_support::peer_removed(&peer_map, session.peer_id).await);
+        // The bot reconnecting straight away with its old token is turned away
+        assert!(UserService::login("helper", test_support::TEST_PASSWORD, &peer_map).await.is_err());
+        let relogged = UserService::login("helper", &token, &peer_map).await.unwrap();
+        assert_eq!(relogged.id, bot.id);
+    }
+
+    #[tokio::test]
+    async fn a_login_checked_before_a_rotation_is_caught_afterwards() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let bot = test_support::create_user_with_role("helper", "Bot").await;
+
+        let before = UserService::credential_epoch();
+        UserService::rotate_bot_token(&admin, bot.id, &peer_map).await.unwrap();
+        let after = UserService::credential_epoch();
+
+        assert!(UserService::credentials_rotated_since(bot.id, before));
+        assert!(!UserService::credentials_rotated_since(bot.id, after));
+        assert!(!UserService::credentials_rotated_since(admin.id, before));
+    }
+
+    #[tokio::test]
+    async fn only_admins_rotate_tokens_and_only_of_bots() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let moderator = test_support::create_user_with_role("mod", "Moderator").await;
+        let bot = test_support::create_user_with_role("helper", "Bot").await;
+
+        let refused = UserService::rotate_bot_token(&moderator, bot.id, &peer_map).await;
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+        let not_a_bot = UserService::rotate_bot_token(&admin, moderator.id, &peer_map).await;
+        assert!(matches!(not_a_bot, Err(ServerError::NotFound(_))));
+        UserService::login("mod", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_admin_user_view_shows_memberships_and_is_audited() {
+        let _db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let carol = test_support::create_user("carol").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::create_channel(server_id, "general").await;
+        test_support::join_server(server_id, bob.id).await;
+        test_support::create_channel(server_id, "random").await;
+        messages::db_store_direct_message(carol.id, bob.id, "psst", crate::util::now_secs()).await.unwrap();
+
+        let refused = UserService::user_view(&carol, bob.id).await;
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+
+        let (memberships, dm_partners) = UserService::user_view(&admin, bob.id).await.unwrap();
+        assert_eq!(memberships.len(), 1);
+        assert_eq!(memberships[0].server_id, server_id);
+        let channels: Vec<&str> = memberships[0].channels.iter().map(|channel| channel.name.as_str()).collect();
+        assert_eq!(channels, vec!["general", "random"]);
+        let partners: Vec<Uuid> = dm_partners.iter().map(|partner| partner.id).collect();
+        assert_eq!(partners, vec![carol.id]);
+
+        let (entries, _) = AuditService::fetch_audit_entries(
+            &admin, 50, 0, None, Some(audit_service::VIEW_USER_AS.to_string()), None, None
+        ).await.unwrap();
+        assert_eq!(entries.len(), 1, "only the admin's view is audited");
+        assert_eq!(entries[0].user_id, admin.id);
+        assert_eq!(entries[0].target.as_deref(), Some(bob.id.to_string().as_str()));
+    }
+
+    #[tokio::test]
+    async fn profile_views_are_recorded_only_between_opted_in_users() {
+        let db = TestDb::new().await;
+        let viewer = test_support::create_user("viewer").await;
+        let owner = test_support::create_user("owner").await;
+
+        // Off on both sides, then on for only one side at a time
+        UserService::record_profile_view(&viewer, owner.id).await;
+        UserService::set_profile_view_notices(owner.id, true).await.unwrap();
+        UserService::record_profile_view(&viewer, owner.id).await;
+        UserService::set_profile_view_notices(owner.id, false).await.unwrap();
+        UserService::set_profile_view_notices(viewer.id, true).await.unwrap();
+        UserService::record_profile_view(&viewer, owner.id).await;
+        assert_eq!(db.count_rows("profile_views"), 0);
+
+        UserService::set_profile_view_notices(owner.id, true).await.unwrap();
+        UserService::record_profile_view(&viewer, owner.id).await;
+        UserService::record_profile_view(&viewer, owner.id).await;
+        assert_eq!(db.count_rows("profile_views"), 1, "a second view within a day is deduplicated");
+    }
+
+    #[tokio::test]
+    async fn admin_and_own_profile_views_are_never_recorded() {
+        let db = TestDb::new().await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        let owner = test_support::create_user("owner").await;
+        UserService::set_profile_view_notices(admin.id, true).await.unwrap();
+        UserService::set_profile_view_notices(owner.id, true).await.unwrap();
+
+        UserService::record_profile_view(&admin, owner.id).await;
+        UserService::record_profile_view(&owner, owner.id).await;
+
+        assert_eq!(db.count_rows("profile_views"), 0);
+    }
+
+    #[tokio::test]
+    async fn own_memberships_list_the_default_server_and_its_channels() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let founder = UserService::register("founder", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+        servers::ensure_default_server_exists().await.unwrap();
+        let server_id = servers::get_default_server_id().await.unwrap().expect("default server");
+        let member = UserService::register("member", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+        let onlooker = test_support::create_user("onlooker").await;
+
+        let memberships = UserService::memberships(&member, member.id).await.unwrap();
+        assert_eq!(memberships.len(), 1);
+        assert_eq!(memberships[0].server_id, server_id);
+        let mut listed: Vec<Uuid> = memberships[0].channels.iter().map(|channel| channel.channel_id).collect();
+        let mut expected = channels::db_get_server_channels(server_id).await.unwrap();
+        listed.sort();
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(listed, expected);
+
+        let refused = UserService::memberships(&onlooker, member.id).await;
+        assert!(matches!(refused, Err(ServerError::Forbidden(_))));
+        // The founder is an admin and may look
+        assert_eq!(UserService::memberships(&founder, member.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_display_name_reaches_channel_peers_but_stays_unlisted_while_off() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        let channel_id = test_support::create_channel(server_id, "general").await;
+        let mut alice_peer = test_support::FakePeer::connect(&peer_map, Some(alice.id)).await;
+
+        let set = UserService::set_display_name(&bob, Some(" Bobby ".to_string()), &test_support::content_filter(), &peer_map).await.unwrap();
+
+        assert_eq!(set.as_deref(), Some("Bobby"));
+        assert!(alice_peer.drain().iter().any(|message| matches!(
+            message,
+            ServerMessage::DisplayNameChanged { user_id, display_name } if *user_id == bob.id && display_name.as_deref() == Some("Bobby")
+        )));
+        let members = channels::db_get_channel_user_list_lightweight(channel_id).await.unwrap();
+        let listed = members.iter().find(|member| member.id == bob.id).unwrap();
+        // [users] display_names is off by default, so lists leave the stored name out
+        assert_eq!(listed.display_name, None);
+        assert_eq!(listed.username, "bob");
+    }
+
+    #[tokio::test]
+    async fn display_names_are_off_by_default() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let bob = test_support::create_user("bob").await;
+
+        let result = UserService::update_display_name(&bob, Some("Bobby".to_string()), &test_support::content_filter(), &peer_map).await;
+
+        assert!(matches!(result, Err(ServerError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn display_names_leave_logins_unique_and_unchanged() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let filter = test_support::content_filter();
+
+        for taken in ["alice", "ALICE", "\u{0430}lice"] {
+            let result = UserService::set_display_name(&bob, Some(taken.to_string()), &filter, &peer_map).await;
+            assert!(matches!(result, Err(ServerError::Validation(_))), "{} was accepted", taken);
+        }
+        UserService::set_display_name(&bob, Some("carol".to_string()), &filter, &peer_map).await.unwrap();
+
+        // The display name is no login, but nobody can register or be renamed to it
+        assert!(UserService::login("carol", test_support::TEST_PASSWORD, &peer_map).await.is_err());
+        assert_eq!(UserService::login("bob", test_support::TEST_PASSWORD, &peer_map).await.unwrap().id, bob.id);
+        for taken in ["carol", "CAROL", "\u{0441}arol"] {
+            let result = UserService::register(taken, test_support::TEST_PASSWORD, &peer_map).await;
+            assert!(result.is_err(), "{} was registered", taken);
+        }
+        assert!(UserService::register("bob", test_support::TEST_PASSWORD, &peer_map).await.is_err());
+        let dave = UserService::register("dave", test_support::TEST_PASSWORD, &peer_map).await.unwrap();
+        assert!(matches!(
+            UserService::rename_user(&admin, dave.id, "Carol").await,
+            Err(ServerError::Validation(_))
+        ));
+        // A user's own display name is free to become their username
+        UserService::rename_user(&admin, bob.id, "carol").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn registration_rejects_short_passwords() {
+        let _db = TestDb::new().await;
+        let peer_map = test_support::peer_map();
+
+        let result = UserService::register("founder", "short", &peer_map).await;
+
+        assert!(matches!(result, Err(ServerError::Validation(_))));
+        assert_eq!(users::db_count_users().await.unwrap(), 0);
+    }
+
+    /// Whether the profile came back with its private fields
+    fn is_full(profile: &UserProfile) -> bool {
+        profile.bio.as_deref() == Some("about me") && profile.location.as_deref() == Some("Earth")
+    }
+
+    #[tokio::test]
+    async fn profile_visibility_decides_who_sees_bio_and_location() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let admin = test_support::create_user_with_role("admin", "Admin").await;
+        users::db_update_user_profile(
+            alice.id, Some("about me".to_string()), None, None, None, Some("Earth".to_string()), None, None
+        ).await.unwrap();
+
+        let public = UserService::get_profile(Some(&bob), alice.id).await.unwrap();
+        assert!(is_full(&public));
+        assert!(is_full(&UserService::get_profile(None, alice.id).await.unwrap()));
+
+        UserService::set_profile_visibility(alice.id, ProfileVisibility::Private).await.unwrap();
+        let private = UserService::get_profile(Some(&bob), alice.id).await.unwrap();
+        assert_eq!(private.username, "alice");
+        assert_eq!(private.bio, None);
+        assert_eq!(private.location, None);
+        assert!(is_full(&UserService::get_profile(Some(&alice), alice.id).await.unwrap()));
+        assert!(is_full(&UserService::get_profile(Some(&admin), alice.id).await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn members_only_profiles_are_shown_to_people_sharing_a_server() {
+        let _db = TestDb::new().await;
+        let alice = test_support::create_user("alice").await;
+        let bob = test_support::create_user("bob").await;
+        let carol = test_support::create_user("carol").await;
+        let server_id = test_support::create_server(&alice, "Test").await;
+        test_support::join_server(server_id, bob.id).await;
+        users::db_update_user_profile(
+            alice.id, Some("about me".to_string()), None, None, None, Some("Earth".to_string()), None, None
+        ).await.unwrap();
+        UserService::set_profile_visibility(alice.id, ProfileVisibility::ServerMembersOnly).await.unwrap();
+
+        assert!(is_full(&UserService::get_profile(Some(&bob), alice.id).await.unwrap()));
+        assert!(!is_full(&UserService::get_profile(Some(&carol), alice.id).await.unwrap()));
+        assert!(!is_full(&UserService::get_profile(None, alice.id).await.unwrap()));
+    }
+}
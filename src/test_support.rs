@@ -0,0 +1,188 @@
+//! Fixtures shared by the unit tests: a throwaway database, quick factories
+//! for users, servers and channels, and a fake connected peer.
+
+use crate::api::connection::{Peer, PeerMap, PeerSender};
+use crate::api::routes::MessageRouter;
+use crate::config::{ModerationConfig, RateLimitConfig};
+use crate::db::{channels, db_config, migrations, servers, users};
+use crate::services::{ContentFilterService, RateLimitService};
+use nexus_tui_common::{ServerMessage, User, UserStatus};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::{mpsc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// Password given to every user the factories create
+pub const TEST_PASSWORD: &str = "hunter22";
+
+/// Messages a fake peer can hold before it overflows, unless asked otherwise
+const FAKE_PEER_CAPACITY: usize = 1024;
+
+/// The database path is process-wide, so tests that use the database take
+/// turns, each on a file of its own
+static DB_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// A freshly migrated database in a temp directory. It is the database every
+/// `db_*` call uses until dropped.
+pub struct TestDb {
+    // Dropped in order: the file goes before the next test may take the lock
+    dir: TempDir,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestDb {
+    /// Point the database at a new file and run the migrations, the way
+    /// startup does
+    pub async fn new() -> Self {
+        let guard = DB_LOCK.lock().await;
+        let dir = tempfile::tempdir().expect("create temp dir for test database");
+        db_config::set_db_path(dir.path().join("nexus.db").to_string_lossy().into_owned());
+        migrations::init_db().await.expect("migrate test database");
+        users::ensure_system_user_exists().await.expect("create System user");
+        Self { dir, _guard: guard }
+    }
+
+    /// Path of the database file
+    pub fn path(&self) -> std::path::PathBuf {
+        self.dir.path().join("nexus.db")
+    }
+
+    /// Count the rows of a table, straight from SQLite
+    pub fn count_rows(&self, table: &str) -> i64 {
+        let conn = rusqlite::Connection::open(self.path()).expect("open test database");
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+            .expect("count rows")
+    }
+}
+
+/// An empty peer map, as at startup
+pub fn peer_map() -> PeerMap {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/// A content filter with the default moderation config
+pub fn content_filter() -> ContentFilterService {
+    ContentFilterService::new(&ModerationConfig::default())
+}
+
+/// A content filter that blocks `blocked_words` and flags `flagged_patterns`
+pub fn content_filter_with(blocked_words: &[&str], flagged_patterns: &[&str]) -> ContentFilterService {
+    ContentFilterService::new(&ModerationConfig {
+        blocked_words: blocked_words.iter().map(|word| word.to_string()).collect(),
+        flagged_patterns: flagged_patterns.iter().map(|pattern| pattern.to_string()).collect(),
+        ..ModerationConfig::default()
+    })
+}
+
+/// A message router for one connection from localhost, with the default
+/// content filter and rate limits
+pub fn router(peer_map: &PeerMap) -> MessageRouter {
+    MessageRouter::new(
+        peer_map.clone(),
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        Arc::new(content_filter()),
+        Arc::new(RateLimitService::new(&RateLimitConfig::default())),
+    )
+}
+
+/// Create a regular user straight in the database
+pub async fn create_user(username: &str) -> User {
+    create_user_with_role(username, "User").await
+}
+
+/// Create a user with a role ("Admin", "Moderator", "User", "Bot")
+pub async fn create_user_with_role(username: &str, role: &str) -> User {
+    let profile = users::db_register_user(username, TEST_PASSWORD, "Green", role).await
+        .expect("create test user");
+    User {
+        id: profile.id,
+        username: profile.username,
+        color: profile.color,
+        role: profile.role,
+        profile_pic: profile.profile_pic,
+        cover_banner: profile.cover_banner,
+        status: UserStatus::Connected,
+    }
+}
+
+/// Create a public server owned (and moderated) by `owner`
+pub async fn create_server(owner: &User, name: &str) -> Uuid {
+    servers::db_create_server(name, "", true, owner.id, None, None).await
+        .expect("create test server")
+}
+
+/// Create a channel; the server's current members are added to it
+pub async fn create_channel(server_id: Uuid, name: &str) -> Uuid {
+    let (channel_id, _) = channels::db_create_channel(server_id, name, "").await
+        .expect("create test channel");
+    channel_id
+}
+
+/// Add a user to a server and every channel it has so far
+pub async fn join_server(server_id: Uuid, user_id: Uuid) {
+    servers::db_add_user_to_server(server_id, user_id).await.expect("join test server");
+    for channel_id in channels::db_get_server_channels(server_id).await.expect("list test channels") {
+        channels::db_add_user_to_channel(channel_id, user_id).await.expect("join test channel");
+    }
+}
+
+/// Whether `peer_id` leaves the peer map within a few seconds; removal
+/// happens on spawned tasks, so tests have to wait for it
+pub async fn peer_removed(peer_map: &PeerMap, peer_id: Uuid) -> bool {
+    let wait = async {
+        while peer_map.lock().await.contains_key(&peer_id) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait).await.is_ok()
+}
+
+/// A connected client as far as the peer map is concerned. Messages sent to
+/// it queue up until the test drains them.
+pub struct FakePeer {
+    pub peer_id: Uuid,
+    pub sender: PeerSender,
+    rx: mpsc::Receiver<ServerMessage>,
+}
+
+impl FakePeer {
+    /// Connect a peer, logged in as `user_id` if given
+    pub async fn connect(peer_map: &PeerMap, user_id: Option<Uuid>) -> Self {
+        Self::connect_with_capacity(peer_map, user_id, FAKE_PEER_CAPACITY).await
+    }
+
+    /// Connect a peer whose send queue holds `capacity` messages
+    pub async fn connect_with_capacity(peer_map: &PeerMap, user_id: Option<Uuid>, capacity: usize) -> Self {
+        let peer_id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(capacity);
+        let sender = PeerSender::new(tx);
+        peer_map.lock().await.insert(
+            peer_id,
+            Peer {
+                user_id,
+                tx: sender.clone(),
+                disconnected: AtomicBool::new(false),
+            },
+        );
+        Self { peer_id, sender, rx }
+    }
+
+    /// Everything sent to the peer since the last drain, oldest first
+    pub fn drain(&mut self) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.rx.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Hang up: the peer's receiver goes away, so sends to it fail
+    pub fn hang_up(self) -> Uuid {
+        self.peer_id
+    }
+}
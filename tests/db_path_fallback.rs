@@ -0,0 +1,59 @@
+//! The database path fallback, in a process of its own since the path is
+//! global and every other test sets it.
+
+use nexus_tui_server::db::db_config;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log output collected in memory
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn the_fallback_path_is_used_once_with_a_warning() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        assert!(!db_config::is_initialized());
+        assert_eq!(db_config::get_db_path(), "nexus.db");
+        assert_eq!(db_config::get_db_path(), "nexus.db");
+    });
+
+    let output = logs.contents();
+    assert_eq!(output.matches("requested before init_db_path").count(), 1, "logs: {}", output);
+    assert!(output.contains("WARN"), "logs: {}", output);
+
+    db_config::set_db_path("configured.db".to_string());
+    assert!(db_config::is_initialized());
+    assert_eq!(db_config::get_db_path(), "configured.db");
+}
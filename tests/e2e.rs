@@ -0,0 +1,131 @@
+//! End to end: boot the server on an ephemeral port with a temp database and a
+//! self-signed certificate, then talk to it over TLS with real bincode frames.
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use nexus_tui_common::{ClientMessage, ServerMessage};
+use nexus_tui_server::db::{db_config, migrations, servers, users};
+use nexus_tui_server::{config, load_tls_config, run_server};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+type Client = Framed<TlsStream<TcpStream>, LengthDelimitedCodec>;
+
+/// How long to wait for any one reply before failing the test
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn send(client: &mut Client, message: &ClientMessage) {
+    let frame = bincode::serialize(message).expect("serialize client message");
+    client.send(Bytes::from(frame)).await.expect("send frame");
+}
+
+/// Read frames until `pick` accepts one, skipping unrelated pushes
+async fn expect<T>(client: &mut Client, mut pick: impl FnMut(ServerMessage) -> Option<T>) -> T {
+    tokio::time::timeout(REPLY_TIMEOUT, async {
+        loop {
+            let frame = client.next().await.expect("connection closed").expect("read frame");
+            let message: ServerMessage = bincode::deserialize(&frame).expect("deserialize server message");
+            if let Some(picked) = pick(message) {
+                return picked;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for a reply")
+}
+
+async fn connect(port: u16, cert: CertificateDer<'static>) -> Client {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).expect("trust test certificate");
+    let tls = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.expect("connect to test server");
+    let stream = TlsConnector::from(Arc::new(tls))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .expect("TLS handshake");
+    Framed::new(stream, LengthDelimitedCodec::new())
+}
+
+#[tokio::test]
+async fn register_send_and_read_back_over_tls() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // A database with an admin and the default server, as after first setup
+    db_config::set_db_path(dir.path().join("nexus.db").to_string_lossy().into_owned());
+    migrations::init_db().await.expect("migrate database");
+    users::ensure_system_user_exists().await.expect("create System user");
+    users::db_register_user("admin", "correct horse", "Green", "Admin").await.expect("create admin");
+    servers::ensure_default_server_exists().await.expect("create default server");
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = dir.path().join("cert.pem");
+    let key_path = dir.path().join("key.pem");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+    let tls_config = load_tls_config(
+        cert_path.to_str().unwrap(),
+        key_path.to_str().unwrap(),
+        &config::settings().tls,
+    )
+    .expect("load TLS config");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let server = run_server(listener, TlsAcceptor::from(Arc::new(tls_config)), shutdown_rx);
+
+    let client = async {
+        let mut client = connect(port, cert.cert.der().clone()).await;
+
+        send(&mut client, &ClientMessage::Register {
+            username: "e2e".to_string(),
+            password: "hunter22".to_string(),
+        }).await;
+        let user = expect(&mut client, |message| match message {
+            ServerMessage::AuthSuccess(user) => Some(Ok(user)),
+            ServerMessage::AuthFailure(reason) => Some(Err(reason)),
+            _ => None,
+        }).await.expect("registration succeeds");
+        assert_eq!(user.username, "e2e");
+
+        send(&mut client, &ClientMessage::GetServers).await;
+        let channel_id = expect(&mut client, |message| match message {
+            ServerMessage::Servers(servers) => servers.into_iter()
+                .flat_map(|server| server.channels)
+                .find(|channel| channel.name == "general")
+                .map(|channel| channel.id),
+            _ => None,
+        }).await;
+
+        send(&mut client, &ClientMessage::SendChannelMessage {
+            channel_id,
+            content: "hello over the wire".to_string(),
+            origin: None,
+        }).await;
+        let sent = expect(&mut client, |message| match message {
+            ServerMessage::NewChannelMessage(message) if message.channel_id == channel_id => Some(message),
+            _ => None,
+        }).await;
+        assert_eq!(sent.sent_by, user.id);
+        assert_eq!(sent.content, "hello over the wire");
+
+        send(&mut client, &ClientMessage::GetChannelMessages { channel_id, before: None }).await;
+        let history = expect(&mut client, |message| match message {
+            ServerMessage::ChannelMessages { channel_id: id, messages, .. } if id == channel_id => Some(messages),
+            _ => None,
+        }).await;
+        assert!(history.iter().any(|message| message.id == sent.id && message.content == "hello over the wire"));
+
+        shutdown_tx.send(true).unwrap();
+    };
+
+    let (served, ()) = tokio::join!(server, client);
+    served.expect("server shuts down cleanly");
+}
@@ -0,0 +1,90 @@
+//! Building the listener's TLS config from the [tls] settings, and what a
+//! hardened config accepts on the wire.
+
+use nexus_tui_server::config::TlsConfig;
+use nexus_tui_server::load_tls_config;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{version, ClientConfig, RootCertStore};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// A self-signed certificate for localhost, written out as PEM files
+struct TestCert {
+    dir: TempDir,
+    cert: rcgen::CertifiedKey,
+}
+
+impl TestCert {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(dir.path().join("cert.pem"), cert.cert.pem()).unwrap();
+        std::fs::write(dir.path().join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+        Self { dir, cert }
+    }
+
+    fn build(&self, min_version: &str, cipher_suites: &[&str]) -> Result<tokio_rustls::rustls::ServerConfig, String> {
+        let tls = TlsConfig {
+            min_version: min_version.to_string(),
+            cipher_suites: cipher_suites.iter().map(|suite| suite.to_string()).collect(),
+        };
+        load_tls_config(
+            self.dir.path().join("cert.pem").to_str().unwrap(),
+            self.dir.path().join("key.pem").to_str().unwrap(),
+            &tls,
+        )
+    }
+}
+
+#[test]
+fn tls_13_only_with_chosen_suites_builds() {
+    let cert = TestCert::new();
+    cert.build("1.3", &[]).expect("1.3 with every suite");
+    cert.build("1.3", &["TLS13_AES_256_GCM_SHA384", "tls13_chacha20_poly1305_sha256"]).expect("1.3 with chosen suites");
+    cert.build("1.2", &["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"]).expect("1.2 with a 1.2 suite");
+}
+
+#[test]
+fn invalid_combinations_are_reported() {
+    let cert = TestCert::new();
+
+    let error = cert.build("1.3", &["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"]).err().unwrap();
+    assert!(error.contains("can't be used with tls.min_version 1.3"), "{}", error);
+    let error = cert.build("1.2", &["TLS_RSA_WITH_RC4_128_SHA"]).err().unwrap();
+    assert!(error.contains("Unknown or unsupported TLS cipher suite"), "{}", error);
+    let error = cert.build("1.1", &[]).err().unwrap();
+    assert!(error.contains("Unsupported tls.min_version '1.1'"), "{}", error);
+}
+
+#[tokio::test]
+async fn a_tls_13_only_listener_refuses_tls_12_clients() {
+    let cert = TestCert::new();
+    let acceptor = TlsAcceptor::from(Arc::new(cert.build("1.3", &[]).unwrap()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        }
+    });
+
+    let handshake = |versions: &'static [&'static tokio_rustls::rustls::SupportedProtocolVersion]| {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.cert.der().clone()).unwrap();
+        let config = ClientConfig::builder_with_protocol_versions(versions)
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        async move {
+            let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            TlsConnector::from(Arc::new(config))
+                .connect(ServerName::try_from("localhost").unwrap(), stream)
+                .await
+        }
+    };
+
+    assert!(handshake(&[&version::TLS12]).await.is_err());
+    handshake(&[&version::TLS13]).await.expect("TLS 1.3 handshake");
+}